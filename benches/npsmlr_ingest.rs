@@ -0,0 +1,39 @@
+use std::{fs::File, path::Path};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use eyre::Result;
+use libcawlr::{collapse::CollapseOptions, npsmlr::train::TrainOptions};
+use tempfile::TempDir;
+
+/// Collapse the positive control once so only loading the samples is timed
+fn prepare(dir: &Path) -> Result<()> {
+    CollapseOptions::try_new("extra/pos_control.bam", Some(dir.join("pos.arrow")))?
+        .run(File::open("extra/pos_control.eventalign.txt")?)?;
+    Ok(())
+}
+
+/// Load every sample of the positive control into the training store. Each
+/// kmer is fit with a single Gaussian, so most of the time is spent adding
+/// reads and, for the SQLite database, building the kmer index afterwards.
+fn ingest(dir: &Path, in_memory: bool) -> Result<()> {
+    TrainOptions::default()
+        .single(true)
+        .db_path(Some(dir.join("pos.db")))
+        .in_memory(in_memory)
+        .run_model(File::open(dir.join("pos.arrow"))?)?;
+    Ok(())
+}
+
+fn bench_ingest(c: &mut Criterion) {
+    let dir = TempDir::new().unwrap();
+    prepare(dir.path()).unwrap();
+
+    let mut group = c.benchmark_group("npsmlr_ingest");
+    group.sample_size(10);
+    group.bench_function("sqlite", |b| b.iter(|| ingest(dir.path(), false).unwrap()));
+    group.bench_function("memory", |b| b.iter(|| ingest(dir.path(), true).unwrap()));
+    group.finish();
+}
+
+criterion_group!(benches, bench_ingest);
+criterion_main!(benches);
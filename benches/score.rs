@@ -0,0 +1,70 @@
+use std::{fs::File, io, path::Path};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use eyre::Result;
+use libcawlr::{
+    collapse::CollapseOptions,
+    rank::{save_ranks, RankMetric, RankOptions},
+    score::ScoreOptions,
+    train::{Train, TrainStrategy},
+    utils::CawlrIO,
+};
+use tempfile::TempDir;
+
+const GENOME: &str = "extra/sacCer3.fa";
+
+/// Collapse, train and rank the controls once so only scoring is timed
+fn prepare(dir: &Path) -> Result<()> {
+    let mut models = Vec::new();
+    for ctrl in ["pos", "neg"] {
+        let collapsed = dir.join(format!("{ctrl}.arrow"));
+        CollapseOptions::try_new(format!("extra/{ctrl}_control.bam"), Some(&collapsed))?
+            .run(File::open(format!("extra/{ctrl}_control.eventalign.txt"))?)?;
+        let model =
+            Train::try_new(&[&collapsed], GENOME, 5_000, TrainStrategy::AllSamples)?.run()?;
+        model.save_as(dir.join(format!("{ctrl}.model")))?;
+        models.push(model);
+    }
+    let ranks = RankOptions::default()
+        .exact(true)
+        .rank(&models[0], &models[1]);
+    save_ranks(
+        &ranks,
+        RankMetric::default(),
+        &mut File::create(dir.join("ranks"))?,
+    )?;
+    Ok(())
+}
+
+fn score(dir: &Path, threads: usize) -> Result<()> {
+    let mut scoring = ScoreOptions::load(
+        dir.join("pos.model"),
+        dir.join("neg.model"),
+        Path::new(GENOME).to_path_buf(),
+        dir.join("ranks"),
+        io::sink(),
+    )?;
+    scoring.threads(Some(threads));
+    scoring.run(File::open(dir.join("pos.arrow"))?)
+}
+
+fn bench_score_threads(c: &mut Criterion) {
+    let dir = TempDir::new().unwrap();
+    prepare(dir.path()).unwrap();
+
+    let mut group = c.benchmark_group("score");
+    group.sample_size(10);
+    let mut n_threads = vec![1, num_cpus::get()];
+    n_threads.dedup();
+    for threads in n_threads {
+        group.bench_with_input(
+            BenchmarkId::new("threads", threads),
+            &threads,
+            |b, &threads| b.iter(|| score(dir.path(), threads).unwrap()),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_score_threads);
+criterion_main!(benches);
@@ -0,0 +1,40 @@
+use std::{fs::File, io::BufReader, path::PathBuf};
+
+use clap::Parser;
+use libcawlr::{calibrate::Calibration, utils::AtomicFileWriter};
+
+use crate::file::ValidPathBuf;
+
+#[derive(Parser, Debug)]
+pub struct CalibrateCmd {
+    /// Scored reads from cawlr score to calibrate
+    #[clap(short, long)]
+    pub input: ValidPathBuf,
+
+    /// Scores of the positive control from cawlr score
+    #[clap(long)]
+    pub pos_ctrl: ValidPathBuf,
+
+    /// Scores of the negative control from cawlr score
+    #[clap(long)]
+    pub neg_ctrl: ValidPathBuf,
+
+    /// Arrow file of the scored reads with calibrated scores
+    #[clap(short, long)]
+    pub output: PathBuf,
+
+    /// Replace the output file if it already exists
+    #[clap(long)]
+    pub overwrite: bool,
+}
+
+impl CalibrateCmd {
+    pub fn run(self) -> eyre::Result<()> {
+        let calibration = Calibration::from_controls(&self.pos_ctrl, &self.neg_ctrl)?;
+        let reader = BufReader::new(File::open(&self.input)?);
+        let mut writer = AtomicFileWriter::create(self.output, self.overwrite)?;
+        let n_reads = calibration.run(reader, &mut writer)?;
+        log::info!("Calibrated {n_reads} reads");
+        writer.commit()
+    }
+}
@@ -4,7 +4,7 @@ use std::{
     path::PathBuf,
 };
 
-use clap::Parser;
+use clap::{error::ErrorKind, CommandFactory, Parser};
 use libcawlr::{collapse::CollapseOptions, utils};
 
 #[derive(Parser, Debug)]
@@ -20,34 +20,91 @@ pub struct CollapseCmd {
 
     #[clap(short, long)]
     /// Path to output file in Apache Arrow format, defaults to stdout if no
-    /// argument provided.
+    /// argument provided or if it is "-".
     pub output: Option<PathBuf>,
 
     #[clap(short, long, default_value_t = 2048)]
     /// Number of eventalign records to hold in memory.
     pub capacity: usize,
+
+    /// Skip reads whose alignment spans fewer than this many bases
+    #[clap(long)]
+    pub min_read_length: Option<u64>,
+
+    /// Only keep the first read with each name, skipping the rest like
+    /// multi-mapped reads
+    #[clap(long)]
+    pub deduplicate: bool,
+
+    /// Only keep reads aligned to this chromosome, case-sensitive
+    #[clap(long)]
+    pub chrom: Option<String>,
+
+    /// Only keep reads ending at or after this position on --chrom
+    #[clap(long)]
+    pub start: Option<u64>,
+
+    /// Only keep reads starting before this position on --chrom
+    #[clap(long)]
+    pub stop: Option<u64>,
+
+    /// Replace the output file if it already exists
+    #[clap(long)]
+    pub overwrite: bool,
+
+    /// Don't show the progress spinner
+    #[clap(long)]
+    pub no_progress: bool,
+
+    /// Read the input file with async I/O, which can be faster on fast
+    /// storage like NVMe drives
+    #[clap(long = "async", requires = "input")]
+    pub async_io: bool,
 }
 
 impl CollapseCmd {
-    pub fn run(self) -> eyre::Result<()> {
+    /// Show a progress spinner if progress is true, unless --no-progress is
+    /// given
+    pub fn run(self, progress: bool) -> eyre::Result<()> {
         if self.capacity == 0 {
             return Err(eyre::eyre!("Capacity must be greater than 0"));
         }
+        if self.chrom.is_none() && (self.start.is_some() || self.stop.is_some()) {
+            let mut cmd = CollapseCmd::command();
+            cmd.error(
+                ErrorKind::MissingRequiredArgument,
+                "--start and --stop require --chrom",
+            )
+            .exit();
+        }
+        let mut input_len = None;
         let final_input: Box<dyn Read> = {
-            if let Some(path) = self.input {
-                Box::new(File::open(path)?)
+            if let Some(path) = &self.input {
+                let file = File::open(path)?;
+                input_len = Some(file.metadata()?.len());
+                Box::new(file)
             } else {
                 let stdin = io::stdin().lock();
                 Box::new(stdin)
             }
         };
 
-        let final_output = utils::stdout_or_file(self.output.as_ref())?;
-        let final_output = BufWriter::new(final_output);
-
-        let mut collapse = CollapseOptions::from_writer(final_output, &self.bam)?;
-        collapse.capacity(self.capacity).progress(true);
-        collapse.run(final_input)?;
-        Ok(())
+        let output = self.output.as_ref().filter(|path| path.as_os_str() != "-");
+        utils::with_stdout_or_file(output, self.overwrite, |final_output| {
+            let final_output = BufWriter::new(final_output);
+            let mut collapse = CollapseOptions::from_writer(final_output, &self.bam)?;
+            collapse
+                .capacity(self.capacity)
+                .progress(progress && !self.no_progress)
+                .input_len(input_len)
+                .min_read_length(self.min_read_length)
+                .deduplicate(self.deduplicate)
+                .chrom_filter(self.chrom.clone())
+                .region_filter(self.start, self.stop);
+            match self.input.as_ref().filter(|_| self.async_io) {
+                Some(path) => collapse.run_async(path),
+                None => collapse.run(final_input),
+            }
+        })
     }
 }
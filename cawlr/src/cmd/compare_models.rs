@@ -0,0 +1,51 @@
+use std::{io::BufWriter, path::PathBuf};
+
+use clap::Parser;
+use libcawlr::{
+    compare::CompareOptions,
+    train::Model,
+    utils::{self, CawlrIO},
+};
+
+use crate::file::ValidPathBuf;
+
+#[derive(Parser, Debug)]
+pub struct CompareModelsCmd {
+    /// First model file from cawlr train or cawlr npsmlr train
+    #[clap(long)]
+    pub model_a: ValidPathBuf,
+
+    /// Second model file from cawlr train or cawlr npsmlr train
+    #[clap(long)]
+    pub model_b: ValidPathBuf,
+
+    /// Path to output tsv, defaults to stdout if no argument provided
+    #[clap(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// Wasserstein distance is estimated via sampling, so to keep values
+    /// consistent between subsequent runs a seed value is used
+    #[clap(long, default_value_t = 2456)]
+    pub seed: u64,
+
+    /// Number of samples drawn from each model to estimate the Wasserstein
+    /// distance
+    #[clap(long, default_value_t = 10_000_usize)]
+    pub samples: usize,
+
+    /// Replace the output file if it already exists
+    #[clap(long)]
+    pub overwrite: bool,
+}
+
+impl CompareModelsCmd {
+    pub fn run(self) -> eyre::Result<()> {
+        let model_a = Model::load(&self.model_a)?;
+        let model_b = Model::load(&self.model_b)?;
+        let distances = CompareOptions::new(self.seed, self.samples).compare(&model_a, &model_b);
+        utils::with_stdout_or_file(self.output.as_ref(), self.overwrite, |writer| {
+            let mut writer = BufWriter::new(writer);
+            CompareOptions::write_tsv(&distances, &mut writer)
+        })
+    }
+}
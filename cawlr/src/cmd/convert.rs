@@ -0,0 +1,49 @@
+use std::{fs::File, io::BufReader, path::PathBuf};
+
+use clap::Parser;
+use libcawlr::{
+    convert::{ConvertFormat, ConvertOptions},
+    strand_map::StrandMap,
+    utils::AtomicFileWriter,
+};
+
+use crate::file::ValidPathBuf;
+
+#[derive(Parser, Debug)]
+pub struct ConvertCmd {
+    /// Tool that produced the input, either "megalodon" for
+    /// per_read_modified_base_calls.txt or "modkit" for modkit pileup
+    /// bedMethyl, where each position is written as a read
+    #[clap(short, long, default_value_t = ConvertFormat::Megalodon)]
+    pub format: ConvertFormat,
+
+    /// Per-read modification calls to convert
+    #[clap(short, long)]
+    pub input: ValidPathBuf,
+
+    /// Bam file of the reads, the strand of each read is taken from here
+    /// instead of the input when provided, only used for megalodon
+    #[clap(short, long)]
+    pub bam: Option<ValidPathBuf>,
+
+    /// Arrow file of scored reads, for use with cawlr sma
+    #[clap(short, long)]
+    pub output: PathBuf,
+
+    /// Replace the output file if it already exists
+    #[clap(long)]
+    pub overwrite: bool,
+}
+
+impl ConvertCmd {
+    pub fn run(self) -> eyre::Result<()> {
+        let strand_map = self.bam.map(StrandMap::from_bam_file).transpose()?;
+        let reader = BufReader::new(File::open(&self.input)?);
+        let mut writer = AtomicFileWriter::create(self.output, self.overwrite)?;
+        let n_reads = ConvertOptions::new(self.format)
+            .strand_map(strand_map)
+            .run(reader, &mut writer)?;
+        log::info!("Converted {n_reads} reads");
+        writer.commit()
+    }
+}
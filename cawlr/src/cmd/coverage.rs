@@ -0,0 +1,31 @@
+use std::{io::BufWriter, path::PathBuf};
+
+use clap::Parser;
+use libcawlr::{coverage::CoverageMap, utils};
+
+use crate::file::ValidPathBuf;
+
+#[derive(Parser, Debug)]
+pub struct CoverageCmd {
+    /// Arrow output from cawlr score
+    #[clap(short, long)]
+    pub input: ValidPathBuf,
+
+    /// Path to output bed, defaults to stdout if no argument provided
+    #[clap(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// Replace the output file if it already exists
+    #[clap(long)]
+    pub overwrite: bool,
+}
+
+impl CoverageCmd {
+    pub fn run(self) -> eyre::Result<()> {
+        let coverage = CoverageMap::from_arrow_file(&self.input)?;
+        utils::with_stdout_or_file(self.output.as_ref(), self.overwrite, |writer| {
+            let mut writer = BufWriter::new(writer);
+            coverage.write_bed(&mut writer)
+        })
+    }
+}
@@ -0,0 +1,41 @@
+use std::{io::BufWriter, path::PathBuf};
+
+use clap::Parser;
+use libcawlr::{
+    train::Model,
+    utils::{self, CawlrIO},
+};
+
+use crate::file::ValidPathBuf;
+
+#[derive(Parser, Debug)]
+pub struct DumpModelCmd {
+    /// Model file from cawlr train or cawlr npsmlr train
+    #[clap(short, long)]
+    pub input: ValidPathBuf,
+
+    /// Path to output file, defaults to stdout if no argument provided
+    #[clap(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// Only print the parameters for this kmer, as JSON
+    #[clap(long)]
+    pub kmer: Option<String>,
+
+    /// Replace the output file if it already exists
+    #[clap(long)]
+    pub overwrite: bool,
+}
+
+impl DumpModelCmd {
+    pub fn run(self) -> eyre::Result<()> {
+        let model = Model::load(&self.input)?;
+        utils::with_stdout_or_file(self.output.as_ref(), self.overwrite, |writer| {
+            let mut writer = BufWriter::new(writer);
+            match self.kmer.as_deref() {
+                Some(kmer) => model.write_kmer_json(&mut writer, kmer),
+                None => model.write_summary(&mut writer, None),
+            }
+        })
+    }
+}
@@ -0,0 +1,35 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter},
+};
+
+use clap::Parser;
+use libcawlr::arrow::inspect::{InspectOptions, DEFAULT_N_ROWS};
+
+use crate::file::ValidPathBuf;
+
+#[derive(Parser, Debug)]
+pub struct InspectArrowCmd {
+    /// Arrow file from any step, ie cawlr collapse or cawlr score
+    #[clap(short, long)]
+    pub input: ValidPathBuf,
+
+    /// Number of rows of the first record batch to print
+    #[clap(short, long, default_value_t = DEFAULT_N_ROWS)]
+    pub n_rows: usize,
+
+    /// Only print the schema
+    #[clap(long)]
+    pub schema_only: bool,
+}
+
+impl InspectArrowCmd {
+    pub fn run(self) -> eyre::Result<()> {
+        let reader = BufReader::new(File::open(&self.input)?);
+        let writer = BufWriter::new(io::stdout().lock());
+        InspectOptions::default()
+            .n_rows(self.n_rows)
+            .schema_only(self.schema_only)
+            .run(reader, writer)
+    }
+}
@@ -1,5 +1,13 @@
+pub mod calibrate;
 pub mod collapse;
+pub mod compare_models;
+pub mod convert;
+pub mod coverage;
+pub mod dump_model;
+pub mod inspect_arrow;
 pub mod score;
+pub mod score_to_modbam;
+pub mod stats;
 pub mod train;
 
 #[cfg(test)]
@@ -7,7 +15,7 @@ mod test {
     use std::path::PathBuf;
 
     use assert_fs::TempDir;
-    use libcawlr::motif::all_bases;
+    use libcawlr::{motif::all_bases, npsmlr::train::ModelSelection};
 
     use super::*;
 
@@ -21,21 +29,45 @@ mod test {
             bam: PathBuf::from("../extra/pos_control.bam"),
             output: Some(collapse_output.clone()),
             capacity: 2048,
+            min_read_length: None,
+            deduplicate: false,
+            chrom: None,
+            start: None,
+            stop: None,
+            overwrite: false,
+            no_progress: true,
+            async_io: false,
         };
-        collapse_cmd.run()?;
+        collapse_cmd.run(false)?;
 
         let train_output = temp_dir.join("train_output");
         let train_db_output = temp_dir.join("train_db");
         let train_cmd = train::TrainCmd {
-            input: collapse_output,
+            input: vec![collapse_output],
             output: train_output,
             motif: all_bases(),
             samples: 50000,
+            min_kmer_samples: 10,
             single: false,
+            model_selection: ModelSelection::Fixed,
             dbscan: true,
+            winsorize: None,
+            holdout: 0.0,
+            holdout_seed: 2456,
+            seed: 2456,
             db_path: Some(train_db_output),
+            keep_db: false,
+            in_memory: false,
+            checkpoint_every: 100,
+            resume: false,
+            kmer_size: 6,
+            stranded: false,
+            signal_min: 40.0,
+            signal_max: 170.0,
+            genome: Some(PathBuf::from("../extra/sacCer3.fa")),
+            overwrite: false,
         };
-        train_cmd.run()?;
+        train_cmd.run(false)?;
         Ok(())
     }
 }
@@ -1,7 +1,15 @@
 use std::{fs::File, io::BufReader, path::PathBuf};
 
 use clap::Parser;
-use libcawlr::{motif::Motif, npsmlr};
+use libcawlr::{
+    motif::Motif,
+    npsmlr::{
+        self,
+        train::{DEFAULT_SIGNAL_MAX, DEFAULT_SIGNAL_MIN},
+    },
+    score::ScorePolicy,
+    utils::{self, AtomicFileWriter},
+};
 
 #[derive(Parser, Debug)]
 pub struct ScoreCmd {
@@ -9,11 +17,11 @@ pub struct ScoreCmd {
     #[clap(short, long)]
     input: PathBuf,
 
-    /// Path to positive control model, usually from cawlr train
+    /// Path to positive control model, usually from cawlr npsmlr train
     #[clap(short, long)]
     pos_ctrl: PathBuf,
 
-    /// Path to negative control model, usually from cawlr train
+    /// Path to negative control model, usually from cawlr npsmlr train
     #[clap(short, long)]
     neg_ctrl: PathBuf,
 
@@ -37,18 +45,51 @@ pub struct ScoreCmd {
     /// If an events has more than freq_thresh samples, it will be filtered
     #[clap(short, long, default_value_t = 10)]
     freq_thresh: usize,
+
+    /// Signals with a mean current (pA) below this are not scored, should
+    /// match --signal-min given to cawlr npsmlr train
+    #[clap(long, default_value_t = DEFAULT_SIGNAL_MIN)]
+    signal_min: f64,
+
+    /// Signals with a mean current (pA) above this are not scored, should
+    /// match --signal-max given to cawlr npsmlr train
+    #[clap(long, default_value_t = DEFAULT_SIGNAL_MAX)]
+    signal_max: f64,
+
+    /// Genome the reads were aligned to, to also compute skipping scores
+    /// like cawlr score. The models need to be trained with --genome.
+    #[clap(short, long)]
+    genome: Option<PathBuf>,
+
+    /// How the signal and skipping score of each position are combined when
+    /// a genome is given: max, signal-only, skip-only, or
+    /// weighted-mean:<alpha>
+    #[clap(long, default_value_t = ScorePolicy::Max)]
+    score_policy: ScorePolicy,
+
+    /// Replace the output file if it already exists
+    #[clap(long)]
+    overwrite: bool,
 }
 
 impl ScoreCmd {
     pub fn run(self) -> eyre::Result<()> {
         let reader = BufReader::new(File::open(self.input)?);
-        let writer = File::create(self.output)?;
+        let mut writer = AtomicFileWriter::create(self.output, self.overwrite)?;
         let mut score_options =
             npsmlr::ScoreOptions::load(self.pos_ctrl, self.neg_ctrl, self.ranks)?;
         score_options
             .freq_thresh(self.freq_thresh)
             .cutoff(self.cutoff)
             .motifs(self.motif)
-            .run(reader, writer)
+            .signal_min(self.signal_min)
+            .signal_max(self.signal_max)
+            .score_policy(self.score_policy);
+        if let Some(genome) = self.genome {
+            utils::index_genome_fasta(&genome)?;
+            score_options.genome(utils::open_genome_fasta(&genome)?);
+        }
+        score_options.run(reader, &mut writer)?;
+        writer.commit()
     }
 }
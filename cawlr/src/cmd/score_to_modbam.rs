@@ -0,0 +1,43 @@
+use std::{fs::File, io::BufReader, path::PathBuf};
+
+use clap::Parser;
+use libcawlr::{
+    modbam::{BaseMod, ModBamOptions},
+    utils::AtomicFileWriter,
+};
+
+use crate::file::ValidPathBuf;
+
+#[derive(Parser, Debug)]
+pub struct ScoreToModbamCmd {
+    /// Scored reads from cawlr score
+    #[clap(short, long)]
+    pub input: ValidPathBuf,
+
+    /// Bam file of the reads, usually the one given to cawlr collapse
+    #[clap(short, long)]
+    pub bam: ValidPathBuf,
+
+    /// Copy of the bam file with MM/ML tags from the scores
+    #[clap(short, long)]
+    pub output: PathBuf,
+
+    /// Modification written to the MM tag as <canonical base>+<code>, ie A+a
+    /// for 6mA or C+m for 5mC. Scores on other bases are left out.
+    #[clap(long, default_value_t = BaseMod::default())]
+    pub base_mod: BaseMod,
+
+    /// Replace the output file if it already exists
+    #[clap(long)]
+    pub overwrite: bool,
+}
+
+impl ScoreToModbamCmd {
+    pub fn run(self) -> eyre::Result<()> {
+        let reader = BufReader::new(File::open(&self.input)?);
+        let mut writer = AtomicFileWriter::create(self.output, self.overwrite)?;
+        let n_tagged = ModBamOptions::new(self.base_mod).run(reader, &self.bam, &mut writer)?;
+        log::info!("Added MM/ML tags to {n_tagged} reads");
+        writer.commit()
+    }
+}
@@ -0,0 +1,44 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::PathBuf,
+};
+
+use clap::Parser;
+use libcawlr::{stats::ScoreStats, utils};
+
+use crate::file::ValidPathBuf;
+
+#[derive(Parser, Debug)]
+pub struct StatsCmd {
+    /// Arrow output from cawlr score
+    #[clap(short, long)]
+    pub input: ValidPathBuf,
+
+    /// Path to output file, defaults to stdout if no argument provided
+    #[clap(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// Print tables meant for reading instead of JSON
+    #[clap(long)]
+    pub human: bool,
+
+    /// Replace the output file if it already exists
+    #[clap(long)]
+    pub overwrite: bool,
+}
+
+impl StatsCmd {
+    pub fn run(self) -> eyre::Result<()> {
+        let reader = BufReader::new(File::open(&self.input)?);
+        let stats = ScoreStats::from_reader(reader)?;
+        utils::with_stdout_or_file(self.output.as_ref(), self.overwrite, |writer| {
+            let mut writer = BufWriter::new(writer);
+            if self.human {
+                stats.write_human(&mut writer)
+            } else {
+                stats.write_json(&mut writer)
+            }
+        })
+    }
+}
@@ -1,16 +1,20 @@
 use std::{fs::File, io::BufReader, path::PathBuf};
 
 use clap::Parser;
+use eyre::Context;
 use libcawlr::{
     motif::{all_bases, Motif},
-    npsmlr::train::TrainOptions,
+    npsmlr::train::{ModelSelection, TrainOptions, DEFAULT_SIGNAL_MAX, DEFAULT_SIGNAL_MIN},
+    train::DEFAULT_KMER_SIZE,
+    utils::{self, AtomicFileWriter},
 };
 
 #[derive(Debug, Parser)]
 pub struct TrainCmd {
-    /// Input arrow file, usually from cawlr collapse
-    #[clap(short, long)]
-    pub input: PathBuf,
+    /// Input arrow file, usually from cawlr collapse, can be given multiple
+    /// times to train on replicates together
+    #[clap(short, long, required = true)]
+    pub input: Vec<PathBuf>,
 
     /// Pickle file containing model parameters
     #[clap(short, long)]
@@ -20,41 +24,163 @@ pub struct TrainCmd {
     #[clap(long, default_value_t = 50000)]
     pub samples: usize,
 
+    /// Kmers with fewer samples than this are not trained
+    #[clap(long, default_value_t = 10)]
+    pub min_kmer_samples: usize,
+
     /// Train a single component GMM (ie fit a single Gaussian)
     #[clap(long)]
     pub single: bool,
 
+    /// How to pick the number of components for each kmer, either "fixed"
+    /// to use --single for every kmer, or choose between one and two
+    /// components with "bic" or "aic"
+    #[clap(long, default_value_t = ModelSelection::Fixed)]
+    pub model_selection: ModelSelection,
+
     /// Filter outliers with DBSCAN algorithm
     #[clap(long)]
     pub dbscan: bool,
 
-    /// Path to SQLite database used for storing training data,
-    /// otherwise created in temporary file and removed after completion
-    #[clap(long)]
+    /// Clamp outliers to these lower and upper quantiles before training,
+    /// ie 0.005,0.995
+    #[clap(long, value_delimiter = ',', num_args = 2)]
+    pub winsorize: Option<Vec<f64>>,
+
+    /// Fraction of each kmer's samples to hold out from training, the
+    /// held-out log-likelihood is written to the training report
+    #[clap(long, default_value_t = 0.0)]
+    pub holdout: f64,
+
+    /// Seed used to choose the held-out samples
+    #[clap(long, default_value_t = 2456)]
+    pub holdout_seed: u64,
+
+    /// Seed used to choose which measurements of each kmer are kept when
+    /// there are more than --samples of them
+    #[clap(long, default_value_t = 2456)]
+    pub seed: u64,
+
+    /// Keep the SQLite database of training data at this path, for querying
+    /// the measurements of each kmer in the data table. Otherwise it is
+    /// created in a temporary file and removed after completion.
+    #[clap(long = "output-db", alias = "db-path")]
     pub db_path: Option<PathBuf>,
 
+    /// Keep the SQLite database of training data at {input}.db, using the
+    /// first input
+    #[clap(long, conflicts_with_all = ["db_path", "in_memory"])]
+    pub keep_db: bool,
+
+    /// Keep a random sample of --samples measurements per kmer in memory
+    /// instead of using a SQLite database, faster for small datasets
+    #[clap(long)]
+    pub in_memory: bool,
+
     /// Only train on kmers containing these motifs, can speed up training
     /// time
     #[clap(short, long, value_delimiter = ',')]
     pub motif: Vec<Motif>,
+
+    /// Save the partially trained model to {output}.partial after this many
+    /// kmers, set to 0 to disable checkpointing
+    #[clap(long, default_value_t = 100)]
+    pub checkpoint_every: usize,
+
+    /// Resume training from {output}.partial, skipping kmers that have
+    /// already been trained
+    #[clap(long)]
+    pub resume: bool,
+
+    /// Length of the kmers reported by nanopolish eventalign
+    #[clap(long, default_value_t = DEFAULT_KMER_SIZE)]
+    pub kmer_size: usize,
+
+    /// Also train separate models for reads on the plus and minus strand
+    #[clap(long)]
+    pub stranded: bool,
+
+    /// Skip measurements below this current (pA). The default is suited to
+    /// R9.4.1 flowcells.
+    #[clap(long, default_value_t = DEFAULT_SIGNAL_MIN)]
+    pub signal_min: f64,
+
+    /// Skip measurements above this current (pA). The default is suited to
+    /// R9.4.1 flowcells.
+    #[clap(long, default_value_t = DEFAULT_SIGNAL_MAX)]
+    pub signal_max: f64,
+
+    /// Genome fasta file, with a .fai index, used to estimate how often each
+    /// kmer is skipped. Without it the model can't be used for the skipping
+    /// score in cawlr score.
+    #[clap(short, long)]
+    pub genome: Option<PathBuf>,
+
+    /// Replace the output file, its training report, and the database from
+    /// --output-db or --keep-db, if they already exist
+    #[clap(long)]
+    pub overwrite: bool,
 }
 
 impl TrainCmd {
-    pub fn run(mut self) -> eyre::Result<()> {
+    /// Show a progress bar over the kmers if progress is true
+    pub fn run(mut self, progress: bool) -> eyre::Result<()> {
         log::info!("Train command");
-        let reader = BufReader::new(File::open(self.input)?);
-        let writer = File::create(self.output)?;
+        let mut checkpoint_path = self.output.clone().into_os_string();
+        checkpoint_path.push(".partial");
+        let checkpoint_path = PathBuf::from(checkpoint_path);
+        let mut report_path = self.output.clone().into_os_string();
+        report_path.push(".train_report.tsv");
+        let report_path = PathBuf::from(report_path);
+        if self.keep_db {
+            let mut db_path = self.input[0].clone().into_os_string();
+            db_path.push(".db");
+            self.db_path = Some(PathBuf::from(db_path));
+        }
+        // Only the temporary database is removed without asking
+        if let Some(db_path) = self.db_path.as_ref().filter(|_| !self.in_memory) {
+            utils::check_output(db_path, self.overwrite)?;
+        }
+        let mut inputs = Vec::new();
+        for input in self.input {
+            let file = File::open(&input)
+                .wrap_err_with(|| format!("Failed to open {}", input.display()))?;
+            let reader = BufReader::new(file);
+            inputs.push((input.display().to_string(), reader));
+        }
+        let mut writer = AtomicFileWriter::create(self.output, self.overwrite)?;
         if self.motif.is_empty() {
             log::info!("No motifs found, will train on all motifs");
             self.motif = all_bases();
         }
-        TrainOptions::default()
+        let mut train_opts = TrainOptions::default();
+        if let Some(quantiles) = self.winsorize {
+            train_opts = train_opts.winsorize(quantiles[0], quantiles[1]);
+        }
+        train_opts
             .n_samples(self.samples)
+            .min_kmer_samples(self.min_kmer_samples)
             .db_path(self.db_path)
+            .in_memory(self.in_memory)
             .single(self.single)
+            .model_selection(self.model_selection)
             .dbscan(self.dbscan)
+            .holdout(self.holdout)
+            .holdout_seed(self.holdout_seed)
+            .seed(self.seed)
             .motifs(self.motif)
-            .run(reader, writer)?;
-        Ok(())
+            .checkpoint_path(Some(checkpoint_path))
+            .checkpoint_every(self.checkpoint_every)
+            .report_path(Some(report_path))
+            .overwrite(self.overwrite)
+            .resume(self.resume)
+            .kmer_size(self.kmer_size)?
+            .stranded(self.stranded)
+            .signal_min(self.signal_min)
+            .signal_max(self.signal_max)
+            .genome(self.genome)
+            .progress(progress)
+            .run_many(inputs, &mut writer)?;
+        writer.commit()
     }
 }
@@ -3,34 +3,42 @@ mod file;
 mod pipeline;
 
 use std::{
+    ffi::OsString,
     fs::File,
-    io::BufReader,
-    path::{Path, PathBuf},
+    io::{self, BufReader, Write},
+    path::PathBuf,
 };
 
-use clap::{error::ErrorKind, CommandFactory, Parser, Subcommand};
+use clap::{error::ErrorKind, Command, CommandFactory, FromArgMatches, Parser, Subcommand};
+use clap_complete::Shell;
 use clap_verbosity_flag::Verbosity;
 use eyre::Result;
 use file::ValidPathBuf;
 use human_panic::setup_panic;
 use libcawlr::{
+    agg_blocks::AggOptions,
     arrow::{
         arrow_utils::{load_apply2, load_read_write_arrow},
         eventalign::Eventalign,
         io::ModFile,
         scored_read::ScoredRead,
     },
+    bedmethyl::BedMethylOptions,
     bkde::BinnedKde,
+    calibrate::Calibration,
     filter::FilterOptions,
-    index,
-    motif::{all_bases, Motif},
-    rank::RankOptions,
-    region::Region,
-    score::ScoreOptions,
+    index::{IndexFormat, IndexOptions},
+    motif::{all_bases, MethylationContext, Motif},
+    rank::{self, RankMetric, RankOptions},
+    region::{load_bed_regions, Region},
+    score::{self, ScoreOptions, ScorePolicy, ScoringMethod},
     score_model,
-    sma::SmaOptions,
+    sma::{
+        strand_bed_path, MotifCtrlScores, Segmentation, SmaOptions, SmaOutputFormat, StrandWriters,
+        DEFAULT_P_STAY,
+    },
     train::{self, Model, Train, TrainStrategy},
-    utils::{self, CawlrIO},
+    utils::{self, AtomicFileWriter, CawlrConfig, CawlrIO, ConfigEntry},
 };
 #[cfg(feature = "mimalloc")]
 use mimalloc::MiMalloc;
@@ -73,6 +81,15 @@ enum FilterCmd {
 
         #[clap(short, long, num_args = 1..)]
         region: Vec<Region>,
+
+        /// Only keep the scores within each region, instead of whole reads
+        /// overlapping it
+        #[clap(long)]
+        trim_to_region: bool,
+
+        /// Replace the output file if it already exists
+        #[clap(long)]
+        overwrite: bool,
     },
 
     Eventalign {
@@ -85,6 +102,10 @@ enum FilterCmd {
 
         #[clap(short, long, num_args = 1..)]
         region: Vec<Region>,
+
+        /// Replace the output file if it already exists
+        #[clap(long)]
+        overwrite: bool,
     },
 }
 
@@ -104,6 +125,12 @@ struct Args {
     #[clap(flatten)]
     verbose: Verbosity,
 
+    /// TOML file with default values for options, with a table for each
+    /// subcommand, ie [score] or [npsmlr.train]. Options given on the
+    /// command line take precedence.
+    #[clap(long, global = true)]
+    config: Option<PathBuf>,
+
     #[clap(subcommand)]
     command: Commands,
 }
@@ -123,13 +150,29 @@ enum Commands {
     /// Preprocess nanopolish eventalign output
     Collapse(cmd::collapse::CollapseCmd),
 
-    /// Create bed file of the reads in the Arrow file
+    /// Create an index of the reads in the Arrow file
     ///
-    /// Output file will be named {input}.idx.bed
+    /// By default the output file will be named {input}.idx.bed, with columns
+    /// chrom, start, end, read name, number of scored positions, and strand,
+    /// sorted by chrom and start. With --format json it is named
+    /// {input}.idx.json, with the byte offset of each read's record batch.
     Index {
         /// Arrow file from collapse or score
         #[clap(short, long)]
         input: PathBuf,
+
+        /// Skip reads covering fewer than this many bases
+        #[clap(long, default_value_t = 0)]
+        min_length: u64,
+
+        /// Write the index as a six column bed file to {input}.idx.bed, or as
+        /// a JSON array to {input}.idx.json
+        #[clap(long, default_value_t = IndexFormat::Bed)]
+        format: IndexFormat,
+
+        /// Replace the index file if it already exists
+        #[clap(long)]
+        overwrite: bool,
     },
 
     /// Filter Arrow output file based on genomic coordinates
@@ -139,9 +182,10 @@ enum Commands {
     /// For each kmer, train a two-component gaussian mixture model and save
     /// models to a file
     Train {
-        /// Positive or negative control output from cawlr collapse
-        #[clap(short, long)]
-        input: PathBuf,
+        /// Positive or negative control output from cawlr collapse, can be
+        /// given multiple times to train on replicates together
+        #[clap(short, long, required = true)]
+        input: Vec<PathBuf>,
 
         /// Path to resulting pickle file
         #[clap(short, long)]
@@ -165,8 +209,51 @@ enum Commands {
         /// using "avg"
         #[clap(long, default_value_t = TrainStrategy::AllSamples, value_parser=parse_strategy)]
         strategy: train::TrainStrategy,
+
+        /// Length of the kmers reported by nanopolish eventalign
+        #[clap(long, default_value_t = train::DEFAULT_KMER_SIZE)]
+        kmer_size: usize,
+
+        /// Also train separate models for reads on the plus and minus strand
+        #[clap(long)]
+        stranded: bool,
+
+        /// Replace the output file if it already exists
+        #[clap(long)]
+        overwrite: bool,
     },
 
+    /// Print the kmer parameters of a trained model as a tsv, or as JSON for
+    /// a single kmer
+    DumpModel(cmd::dump_model::DumpModelCmd),
+
+    /// Compute per-kmer distances between the parameters of two trained
+    /// models
+    CompareModels(cmd::compare_models::CompareModelsCmd),
+
+    /// Summarize the reads and scores from cawlr score, as JSON by default
+    Stats(cmd::stats::StatsCmd),
+
+    /// Count the plus and minus strand reads scored at each position, as a
+    /// bed file
+    Coverage(cmd::coverage::CoverageCmd),
+
+    /// Convert per-read modification calls from other tools, such as
+    /// Megalodon or modkit, into the Arrow format of cawlr score
+    Convert(cmd::convert::ConvertCmd),
+
+    /// Add scores calibrated into probabilities of modification, fit on the
+    /// scores of a positive and negative control, to a file from cawlr score
+    Calibrate(cmd::calibrate::CalibrateCmd),
+
+    /// Add MM/ML tags with the scores from cawlr score to a bam file, for
+    /// IGV's base modification coloring or modkit
+    ScoreToModbam(cmd::score_to_modbam::ScoreToModbamCmd),
+
+    /// Print the schema and first rows of an Arrow file, such as the output
+    /// of cawlr collapse or cawlr score
+    InspectArrow(cmd::inspect_arrow::InspectArrowCmd),
+
     /// Rank each kmer by the Kulback-Leibler Divergence and between the trained
     /// models
     Rank {
@@ -192,6 +279,64 @@ enum Commands {
         /// accurate
         #[clap(long, default_value_t = 100_000_usize)]
         samples: usize,
+
+        /// How to compare the control models, one of kl, symmetric-kl, js
+        /// (Jensen-Shannon), hellinger, wasserstein or auc (area under the ROC
+        /// curve of the scores from each control)
+        #[clap(long, default_value_t = RankMetric::Kl)]
+        metric: RankMetric,
+
+        /// Compute ranks by numerical integration over the models instead of
+        /// sampling, which is much faster, ignores --seed and --samples, and
+        /// falls back to sampling for models with more than two components
+        #[clap(long)]
+        exact: bool,
+
+        /// Also write a tsv of the ranks sorted from most to least different
+        /// kmer, in a column named after the --metric, with a bootstrap
+        /// standard error and 95% confidence interval, and a suggested cutoff
+        /// for each kmer to use with cawlr score --per-kmer-cutoffs
+        #[clap(long)]
+        tsv: Option<PathBuf>,
+
+        /// Only keep the N kmers with the highest ranks in the outputs
+        #[clap(long = "filter-min-rank", alias = "top-n")]
+        top_n: Option<usize>,
+
+        /// Also write a csv of kmer and rank score with a header, sorted from
+        /// most to least different kmer
+        #[clap(long)]
+        output_csv: Option<PathBuf>,
+
+        /// Number of times the samples are resampled to estimate the
+        /// uncertainty in the --tsv output, each one takes about as long as
+        /// ranking without --tsv
+        #[clap(long, default_value_t = rank::DEFAULT_BOOTSTRAP)]
+        bootstrap: usize,
+
+        /// Number of threads to use for ranking, by default num cpus
+        #[clap(short = 'j', long)]
+        threads: Option<usize>,
+
+        /// Only rank kmers that cawlr score looks at for this motif, other
+        /// kmers are left out. Format = "{position of modified base}:{motif}",
+        /// ie "2:GC" if the C in GC is the modified base.
+        #[clap(short, long)]
+        motif: Option<Vec<Motif>>,
+
+        /// Kmers with a rank below this have indistinguishable controls, and
+        /// are reported with a warning. Defaults to 0.05, or 0.55 for auc.
+        #[clap(long)]
+        min_divergence: Option<f64>,
+
+        /// Write the kmers with indistinguishable controls to this file, one
+        /// per line
+        #[clap(long)]
+        indistinguishable: Option<PathBuf>,
+
+        /// Replace the output file if it already exists
+        #[clap(long)]
+        overwrite: bool,
     },
 
     /// Score each kmer with likelihood based on positive and negative controls
@@ -216,24 +361,149 @@ enum Commands {
         #[clap(short, long)]
         ranks: PathBuf,
 
-        /// Path to fasta file for organisms genome, must have a .fai file from
-        /// samtools faidx
+        /// Path to fasta file for organisms genome, indexed automatically if
+        /// it doesn't have a .fai file from samtools faidx
         #[clap(short, long)]
         genome: PathBuf,
 
+        /// Fail if the genome doesn't have a .fai index instead of creating
+        /// one
+        #[clap(long)]
+        no_auto_index: bool,
+
         /// Threshold for current value to be considered reasonable
         #[clap(long, default_value_t = 10.0)]
         cutoff: f64,
 
+        /// Two column tsv of kmer and cutoff, overriding --cutoff for those
+        /// kmers
+        #[clap(long)]
+        per_kmer_cutoffs: Option<ValidPathBuf>,
+
         /// Threshold for kmer model to be used
         #[clap(long, default_value_t = 0.05)]
         p_value_threshold: f64,
 
+        /// How to combine the control likelihoods into a score, ratio gives
+        /// scores between 0 and 1, log-odds gives unbounded scores centered
+        /// on 0
+        #[clap(long, default_value_t = ScoringMethod::ProbabilityRatio)]
+        scoring_method: ScoringMethod,
+
+        /// How the signal and skipping score of each position are combined:
+        /// max, signal-only, skip-only, or weighted-mean:<alpha> with alpha the
+        /// weight of the signal score
+        #[clap(long, default_value_t = ScorePolicy::Max)]
+        score_policy: ScorePolicy,
+
+        /// Don't calculate skipping scores, positions without a signal score
+        /// get a NaN score and --score-policy is ignored
+        #[clap(long)]
+        no_skipping_score: bool,
+
+        /// Stop with an error on reads aligned to contigs missing from the
+        /// genome or that fail to score, instead of skipping them, or if the
+        /// ranks weren't computed from the control models
+        #[clap(long)]
+        strict: bool,
+
         /// Only score in kmers that contain this motif, by default will score
         /// all kmers. Format = "{position of modified base}:{motif}", ie "2:GC"
         /// if the C in GC is the modified base.
         #[clap(short, long)]
         motif: Option<Vec<Motif>>,
+
+        /// Also score the motifs of a cytosine methylation context, one of
+        /// CG, CHG, CHH, or all
+        #[clap(long)]
+        context: Option<MethylationContext>,
+
+        /// Score this many reads at a time, whatever the size of the batches
+        /// in the input. Lower values use less memory, the output is the same.
+        #[clap(long, alias = "batch-size", default_value_t = score::DEFAULT_BATCH_READS)]
+        batch_reads: usize,
+
+        /// Score reads longer than this many bases a window at a time, so
+        /// memory use doesn't grow with the length of ultra-long reads
+        #[clap(long, default_value_t = score::DEFAULT_WINDOW_SIZE)]
+        window_size: u64,
+
+        /// Don't use signal from kmers ranked below this, whose controls are
+        /// indistinguishable, and use the skipping score instead. See
+        /// --min-divergence in cawlr rank.
+        #[clap(long)]
+        min_rank: Option<f64>,
+
+        /// Also score motifs on the minus strand of plus strand reads, for
+        /// motifs that aren't their own reverse complement
+        #[clap(long)]
+        both_strand_motifs: bool,
+
+        /// Write positions whose kmer is in neither control model, with NaN
+        /// scores and missing_model set, instead of scoring them from the
+        /// surrounding kmers
+        #[clap(long)]
+        output_missing_kmers: bool,
+
+        /// Also score positions that aren't the modified base of any --motif,
+        /// with matches_motif unset, to compare motif and non-motif positions
+        #[clap(long)]
+        output_all_positions: bool,
+
+        /// Number of threads to use for scoring, by default num cpus
+        #[clap(short = 'j', long)]
+        threads: Option<usize>,
+
+        /// Number of genome sequences fetched for reads to keep in memory, so
+        /// reads at the same positions reuse them, 0 to turn off caching
+        #[clap(long, default_value_t = score::DEFAULT_GENOME_CACHE_SIZE)]
+        genome_cache_size: usize,
+
+        /// Only score reads overlapping any of these regions (chr:start-end),
+        /// reads are scored in full if they overlap at all
+        #[clap(long, num_args = 1..)]
+        region: Vec<Region>,
+
+        /// Only score reads overlapping any region in this bed file, combined
+        /// with --region
+        #[clap(long)]
+        regions_bed: Option<ValidPathBuf>,
+
+        /// Only score reads with these names, given as a comma separated list
+        /// or files with one read name per line
+        #[clap(long, num_args = 1.., value_delimiter = ',')]
+        read_names: Vec<String>,
+
+        /// Scores of the positive control from cawlr score, used with
+        /// --calibrate-neg to also write calibrated probabilities of
+        /// modification, see cawlr calibrate
+        #[clap(long, requires = "calibrate_neg")]
+        calibrate_pos: Option<ValidPathBuf>,
+
+        /// Scores of the negative control from cawlr score, used with
+        /// --calibrate-pos
+        #[clap(long, requires = "calibrate_pos")]
+        calibrate_neg: Option<ValidPathBuf>,
+
+        /// Also write the fraction of reads modified at each position as
+        /// bedMethyl, for methylartist, modbamtools or DSS
+        #[clap(long)]
+        bedmethyl: Option<PathBuf>,
+
+        /// Scores at or above this count as modified in --bedmethyl, by
+        /// default 0.5 for --scoring-method ratio and 0 for log-odds
+        #[clap(long, requires = "bedmethyl")]
+        bedmethyl_threshold: Option<f64>,
+
+        /// Also write a tsv with the number of scored motif positions, the
+        /// fraction of them with a signal score and the mean distance of the
+        /// probabilities from 0.5 for each read, to flag low information reads
+        #[clap(long)]
+        read_report: Option<PathBuf>,
+
+        /// Replace the output file if it already exists
+        #[clap(long)]
+        overwrite: bool,
     },
     /// Compute kernel density estimate of control score data
     ModelScores {
@@ -262,6 +532,15 @@ enum Commands {
         /// Specification link: https://samtools.github.io/hts-specs/SAMtags.pdf
         #[clap(short, long)]
         tag: Option<String>,
+
+        /// Print the score at this quantile of the estimated distribution,
+        /// ie 0.05 for the 5th percentile. Can be used multiple times.
+        #[clap(long)]
+        percentile: Vec<f64>,
+
+        /// Replace the output file if it already exists
+        #[clap(long)]
+        overwrite: bool,
     },
     /// Infer nucleosome positions on single molecules
     Sma {
@@ -281,6 +560,13 @@ enum Commands {
         #[clap(long)]
         neg_ctrl_scores: ValidPathBuf,
 
+        /// Use the treated and untreated control scores of a motif for scores
+        /// from it, in the form <motif>,<pos>,<neg>, ie
+        /// 1:GC,gc_pos.pickle,gc_neg.pickle. Can be given more than once,
+        /// other scores use --pos-ctrl-scores and --neg-ctrl-scores.
+        #[clap(long)]
+        motif_ctrl_scores: Vec<MotifCtrlScores>,
+
         // /// Only that contain this motif will be used to perform single molecule
         // /// analysis, by default will use all kmers
         // #[clap(short, long)]
@@ -293,45 +579,270 @@ enum Commands {
         /// Specification link: https://samtools.github.io/hts-specs/SAMtags.pdf
         #[clap(short, long)]
         tag: Option<String>,
+
+        /// Ignore scores at positions covered by fewer than this many reads
+        #[clap(long, default_value_t = 1)]
+        min_coverage: usize,
+
+        /// Either "bed" for nucleosome calls on each read, or "wig" or
+        /// "bedgraph" for the fraction of reads accessible at each scored
+        /// position
+        #[clap(long, default_value_t = SmaOutputFormat::Bed)]
+        output_format: SmaOutputFormat,
+
+        /// Either "independent" to call each position on its own and place
+        /// nucleosomes of exactly 147bp, or "hmm" for footprints of any length
+        /// from a two state HMM of accessible and footprinted bases
+        #[clap(long, default_value_t = Segmentation::Independent)]
+        segmentation: Segmentation,
+
+        /// Probability of staying in the same state at the next base with
+        /// --segmentation hmm, higher values give fewer and longer blocks
+        #[clap(long, default_value_t = DEFAULT_P_STAY, conflicts_with = "footprint_length")]
+        p_stay: f64,
+
+        /// Set --p-stay so footprints are this many bases long on average with
+        /// --segmentation hmm
+        #[clap(long)]
+        footprint_length: Option<u64>,
+
+        /// Drop footprints shorter than this many bases, after merging them
+        /// with --merge-gap
+        #[clap(long, default_value_t = 0)]
+        min_block_len: usize,
+
+        /// Fuse footprints separated by fewer than this many accessible bases
+        #[clap(long, default_value_t = 0)]
+        merge_gap: usize,
+
+        /// Number of threads to find the footprints of reads on, by default
+        /// num cpus
+        #[clap(short = 'j', long)]
+        threads: Option<usize>,
+
+        /// Read at most this many reads from the input at a time, splitting
+        /// larger batches to limit memory use. By default batches are read
+        /// whole.
+        #[clap(long)]
+        batch_size: Option<usize>,
+
+        /// Merge adjacent positions with the same value into a single
+        /// interval in bedgraph output
+        #[clap(long, default_value_t = true, action = clap::ArgAction::Set)]
+        run_length_encode: bool,
+
+        /// Smooth the fraction of reads accessible in wig and bedgraph output
+        /// with a centered rolling mean over this many scored positions
+        #[clap(long)]
+        smoothing_window: Option<usize>,
+
+        /// Skip reads with fewer scored motif positions than this, see
+        /// --read-report in cawlr score
+        #[clap(long, default_value_t = 0)]
+        min_scored_positions: u64,
+
+        /// Also write a BED12 line per read to this file, with each scored
+        /// position as a block and the fraction of positions scored above 0.5
+        /// (0-1000) as the score
+        #[clap(long)]
+        per_read_output: Option<PathBuf>,
+
+        /// Also write the log-likelihood of each scored position under both
+        /// control score models and the posterior probability of it being
+        /// accessible to this Arrow file
+        #[clap(long)]
+        posteriors: Option<PathBuf>,
+
+        /// Also write the reads on each strand to BED files next to the
+        /// output, ie sma.plus.bed, sma.minus.bed and sma.unknown.bed for
+        /// sma.bed
+        #[clap(long, requires = "output")]
+        split_by_strand: bool,
+
+        /// Write the reads on each chromosome to their own BED file, using
+        /// the output as a prefix, ie sma.chrI.bed for -o sma
+        #[clap(long, requires = "output", conflicts_with = "split_by_strand")]
+        per_chromosome: bool,
+
+        /// Only use scores in a cytosine methylation context, one of CG, CHG,
+        /// CHH, or all. Minus strand reads are used whole.
+        #[clap(long)]
+        context: Option<MethylationContext>,
+
+        /// Replace the output files, including the files of each chromosome,
+        /// if they already exist
+        #[clap(long)]
+        overwrite: bool,
+    },
+
+    /// Aggregate nucleosome calls from cawlr sma into windows
+    ///
+    /// Every base of each read in the bed file is a site, scored 1 if it is
+    /// within one of the read's blocks (nucleosomes) and 0 otherwise. Output
+    /// is a tsv with columns chrom, start, end, mean_score, n_sites, n_reads,
+    /// where mean_score is the mean over the sites in the window of the
+    /// fraction of reads overlapping a block.
+    AggBlocks {
+        /// Bed file from cawlr sma
+        #[clap(short, long)]
+        input: ValidPathBuf,
+
+        /// Path to output file, defaults to stdout
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+
+        /// Size of each window in bases
+        #[clap(long, default_value_t = 200)]
+        window: u64,
+
+        /// Distance in bases between the starts of consecutive windows
+        #[clap(long, default_value_t = 50)]
+        step: u64,
+
+        /// Replace the output file if it already exists
+        #[clap(long)]
+        overwrite: bool,
+    },
+
+    /// Print a shell completion script for cawlr to stdout
+    ///
+    /// For example, with bash: cawlr completions --shell bash >
+    /// ~/.local/share/bash-completion/completions/cawlr
+    Completions {
+        /// Shell to generate completions for
+        #[clap(long)]
+        shell: Shell,
     },
 }
 
+/// Path given with --config, found before parsing so its values can be used
+/// as defaults
+fn config_path<I: IntoIterator<Item = OsString>>(args: I) -> Option<PathBuf> {
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        } else if arg == "--config" {
+            return args.next().map(PathBuf::from);
+        } else if let Some(path) = arg.to_str().and_then(|a| a.strip_prefix("--config=")) {
+            return Some(PathBuf::from(path));
+        }
+    }
+    None
+}
+
+/// Compare names ignoring the difference between - and _, so config keys can
+/// use either the option name or the field name
+fn same_name(a: &str, b: &str) -> bool {
+    a.replace('-', "_") == b.replace('-', "_")
+}
+
+/// Set the values in the config as the defaults for each subcommand's
+/// options, which also makes them no longer required.
+fn apply_config(mut cmd: Command, config: &CawlrConfig) -> Result<Command> {
+    for (key, entry) in config.entries() {
+        match entry {
+            ConfigEntry::Subcommand(sub_config) => {
+                let name = cmd
+                    .get_subcommands()
+                    .map(|sub| sub.get_name().to_string())
+                    .find(|name| same_name(name, key))
+                    .ok_or_else(|| eyre::eyre!("Unknown subcommand [{key}] in config"))?;
+                let sub = cmd
+                    .find_subcommand(&name)
+                    .expect("Subcommand name from get_subcommands")
+                    .clone();
+                let sub = apply_config(sub, sub_config)?;
+                cmd = cmd.mut_subcommand(name, |_| sub);
+            }
+            _ => {
+                let id = cmd
+                    .get_arguments()
+                    .map(|arg| arg.get_id().to_string())
+                    .find(|id| same_name(id, key))
+                    .ok_or_else(|| {
+                        eyre::eyre!("Unknown option {key} for {} in config", cmd.get_name())
+                    })?;
+                let values = entry.values()?;
+                cmd = cmd.mut_arg(id, |arg| arg.default_values(values).required(false));
+            }
+        }
+    }
+    Ok(cmd)
+}
+
+/// Parse the command line arguments, using the --config file for defaults
+fn parse_args<I>(args: I) -> Result<Args>
+where
+    I: IntoIterator<Item = OsString> + Clone,
+{
+    let mut cmd = Args::command();
+    if let Some(path) = config_path(args.clone()) {
+        let config = CawlrConfig::load(path)?;
+        cmd = apply_config(cmd, &config)?;
+    }
+    let matches = cmd.get_matches_from(args);
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    Ok(args)
+}
+
 fn main() -> Result<()> {
     setup_panic!();
     jane_eyre::install()?;
 
-    let args = Args::parse();
+    let args = parse_args(std::env::args_os().collect::<Vec<_>>())?;
     let log_level_filter = args.verbose.log_level_filter();
+    // Progress bars are hidden by --quiet, and aren't drawn if stderr isn't a
+    // terminal
+    let show_progress = !args.verbose.is_silent();
 
     match args.command {
-        Commands::Collapse(cmd) => cmd.run()?,
-        Commands::Index { input } => {
-            index::index(input)?;
+        Commands::Collapse(cmd) => cmd.run(show_progress)?,
+        Commands::Index {
+            input,
+            min_length,
+            format,
+            overwrite,
+        } => {
+            IndexOptions::default()
+                .min_length(min_length)
+                .output_format(format)
+                .overwrite(overwrite)
+                .run(input)?;
         }
         Commands::Filter(FilterCmd::Eventalign {
             input,
             output,
             region,
+            overwrite,
         }) => {
             let filters = FilterOptions::new(region);
             let reader = File::open(input)?;
-            let writer = File::create(output)?;
-            load_read_write_arrow(reader, writer, |xs: Vec<Eventalign>| {
+            let mut writer = AtomicFileWriter::create(output, overwrite)?;
+            load_read_write_arrow(reader, &mut writer, |xs: Vec<Eventalign>| {
                 Ok(xs.into_iter().filter(|x| filters.any_valid(x)).collect())
             })?;
+            writer.commit()?;
         }
 
         Commands::Filter(FilterCmd::Score {
             input,
             output,
             region,
+            trim_to_region,
+            overwrite,
         }) => {
             let filters = FilterOptions::new(region);
             let reader = File::open(input)?;
-            let writer = File::create(output)?;
-            load_read_write_arrow(reader, writer, |xs: Vec<ScoredRead>| {
-                Ok(xs.into_iter().filter(|x| filters.any_valid(x)).collect())
+            let mut writer = AtomicFileWriter::create(output, overwrite)?;
+            load_read_write_arrow(reader, &mut writer, |xs: Vec<ScoredRead>| {
+                if trim_to_region {
+                    Ok(xs.iter().flat_map(|x| filters.trim_to_regions(x)).collect())
+                } else {
+                    Ok(xs.into_iter().filter(|x| filters.any_valid(x)).collect())
+                }
             })?;
+            writer.commit()?;
         }
 
         Commands::Train {
@@ -341,8 +852,13 @@ fn main() -> Result<()> {
             samples,
             strategy,
             num_threads,
+            kmer_size,
+            stranded,
+            overwrite,
         } => {
             log::info!("Train command");
+            utils::validate_genome_fasta(&genome)?;
+            let mut writer = AtomicFileWriter::create(output, overwrite)?;
             let mut n_logical_cores = num_cpus::get();
 
             if let Some(n) = num_threads {
@@ -354,22 +870,86 @@ fn main() -> Result<()> {
 
             log::info!("Using {n_logical_cores} logical cores");
             log::info!("Using strategy: {strategy}");
-            let train = Train::try_new(input, genome, samples, strategy)?;
+            let mut train = Train::try_new(&input, genome, samples, strategy)?;
+            train
+                .kmer_size(kmer_size)?
+                .stranded(stranded)
+                .progress(show_progress);
             let model = train.run()?;
-            model.save_as(output)?;
+            model.save(&mut writer)?;
+            writer.commit()?;
         }
 
+        Commands::DumpModel(cmd) => cmd.run()?,
+
+        Commands::CompareModels(cmd) => cmd.run()?,
+        Commands::Stats(cmd) => cmd.run()?,
+        Commands::Coverage(cmd) => cmd.run()?,
+        Commands::Convert(cmd) => cmd.run()?,
+        Commands::Calibrate(cmd) => cmd.run()?,
+        Commands::ScoreToModbam(cmd) => cmd.run()?,
+        Commands::InspectArrow(cmd) => cmd.run()?,
+
         Commands::Rank {
             pos_ctrl,
             neg_ctrl,
             output,
             seed,
             samples,
+            metric,
+            exact,
+            tsv,
+            output_csv,
+            top_n,
+            bootstrap,
+            threads,
+            motif,
+            min_divergence,
+            indistinguishable,
+            overwrite,
         } => {
+            if let Some(n) = threads {
+                rayon::ThreadPoolBuilder::new()
+                    .num_threads(n)
+                    .build_global()?;
+            }
+            let mut writer = AtomicFileWriter::create(output, overwrite)?;
             let pos_ctrl_db = Model::load(pos_ctrl)?;
             let neg_ctrl_db = Model::load(neg_ctrl)?;
-            let kmer_ranks = RankOptions::new(seed, samples).rank(&pos_ctrl_db, &neg_ctrl_db);
-            kmer_ranks.save_as(output)?;
+            let mut rank_opts = RankOptions::new(seed, samples);
+            rank_opts
+                .metric(metric)
+                .exact(exact)
+                .bootstrap_samples(bootstrap)
+                .motifs(motif)
+                .min_divergence(min_divergence)
+                .top_n(top_n);
+            let kmer_ranks = if let Some(tsv) = tsv {
+                let mut tsv_writer = AtomicFileWriter::create(tsv, overwrite)?;
+                let kmer_stats = rank_opts.rank_with_stats(&pos_ctrl_db, &neg_ctrl_db);
+                let cutoffs = rank_opts.suggested_cutoffs(&pos_ctrl_db, &neg_ctrl_db);
+                rank::write_ranks_tsv(&kmer_stats, metric, &cutoffs, &mut tsv_writer)?;
+                tsv_writer.commit()?;
+                rank::estimates(&kmer_stats)
+            } else {
+                rank_opts.rank(&pos_ctrl_db, &neg_ctrl_db)
+            };
+            if let Some(path) = indistinguishable {
+                let mut kmers_writer = AtomicFileWriter::create(path, overwrite)?;
+                let min_divergence =
+                    min_divergence.unwrap_or_else(|| metric.default_min_divergence());
+                for kmer in rank::indistinguishable_kmers(&kmer_ranks, min_divergence) {
+                    writeln!(kmers_writer, "{kmer}")?;
+                }
+                kmers_writer.commit()?;
+            }
+            if let Some(path) = output_csv {
+                let mut csv_writer = AtomicFileWriter::create(path, overwrite)?;
+                rank::write_ranks_csv(&kmer_ranks, &mut csv_writer)?;
+                csv_writer.commit()?;
+            }
+            rank::save_ranks(&kmer_ranks, metric, &mut writer)?;
+            writer.commit()?;
         }
 
         Commands::Score {
@@ -379,29 +959,57 @@ fn main() -> Result<()> {
             neg_ctrl,
             ranks,
             genome,
+            no_auto_index,
             cutoff,
+            per_kmer_cutoffs,
             p_value_threshold,
+            scoring_method,
+            score_policy,
+            no_skipping_score,
+            strict,
             motif,
+            context,
+            batch_reads,
+            window_size,
+            min_rank,
+            both_strand_motifs,
+            output_missing_kmers,
+            output_all_positions,
+            threads,
+            genome_cache_size,
+            mut region,
+            regions_bed,
+            read_names,
+            calibrate_pos,
+            calibrate_neg,
+            bedmethyl,
+            bedmethyl_threshold,
+            read_report,
+            overwrite,
         } => {
-            let fai_file = format!("{}.fai", genome.display());
-            let fai_file = Path::new(&fai_file);
-            log::debug!("fasta index file filename: {fai_file:?}");
-            if !fai_file.exists() {
-                let mut cmd = Args::command();
-                cmd.error(
-                    ErrorKind::MissingRequiredArgument,
-                    "Missing .fai index file, run samtools faidx on genome file.",
-                )
-                .exit();
+            if no_auto_index {
+                utils::validate_genome_fasta(&genome)?;
+            } else {
+                utils::index_genome_fasta(&genome)?;
             }
 
+            let mut scoring = ScoreOptions::load_models(&pos_ctrl, &neg_ctrl, &genome, &ranks)?;
+            let kmer_size = scoring.kmer_size();
+            let motif = match context {
+                Some(context) => {
+                    let mut motif = motif.unwrap_or_default();
+                    motif.extend(context.motifs());
+                    Some(motif)
+                }
+                None => motif,
+            };
             motif.iter().for_each(|ms| {
                 ms.iter().for_each(|m| {
-                    if m.len_motif() > 6 {
+                    if m.len_motif() > kmer_size {
                         let mut cmd = Args::command();
                         cmd.error(
                             ErrorKind::InvalidValue,
-                            "Length of motif must be less than 6 (size of kmer)",
+                            format!("Length of motif must be less than {kmer_size} (size of kmer)"),
                         )
                         .exit();
                     }
@@ -409,13 +1017,59 @@ fn main() -> Result<()> {
             });
 
             log::debug!("Motifs parsed: {motif:?}");
-            let mut scoring =
-                ScoreOptions::try_new(&pos_ctrl, &neg_ctrl, &genome, &ranks, &output)?;
-            scoring.cutoff(cutoff).p_value_threshold(p_value_threshold);
+            let per_kmer_cutoffs = per_kmer_cutoffs.map(score::load_kmer_cutoffs).transpose()?;
+            if let Some(regions_bed) = regions_bed {
+                region.extend(load_bed_regions(regions_bed)?);
+            }
+            scoring
+                .cutoff(cutoff)
+                .per_kmer_cutoffs(per_kmer_cutoffs)
+                .p_value_threshold(p_value_threshold)
+                .scoring_method(scoring_method)
+                .score_policy(score_policy)
+                .use_skipping_score(!no_skipping_score)
+                .strict(strict)
+                .batch_reads(batch_reads)
+                .window_size(window_size)
+                .min_rank(min_rank)
+                .both_strand_motifs(both_strand_motifs)
+                .output_missing_kmers(output_missing_kmers)
+                .output_all_positions(output_all_positions)
+                .threads(threads)
+                .genome_cache_size(genome_cache_size)
+                .regions(region)
+                .read_names(score::load_read_names(read_names)?);
             if let Some(motifs) = motif {
                 scoring.motifs(motifs);
             }
-            scoring.run(input)?;
+            if let (Some(pos), Some(neg)) = (calibrate_pos, calibrate_neg) {
+                scoring.calibration(Calibration::from_controls(pos, neg)?);
+            }
+            let input = BufReader::new(File::open(input)?);
+
+            let mut writer = AtomicFileWriter::create(&output, overwrite)?;
+            let bedmethyl_writer = bedmethyl
+                .map(|path| AtomicFileWriter::create(path, overwrite))
+                .transpose()?;
+            let read_report_writer = read_report
+                .map(|path| AtomicFileWriter::create(path, overwrite))
+                .transpose()?;
+            scoring.run_with_writer(input, &mut writer)?;
+            writer.commit()?;
+            if let Some(mut bedmethyl_writer) = bedmethyl_writer {
+                let n_lines = BedMethylOptions::default()
+                    .threshold(bedmethyl_threshold)
+                    .run(BufReader::new(File::open(&output)?), &mut bedmethyl_writer)?;
+                log::info!("Wrote {n_lines} bedMethyl positions");
+                bedmethyl_writer.commit()?;
+            }
+            if let Some(mut read_report_writer) = read_report_writer {
+                score::write_read_report(
+                    BufReader::new(File::open(&output)?),
+                    &mut read_report_writer,
+                )?;
+                read_report_writer.commit()?;
+            }
         }
 
         Commands::ModelScores {
@@ -424,13 +1078,23 @@ fn main() -> Result<()> {
             bins,
             samples,
             tag,
+            percentile,
+            overwrite,
         } => {
+            if let Some(q) = percentile.iter().find(|q| !(0.0..=1.0).contains(*q)) {
+                return Err(eyre::eyre!("Percentile {q} must be between 0 and 1"));
+            }
+            let mut writer = AtomicFileWriter::create(output, overwrite)?;
             let mod_file = ModFile::open_path(input, tag)?;
             let bkde = score_model::Options::default()
                 .bins(bins)
                 .samples(samples)
                 .run_modfile(mod_file)?;
-            bkde.save_as(output)?;
+            bkde.save(&mut writer)?;
+            writer.commit()?;
+            for (q, score) in percentile.iter().zip(bkde.percentiles(&percentile)) {
+                println!("{q}\t{score}");
+            }
         }
 
         Commands::Sma {
@@ -438,24 +1102,130 @@ fn main() -> Result<()> {
             output,
             pos_ctrl_scores,
             neg_ctrl_scores,
+            motif_ctrl_scores,
             // motif,
             tag,
+            min_coverage,
+            output_format,
+            segmentation,
+            p_stay,
+            footprint_length,
+            min_block_len,
+            merge_gap,
+            threads,
+            batch_size,
+            run_length_encode,
+            smoothing_window,
+            min_scored_positions,
+            per_read_output,
+            posteriors,
+            split_by_strand,
+            per_chromosome,
+            context,
+            overwrite,
         } => {
             let mod_file = ModFile::open_path(input, tag)?;
+            let mut strand_writers = match output.as_ref().filter(|_| split_by_strand) {
+                Some(output) => Some((
+                    AtomicFileWriter::create(strand_bed_path(output, "plus"), overwrite)?,
+                    AtomicFileWriter::create(strand_bed_path(output, "minus"), overwrite)?,
+                    AtomicFileWriter::create(strand_bed_path(output, "unknown"), overwrite)?,
+                )),
+                None => None,
+            };
+            let mut per_read_writer = per_read_output
+                .map(|path| AtomicFileWriter::create(path, overwrite))
+                .transpose()?;
+            let mut posteriors_writer = posteriors
+                .map(|path| AtomicFileWriter::create(path, overwrite))
+                .transpose()?;
             let pos_bkde = BinnedKde::load(pos_ctrl_scores)?;
             let neg_bkde = BinnedKde::load(neg_ctrl_scores)?;
-            let writer = utils::stdout_or_file(output.as_ref())?;
-            let motifs = all_bases();
-            let mut sma = SmaOptions::new(pos_bkde, neg_bkde, motifs, writer);
-            if let Some(output_filename) = output {
-                let track_name = output_filename
-                    .file_name()
-                    .ok_or_else(|| eyre::eyre!("Not a filename"))?
-                    .to_str()
-                    .unwrap();
-                sma.track_name(track_name);
+            let motif_models = motif_ctrl_scores
+                .into_iter()
+                .map(|scores| {
+                    let pos_bkde = BinnedKde::load(&scores.pos_ctrl_scores)?;
+                    let neg_bkde = BinnedKde::load(&scores.neg_ctrl_scores)?;
+                    Ok((scores.motif, pos_bkde, neg_bkde))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let track_name = output
+                .as_ref()
+                .map(|output_filename| {
+                    output_filename
+                        .file_name()
+                        .and_then(|name| name.to_str())
+                        .ok_or_else(|| eyre::eyre!("Not a filename"))
+                })
+                .transpose()?;
+            // With --per-chromosome the output is only a prefix and nothing is
+            // written to stdout
+            let sma_output = output.as_ref().filter(|_| !per_chromosome);
+            utils::with_stdout_or_file(sma_output, overwrite, |writer| {
+                let motifs = context.map_or_else(all_bases, MethylationContext::motifs);
+                let mut sma = SmaOptions::new(pos_bkde, neg_bkde, motifs, Box::new(writer));
+                sma.min_coverage(min_coverage)
+                    .output_format(output_format)
+                    .segmentation(segmentation)
+                    .p_stay(p_stay)
+                    .min_block_len(min_block_len)
+                    .merge_gap(merge_gap)
+                    .threads(threads)
+                    .batch_size(batch_size)
+                    .run_length_encode(run_length_encode)
+                    .smoothing_window(smoothing_window)
+                    .min_scored_positions(min_scored_positions)
+                    .per_read_output(
+                        per_read_writer
+                            .as_mut()
+                            .map(|writer| Box::new(writer) as Box<dyn Write + '_>),
+                    )
+                    .posteriors_output(
+                        posteriors_writer
+                            .as_mut()
+                            .map(|writer| Box::new(writer) as Box<dyn Write + '_>),
+                    )
+                    .split_by_strand(strand_writers.as_mut().map(|(plus, minus, unknown)| {
+                        StrandWriters::new(Box::new(plus), Box::new(minus), Box::new(unknown))
+                    }))
+                    .per_chromosome(output.clone().filter(|_| per_chromosome))
+                    .overwrite(overwrite);
+                for (motif, pos_bkde, neg_bkde) in motif_models {
+                    sma.motif_models(motif, pos_bkde, neg_bkde);
+                }
+                if let Some(footprint_length) = footprint_length {
+                    sma.footprint_length(footprint_length);
+                }
+                if let Some(track_name) = track_name {
+                    sma.track_name(track_name);
+                }
+                sma.run_modfile(mod_file)
+            })?;
+            if let Some(per_read_writer) = per_read_writer {
+                per_read_writer.commit()?;
             }
-            sma.run_modfile(mod_file)?;
+            if let Some(posteriors_writer) = posteriors_writer {
+                posteriors_writer.commit()?;
+            }
+            if let Some((plus, minus, unknown)) = strand_writers {
+                plus.commit()?;
+                minus.commit()?;
+                unknown.commit()?;
+            }
+        }
+        Commands::AggBlocks {
+            input,
+            output,
+            window,
+            step,
+            overwrite,
+        } => {
+            utils::with_stdout_or_file(output.as_ref(), overwrite, |writer| {
+                AggOptions::default()
+                    .window(window)
+                    .step(step)
+                    .run(input.as_ref(), writer)
+            })?;
         }
         Commands::QC(cmd) => match cmd {
             QCCmd::Score { input } => {
@@ -469,10 +1239,114 @@ fn main() -> Result<()> {
         },
 
         Commands::Npsmlr(cmd) => match cmd {
-            NpsmlrCmd::Train(cmd) => cmd.run()?,
+            NpsmlrCmd::Train(cmd) => cmd.run(show_progress)?,
             NpsmlrCmd::Score(cmd) => cmd.run()?,
         },
         Commands::Pipeline(plcmd) => plcmd.run(log_level_filter)?,
+        Commands::Completions { shell } => {
+            clap_complete::generate(shell, &mut Args::command(), "cawlr", &mut io::stdout());
+        }
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use assert_fs::TempDir;
+
+    use super::*;
+
+    fn score_args(config: &Path, extra: &[&str]) -> Vec<OsString> {
+        let mut args = vec![
+            "cawlr",
+            "score",
+            "-i",
+            "in.arrow",
+            "-o",
+            "out.arrow",
+            "--pos-ctrl",
+            "pos",
+            "--neg-ctrl",
+            "neg",
+            "-r",
+            "ranks",
+        ]
+        .into_iter()
+        .map(OsString::from)
+        .collect::<Vec<_>>();
+        args.push("--config".into());
+        args.push(config.into());
+        args.extend(extra.iter().map(OsString::from));
+        args
+    }
+
+    #[test]
+    fn test_config() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let config = temp_dir.path().join("cawlr.toml");
+        std::fs::write(
+            &config,
+            "[score]\ngenome = \"~/sacCer3.fa\"\ncutoff = 5.0\nmotif = [\"2:GC\"]\n",
+        )?;
+        let home = std::env::var("HOME")?;
+
+        let args = parse_args(score_args(&config, &[]))?;
+        match args.command {
+            Commands::Score {
+                genome,
+                cutoff,
+                motif,
+                p_value_threshold,
+                ..
+            } => {
+                assert_eq!(genome, Path::new(&home).join("sacCer3.fa"));
+                assert_eq!(cutoff, 5.0);
+                assert_eq!(motif.map(|ms| ms.len()), Some(1));
+                assert_eq!(p_value_threshold, 0.05);
+            }
+            _ => panic!("Expected score command"),
+        }
+
+        // Command line takes precedence over the config
+        let args = parse_args(score_args(&config, &["-g", "other.fa", "--cutoff", "8"]))?;
+        match args.command {
+            Commands::Score { genome, cutoff, .. } => {
+                assert_eq!(genome, PathBuf::from("other.fa"));
+                assert_eq!(cutoff, 8.0);
+            }
+            _ => panic!("Expected score command"),
+        }
+
+        std::fs::write(&config, "[score]\nnot_an_option = 1\n")?;
+        assert!(parse_args(score_args(&config, &[])).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_min_rank() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let ctrl = temp_dir.path().join("ctrl");
+        std::fs::write(&ctrl, "")?;
+        for flag in ["--filter-min-rank", "--top-n"] {
+            let args: Vec<OsString> = vec![
+                "cawlr".into(),
+                "rank".into(),
+                "--pos-ctrl".into(),
+                ctrl.clone().into(),
+                "--neg-ctrl".into(),
+                ctrl.clone().into(),
+                "-o".into(),
+                "ranks".into(),
+                flag.into(),
+                "100".into(),
+            ];
+            match parse_args(args)?.command {
+                Commands::Rank { top_n, .. } => assert_eq!(top_n, Some(100)),
+                _ => panic!("Expected rank command"),
+            }
+        }
+        Ok(())
+    }
+}
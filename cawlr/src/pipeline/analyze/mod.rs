@@ -1,19 +1,14 @@
 mod cmd;
 
-use std::{
-    ffi::OsStr,
-    fs::{self, File},
-    path::Path,
-    process::Command,
-};
+use std::{ffi::OsStr, fs::File, io::BufWriter, path::Path, process::Command};
 
 pub use cmd::AnalyzeCmd;
 use eyre::Context;
 use libcawlr::{
-    agg_blocks,
+    agg_blocks::AggOptions,
     motif::all_bases,
     region::Region,
-    sma::SmaOptions,
+    sma::{strand_bed_path, SmaOptions, StrandWriters},
     utils::{self, wrap_cmd},
 };
 use log::LevelFilter;
@@ -60,10 +55,8 @@ fn cluster_region_cmd<S: AsRef<OsStr>>(
 }
 
 pub fn run(args: AnalyzeCmd, log_level_filter: LevelFilter) -> eyre::Result<()> {
-    if !args.no_overwrite && args.output_dir.exists() {
-        fs::remove_dir_all(&args.output_dir)?;
-    }
-    fs::create_dir_all(&args.output_dir)?;
+    utils::validate_genome_fasta(&args.genome.0)?;
+    utils::prepare_output_dir(&args.output_dir, !args.no_overwrite)?;
 
     let log_file_path = args.output_dir.join("log.txt");
     let log_file = File::create(log_file_path)?;
@@ -117,39 +110,30 @@ pub fn run(args: AnalyzeCmd, log_level_filter: LevelFilter) -> eyre::Result<()>
 
     let track_name = format!("{name}.cawlr.sma");
     let sma = args.output_dir.join(format!("{track_name}.bed"));
+    let plus_filepath = strand_bed_path(&sma, "plus");
+    let minus_filepath = strand_bed_path(&sma, "minus");
+    let unknown_filepath = strand_bed_path(&sma, "unknown");
     wrap_cmd("cawlr sma", || {
         let mut sma_opts =
             SmaOptions::try_new(&args.pos_scores.0, &args.neg_scores.0, all_bases(), &sma)?;
-        sma_opts.track_name(&track_name);
+        let strand_writers = StrandWriters::new(
+            Box::new(BufWriter::new(File::create(&plus_filepath)?)),
+            Box::new(BufWriter::new(File::create(&minus_filepath)?)),
+            Box::new(BufWriter::new(File::create(&unknown_filepath)?)),
+        );
+        sma_opts
+            .track_name(&track_name)
+            .split_by_strand(Some(strand_writers));
         sma_opts.run(&scored).wrap_err("cawlr sma failed")
     })?;
 
     let agg_output = args.output_dir.join(format!("{track_name}.tsv"));
     wrap_cmd("Aggregating blocks", || {
-        agg_blocks::run(&sma, Some(&agg_output))
+        AggOptions::default()
+            .run(&sma, File::create(&agg_output)?)
             .wrap_err("Failed to aggregate single molecule data")
     })?;
 
-    wrap_cmd("Splitting by strand", || {
-        let mut cmd = Command::new("split_by_strand.py");
-        cmd.arg("-i").arg(&sma);
-        log::info!("{cmd:?}");
-        cmd.output().wrap_err("Failed to split by strand")?;
-        Ok(())
-    })?;
-
-    let minus_filepath: &Path = sma.file_stem().unwrap().as_ref();
-    let minus_filepath = sma
-        .parent()
-        .unwrap()
-        .join(format!("{}.minus.bed", minus_filepath.display()));
-
-    let plus_filepath: &Path = sma.file_stem().unwrap().as_ref();
-    let plus_filepath = sma
-        .parent()
-        .unwrap()
-        .join(format!("{}.plus.bed", plus_filepath.display()));
-
     wrap_cmd("Clustering all reads", || {
         let mut cmd = cluster_region_cmd(
             &args.locus,
@@ -43,7 +43,7 @@ where
         .take()
         .ok_or_else(|| eyre::eyre!("Could not capture stdout"))?;
     let reader = BufReader::new(stdout);
-    let mut collapse = CollapseOptions::try_new(bam, output)?;
+    let mut collapse = CollapseOptions::try_new(bam, Some(output))?;
     collapse.run(reader)?;
     Ok(())
 }
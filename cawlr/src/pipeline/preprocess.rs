@@ -1,5 +1,5 @@
 use std::{
-    fs::{self, File},
+    fs::File,
     io::{BufRead, BufReader, BufWriter, Write},
     path::{Path, PathBuf},
     process::{Command, Stdio},
@@ -57,10 +57,7 @@ pub struct PreprocessCmd {
 
 impl PreprocessCmd {
     pub fn run(self) -> eyre::Result<()> {
-        if self.overwrite && self.output_dir.exists() {
-            fs::remove_dir_all(&self.output_dir)?;
-        }
-        fs::create_dir_all(&self.output_dir)?;
+        utils::prepare_output_dir(&self.output_dir, self.overwrite)?;
 
         let log_file_path = self.output_dir.join("log.txt");
         let log_file = File::create(log_file_path)?;
@@ -166,7 +166,7 @@ fn eventalign_collapse(
         .take()
         .ok_or_else(|| eyre::eyre!("Could not capture stdout"))?;
     let reader = BufReader::new(stdout);
-    let mut collapse = CollapseOptions::try_new(bam, output)?;
+    let mut collapse = CollapseOptions::try_new(bam, Some(output))?;
     collapse.run(reader)?;
     Ok(())
 }
@@ -174,6 +174,8 @@ fn eventalign_collapse(
 fn train_npsmlr(
     collapse_file: &Path,
     db_file: &Path,
+    report_file: &Path,
+    genome: &Path,
     single: bool,
     motifs: &[Motif],
 ) -> Result<Model> {
@@ -181,9 +183,18 @@ fn train_npsmlr(
         .dbscan(true)
         .single(single)
         .db_path(Some(db_file.to_path_buf()))
+        .genome(Some(genome.to_path_buf()))
         .motifs(motifs.to_vec());
     let reader = BufReader::new(File::open(collapse_file)?);
-    let model = train_opts.run_model(reader)?;
+    let (model, report) = train_opts.run_model(reader)?;
+    let n_failed = report.failed().count();
+    if n_failed > 0 {
+        log::warn!(
+            "{n_failed} kmers failed to train, see {}",
+            report_file.display()
+        );
+    }
+    report.save_as(report_file, true)?;
     Ok(model)
 }
 
@@ -339,12 +350,26 @@ pub fn run(args: TrainCtrlPipelineCmd) -> eyre::Result<()> {
 
     let pos_model = wrap_cmd_output("Train (+) ctrl", || {
         log::info!("Starting  + training");
-        train_npsmlr(&pos_collapse, &pos_db_file, false, &args.motifs)
+        train_npsmlr(
+            &pos_collapse,
+            &pos_db_file,
+            &args.output_dir.join("pos_train.pickle.train_report.tsv"),
+            args.genome.as_ref(),
+            false,
+            &args.motifs,
+        )
     })?;
     pos_model.save_as(pos_train)?;
     let neg_model = wrap_cmd_output("Train (-) ctrl", || {
         log::info!("Starting - training");
-        train_npsmlr(&neg_collapse, &neg_db_file, true, &args.motifs)
+        train_npsmlr(
+            &neg_collapse,
+            &neg_db_file,
+            &args.output_dir.join("neg_train.pickle.train_report.tsv"),
+            args.genome.as_ref(),
+            true,
+            &args.motifs,
+        )
     })?;
     neg_model.save_as(neg_train)?;
 
@@ -353,7 +378,8 @@ pub fn run(args: TrainCtrlPipelineCmd) -> eyre::Result<()> {
         rank_models(&rank_output, &pos_model, &neg_model)
     })?;
 
-    let score_opts = ScoreOptions::new(pos_model, neg_model, ranks, 10, 10.0, args.motifs.clone());
+    let mut score_opts = ScoreOptions::from_models(pos_model, neg_model, ranks);
+    score_opts.motifs(args.motifs.clone());
 
     let pos_scores_path = args.output_dir.join("pos_scored.arrow");
     wrap_cmd("Scoring (+) ctrl", || {
@@ -54,19 +54,18 @@ def main():
     bw_df = pd.concat(bw_dfs, ignore_index=True, sort=False)
     bw_df.columns = columns
 
-    agg_blocks = pd.read_csv(args.input, delimiter="\t", header=None)
-    agg_blocks = agg_blocks.sort_values(by=1)
-    agg_blocks = agg_blocks[(args.start <= agg_blocks[1]) & (agg_blocks[1] <= args.end)]
-    agg_blocks[5] = agg_blocks[2].rolling(75, center=True).sum()
-    agg_blocks["rolling_total"] = agg_blocks[3].rolling(75, center=True).sum()
-    agg_blocks["rolling_avg"] = agg_blocks[5] / agg_blocks["rolling_total"]
-    agg_blocks[7] = "sample"
-    only_needed = agg_blocks[[1, 6, 7]]
-    only_needed.columns = columns
-    combined = pd.concat([only_needed, agg_blocks], ignore_index=True)
-
+    # Windows from cawlr agg-blocks, plotted at their midpoints
+    agg_blocks = pd.read_csv(args.input, delimiter="\t")
+    agg_blocks = agg_blocks[agg_blocks["chrom"] == args.chrom]
+    agg_blocks = agg_blocks[
+        (args.start <= agg_blocks["start"]) & (agg_blocks["end"] <= args.end)
+    ]
+    agg_blocks = agg_blocks.sort_values(by="start")
+    agg_blocks["pos"] = (agg_blocks["start"] + agg_blocks["end"]) // 2
     scaler = StandardScaler()
-    agg_blocks[6] = scaler.fit_transform(agg_blocks["rolling_avg"])
+    agg_blocks["score"] = scaler.fit_transform(agg_blocks[["mean_score"]]).ravel()
+    agg_blocks["group"] = "sample"
+    combined = pd.concat([agg_blocks[columns], bw_df], ignore_index=True)
 
     _, ax = plt.subplots(1, 1, figsize=(10, 4))
     sns.lineplot(data=combined, x="pos", y="score", hue="group")
@@ -1,22 +1,23 @@
+//! Aggregate single molecule nucleosome calls from cawlr sma into windows.
+//!
+//! Each read in the SMA bed covers chromStart to chromEnd, and every base of
+//! the read is a site scored 1 if it falls within one of the read's blocks
+//! (nucleosomes) or 0 otherwise. Windows of `window` bases are placed every
+//! `step` bases, starting at multiples of `step`, and each window reports the
+//! mean of the per-site fraction of reads overlapping a block.
 use std::{
+    collections::BTreeMap,
     fs::File,
-    io::{BufRead, BufReader},
-    path::{Path, PathBuf},
+    io::{BufRead, BufReader, Write},
+    path::Path,
 };
 
 use csv::StringRecord;
-use fnv::{FnvHashMap, FnvHashSet};
+use fnv::FnvHashSet;
 use serde::{de::IgnoredAny, Deserialize};
 use serde_with::{formats::CommaSeparator, serde_as, StringWithSeparator};
 
-use crate::utils::stdout_or_file;
-
-#[derive(Eq, Hash, PartialEq, Clone)]
-struct Position {
-    chrom: String,
-    pos: u64,
-}
-#[derive(Default)]
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
 struct Count {
     count: u64,
     total: u64,
@@ -37,12 +38,6 @@ impl Count {
     }
 }
 
-impl Position {
-    fn new(chrom: String, pos: u64) -> Self {
-        Self { chrom, pos }
-    }
-}
-
 #[serde_as]
 #[derive(Deserialize)]
 pub struct Bed {
@@ -63,15 +58,20 @@ pub struct Bed {
 }
 
 impl Bed {
-    fn iter_counts(self) -> impl Iterator<Item = Position> {
+    /// Genomic positions covered by a block. Single base blocks at either end
+    /// of the read are the pseudo blocks cawlr sma adds when the read doesn't
+    /// start or end with a nucleosome, and are skipped.
+    fn overlaps(&self) -> FnvHashSet<u64> {
+        let read_len = self.stop - self.start;
         self.bsizes
-            .into_iter()
-            .zip(self.bstarts.into_iter())
-            .map(move |(a, b)| Position::new(self.chrom.clone(), self.start + a + b))
-    }
-
-    fn overlaps(self) -> FnvHashSet<Position> {
-        self.iter_counts().collect()
+            .iter()
+            .zip(&self.bstarts)
+            .filter(|(&size, &bstart)| !(size == 1 && (bstart == 0 || bstart + 1 == read_len)))
+            .flat_map(|(&size, &bstart)| {
+                let block_start = self.start + bstart;
+                block_start..block_start + size
+            })
+            .collect()
     }
 
     pub fn bstarts(&self) -> &[u64] {
@@ -83,42 +83,249 @@ impl Bed {
     }
 }
 
-pub fn run(input: &Path, output: Option<&PathBuf>) -> eyre::Result<()> {
-    let input = BufReader::new(File::open(input)?);
-    // Skip header
-
-    let mut counts: FnvHashMap<Position, Count> = FnvHashMap::default();
-    for rec in input.lines().skip(1) {
-        let rec = rec?;
-        let line: Vec<&str> = rec.split('\t').collect();
-        let line = StringRecord::from(line);
-        let line = line.deserialize::<Bed>(None)?;
-        let chrom = line.chrom.clone();
-        let start = line.start;
-        let stop = line.stop;
-        let overlapped = line.overlaps();
-        (start..stop).for_each(|pos| {
-            let pos = Position::new(chrom.clone(), pos);
-            let e = counts.entry(pos.clone()).or_default();
+/// Per chromosome counts at each site and the span of each read
+#[derive(Default)]
+struct Sites {
+    counts: BTreeMap<String, BTreeMap<u64, Count>>,
+    reads: BTreeMap<String, Vec<(u64, u64)>>,
+}
+
+impl Sites {
+    fn from_reader<R: BufRead>(reader: R) -> eyre::Result<Self> {
+        let mut sites = Sites::default();
+        for rec in reader.lines() {
+            let rec = rec?;
+            // Skip track line
+            if rec.starts_with("track") || rec.is_empty() {
+                continue;
+            }
+            let line: Vec<&str> = rec.split('\t').collect();
+            let line = StringRecord::from(line);
+            let line = line.deserialize::<Bed>(None)?;
+            sites.add(&line);
+        }
+        Ok(sites)
+    }
+
+    fn add(&mut self, bed: &Bed) {
+        let overlapped = bed.overlaps();
+        let chrom_counts = self.counts.entry(bed.chrom.clone()).or_default();
+        for pos in bed.start..bed.stop {
+            let e = chrom_counts.entry(pos).or_default();
             if overlapped.contains(&pos) {
                 e.both();
             } else {
                 e.total();
             }
-        });
+        }
+        self.reads
+            .entry(bed.chrom.clone())
+            .or_default()
+            .push((bed.start, bed.stop));
+    }
+}
+
+/// Summary of the sites within a window
+#[derive(Debug, Clone, PartialEq)]
+struct Block {
+    chrom: String,
+    start: u64,
+    end: u64,
+    mean_score: f64,
+    n_sites: usize,
+    n_reads: usize,
+}
+
+pub struct AggOptions {
+    window: u64,
+    step: u64,
+}
+
+impl Default for AggOptions {
+    fn default() -> Self {
+        Self {
+            window: 200,
+            step: 50,
+        }
+    }
+}
+
+impl AggOptions {
+    /// Size of each window in bases
+    pub fn window(&mut self, window: u64) -> &mut Self {
+        self.window = window;
+        self
+    }
+
+    /// Distance in bases between the start of each window
+    pub fn step(&mut self, step: u64) -> &mut Self {
+        self.step = step;
+        self
     }
 
-    let mut output = stdout_or_file(output)?;
-    for (p, c) in counts.into_iter() {
+    /// Aggregate the bed file from cawlr sma into a tsv with columns chrom,
+    /// start, end, mean_score, n_sites, n_reads.
+    pub fn run<W: Write>(&self, input: &Path, mut output: W) -> eyre::Result<()> {
+        if self.window == 0 || self.step == 0 {
+            return Err(eyre::eyre!("Window and step size must be greater than 0"));
+        }
+        let input = BufReader::new(File::open(input)?);
+        let sites = Sites::from_reader(input)?;
+
         writeln!(
             &mut output,
-            "{}\t{}\t{}\t{}\t{}",
-            p.chrom,
-            p.pos,
-            c.count,
-            c.total,
-            c.frac()
+            "chrom\tstart\tend\tmean_score\tn_sites\tn_reads"
         )?;
+        for b in self.blocks(&sites) {
+            writeln!(
+                &mut output,
+                "{}\t{}\t{}\t{}\t{}\t{}",
+                b.chrom, b.start, b.end, b.mean_score, b.n_sites, b.n_reads
+            )?;
+        }
+        output.flush()?;
+        Ok(())
+    }
+
+    /// Every window with at least one site, sorted by chromosome and start
+    fn blocks(&self, sites: &Sites) -> Vec<Block> {
+        let mut blocks = Vec::new();
+        for (chrom, counts) in sites.counts.iter() {
+            let (first, last) = match (counts.keys().next(), counts.keys().next_back()) {
+                (Some(&first), Some(&last)) => (first, last),
+                _ => continue,
+            };
+            let mut read_starts = Vec::new();
+            let mut read_stops = Vec::new();
+            for &(start, stop) in sites.reads[chrom].iter() {
+                read_starts.push(start);
+                read_stops.push(stop);
+            }
+            read_starts.sort_unstable();
+            read_stops.sort_unstable();
+
+            // First window that could contain the first site
+            let mut start = first.saturating_sub(self.window - 1) / self.step * self.step;
+            while start <= last {
+                let end = start + self.window;
+                let window_counts = counts.range(start..end).map(|(_, c)| c);
+                let (n_sites, total) =
+                    window_counts.fold((0, 0.0), |(n, total), c| (n + 1, total + c.frac()));
+                if n_sites > 0 {
+                    // Reads that start before the window ends, minus those
+                    // that stop before the window starts
+                    let n_reads = read_starts.partition_point(|&s| s < end)
+                        - read_stops.partition_point(|&s| s <= start);
+                    blocks.push(Block {
+                        chrom: chrom.clone(),
+                        start,
+                        end,
+                        mean_score: total / n_sites as f64,
+                        n_sites,
+                        n_reads,
+                    });
+                }
+                start += self.step;
+            }
+        }
+        blocks
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use float_eq::assert_float_eq;
+
+    use super::*;
+
+    fn sma_line(chrom: &str, start: u64, stop: u64, blocks: &[(u64, u64)]) -> String {
+        let sizes = blocks.iter().map(|b| b.1.to_string()).collect::<Vec<_>>();
+        let starts = blocks.iter().map(|b| b.0.to_string()).collect::<Vec<_>>();
+        format!(
+            "{chrom}\t{start}\t{stop}\tread\t0\t+\t{start}\t{stop}\t0,0,0\t{}\t{}\t{}",
+            blocks.len(),
+            sizes.join(","),
+            starts.join(",")
+        )
+    }
+
+    #[test]
+    fn test_overlaps() {
+        let line = sma_line("chrI", 100, 120, &[(0, 1), (5, 10), (19, 1)]);
+        let bed = StringRecord::from(line.split('\t').collect::<Vec<_>>())
+            .deserialize::<Bed>(None)
+            .unwrap();
+        let overlaps = bed.overlaps();
+        assert_eq!(overlaps.len(), 10);
+        assert!((105..115).all(|pos| overlaps.contains(&pos)));
+
+        // Only single base blocks at the ends of the read are pseudo blocks
+        let line = sma_line("chrI", 100, 120, &[(0, 1), (5, 1), (10, 5), (19, 1)]);
+        let bed = StringRecord::from(line.split('\t').collect::<Vec<_>>())
+            .deserialize::<Bed>(None)
+            .unwrap();
+        let mut overlaps = bed.overlaps().into_iter().collect::<Vec<_>>();
+        overlaps.sort_unstable();
+        assert_eq!(overlaps, [105, 110, 111, 112, 113, 114]);
+    }
+
+    #[test]
+    fn test_agg_blocks() -> eyre::Result<()> {
+        // Two reads over 100..140, one with a nucleosome over 110..130, and a
+        // third read on another chromosome
+        let bed = [
+            "track name=\"sma\" itemRgb=\"on\" visibility=2".to_string(),
+            sma_line("chrI", 100, 140, &[(0, 1), (39, 1)]),
+            sma_line("chrI", 100, 140, &[(0, 1), (10, 20), (39, 1)]),
+            sma_line("chrII", 0, 10, &[(0, 10)]),
+        ]
+        .join("\n");
+        let sites = Sites::from_reader(bed.as_bytes())?;
+
+        let mut opts = AggOptions::default();
+        opts.window(20).step(10);
+        let blocks = opts.blocks(&sites);
+        let windows = blocks
+            .iter()
+            .map(|b| (b.chrom.as_str(), b.start, b.end))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            windows,
+            vec![
+                ("chrI", 90, 110),
+                ("chrI", 100, 120),
+                ("chrI", 110, 130),
+                ("chrI", 120, 140),
+                ("chrI", 130, 150),
+                ("chrII", 0, 20),
+            ]
+        );
+
+        let n_sites = blocks.iter().map(|b| b.n_sites).collect::<Vec<_>>();
+        assert_eq!(n_sites, vec![10, 20, 20, 20, 10, 10]);
+        let n_reads = blocks.iter().map(|b| b.n_reads).collect::<Vec<_>>();
+        assert_eq!(n_reads, vec![2, 2, 2, 2, 2, 1]);
+
+        // Half of the reads overlap the nucleosome at each of its sites
+        let means = blocks.iter().map(|b| b.mean_score).collect::<Vec<_>>();
+        for (mean, expected) in means.into_iter().zip([0.0, 0.25, 0.5, 0.25, 0.0, 1.0]) {
+            assert_float_eq!(mean, expected, abs <= 1e-9);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_agg_blocks_step_larger_than_window() -> eyre::Result<()> {
+        let bed = sma_line("chrI", 0, 100, &[(0, 1), (99, 1)]);
+        let sites = Sites::from_reader(bed.as_bytes())?;
+        let mut opts = AggOptions::default();
+        opts.window(10).step(30);
+        let starts = opts
+            .blocks(&sites)
+            .iter()
+            .map(|b| b.start)
+            .collect::<Vec<_>>();
+        assert_eq!(starts, vec![0, 30, 60, 90]);
+        Ok(())
     }
-    Ok(())
 }
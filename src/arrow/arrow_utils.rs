@@ -7,9 +7,9 @@ use std::{
 };
 
 use arrow2::{
-    array::Array,
+    array::{new_null_array, Array, BooleanArray, ListArray, StructArray},
     chunk::Chunk,
-    datatypes::{Field, Schema},
+    datatypes::{DataType, Field, Schema},
     io::ipc::{
         read::{read_file_metadata, FileReader},
         write::{Compression, FileWriter, WriteOptions},
@@ -120,6 +120,84 @@ where
     Ok(reader)
 }
 
+/// Non-nullable fields added to cawlr structs after files were first written
+/// with them, and the value rows from older files are read with. Missing
+/// nullable fields are read as null.
+const ADDED_BOOL_FIELDS: [(&str, bool); 2] = [("missing_model", false), ("matches_motif", true)];
+
+/// Converts an array from an Arrow file to the data type it is deserialized
+/// as. arrow2_convert reads struct fields by position, so the fields of files
+/// written before fields were added to a struct are matched up by name and
+/// the missing fields filled in.
+fn conform_array(array: Box<dyn Array>, data_type: &DataType) -> Result<Box<dyn Array>> {
+    if array.data_type() == data_type {
+        return Ok(array);
+    }
+    match (array.data_type(), data_type) {
+        (DataType::Struct(_), DataType::Struct(fields)) => {
+            let array = array
+                .as_any()
+                .downcast_ref::<StructArray>()
+                .ok_or_else(|| eyre::eyre!("Expected a struct array"))?;
+            let values = fields
+                .iter()
+                .map(
+                    |field| match array.fields().iter().position(|f| f.name == field.name) {
+                        Some(idx) => conform_array(array.values()[idx].clone(), &field.data_type),
+                        None => missing_field(field, array.len()),
+                    },
+                )
+                .collect::<Result<Vec<_>>>()?;
+            Ok(StructArray::new(data_type.clone(), values, array.validity().cloned()).boxed())
+        }
+        (DataType::List(_), DataType::List(field)) => {
+            let array = array
+                .as_any()
+                .downcast_ref::<ListArray<i32>>()
+                .ok_or_else(|| eyre::eyre!("Expected a list array"))?;
+            let values = conform_array(array.values().clone(), &field.data_type)?;
+            let array = ListArray::new(
+                data_type.clone(),
+                array.offsets().clone(),
+                values,
+                array.validity().cloned(),
+            );
+            Ok(array.boxed())
+        }
+        (from, to) => Err(eyre::eyre!(
+            "Arrow file has a {from:?} column where {to:?} was expected"
+        )),
+    }
+}
+
+/// Column for a field missing from an older Arrow file
+fn missing_field(field: &Field, len: usize) -> Result<Box<dyn Array>> {
+    if field.is_nullable {
+        return Ok(new_null_array(field.data_type.clone(), len));
+    }
+    match ADDED_BOOL_FIELDS
+        .iter()
+        .find(|(name, _)| *name == field.name)
+    {
+        Some(&(_, value)) => Ok(BooleanArray::from_slice(vec![value; len]).boxed()),
+        None => Err(eyre::eyre!(
+            "Arrow file is missing the {} field",
+            field.name
+        )),
+    }
+}
+
+/// Deserializes an array from an Arrow file, including files written before
+/// fields were added to T
+pub(crate) fn try_into_vec<T>(array: Box<dyn Array>) -> Result<Vec<T>>
+where
+    T: ArrowField<Type = T> + ArrowDeserialize + 'static,
+    for<'a> &'a <T as ArrowDeserialize>::ArrayType: IntoIterator,
+{
+    let array = conform_array(array, &T::data_type())?;
+    Ok(array.try_into_collection()?)
+}
+
 pub fn is_arrow_file<P>(path: P) -> bool
 where
     P: AsRef<Path>,
@@ -132,6 +210,22 @@ where
     is_arrow().is_ok()
 }
 
+/// Returns the name of the top level field in the Arrow file schema, which
+/// matches [SchemaExt::type_as_str] for files written by cawlr.
+pub fn arrow_type_tag<P>(path: P) -> Result<String>
+where
+    P: AsRef<Path>,
+{
+    let mut reader = File::open(path)?;
+    let metadata = read_file_metadata(&mut reader)?;
+    let field = metadata
+        .schema
+        .fields
+        .first()
+        .ok_or_else(|| eyre::eyre!("Arrow file schema has no fields"))?;
+    Ok(field.name.clone())
+}
+
 /// Apply a function to chunks of data loaded from an Arrow Feather File.
 ///
 /// # Example
@@ -170,7 +264,7 @@ where
     for read in feather {
         if let Ok(chunk) = read {
             for arr in chunk.into_arrays().into_iter() {
-                let eventaligns: Vec<T> = arr.try_into_collection()?;
+                let eventaligns: Vec<T> = try_into_vec(arr)?;
                 func(eventaligns)?;
             }
         } else {
@@ -180,6 +274,42 @@ where
     Ok(())
 }
 
+/// Like [load_apply], but record batches with more than max_batch_size rows
+/// are split so func is never called with more than max_batch_size reads.
+/// Limits memory use when the file was written with large batches.
+pub fn load_apply_batched<R, F, T>(reader: R, max_batch_size: usize, mut func: F) -> Result<()>
+where
+    R: Read + Seek,
+    F: FnMut(Vec<T>) -> eyre::Result<()>,
+    T: ArrowField<Type = T> + ArrowDeserialize + 'static,
+    for<'a> &'a <T as ArrowDeserialize>::ArrayType: IntoIterator,
+{
+    if max_batch_size == 0 {
+        return Err(eyre::eyre!("Batch size must be greater than 0"));
+    }
+    let feather = load(reader)?;
+    for read in feather {
+        if let Ok(chunk) = read {
+            for arr in chunk.into_arrays().into_iter() {
+                if arr.len() <= max_batch_size {
+                    let xs: Vec<T> = try_into_vec(arr)?;
+                    func(xs)?;
+                    continue;
+                }
+                let arr = conform_array(arr, &T::data_type())?;
+                for offset in (0..arr.len()).step_by(max_batch_size) {
+                    let len = max_batch_size.min(arr.len() - offset);
+                    let xs: Vec<T> = arr.slice(offset, len).try_into_collection()?;
+                    func(xs)?;
+                }
+            }
+        } else {
+            log::warn!("Failed to load arrow chunk")
+        }
+    }
+    Ok(())
+}
+
 pub fn load_apply2<R, F, T>(reader: R, mut func: F) -> Result<()>
 where
     R: Read + Seek,
@@ -189,7 +319,8 @@ where
 {
     let feather = load(reader)?;
     for chunk in feather {
-        for arr in chunk?.arrays() {
+        for arr in chunk?.into_arrays() {
+            let arr = conform_array(arr, &T::data_type())?;
             let iter = arrow_array_deserialize_iterator(arr.as_ref())?;
             for x in iter {
                 func(x)?;
@@ -211,6 +342,7 @@ where
     for read in feather {
         if let Ok(chunk) = read {
             for arr in chunk.into_arrays().into_iter() {
+                let arr = conform_array(arr, &T::data_type())?;
                 let iter = arrow_array_deserialize_iterator(arr.borrow())?;
                 for x in iter {
                     func(x)?;
@@ -244,7 +376,7 @@ where
     for read in feather {
         if let Ok(chunk) = read {
             for arr in chunk.into_arrays().into_iter() {
-                let eventaligns: Vec<T> = arr.try_into_collection()?;
+                let eventaligns: Vec<T> = try_into_vec(arr)?;
                 let res = func(eventaligns)?;
                 save(&mut writer, &res)?;
             }
@@ -271,7 +403,7 @@ where
     for read in feather {
         if let Ok(chunk) = read {
             for arr in chunk.into_arrays().into_iter() {
-                let eventaligns: Vec<T> = arr.try_into_collection()?;
+                let eventaligns: Vec<T> = try_into_vec(arr)?;
                 let res = func(eventaligns)?;
                 save_t(&mut writer, &res)?;
             }
@@ -295,7 +427,7 @@ where
     for read in feather {
         if let Ok(chunk) = read {
             for arr in chunk.into_arrays().into_iter() {
-                let eventaligns: Vec<T> = arr.try_into_collection()?;
+                let eventaligns: Vec<T> = try_into_vec(arr)?;
                 func(eventaligns)?;
             }
         } else {
@@ -326,7 +458,7 @@ where
     for read in feather {
         if let Ok(chunk) = read {
             for arr in chunk.into_arrays().into_iter() {
-                let eventaligns: Vec<T> = arr.try_into_collection()?;
+                let eventaligns: Vec<T> = try_into_vec(arr)?;
                 func(eventaligns)?;
             }
         } else {
@@ -381,7 +513,135 @@ where
 
 #[cfg(test)]
 mod test {
+    use assert_fs::TempDir;
+
     use super::*;
+    use crate::arrow::{
+        metadata::{Metadata, Strand},
+        scored_read::ReadQuality,
+    };
+
+    /// Score and ScoredRead as written before fields were added to them
+    #[derive(Debug, Clone, arrow2_convert::ArrowField)]
+    struct OldScore {
+        pos: u64,
+        kmer: String,
+        skipped: bool,
+        signal_score: Option<f64>,
+        skip_score: f64,
+        score: f64,
+    }
+
+    #[derive(Debug, Clone, arrow2_convert::ArrowField)]
+    struct OldScoredRead {
+        metadata: Metadata,
+        scores: Vec<OldScore>,
+    }
+
+    #[test]
+    fn test_load_apply_batched() -> Result<()> {
+        let reads = (0..10)
+            .map(|i| {
+                let metadata = Metadata::new(
+                    format!("read{i}"),
+                    "chrI".to_string(),
+                    i,
+                    1,
+                    Strand::plus(),
+                    String::new(),
+                );
+                Eventalign::new(metadata, Vec::new())
+            })
+            .collect::<Vec<_>>();
+        let temp_dir = TempDir::new()?;
+        let path = temp_dir.path().join("reads.arrow");
+        let mut writer = wrap_writer(File::create(&path)?, &Eventalign::schema())?;
+        save(&mut writer, &reads)?;
+        writer.finish()?;
+
+        let mut batch_sizes = Vec::new();
+        let mut loaded = Vec::new();
+        load_apply_batched(File::open(&path)?, 3, |xs: Vec<Eventalign>| {
+            batch_sizes.push(xs.len());
+            loaded.extend(xs);
+            Ok(())
+        })?;
+        assert_eq!(batch_sizes, vec![3, 3, 3, 1]);
+        assert_eq!(loaded, reads);
+
+        let mut n_calls = 0;
+        load_apply_batched(File::open(&path)?, usize::MAX, |xs: Vec<Eventalign>| {
+            n_calls += 1;
+            assert_eq!(xs.len(), 10);
+            Ok(())
+        })?;
+        assert_eq!(n_calls, 1);
+
+        assert!(load_apply_batched(File::open(&path)?, 0, |_: Vec<Eventalign>| Ok(())).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_old_scored_read_schema() -> Result<()> {
+        let reads = (0..3)
+            .map(|i| OldScoredRead {
+                metadata: Metadata::new(
+                    "read".to_string(),
+                    "chrI".to_string(),
+                    i,
+                    2,
+                    Strand::plus(),
+                    String::new(),
+                ),
+                scores: vec![OldScore {
+                    pos: i,
+                    kmer: "AAAAAA".to_string(),
+                    skipped: false,
+                    signal_score: Some(0.9),
+                    skip_score: 0.5,
+                    score: 0.9,
+                }],
+            })
+            .collect::<Vec<_>>();
+        let schema = Schema::from(vec![Field::new(
+            "scored",
+            OldScoredRead::data_type(),
+            false,
+        )]);
+        let mut file = Vec::new();
+        let mut writer = wrap_writer(&mut file, &schema)?;
+        save(&mut writer, &reads)?;
+        writer.finish()?;
+
+        let mut loaded = Vec::new();
+        load_apply(std::io::Cursor::new(&file), |xs: Vec<ScoredRead>| {
+            loaded.extend(xs);
+            Ok(())
+        })?;
+        assert_eq!(loaded.len(), 3);
+        for (read, old) in loaded.iter().zip(&reads) {
+            assert_eq!(read.metadata, old.metadata);
+            assert_eq!(read.quality, None::<ReadQuality>);
+            let score = &read.scores()[0];
+            assert_eq!(score.pos, old.scores[0].pos);
+            assert_eq!(score.score, 0.9);
+            assert_eq!(score.signal_score, Some(0.9));
+            assert!(!score.missing_model);
+            assert!(score.matches_motif);
+            assert_eq!(score.pos_ln_f(), None);
+            assert_eq!(score.calibrated_score(), None);
+            assert_eq!(score.motif(), None);
+        }
+
+        let mut n_batches = 0;
+        load_apply_batched(std::io::Cursor::new(&file), 2, |xs: Vec<ScoredRead>| {
+            n_batches += 1;
+            assert!(xs.iter().all(|read| read.scores()[0].matches_motif));
+            Ok(())
+        })?;
+        assert_eq!(n_batches, 2);
+        Ok(())
+    }
 
     #[test]
     fn test_is_arrow_file() {
@@ -39,6 +39,24 @@ impl Eventalign {
     pub fn metadata(&self) -> &Metadata {
         &self.metadata
     }
+
+    /// Length of the kmers in the signal data, None if there is no signal data
+    pub fn kmer_size(&self) -> Option<usize> {
+        self.signal_data.first().map(|s| s.kmer.len())
+    }
+
+    /// Returns an error if the kmers in the signal data don't match the given
+    /// kmer size, ie when scoring with a model trained on a different pore
+    /// model.
+    pub fn check_kmer_size(&self, kmer_size: usize) -> eyre::Result<()> {
+        match self.kmer_size() {
+            Some(k) if k != kmer_size => Err(eyre::eyre!(
+                "Read {} has kmers of length {k}, expected kmer size {kmer_size}",
+                self.metadata.name
+            )),
+            _ => Ok(()),
+        }
+    }
 }
 
 impl MetadataExt for Eventalign {
@@ -0,0 +1,280 @@
+//! Print the schema and first rows of an Arrow file, to check the output of a
+//! step in the pipeline without deserializing it.
+use std::io::{Read, Seek, Write};
+
+use arrow2::{
+    array::{Array, BooleanArray, ListArray, PrimitiveArray, StructArray, Utf8Array},
+    datatypes::{DataType, Field},
+    io::ipc::read::{read_file_metadata, FileReader},
+};
+use eyre::Result;
+
+/// Number of rows shown by default
+pub const DEFAULT_N_ROWS: usize = 5;
+
+/// Values longer than this are cut short in the table of rows
+const MAX_CELL_WIDTH: usize = 40;
+
+/// Options for printing an Arrow file. Struct columns, like the single
+/// column of the files from cawlr collapse and score, are flattened into a
+/// column for each of their fields, ie eventalign.metadata.chrom.
+#[derive(Debug, Clone)]
+pub struct InspectOptions {
+    n_rows: usize,
+    schema_only: bool,
+}
+
+impl Default for InspectOptions {
+    fn default() -> Self {
+        Self {
+            n_rows: DEFAULT_N_ROWS,
+            schema_only: false,
+        }
+    }
+}
+
+impl InspectOptions {
+    /// Number of rows of the first record batch to show
+    pub fn n_rows(&mut self, n_rows: usize) -> &mut Self {
+        self.n_rows = n_rows;
+        self
+    }
+
+    /// Only print the schema
+    pub fn schema_only(&mut self, schema_only: bool) -> &mut Self {
+        self.schema_only = schema_only;
+        self
+    }
+
+    /// Write the columns of the schema with their data type and whether they
+    /// are nullable, then the first rows of the first record batch
+    pub fn run<R, W>(&self, mut reader: R, mut writer: W) -> Result<()>
+    where
+        R: Read + Seek,
+        W: Write,
+    {
+        let metadata = read_file_metadata(&mut reader)?;
+        let mut columns = Vec::new();
+        for field in metadata.schema.fields.iter() {
+            flatten_field(field.name.clone(), field, &mut columns);
+        }
+        let schema_rows = columns
+            .iter()
+            .map(|(name, field)| {
+                vec![
+                    name.clone(),
+                    type_name(&field.data_type),
+                    field.is_nullable.to_string(),
+                ]
+            })
+            .collect::<Vec<_>>();
+        write_table(
+            &mut writer,
+            &["column_name", "data_type", "nullable"],
+            &schema_rows,
+        )?;
+        if self.schema_only {
+            return Ok(());
+        }
+
+        let fields = metadata.schema.fields.clone();
+        let mut reader = FileReader::new(reader, metadata, None, Some(self.n_rows));
+        writeln!(writer)?;
+        let chunk = match reader.next() {
+            Some(chunk) => chunk?,
+            None => {
+                writeln!(writer, "No rows")?;
+                return Ok(());
+            }
+        };
+        let mut arrays = Vec::new();
+        for (field, array) in fields.iter().zip(chunk.arrays()) {
+            flatten_array(field.name.clone(), array.as_ref(), &mut arrays);
+        }
+        let header = arrays
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        let rows = (0..chunk.len().min(self.n_rows))
+            .map(|row| arrays.iter().map(|(_, array)| cell(*array, row)).collect())
+            .collect::<Vec<_>>();
+        write_table(&mut writer, &header, &rows)
+    }
+}
+
+fn flatten_field<'a>(name: String, field: &'a Field, columns: &mut Vec<(String, &'a Field)>) {
+    match &field.data_type {
+        DataType::Struct(children) => {
+            for child in children {
+                flatten_field(format!("{name}.{}", child.name), child, columns);
+            }
+        }
+        _ => columns.push((name, field)),
+    }
+}
+
+fn flatten_array<'a>(
+    name: String,
+    array: &'a dyn Array,
+    arrays: &mut Vec<(String, &'a dyn Array)>,
+) {
+    match array.as_any().downcast_ref::<StructArray>() {
+        Some(array) => {
+            for (child, values) in array.fields().iter().zip(array.values()) {
+                flatten_array(format!("{name}.{}", child.name), values.as_ref(), arrays);
+            }
+        }
+        None => arrays.push((name, array)),
+    }
+}
+
+/// Short name of the data type, with the type of list items
+fn type_name(data_type: &DataType) -> String {
+    match data_type {
+        DataType::List(item) | DataType::LargeList(item) => {
+            format!("List<{}>", type_name(&item.data_type))
+        }
+        DataType::Struct(_) => "Struct".to_string(),
+        DataType::Extension(name, _, _) => name.clone(),
+        data_type => format!("{data_type:?}"),
+    }
+}
+
+/// Value of the array at the row, lists only show their length
+fn cell(array: &dyn Array, row: usize) -> String {
+    if array.is_null(row) {
+        return "null".to_string();
+    }
+    let any = array.as_any();
+    macro_rules! display_primitive {
+        ($($t:ty),*) => {
+            $(
+                if let Some(array) = any.downcast_ref::<PrimitiveArray<$t>>() {
+                    return array.value(row).to_string();
+                }
+            )*
+        };
+    }
+    display_primitive!(u8, u16, u32, u64, i8, i16, i32, i64, f32, f64);
+    let value = if let Some(array) = any.downcast_ref::<Utf8Array<i32>>() {
+        array.value(row).to_string()
+    } else if let Some(array) = any.downcast_ref::<Utf8Array<i64>>() {
+        array.value(row).to_string()
+    } else if let Some(array) = any.downcast_ref::<BooleanArray>() {
+        array.value(row).to_string()
+    } else if let Some(array) = any.downcast_ref::<ListArray<i32>>() {
+        format!("[{} items]", array.value(row).len())
+    } else if let Some(array) = any.downcast_ref::<ListArray<i64>>() {
+        format!("[{} items]", array.value(row).len())
+    } else {
+        format!("<{}>", type_name(array.data_type()))
+    };
+    if value.chars().count() > MAX_CELL_WIDTH {
+        let short = value.chars().take(MAX_CELL_WIDTH - 3).collect::<String>();
+        format!("{short}...")
+    } else {
+        value
+    }
+}
+
+/// Columns separated by two spaces and padded to the widest value, with a
+/// line under the header
+fn write_table<W: Write>(writer: &mut W, header: &[&str], rows: &[Vec<String>]) -> Result<()> {
+    let widths = header
+        .iter()
+        .enumerate()
+        .map(|(idx, name)| {
+            rows.iter()
+                .map(|row| row[idx].chars().count())
+                .chain([name.chars().count()])
+                .max()
+                .unwrap_or(0)
+        })
+        .collect::<Vec<_>>();
+    let mut write_row = |values: Vec<&str>| -> Result<()> {
+        let line = values
+            .iter()
+            .zip(widths.iter())
+            .map(|(value, &width)| format!("{value:width$}"))
+            .collect::<Vec<_>>()
+            .join("  ");
+        writeln!(writer, "{}", line.trim_end())?;
+        Ok(())
+    };
+    write_row(header.to_vec())?;
+    let lines = widths.iter().map(|&w| "-".repeat(w)).collect::<Vec<_>>();
+    write_row(lines.iter().map(String::as_str).collect())?;
+    for row in rows {
+        write_row(row.iter().map(String::as_str).collect())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::arrow::{
+        arrow_utils::{save, wrap_writer},
+        eventalign::Eventalign,
+        metadata::{Metadata, Strand},
+        signal::Signal,
+    };
+
+    fn inspect(options: &InspectOptions) -> Result<String> {
+        let reads = (0..3)
+            .map(|i| {
+                let metadata = Metadata::new(
+                    format!("read_{i}"),
+                    "chrI".to_string(),
+                    100 * i,
+                    10,
+                    Strand::plus(),
+                    String::new(),
+                );
+                let signal = Signal::new(100 * i, "AAAAAA".to_string(), 80.0, 0.01, vec![80.0]);
+                Eventalign::new(metadata, vec![signal])
+            })
+            .collect::<Vec<_>>();
+        let mut input = Vec::new();
+        let mut writer = wrap_writer(&mut input, &Eventalign::schema())?;
+        save(&mut writer, &reads)?;
+        writer.finish()?;
+
+        let mut output = Vec::new();
+        options.run(Cursor::new(input), &mut output)?;
+        Ok(String::from_utf8(output)?)
+    }
+
+    #[test]
+    fn test_inspect() -> Result<()> {
+        let output = inspect(InspectOptions::default().n_rows(2))?;
+        let lines = output.lines().collect::<Vec<_>>();
+        assert!(lines[0].starts_with("column_name"));
+        assert!(lines
+            .iter()
+            .any(|line| line.starts_with("eventalign.metadata.chrom ")));
+        assert!(lines.iter().any(
+            |line| line.starts_with("eventalign.signal_data ") && line.contains("List<Struct>")
+        ));
+
+        let (_, rows) = output.split_once("\n\n").unwrap();
+        let rows = rows.lines().collect::<Vec<_>>();
+        // Header, line under it and 2 rows
+        assert_eq!(rows.len(), 4);
+        assert!(rows[0].starts_with("eventalign.metadata.name"));
+        assert!(rows[2].starts_with("read_0 "));
+        assert!(rows[3].contains("[1 items]"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_inspect_schema_only() -> Result<()> {
+        let output = inspect(InspectOptions::default().schema_only(true))?;
+        assert!(output.contains("eventalign.metadata.name"));
+        assert!(!output.contains("read_0"));
+        assert!(!output.contains("\n\n"));
+        Ok(())
+    }
+}
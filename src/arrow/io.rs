@@ -1,10 +1,17 @@
-use std::{fs::File, io, path::Path};
+use std::{
+    fs::File,
+    io::{self, Seek, SeekFrom},
+    path::Path,
+};
+
+use arrow2::io::ipc::read::read_file_metadata;
 
 use super::{
     arrow_utils::{is_arrow_file, load_apply_indy},
     mod_bam::{BamRecords, ModBamIter},
     scored_read::ScoredRead,
 };
+use crate::score::ScoringMethod;
 
 pub enum ModFile {
     Arrow(File),
@@ -29,6 +36,40 @@ impl ModFile {
         })
     }
 
+    /// Duplicate the file handle, both handles share the same cursor
+    pub fn try_clone(&self) -> io::Result<Self> {
+        match self {
+            ModFile::Arrow(file) => Ok(ModFile::Arrow(file.try_clone()?)),
+            ModFile::ModBam { file, mod_tag } => Ok(ModFile::ModBam {
+                file: file.try_clone()?,
+                mod_tag: mod_tag.clone(),
+            }),
+        }
+    }
+
+    /// Seek back to the start of the file so it can be read again
+    pub fn rewind(&mut self) -> io::Result<()> {
+        let file = match self {
+            ModFile::Arrow(file) => file,
+            ModFile::ModBam { file, .. } => file,
+        };
+        file.seek(SeekFrom::Start(0))?;
+        Ok(())
+    }
+
+    /// Scoring method used for the scores in the file. Modification bam files
+    /// store probabilities, so they are treated as probability ratios.
+    pub fn scoring_method(&mut self) -> eyre::Result<ScoringMethod> {
+        match self {
+            ModFile::Arrow(file) => {
+                let metadata = read_file_metadata(file)?;
+                self.rewind()?;
+                ScoringMethod::from_schema(&metadata.schema)
+            }
+            ModFile::ModBam { .. } => Ok(ScoringMethod::ProbabilityRatio),
+        }
+    }
+
     pub fn open_path<P, B>(path: P, tag: Option<B>) -> eyre::Result<Self>
     where
         P: AsRef<Path>,
@@ -109,6 +109,22 @@ pub trait MetadataMutExt {
     fn strand_mut(&mut self) -> &mut Strand {
         &mut self.metadata_mut().strand
     }
+
+    /// Zero-based start of read alignment
+    fn start_mut(&mut self) -> &mut u64 {
+        &mut self.metadata_mut().start
+    }
+
+    /// Read length where nanopolish output ends, see [MetadataExt::np_length]
+    fn length_mut(&mut self) -> &mut u64 {
+        &mut self.metadata_mut().length
+    }
+}
+
+impl MetadataMutExt for Metadata {
+    fn metadata_mut(&mut self) -> &mut Metadata {
+        self
+    }
 }
 
 /// Read orientation relative to a genome
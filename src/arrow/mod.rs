@@ -1,8 +1,10 @@
 pub mod arrow_utils;
 pub mod eventalign;
+pub mod inspect;
 pub mod io;
 pub mod metadata;
 mod mod_bam;
+pub mod posterior;
 pub mod scored_read;
 pub mod signal;
 
@@ -0,0 +1,63 @@
+use arrow2::datatypes::{Field, Schema};
+use arrow2_convert::{field::ArrowField, ArrowField};
+
+use super::metadata::{Metadata, MetadataExt};
+
+/// Posterior probabilities of each scored position of a read being
+/// accessible, written by cawlr sma --posteriors
+#[derive(Debug, Clone, ArrowField, Default)]
+pub struct ReadPosteriors {
+    pub metadata: Metadata,
+    pub posteriors: Vec<Posterior>,
+}
+
+impl ReadPosteriors {
+    pub fn new(metadata: Metadata, posteriors: Vec<Posterior>) -> Self {
+        Self {
+            metadata,
+            posteriors,
+        }
+    }
+
+    /// Schema used for outputing into Arrow file
+    pub fn schema() -> Schema {
+        let data_type = Self::data_type();
+        Schema::from(vec![Field::new("posteriors", data_type, false)])
+    }
+
+    pub fn posteriors(&self) -> &[Posterior] {
+        &self.posteriors
+    }
+}
+
+impl MetadataExt for ReadPosteriors {
+    fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+}
+
+/// Log-likelihoods of a score under the positive (accessible) and negative
+/// control score models, and the posterior probability of the position being
+/// accessible with equal priors
+#[derive(Debug, Clone, ArrowField, Default, PartialEq)]
+pub struct Posterior {
+    pub pos: u64,
+    pub score: f64,
+    pub pos_ln_likelihood: f64,
+    pub neg_ln_likelihood: f64,
+    pub posterior: f64,
+}
+
+impl Posterior {
+    pub fn new(pos: u64, score: f64, pos_ln_likelihood: f64, neg_ln_likelihood: f64) -> Self {
+        // Same as pos_lik / (pos_lik + neg_lik) without underflowing
+        let posterior = 1. / (1. + (neg_ln_likelihood - pos_ln_likelihood).exp());
+        Self {
+            pos,
+            score,
+            pos_ln_likelihood,
+            neg_ln_likelihood,
+            posterior,
+        }
+    }
+}
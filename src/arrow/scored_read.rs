@@ -3,19 +3,27 @@ use arrow2_convert::{field::ArrowField, ArrowField};
 
 use super::{
     eventalign::Eventalign,
-    metadata::{Metadata, MetadataExt},
+    metadata::{Metadata, MetadataExt, MetadataMutExt},
 };
+use crate::{motif::Motif, score::ScoringMethod};
 
 /// Represents a single read scored by cawlr score
 #[derive(Debug, Clone, ArrowField, Default)]
 pub struct ScoredRead {
     pub metadata: Metadata,
     pub scores: Vec<Score>,
+    /// How much information the scores of the read carry, null if the read
+    /// wasn't scored by cawlr score
+    pub quality: Option<ReadQuality>,
 }
 
 impl ScoredRead {
     pub fn new(metadata: Metadata, scores: Vec<Score>) -> Self {
-        ScoredRead { metadata, scores }
+        ScoredRead {
+            metadata,
+            scores,
+            quality: None,
+        }
     }
 
     /// Creates new ScoredRead using metadata from Eventalign output
@@ -33,6 +41,47 @@ impl ScoredRead {
     pub fn scores(&self) -> &[Score] {
         &self.scores
     }
+
+    pub fn with_quality(mut self, quality: ReadQuality) -> Self {
+        self.quality = Some(quality);
+        self
+    }
+
+    /// Stored quality of the read, or computed from its scores if it has none
+    pub fn quality_or_compute(&self, scoring_method: ScoringMethod) -> ReadQuality {
+        self.quality
+            .unwrap_or_else(|| ReadQuality::from_scores(&self.scores, scoring_method))
+    }
+
+    /// Copy of the read with only the scores with positions between start and
+    /// end inclusive, with the read start and length clamped to the region.
+    /// Returns None if the read doesn't overlap the region.
+    pub fn trim_to_region(&self, chrom: &str, start: u64, end: u64) -> Option<ScoredRead> {
+        let read_start = self.start_0b();
+        let read_last = (read_start + self.np_length()).saturating_sub(1);
+        if self.chrom() != chrom || start > read_last || end < read_start {
+            return None;
+        }
+        let new_start = start.max(read_start);
+        let new_last = end.min(read_last);
+
+        let mut metadata = self.metadata.clone();
+        *metadata.start_mut() = new_start;
+        *metadata.length_mut() = new_last - new_start + 1;
+        if !metadata.seq.is_empty() {
+            let offset = (new_start - read_start) as usize;
+            let seq_end = (offset + metadata.seq_length() as usize).min(metadata.seq.len());
+            metadata.seq = metadata.seq.get(offset..seq_end).unwrap_or("").to_string();
+        }
+
+        let scores = self
+            .scores
+            .iter()
+            .filter(|s| (new_start..=new_last).contains(&s.pos))
+            .cloned()
+            .collect();
+        Some(ScoredRead::new(metadata, scores))
+    }
 }
 
 impl MetadataExt for ScoredRead {
@@ -41,7 +90,52 @@ impl MetadataExt for ScoredRead {
     }
 }
 
-#[derive(Default, Debug, Clone, ArrowField)]
+impl MetadataMutExt for ScoredRead {
+    fn metadata_mut(&mut self) -> &mut Metadata {
+        &mut self.metadata
+    }
+}
+
+/// Summary of the scores of a read, to flag reads with little information
+/// such as few scored positions or mostly skipping scores
+#[derive(Debug, Clone, Copy, ArrowField, Default, PartialEq)]
+pub struct ReadQuality {
+    /// Number of positions matching a motif with a score
+    pub n_motif_positions: u64,
+    /// Fraction of those positions with a signal score rather than only a
+    /// skipping score
+    pub frac_signal: f64,
+    /// Mean distance of the probability of modification from 0.5, from 0 for
+    /// uninformative scores to 0.5 for confident ones
+    pub mean_confidence: f64,
+}
+
+impl ReadQuality {
+    /// Quality of scores from the scoring method, fractions and means are 0
+    /// without any scored motif positions
+    pub fn from_scores(scores: &[Score], scoring_method: ScoringMethod) -> Self {
+        let scored = scores
+            .iter()
+            .filter(|s| s.matches_motif && !s.score.is_nan())
+            .collect::<Vec<_>>();
+        if scored.is_empty() {
+            return ReadQuality::default();
+        }
+        let n = scored.len() as f64;
+        let n_signal = scored.iter().filter(|s| s.signal_score.is_some()).count();
+        let total_confidence = scored
+            .iter()
+            .map(|s| (scoring_method.probability(s.score) - 0.5).abs())
+            .sum::<f64>();
+        ReadQuality {
+            n_motif_positions: scored.len() as u64,
+            frac_signal: n_signal as f64 / n,
+            mean_confidence: total_confidence / n,
+        }
+    }
+}
+
+#[derive(Debug, Clone, ArrowField)]
 pub struct Score {
     pub pos: u64,
     pub kmer: String,
@@ -49,6 +143,33 @@ pub struct Score {
     pub signal_score: Option<f64>,
     pub skip_score: f64,
     pub score: f64,
+    /// Neither control has a model for the kmer, see
+    /// [crate::score::ScoreOptions::output_missing_kmers]
+    pub missing_model: bool,
+    /// Log likelihood of the signal under the positive control model, null
+    /// if the position has no signal score
+    pub pos_ln_f: Option<f64>,
+    /// Log likelihood of the signal under the negative control model, null
+    /// if the position has no signal score
+    pub neg_ln_f: Option<f64>,
+    /// Score mapped to a probability of modification by
+    /// [crate::calibrate::Calibration], null if the scores weren't calibrated
+    pub calibrated_score: Option<f64>,
+    /// Whether the position is the modified base of a motif it was scored
+    /// for, only false with [crate::score::ScoreOptions::output_all_positions]
+    pub matches_motif: bool,
+    /// Motif the position was scored for, ie 2:GC. When the modified base of
+    /// more than one motif is at a position, it is scored once for the first
+    /// of them given.
+    pub motif: Option<String>,
+}
+
+/// Unscored position that matches a motif, like [Score::new], so it isn't
+/// dropped by filters on [Score::matches_motif]
+impl Default for Score {
+    fn default() -> Self {
+        Score::new(0, String::new(), false, None, 0.0, 0.0)
+    }
 }
 
 impl Score {
@@ -67,6 +188,152 @@ impl Score {
             signal_score,
             skip_score,
             score,
+            missing_model: false,
+            pos_ln_f: None,
+            neg_ln_f: None,
+            calibrated_score: None,
+            matches_motif: true,
+            motif: None,
+        }
+    }
+
+    /// Keep the log likelihoods of the signal under each control model that
+    /// the signal score was computed from
+    pub fn with_likelihoods(mut self, pos_ln_f: f64, neg_ln_f: f64) -> Self {
+        self.pos_ln_f = Some(pos_ln_f);
+        self.neg_ln_f = Some(neg_ln_f);
+        self
+    }
+
+    pub fn pos_ln_f(&self) -> Option<f64> {
+        self.pos_ln_f
+    }
+
+    pub fn neg_ln_f(&self) -> Option<f64> {
+        self.neg_ln_f
+    }
+
+    /// Set whether the position is the modified base of a scored motif
+    pub fn with_matches_motif(mut self, matches_motif: bool) -> Self {
+        self.matches_motif = matches_motif;
+        self
+    }
+
+    /// Set the motif the position was scored for
+    pub fn with_motif(mut self, motif: Option<&Motif>) -> Self {
+        self.motif = motif.map(Motif::to_string);
+        self
+    }
+
+    pub fn motif(&self) -> Option<&str> {
+        self.motif.as_deref()
+    }
+
+    pub fn calibrated_score(&self) -> Option<f64> {
+        self.calibrated_score
+    }
+
+    /// Position whose kmer isn't in either control model, with NaN scores
+    pub fn missing_model(pos: u64, kmer: String) -> Self {
+        Self {
+            pos,
+            kmer,
+            skipped: true,
+            signal_score: None,
+            skip_score: f64::NAN,
+            score: f64::NAN,
+            missing_model: true,
+            pos_ln_f: None,
+            neg_ln_f: None,
+            calibrated_score: None,
+            matches_motif: true,
+            motif: None,
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::arrow::metadata::Strand;
+
+    fn read_100_to_200() -> ScoredRead {
+        let metadata = Metadata::new(
+            "read".to_string(),
+            "chrI".to_string(),
+            100,
+            101,
+            Strand::plus(),
+            String::new(),
+        );
+        let scores = (100..=200)
+            .map(|pos| Score::new(pos, "AAAAAA".to_string(), false, Some(0.5), 0.5, 0.5))
+            .collect();
+        ScoredRead::new(metadata, scores)
+    }
+
+    #[test]
+    fn test_trim_to_region() {
+        let read = read_100_to_200();
+        let trimmed = read.trim_to_region("chrI", 120, 160).unwrap();
+        let positions: Vec<u64> = trimmed.scores().iter().map(|s| s.pos).collect();
+        assert_eq!(positions, (120..=160).collect::<Vec<_>>());
+        assert_eq!(trimmed.start_0b(), 120);
+        assert_eq!(trimmed.np_length(), 41);
+
+        // Region extends past the end of the read
+        let trimmed = read.trim_to_region("chrI", 150, 300).unwrap();
+        assert_eq!(trimmed.start_0b(), 150);
+        assert_eq!(trimmed.np_length(), 51);
+        assert_eq!(trimmed.scores().len(), 51);
+    }
+
+    #[test]
+    fn test_read_quality() {
+        let scores = vec![
+            Score::new(1, "AAAAAA".to_string(), false, Some(0.9), 0.5, 0.9),
+            Score::new(2, "AAAAAA".to_string(), true, None, 0.3, 0.3),
+            Score::new(3, "AAAAAA".to_string(), false, Some(0.5), 0.5, 0.5),
+            Score::new(4, "AAAAAA".to_string(), false, Some(0.9), 0.5, 0.9)
+                .with_matches_motif(false),
+            Score::missing_model(5, "CCCCCC".to_string()),
+        ];
+        let quality = ReadQuality::from_scores(&scores, ScoringMethod::ProbabilityRatio);
+        assert_eq!(quality.n_motif_positions, 3);
+        assert!((quality.frac_signal - 2. / 3.).abs() < 1e-9);
+        assert!((quality.mean_confidence - 0.2).abs() < 1e-9);
+
+        let quality = ReadQuality::from_scores(&scores[..1], ScoringMethod::LogOdds);
+        assert!((quality.mean_confidence - (1. / (1. + (-0.9f64).exp()) - 0.5)).abs() < 1e-9);
+
+        let read = ScoredRead::new(read_100_to_200().metadata, Vec::new());
+        assert_eq!(
+            read.quality_or_compute(ScoringMethod::ProbabilityRatio),
+            ReadQuality::default()
+        );
+        let read = read.with_quality(quality);
+        assert_eq!(
+            read.quality_or_compute(ScoringMethod::ProbabilityRatio),
+            quality
+        );
+    }
+
+    #[test]
+    fn test_default_score_matches_motif() {
+        let score = Score {
+            score: 0.9,
+            ..Default::default()
+        };
+        assert!(score.matches_motif);
+        let quality = ReadQuality::from_scores(&[score], ScoringMethod::ProbabilityRatio);
+        assert_eq!(quality.n_motif_positions, 1);
+    }
+
+    #[test]
+    fn test_trim_to_region_no_overlap() {
+        let read = read_100_to_200();
+        assert!(read.trim_to_region("chrI", 201, 300).is_none());
+        assert!(read.trim_to_region("chrI", 0, 99).is_none());
+        assert!(read.trim_to_region("chrII", 120, 160).is_none());
+    }
+}
@@ -1,6 +1,10 @@
+use std::ops::RangeInclusive;
+
 use arrow2_convert::ArrowField;
 use rv::traits::ContinuousDistr;
 
+use crate::npsmlr::train::{DEFAULT_SIGNAL_MAX, DEFAULT_SIGNAL_MIN};
+
 #[derive(Debug, Clone, ArrowField, Default, PartialEq)]
 pub struct Signal {
     pub pos: u64,
@@ -27,7 +31,23 @@ impl Signal {
         }
     }
 
+    /// Log likelihood sums of the samples under each model, only using
+    /// samples within the default training range of cawlr npsmlr train
     pub fn score_lnsum<M, N>(&self, pm: &M, nm: &N) -> Option<(f64, f64)>
+    where
+        M: ContinuousDistr<f64>,
+        N: ContinuousDistr<f64>,
+    {
+        self.score_lnsum_in(pm, nm, &(DEFAULT_SIGNAL_MIN..=DEFAULT_SIGNAL_MAX))
+    }
+
+    /// Like [Signal::score_lnsum], only using samples within the range
+    pub fn score_lnsum_in<M, N>(
+        &self,
+        pm: &M,
+        nm: &N,
+        range: &RangeInclusive<f64>,
+    ) -> Option<(f64, f64)>
     where
         M: ContinuousDistr<f64>,
         N: ContinuousDistr<f64>,
@@ -35,7 +55,7 @@ impl Signal {
         let mut samples = self
             .samples
             .iter()
-            .filter(|&x| (&40.0..=&170.0).contains(&x))
+            .filter(|&x| range.contains(x))
             .peekable();
         // If iterator is empty, we just return None
         samples.peek()?;
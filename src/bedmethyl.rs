@@ -0,0 +1,350 @@
+//! Aggregate scored reads into the fraction of reads modified at each
+//! position, written as bedMethyl for tools like methylartist or DSS.
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BinaryHeap},
+    fs::{self, File},
+    io::{BufRead, BufReader, BufWriter, Read, Seek, Write},
+    path::PathBuf,
+};
+
+use arrow2::io::ipc::read::read_file_metadata;
+use eyre::Result;
+
+use crate::{
+    arrow::{arrow_utils::load_apply, metadata::MetadataExt, scored_read::ScoredRead},
+    score::ScoringMethod,
+};
+
+/// Number of positions counted in memory before they are written to a
+/// temporary file, about 1GB of memory
+pub const DEFAULT_MAX_POSITIONS: usize = 10_000_000;
+
+/// Chromosome, position and strand of the reads, ordered like a sorted bed
+/// file
+type Position = (String, u64, char);
+
+/// Number of reads modified and number of reads scored at a position
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Counts {
+    n_mod: u64,
+    n_total: u64,
+}
+
+impl Counts {
+    fn add(&mut self, other: Counts) {
+        self.n_mod += other.n_mod;
+        self.n_total += other.n_total;
+    }
+}
+
+/// Options for writing the output of cawlr score as bedMethyl.
+///
+/// Each position is counted separately for plus, minus and unknown strand
+/// reads. A read is modified at a position if its score is at least the
+/// threshold. Positions without a score or that don't match a motif, see
+/// [crate::score::ScoreOptions::output_all_positions], aren't counted.
+#[derive(Debug, Clone)]
+pub struct BedMethylOptions {
+    threshold: Option<f64>,
+    max_positions: usize,
+    spill_dir: PathBuf,
+}
+
+impl Default for BedMethylOptions {
+    fn default() -> Self {
+        Self {
+            threshold: None,
+            max_positions: DEFAULT_MAX_POSITIONS,
+            spill_dir: std::env::temp_dir(),
+        }
+    }
+}
+
+impl BedMethylOptions {
+    /// Scores at or above this are modified, by default 0.5 for probability
+    /// ratios and 0 for log odds
+    pub fn threshold(&mut self, threshold: Option<f64>) -> &mut Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Write sorted counts to a temporary file once this many positions are
+    /// in memory, the files are merged at the end
+    pub fn max_positions(&mut self, max_positions: usize) -> &mut Self {
+        self.max_positions = max_positions.max(1);
+        self
+    }
+
+    /// Directory for the temporary files, by default the system's temporary
+    /// directory
+    pub fn spill_dir(&mut self, spill_dir: PathBuf) -> &mut Self {
+        self.spill_dir = spill_dir;
+        self
+    }
+
+    /// Count the reads in an Arrow file from cawlr score and write the
+    /// positions to the writer sorted by chromosome and position. Returns
+    /// the number of lines written.
+    pub fn run<R, W>(&self, mut reader: R, mut writer: W) -> Result<usize>
+    where
+        R: Read + Seek,
+        W: Write,
+    {
+        let metadata = read_file_metadata(&mut reader)?;
+        reader.rewind()?;
+        let threshold = match self.threshold {
+            Some(threshold) => threshold,
+            None => match ScoringMethod::from_schema(&metadata.schema)? {
+                ScoringMethod::ProbabilityRatio => 0.5,
+                ScoringMethod::LogOdds => 0.0,
+            },
+        };
+        let mut acc = Accumulator::new(self);
+        load_apply(reader, |reads: Vec<ScoredRead>| {
+            for read in reads.iter() {
+                acc.add_read(read, threshold)?;
+            }
+            Ok(())
+        })?;
+        acc.write(&mut writer)
+    }
+}
+
+/// Counts of the positions in memory, and the temporary files of counts
+/// written once there were too many of them
+struct Accumulator {
+    counts: BTreeMap<Position, Counts>,
+    max_positions: usize,
+    spill_dir: PathBuf,
+    spills: Vec<PathBuf>,
+}
+
+impl Accumulator {
+    fn new(options: &BedMethylOptions) -> Self {
+        Self {
+            counts: BTreeMap::new(),
+            max_positions: options.max_positions,
+            spill_dir: options.spill_dir.clone(),
+            spills: Vec::new(),
+        }
+    }
+
+    fn add_read(&mut self, read: &ScoredRead, threshold: f64) -> Result<()> {
+        let strand = read.strand().as_str().chars().next().unwrap_or('.');
+        for score in read.scores() {
+            if score.score.is_nan() || !score.matches_motif {
+                continue;
+            }
+            let counts = self
+                .counts
+                .entry((read.chrom().to_string(), score.pos, strand))
+                .or_default();
+            counts.add(Counts {
+                n_mod: (score.score >= threshold) as u64,
+                n_total: 1,
+            });
+            if self.counts.len() >= self.max_positions {
+                self.spill()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the counts in memory to a new temporary file, already sorted
+    fn spill(&mut self) -> Result<()> {
+        let path = self.spill_dir.join(format!(
+            "cawlr-bedmethyl-{}-{}.tsv",
+            std::process::id(),
+            self.spills.len()
+        ));
+        // Added first so the file is removed even if writing fails
+        self.spills.push(path.clone());
+        let mut writer = BufWriter::new(File::create(&path)?);
+        for ((chrom, pos, strand), counts) in std::mem::take(&mut self.counts) {
+            writeln!(
+                writer,
+                "{chrom}\t{pos}\t{strand}\t{}\t{}",
+                counts.n_mod, counts.n_total
+            )?;
+        }
+        writer.flush()?;
+        log::debug!("Wrote bedMethyl counts to {}", path.display());
+        Ok(())
+    }
+
+    fn write<W: Write>(mut self, writer: &mut W) -> Result<usize> {
+        if self.spills.is_empty() {
+            let positions = std::mem::take(&mut self.counts);
+            for (pos, counts) in positions.iter() {
+                write_line(writer, pos, counts)?;
+            }
+            return Ok(positions.len());
+        }
+        if !self.counts.is_empty() {
+            self.spill()?;
+        }
+
+        // k-way merge of the sorted files, summing the counts of positions
+        // found in more than one
+        let mut sources = self
+            .spills
+            .iter()
+            .map(|path| Ok(BufReader::new(File::open(path)?).lines()))
+            .collect::<Result<Vec<_>>>()?;
+        let mut heap = BinaryHeap::new();
+        for (idx, source) in sources.iter_mut().enumerate() {
+            if let Some(line) = source.next() {
+                let (pos, counts) = parse_spill_line(&line?)?;
+                heap.push(Reverse((pos, counts, idx)));
+            }
+        }
+        let mut n_lines = 0;
+        let mut current: Option<(Position, Counts)> = None;
+        while let Some(Reverse((pos, counts, idx))) = heap.pop() {
+            if let Some(line) = sources[idx].next() {
+                let (next_pos, next_counts) = parse_spill_line(&line?)?;
+                heap.push(Reverse((next_pos, next_counts, idx)));
+            }
+            if let Some((current_pos, current_counts)) = current.as_mut() {
+                if *current_pos == pos {
+                    current_counts.add(counts);
+                    continue;
+                }
+            }
+            if let Some((prev_pos, prev_counts)) = current.replace((pos, counts)) {
+                write_line(writer, &prev_pos, &prev_counts)?;
+                n_lines += 1;
+            }
+        }
+        if let Some((pos, counts)) = current {
+            write_line(writer, &pos, &counts)?;
+            n_lines += 1;
+        }
+        Ok(n_lines)
+    }
+}
+
+impl Drop for Accumulator {
+    fn drop(&mut self) {
+        for path in self.spills.iter() {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+fn parse_spill_line(line: &str) -> Result<(Position, Counts)> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    if fields.len() != 5 {
+        return Err(eyre::eyre!("Invalid line in bedMethyl counts: {line}"));
+    }
+    let strand = fields[2].chars().next().unwrap_or('.');
+    let counts = Counts {
+        n_mod: fields[3].parse()?,
+        n_total: fields[4].parse()?,
+    };
+    Ok(((fields[0].to_string(), fields[1].parse()?, strand), counts))
+}
+
+/// Line with the 11 bedMethyl columns, the score column is the number of
+/// reads capped at 1000
+fn write_line<W: Write>(writer: &mut W, pos: &Position, counts: &Counts) -> Result<()> {
+    let (chrom, start, strand) = pos;
+    let end = start + 1;
+    let score = counts.n_total.min(1000);
+    let percent = 100. * counts.n_mod as f64 / counts.n_total as f64;
+    writeln!(
+        writer,
+        "{chrom}\t{start}\t{end}\t.\t{score}\t{strand}\t{start}\t{end}\t255,0,0\t{}\t{percent:.2}",
+        counts.n_total
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use assert_fs::TempDir;
+
+    use super::*;
+    use crate::arrow::{
+        arrow_utils::{save, wrap_writer},
+        metadata::{Metadata, Strand},
+        scored_read::Score,
+    };
+
+    fn read(chrom: &str, strand: Strand, scores: &[(u64, f64)]) -> ScoredRead {
+        let metadata = Metadata::new(
+            "read".to_string(),
+            chrom.to_string(),
+            scores[0].0,
+            scores.len() as u64,
+            strand,
+            String::new(),
+        );
+        let scores = scores
+            .iter()
+            .map(|&(pos, score)| {
+                Score::new(pos, "AAAAAA".to_string(), false, Some(score), 0.0, score)
+            })
+            .collect();
+        ScoredRead::new(metadata, scores)
+    }
+
+    fn bedmethyl(options: &BedMethylOptions) -> Result<Vec<String>> {
+        let reads = vec![
+            read("chrII", Strand::plus(), &[(10, 0.9), (11, 0.2)]),
+            read("chrI", Strand::plus(), &[(20, 0.6), (10, 0.1)]),
+            read("chrII", Strand::plus(), &[(10, 0.3), (11, f64::NAN)]),
+            read("chrII", Strand::minus(), &[(10, 0.7)]),
+            read("chrI", Strand::plus(), &[(20, 0.5)]),
+        ];
+        let mut input = Vec::new();
+        let mut writer = wrap_writer(&mut input, &ScoringMethod::ProbabilityRatio.schema())?;
+        save(&mut writer, &reads)?;
+        writer.finish()?;
+
+        let mut output = Vec::new();
+        let n_lines = options.run(Cursor::new(input), &mut output)?;
+        let lines = String::from_utf8(output)?
+            .lines()
+            .map(String::from)
+            .collect::<Vec<_>>();
+        assert_eq!(lines.len(), n_lines);
+        Ok(lines)
+    }
+
+    #[test]
+    fn test_bedmethyl() -> Result<()> {
+        let lines = bedmethyl(&BedMethylOptions::default())?;
+        assert_eq!(
+            lines,
+            vec![
+                "chrI\t10\t11\t.\t1\t+\t10\t11\t255,0,0\t1\t0.00",
+                "chrI\t20\t21\t.\t2\t+\t20\t21\t255,0,0\t2\t100.00",
+                "chrII\t10\t11\t.\t2\t+\t10\t11\t255,0,0\t2\t50.00",
+                "chrII\t10\t11\t.\t1\t-\t10\t11\t255,0,0\t1\t100.00",
+                "chrII\t11\t12\t.\t1\t+\t11\t12\t255,0,0\t1\t0.00",
+            ]
+        );
+
+        let lines = bedmethyl(BedMethylOptions::default().threshold(Some(0.55)))?;
+        assert!(lines[1].ends_with("\t2\t50.00"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_bedmethyl_spill() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let in_memory = bedmethyl(&BedMethylOptions::default())?;
+        let spilled = bedmethyl(
+            BedMethylOptions::default()
+                .max_positions(2)
+                .spill_dir(temp_dir.path().to_path_buf()),
+        )?;
+        assert_eq!(in_memory, spilled);
+        assert_eq!(fs::read_dir(temp_dir.path())?.count(), 0);
+        Ok(())
+    }
+}
@@ -1,5 +1,5 @@
 use std::{
-    fs::{self, File},
+    fs::File,
     io::{BufRead, BufReader, BufWriter, Write},
     path::PathBuf,
     process::Command,
@@ -9,11 +9,11 @@ mod convert_detection;
 
 use clap::Parser;
 use libcawlr::{
-    agg_blocks,
+    agg_blocks::AggOptions,
     motif::all_bases,
     region::Region,
     sma::SmaOptions,
-    utils::{parse_name_from_output_dir, wrap_cmd},
+    utils::{self, parse_name_from_output_dir, wrap_cmd},
 };
 use log::LevelFilter;
 
@@ -23,8 +23,10 @@ struct Args {
     #[clap(short, long)]
     detection: PathBuf,
 
+    /// Bam files to add strand information from, can be given multiple
+    /// times for sharded bam files
     #[clap(short, long)]
-    bam: Option<PathBuf>,
+    bam: Vec<PathBuf>,
 
     /// Locus to filter on, in form of {chrom}:{start}-{end}
     #[clap(short, long)]
@@ -87,10 +89,7 @@ fn convert_chrom(s: &str) -> eyre::Result<&str> {
 fn main() -> eyre::Result<()> {
     let args = Args::parse();
 
-    if args.overwrite && args.output_dir.exists() {
-        fs::remove_dir_all(&args.output_dir)?;
-    }
-    fs::create_dir_all(&args.output_dir)?;
+    utils::prepare_output_dir(&args.output_dir, args.overwrite)?;
 
     let log_file = args.output_dir.join("log.txt");
     simple_logging::log_to_file(log_file, LevelFilter::Info)?;
@@ -136,7 +135,9 @@ fn main() -> eyre::Result<()> {
     })?;
 
     let agg_output = args.output_dir.join(format!("{}.tsv", track_name));
-    wrap_cmd("Aggregating", || agg_blocks::run(&sma, Some(&agg_output)))?;
+    wrap_cmd("Aggregating", || {
+        AggOptions::default().run(&sma, File::create(&agg_output)?)
+    })?;
 
     wrap_cmd("Clustering reads", || {
         let mut cmd = Command::new("cluster_region.py");
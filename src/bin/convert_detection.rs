@@ -13,7 +13,7 @@ use libcawlr::{
         metadata::{Metadata, MetadataExt, Strand},
         scored_read::{Score, ScoredRead},
     },
-    plus_strand_map::PlusStrandMap,
+    strand_map::StrandMap,
 };
 use serde::Deserialize;
 
@@ -23,9 +23,10 @@ struct Args {
     #[clap(short, long)]
     input: PathBuf,
 
-    /// bam file for adding strand information
+    /// bam files for adding strand information, can be given multiple times
+    /// for sharded bam files
     #[clap(short, long)]
-    bam: Option<PathBuf>,
+    bam: Vec<PathBuf>,
 
     /// Arrow file for use in cawlr sma
     #[clap(short, long)]
@@ -38,8 +39,8 @@ struct DetectionLine {
     pos: u64,
     kmer: String,
     read_name: String,
-    _pos_log_prob: f64,
-    _neg_log_prob: f64,
+    pos_log_prob: f64,
+    neg_log_prob: f64,
     score: f64,
 }
 
@@ -49,17 +50,24 @@ impl DetectionLine {
     }
 }
 
-fn convert_to_read(dlines: &[DetectionLine]) -> ScoredRead {
+/// Read with the scores of its detection lines, and its strand from the bam
+/// files if it's in them
+fn convert_to_read(dlines: &[DetectionLine], strand_map: &StrandMap) -> ScoredRead {
     let chrom = dlines[0].chrom.clone();
     let read_name = dlines[0].read_name.clone();
     let start = dlines.iter().map(|dline| dline.pos).min().unwrap();
     let end = dlines.iter().map(|dline| dline.pos).max().unwrap();
+    let strand = match strand_map.get_plus_stranded(&read_name) {
+        Some(true) => Strand::plus(),
+        Some(false) => Strand::minus(),
+        None => Strand::unknown(),
+    };
     let meta = Metadata::new(
         read_name,
         chrom,
         start,
         end - start + 1,
-        Strand::unknown(),
+        strand,
         String::new(),
     );
     let scores: Vec<Score> = dlines
@@ -73,19 +81,14 @@ fn convert_to_read(dlines: &[DetectionLine]) -> ScoredRead {
                 0.0,
                 dline.score,
             )
+            .with_likelihoods(dline.pos_log_prob, dline.neg_log_prob)
         })
         .collect();
     ScoredRead::new(meta, scores)
 }
 
-pub fn run(input: &Path, bam: &Option<PathBuf>, output: &Path) -> eyre::Result<()> {
-    let strand_map = {
-        if let Some(bam_file) = bam {
-            PlusStrandMap::from_bam_file(bam_file)?
-        } else {
-            PlusStrandMap::default()
-        }
-    };
+pub fn run(input: &Path, bams: &[PathBuf], output: &Path) -> eyre::Result<()> {
+    let strand_map = StrandMap::from_multiple_bam_files(bams.iter())?;
 
     let style = ProgressStyle::with_template("[{elapsed_precise}] [{binary_bytes_per_sec}] {msg}")?;
     let pb = ProgressBar::new_spinner().with_style(style);
@@ -107,21 +110,14 @@ pub fn run(input: &Path, bam: &Option<PathBuf>, output: &Path) -> eyre::Result<(
         if dline.read_name() == curr_read {
             acc.push(dline);
         } else {
-            let mut read = convert_to_read(&acc);
-            if let Some(plus_stranded) = strand_map.get(read.name()) {
-                read.metadata.strand = if plus_stranded {
-                    Strand::plus()
-                } else {
-                    Strand::minus()
-                };
-            }
+            let read = convert_to_read(&acc, &strand_map);
             save(&mut writer, &[read])?;
             curr_read = dline.read_name().to_owned();
             acc = vec![dline];
         }
     }
     if !acc.is_empty() {
-        let read = convert_to_read(&acc);
+        let read = convert_to_read(&acc, &strand_map);
         save(&mut writer, &[read])?;
     }
     writer.finish()?;
@@ -135,3 +131,60 @@ fn main() -> Result<(), Box<dyn Error>> {
     run(&args.input, &args.bam, &args.output)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use assert_fs::TempDir;
+    use libcawlr::arrow::arrow_utils::load_apply;
+
+    use super::*;
+
+    #[test]
+    fn test_strands() -> eyre::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let input = temp_dir.path().join("detection.txt");
+        let output = temp_dir.path().join("converted.arrow");
+        std::fs::write(
+            &input,
+            "chrXIII\t182550\tGCAAAA\t20d1aac0-29de-43ae-a0ef-aa8a6766eb70\t-1.0\t-2.0\t0.7\n\
+             chrXIII\t182560\tGCAAAA\t20d1aac0-29de-43ae-a0ef-aa8a6766eb70\t-1.0\t-2.0\t0.7\n\
+             chrI\t1000\tGCAAAA\tnot_in_bam\t-2.0\t-1.0\t0.3\n\
+             chrII\t2000\tGCAAAA\tca10c9e3-61d4-439b-abb3-078767d19f8c\t-2.0\t-1.0\t0.3\n",
+        )?;
+        let bams = [
+            PathBuf::from("extra/single_read.bam"),
+            PathBuf::from("extra/pos_control.bam"),
+        ];
+        run(&input, &bams, &output)?;
+
+        let mut strands = Vec::new();
+        load_apply(
+            Cursor::new(std::fs::read(&output)?),
+            |reads: Vec<ScoredRead>| {
+                strands.extend(
+                    reads
+                        .iter()
+                        .map(|read| (read.name().to_string(), read.strand())),
+                );
+                Ok(())
+            },
+        )?;
+        assert_eq!(
+            strands,
+            vec![
+                (
+                    "20d1aac0-29de-43ae-a0ef-aa8a6766eb70".to_string(),
+                    Strand::plus()
+                ),
+                ("not_in_bam".to_string(), Strand::unknown()),
+                (
+                    "ca10c9e3-61d4-439b-abb3-078767d19f8c".to_string(),
+                    Strand::minus()
+                ),
+            ]
+        );
+        Ok(())
+    }
+}
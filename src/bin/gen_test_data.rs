@@ -35,7 +35,7 @@ fn train(input: &PathBuf, output: &PathBuf, is_neg_ctrl: bool) -> Result<Model>
             Motif::from_str("1:TA").unwrap(),
         ])
         .single(is_neg_ctrl);
-    let model = train.run_model(input)?;
+    let (model, _report) = train.run_model(input)?;
     model.save_as(output)?;
     Ok(model)
 }
@@ -60,15 +60,9 @@ fn score(
         Motif::from_str("2:AT").unwrap(),
         Motif::from_str("1:TA").unwrap(),
     ];
-    let score_opts = npsmlr::ScoreOptions::new(
-        pos_model.clone(),
-        neg_model.clone(),
-        ranks.clone(),
-        10,
-        10.0,
-        motifs,
-    );
-    score_opts.run(reader, writer)?;
+    npsmlr::ScoreOptions::from_models(pos_model.clone(), neg_model.clone(), ranks.clone())
+        .motifs(motifs)
+        .run(reader, writer)?;
     Ok(())
 }
 
@@ -94,9 +88,9 @@ fn main() -> Result<()> {
     let pos_ctrl = File::open(POS_CTRL)?;
     let read = File::open(READ)?;
 
-    CollapseOptions::try_new(NEG_CTRL_BAM, &neg_output)?.run(neg_ctrl)?;
-    CollapseOptions::try_new(POS_CTRL_BAM, &pos_output)?.run(pos_ctrl)?;
-    CollapseOptions::try_new(READ_BAM, &read_output)?.run(read)?;
+    CollapseOptions::try_new(NEG_CTRL_BAM, Some(&neg_output))?.run(neg_ctrl)?;
+    CollapseOptions::try_new(POS_CTRL_BAM, Some(&pos_output))?.run(pos_ctrl)?;
+    CollapseOptions::try_new(READ_BAM, Some(&read_output))?.run(read)?;
 
     let pos_model_path = output_dir.join("pos_model");
     let neg_model_path = output_dir.join("neg_model");
@@ -5,22 +5,53 @@ use rv::misc::linspace;
 use serde::{Deserialize, Serialize};
 use serde_pickle::from_reader;
 
-use crate::utils::CawlrIO;
+use crate::{score::ScoringMethod, utils::CawlrIO};
+
+/// Models saved before the scoring method was recorded only used probability
+/// ratios, which are always between 0 and 1
+fn default_range() -> (f64, f64) {
+    (0., 1.)
+}
 
 #[derive(Serialize, Deserialize)]
 pub struct BinnedKde {
     bins: Vec<f64>,
+    #[serde(default)]
+    scoring_method: ScoringMethod,
+    #[serde(default = "default_range")]
+    range: (f64, f64),
 }
 
 impl BinnedKde {
-    fn new(bins: Vec<f64>) -> Self {
-        Self { bins }
+    #[cfg(test)]
+    pub(crate) fn new(bins: Vec<f64>) -> Self {
+        Self {
+            bins,
+            scoring_method: ScoringMethod::ProbabilityRatio,
+            range: default_range(),
+        }
     }
 
     pub(crate) fn from_kde(n_bins: i32, kde: &Kde<f64, Gaussian>) -> Self {
+        Self::from_kde_with(
+            n_bins,
+            kde,
+            ScoringMethod::ProbabilityRatio,
+            default_range(),
+        )
+    }
+
+    /// Bin the kde over the range of scores, used for scoring methods that
+    /// aren't bounded between 0 and 1.
+    pub(crate) fn from_kde_with(
+        n_bins: i32,
+        kde: &Kde<f64, Gaussian>,
+        scoring_method: ScoringMethod,
+        range: (f64, f64),
+    ) -> Self {
         // TODO explore using a different linspace implementation, only want positive
         // values
-        let mut bins: Vec<f64> = linspace(0., 1., n_bins)
+        let mut bins: Vec<f64> = linspace(range.0, range.1, n_bins)
             .into_iter()
             // Add minimum positive values to avoid zero values in PMF -> logarithm errors
             .map(|x| kde.estimate(x) + f64::MIN_POSITIVE)
@@ -28,14 +59,58 @@ impl BinnedKde {
         let total: f64 = bins.iter().sum();
         // Normalize so area approximately sums to 1
         bins.iter_mut().for_each(|x| *x /= total);
-        BinnedKde::new(bins)
+        Self {
+            bins,
+            scoring_method,
+            range,
+        }
     }
 
+    /// Scoring method of the scores the kde was built from
+    pub fn scoring_method(&self) -> ScoringMethod {
+        self.scoring_method
+    }
+
+    /// Scores outside of the binned range use the closest bin
     pub(crate) fn pmf_from_score(&self, x: f64) -> f64 {
-        let idx = x * (self.bins.len() - 1) as f64;
-        let idx = idx.round() as usize;
+        let (lo, hi) = self.range;
+        let last = (self.bins.len() - 1) as f64;
+        let idx = (x - lo) / (hi - lo) * last;
+        let idx = idx.round().max(0.).min(last) as usize;
         self.bins[idx]
     }
+
+    /// Scores at each quantile in qs, found by inverting the cumulative
+    /// distribution of the bins and interpolating between bins. Quantiles are
+    /// expected to be between 0 and 1, scores are within the binned range.
+    pub fn percentiles(&self, qs: &[f64]) -> Vec<f64> {
+        let cdf = self
+            .bins
+            .iter()
+            .scan(0.0, |acc, x| {
+                *acc += x;
+                Some(*acc)
+            })
+            .collect::<Vec<_>>();
+        let total = cdf.last().copied().unwrap_or(0.0);
+        let (start, end) = self.range;
+        let step = (end - start) / (self.bins.len().max(2) - 1) as f64;
+        qs.iter()
+            .map(|&q| {
+                let q = q * total;
+                let idx = cdf.partition_point(|&c| c < q);
+                if idx == 0 {
+                    start
+                } else if idx >= cdf.len() {
+                    end
+                } else {
+                    let (lo, hi) = (cdf[idx - 1], cdf[idx]);
+                    let frac = if hi > lo { (q - lo) / (hi - lo) } else { 0.0 };
+                    start + (idx as f64 - 1.0 + frac) * step
+                }
+            })
+            .collect()
+    }
 }
 
 impl CawlrIO for BinnedKde {
@@ -97,4 +172,44 @@ mod test {
             }
         }
     }
+
+    #[test]
+    fn test_bkde_log_odds_range() {
+        let mut rng = SmallRng::seed_from_u64(1234);
+        let gauss = rv::prelude::Gaussian::new_unchecked(0.0, 2.0);
+        let samples: Vec<f64> = gauss.sample(1000, &mut rng);
+        let samples = Sample::new(&samples);
+        let kde = Kde::new(samples, Gaussian, Bandwidth::Silverman);
+        let bkde = BinnedKde::from_kde_with(1000, &kde, ScoringMethod::LogOdds, (-8.0, 8.0));
+        assert_eq!(bkde.scoring_method(), ScoringMethod::LogOdds);
+
+        // Scores outside of the range use the edge bins
+        assert_eq!(bkde.pmf_from_score(-100.0), bkde.pmf_from_score(-8.0));
+        assert_eq!(bkde.pmf_from_score(100.0), bkde.pmf_from_score(8.0));
+        assert!(bkde.pmf_from_score(0.0) > bkde.pmf_from_score(4.0));
+
+        let ps = bkde.percentiles(&[0.0, 0.5, 1.0]);
+        assert_float_eq!(ps[0], -8.0, abs <= 1e-9);
+        assert_float_eq!(ps[1], 0.0, abs <= 0.3);
+        assert_float_eq!(ps[2], 8.0, abs <= 1e-9);
+    }
+
+    #[test]
+    fn test_percentiles() {
+        let bkde = BinnedKde::new(vec![0.001; 1001]);
+        let ps = bkde.percentiles(&[0.0, 0.05, 0.5, 0.95, 1.0]);
+        assert_float_eq!(ps[0], 0.0, abs <= 1e-9);
+        assert_float_eq!(ps[1], 0.05, abs <= 0.002);
+        assert_float_eq!(ps[2], 0.5, abs <= 0.002);
+        assert_float_eq!(ps[3], 0.95, abs <= 0.002);
+        assert_float_eq!(ps[4], 1.0, abs <= 1e-9);
+
+        // All of the mass in the upper half
+        let mut bins = vec![f64::MIN_POSITIVE; 500];
+        bins.extend(vec![0.002; 500]);
+        let bkde = BinnedKde::new(bins);
+        let ps = bkde.percentiles(&[0.25, 0.5, 0.75]);
+        assert!(ps.windows(2).all(|w| w[0] < w[1]));
+        assert_float_eq!(ps[1], 0.75, abs <= 0.002);
+    }
 }
@@ -0,0 +1,291 @@
+use std::{
+    fs::File,
+    io::{Read, Seek, Write},
+    path::Path,
+};
+
+use arrow2::io::ipc::read::read_file_metadata;
+use eyre::{Context as _, Result};
+
+use crate::{
+    arrow::{
+        arrow_utils::{load_apply, save, wrap_writer},
+        scored_read::ScoredRead,
+    },
+    score::ScoringMethod,
+};
+
+/// Monotonic mapping from scores to the probability that a position is
+/// modified, fit with isotonic regression on scores from a positive and a
+/// negative control.
+///
+/// Both controls are weighted equally regardless of how many scores they
+/// have, so the probabilities assume half of the positions are modified.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Calibration {
+    // Mean score of each block of the fit, increasing
+    xs: Vec<f64>,
+    // Weighted fraction of each block from the positive control,
+    // non-decreasing
+    ys: Vec<f64>,
+}
+
+/// Run of consecutive scores pooled together by the isotonic regression
+struct Block {
+    sum_x: f64,
+    sum_y: f64,
+    weight: f64,
+    max_x: f64,
+}
+
+impl Block {
+    fn new(x: f64, y: f64, weight: f64) -> Self {
+        Self {
+            sum_x: x * weight,
+            sum_y: y * weight,
+            weight,
+            max_x: x,
+        }
+    }
+
+    fn merge(&mut self, other: Block) {
+        self.sum_x += other.sum_x;
+        self.sum_y += other.sum_y;
+        self.weight += other.weight;
+        self.max_x = self.max_x.max(other.max_x);
+    }
+
+    fn mean_x(&self) -> f64 {
+        self.sum_x / self.weight
+    }
+
+    fn mean_y(&self) -> f64 {
+        self.sum_y / self.weight
+    }
+}
+
+impl Calibration {
+    /// Fit the calibration with the pool adjacent violators algorithm,
+    /// scores that aren't finite are ignored.
+    pub fn fit(pos_scores: &[f64], neg_scores: &[f64]) -> Result<Self> {
+        let pos: Vec<f64> = pos_scores
+            .iter()
+            .copied()
+            .filter(|x| x.is_finite())
+            .collect();
+        let neg: Vec<f64> = neg_scores
+            .iter()
+            .copied()
+            .filter(|x| x.is_finite())
+            .collect();
+        if pos.is_empty() || neg.is_empty() {
+            return Err(eyre::eyre!(
+                "Both controls need at least one finite score for calibration"
+            ));
+        }
+        let pos_weight = 1. / pos.len() as f64;
+        let neg_weight = 1. / neg.len() as f64;
+        let mut points: Vec<(f64, f64, f64)> = pos
+            .into_iter()
+            .map(|x| (x, 1., pos_weight))
+            .chain(neg.into_iter().map(|x| (x, 0., neg_weight)))
+            .collect();
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut blocks: Vec<Block> = Vec::new();
+        for (x, y, weight) in points {
+            let point = Block::new(x, y, weight);
+            // Equal scores have to end up with the same probability
+            match blocks.last_mut() {
+                Some(last) if last.max_x == x => last.merge(point),
+                _ => blocks.push(point),
+            }
+            while blocks.len() > 1 {
+                let n = blocks.len();
+                if blocks[n - 2].mean_y() <= blocks[n - 1].mean_y() {
+                    break;
+                }
+                let last = blocks.pop().unwrap();
+                blocks[n - 2].merge(last);
+            }
+        }
+        Ok(Self {
+            xs: blocks.iter().map(Block::mean_x).collect(),
+            ys: blocks.iter().map(Block::mean_y).collect(),
+        })
+    }
+
+    /// Fit the calibration on the scores in Arrow files from cawlr score of
+    /// a positive and negative control
+    pub fn from_controls<P: AsRef<Path>>(pos_ctrl: P, neg_ctrl: P) -> Result<Self> {
+        let pos_scores = control_scores(pos_ctrl.as_ref())?;
+        let neg_scores = control_scores(neg_ctrl.as_ref())?;
+        log::info!(
+            "Calibrating on {} positive and {} negative control scores",
+            pos_scores.len(),
+            neg_scores.len()
+        );
+        Calibration::fit(&pos_scores, &neg_scores)
+    }
+
+    /// Probability that a position with this score is modified, interpolated
+    /// linearly between the blocks of the fit. NaN scores stay NaN.
+    pub fn apply(&self, score: f64) -> f64 {
+        if score.is_nan() {
+            return f64::NAN;
+        }
+        let idx = self.xs.partition_point(|&x| x < score);
+        if idx == 0 {
+            self.ys[0]
+        } else if idx == self.xs.len() {
+            self.ys[idx - 1]
+        } else {
+            let (x0, x1) = (self.xs[idx - 1], self.xs[idx]);
+            let (y0, y1) = (self.ys[idx - 1], self.ys[idx]);
+            y0 + (y1 - y0) * (score - x0) / (x1 - x0)
+        }
+    }
+
+    /// Set the calibrated score of every position in the read with a score
+    pub fn calibrate_read(&self, read: &mut ScoredRead) {
+        for score in read.scores.iter_mut() {
+            score.calibrated_score = (!score.score.is_nan()).then(|| self.apply(score.score));
+        }
+    }
+
+    /// Rewrite an Arrow file from cawlr score with calibrated scores, keeping
+    /// its scoring method. Returns the number of reads written.
+    pub fn run<R, W>(&self, mut reader: R, writer: W) -> Result<usize>
+    where
+        R: Read + Seek,
+        W: Write,
+    {
+        let metadata = read_file_metadata(&mut reader)?;
+        reader.rewind()?;
+        let scoring_method = ScoringMethod::from_schema(&metadata.schema)?;
+        let mut writer = wrap_writer(writer, &scoring_method.schema())?;
+        let mut n_reads = 0;
+        load_apply(reader, |mut reads: Vec<ScoredRead>| {
+            for read in reads.iter_mut() {
+                self.calibrate_read(read);
+            }
+            n_reads += reads.len();
+            save(&mut writer, &reads)
+        })?;
+        writer.finish()?;
+        Ok(n_reads)
+    }
+}
+
+/// Finite scores of every position in a scored Arrow file
+fn control_scores(path: &Path) -> Result<Vec<f64>> {
+    let file = File::open(path).wrap_err_with(|| format!("Failed to open {}", path.display()))?;
+    let mut scores = Vec::new();
+    load_apply(file, |reads: Vec<ScoredRead>| {
+        for read in reads {
+            scores.extend(
+                read.scores()
+                    .iter()
+                    .map(|s| s.score)
+                    .filter(|x| x.is_finite()),
+            );
+        }
+        Ok(())
+    })?;
+    Ok(scores)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use float_eq::assert_float_eq;
+
+    use super::*;
+    use crate::arrow::{
+        metadata::{Metadata, Strand},
+        scored_read::Score,
+    };
+
+    #[test]
+    fn test_fit() {
+        let pos = [0.7, 0.8, 0.9, 0.95];
+        let neg = [0.1, 0.2, 0.3, 0.7];
+        let calibration = Calibration::fit(&pos, &neg).unwrap();
+        assert_eq!(calibration.apply(0.0), 0.0);
+        assert_eq!(calibration.apply(0.2), 0.0);
+        assert_eq!(calibration.apply(0.9), 1.0);
+        assert_eq!(calibration.apply(1.0), 1.0);
+        // Tied scores from both controls
+        assert_float_eq!(calibration.apply(0.7), 0.5, abs <= 1e-9);
+        assert_float_eq!(calibration.apply(0.75), 0.75, abs <= 1e-9);
+        assert!(calibration.apply(f64::NAN).is_nan());
+
+        let xs = (0..=100).map(|i| i as f64 / 100.).collect::<Vec<_>>();
+        let ys = xs.iter().map(|&x| calibration.apply(x)).collect::<Vec<_>>();
+        assert!(ys.windows(2).all(|w| w[0] <= w[1]));
+        assert!(ys.iter().all(|y| (0.0..=1.0).contains(y)));
+
+        assert!(Calibration::fit(&pos, &[f64::NAN]).is_err());
+    }
+
+    #[test]
+    fn test_fit_unbalanced() {
+        // Out of order scores from the negative control are pooled with the
+        // positive control scores around them
+        let pos = [0.2, 0.8, 0.9];
+        let neg = [0.1, 0.3, 0.3, 0.3, 0.85];
+        let calibration = Calibration::fit(&pos, &neg).unwrap();
+        assert_eq!(calibration.ys.len(), calibration.xs.len());
+        assert!(calibration.ys.windows(2).all(|w| w[0] <= w[1]));
+        assert!(calibration.xs.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(calibration.apply(0.1), 0.0);
+        assert_eq!(calibration.apply(0.95), 1.0);
+    }
+
+    #[test]
+    fn test_run() -> Result<()> {
+        let metadata = Metadata::new(
+            "read".to_string(),
+            "chrI".to_string(),
+            0,
+            100,
+            Strand::plus(),
+            String::new(),
+        );
+        let scores = vec![
+            Score::new(1, "AAAAAA".to_string(), false, Some(0.9), 0.0, 0.9),
+            Score::new(2, "AAAAAA".to_string(), false, Some(0.1), 0.0, 0.1),
+            Score::missing_model(3, "CCCCCC".to_string()),
+        ];
+        let mut input = Vec::new();
+        let mut writer = wrap_writer(&mut input, &ScoringMethod::LogOdds.schema())?;
+        save(&mut writer, &[ScoredRead::new(metadata, scores)])?;
+        writer.finish()?;
+
+        let calibration = Calibration::fit(&[0.8, 0.9], &[0.1, 0.2])?;
+        let mut output = Vec::new();
+        let n_reads = calibration.run(Cursor::new(input), &mut output)?;
+        assert_eq!(n_reads, 1);
+
+        let mut output = Cursor::new(output);
+        let metadata = read_file_metadata(&mut output)?;
+        assert_eq!(
+            ScoringMethod::from_schema(&metadata.schema)?,
+            ScoringMethod::LogOdds
+        );
+        output.rewind()?;
+        let mut reads = Vec::new();
+        load_apply(output, |mut rs: Vec<ScoredRead>| {
+            reads.append(&mut rs);
+            Ok(())
+        })?;
+        let calibrated = reads[0]
+            .scores()
+            .iter()
+            .map(|s| s.calibrated_score())
+            .collect::<Vec<_>>();
+        assert_eq!(calibrated, vec![Some(1.0), Some(0.0), None]);
+        Ok(())
+    }
+}
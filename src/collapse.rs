@@ -1,17 +1,20 @@
 use std::{
     fs::File,
-    io::{BufWriter, Read, Write},
+    io::{self, BufWriter, IsTerminal, Read, Write},
     path::Path,
     time::Duration,
 };
 
 use arrow2::io::ipc::write::FileWriter;
 use bio::alphabets::dna::revcomp;
+use csv::StringRecord;
 use eyre::Result;
+use fnv::FnvHashSet;
 use indicatif::{ProgressBar, ProgressBarIter, ProgressFinish, ProgressStyle};
 use serde::Deserialize;
 use serde_with::{formats::CommaSeparator, serde_as, StringWithSeparator};
 use statrs::statistics::Statistics;
+use tokio::io::AsyncReadExt;
 
 use crate::{
     arrow::{
@@ -20,7 +23,7 @@ use crate::{
         metadata::{Metadata, MetadataExt, Strand},
         signal::Signal,
     },
-    plus_strand_map::PlusStrandMap,
+    strand_map::StrandMap,
 };
 
 fn empty_from_npr(npr: Npr) -> Eventalign {
@@ -44,7 +47,7 @@ fn empty_from_npr(npr: Npr) -> Eventalign {
 /// Takes a vector of nanpolish records and converts them into a Eventalign.
 fn nprs_to_eventalign(
     mut nprs: impl Iterator<Item = Npr>,
-    strand_map: &PlusStrandMap,
+    strand_map: &StrandMap,
 ) -> Result<Option<Eventalign>> {
     let mut eventalign = nprs
         .next()
@@ -67,7 +70,7 @@ fn nprs_to_eventalign(
     }
 
     // Update strand from bam file results
-    let strand = strand_map.get(eventalign.name());
+    let strand = strand_map.get_plus_stranded(eventalign.name());
     if let Some(b) = strand {
         eventalign.metadata.strand = if b { Strand::plus() } else { Strand::minus() };
     } else {
@@ -99,49 +102,121 @@ fn nprs_to_eventalign(
     Ok(Some(eventalign))
 }
 
-/// Create spinner that wraps an IO read iterator
-fn spin_iter<I: Read>(iter: I, show_progress: bool) -> ProgressBarIter<I> {
-    let pb = if show_progress {
-        ProgressBar::new_spinner()
-    } else {
-        ProgressBar::hidden()
+/// Create spinner tracking the number of bytes read. If the input length is
+/// known, the ETA is shown as well.
+fn progress_bar(show_progress: bool, input_len: Option<u64>) -> ProgressBar {
+    let pb = match (show_progress, input_len) {
+        (false, _) => ProgressBar::hidden(),
+        (true, Some(len)) => ProgressBar::new(len),
+        (true, None) => ProgressBar::new_spinner(),
     };
     pb.enable_steady_tick(Duration::from_millis(100));
-    let style = ProgressStyle::default_spinner()
-        .template("{spinner} [{elapsed_precise}] {binary_bytes_per_sec} {msg}")
-        .unwrap();
+    let template = if input_len.is_some() {
+        "{spinner} [{elapsed_precise}] {binary_bytes}/{binary_total_bytes} \
+         {binary_bytes_per_sec} (ETA {eta}) {msg}"
+    } else {
+        "{spinner} [{elapsed_precise}] {binary_bytes} {binary_bytes_per_sec} {msg}"
+    };
+    let style = ProgressStyle::default_spinner().template(template).unwrap();
     pb.with_message("Processing eventalign data")
         .with_style(style)
         .with_finish(ProgressFinish::AndLeave)
-        .wrap_read(iter)
+}
+
+/// Create spinner that wraps an IO read iterator, see [progress_bar]
+fn spin_iter<I: Read>(iter: I, show_progress: bool, input_len: Option<u64>) -> ProgressBarIter<I> {
+    progress_bar(show_progress, input_len).wrap_read(iter)
+}
+
+/// Size of the buffer used to read the input with [CollapseOptions::run_async]
+const ASYNC_READ_CAPACITY: usize = 1 << 20;
+
+/// Reads a file with tokio's async file I/O on a single threaded runtime,
+/// blocking on each read, so [CollapseOptions::run_async] parses it with the
+/// same csv reader as [CollapseOptions::run]
+struct AsyncFileReader {
+    runtime: tokio::runtime::Runtime,
+    file: tokio::io::BufReader<tokio::fs::File>,
+}
+
+impl AsyncFileReader {
+    fn open(path: &Path) -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_current_thread().build()?;
+        let file = runtime.block_on(tokio::fs::File::open(path))?;
+        let file = tokio::io::BufReader::with_capacity(ASYNC_READ_CAPACITY, file);
+        Ok(Self { runtime, file })
+    }
+}
+
+impl Read for AsyncFileReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.runtime.block_on(self.file.read(buf))
+    }
+}
+
+/// Whether the record is on the chromosome being filtered for, if any
+fn on_chrom(chrom_filter: Option<&str>, contig_idx: Option<usize>, record: &StringRecord) -> bool {
+    match chrom_filter {
+        Some(chrom) => contig_idx.and_then(|idx| record.get(idx)) == Some(chrom),
+        None => true,
+    }
+}
+
+/// Records of the read currently being collapsed, and the finished reads
+/// waiting to be written
+#[derive(Default)]
+struct Pending {
+    acc: Vec<Npr>,
+    flats: Vec<Eventalign>,
+    position: u64,
+    idx_diff: u64,
 }
 
 pub struct CollapseOptions<W: Write> {
     writer: FileWriter<W>,
-    strand_db: PlusStrandMap,
+    strand_db: StrandMap,
     capacity: usize,
     progress: bool,
+    input_len: Option<u64>,
+    min_read_length: Option<u64>,
+    deduplicate: bool,
+    seen_reads: FnvHashSet<String>,
+    n_duplicates: usize,
+    chrom_filter: Option<String>,
+    region_filter: (Option<u64>, Option<u64>),
 }
 
-impl CollapseOptions<BufWriter<File>> {
-    pub fn try_new<Q, R>(bam_file: Q, output: R) -> Result<Self>
+impl CollapseOptions<Box<dyn Write>> {
+    /// Write to the output file, or to stdout if no output is given. The
+    /// Arrow IPC format is self-framing so the output can be piped into
+    /// another process.
+    pub fn try_new<Q, R>(bam_file: Q, output: Option<R>) -> Result<Self>
     where
         Q: AsRef<Path>,
         R: AsRef<Path>,
     {
-        let writer = File::create(output)?;
-        let writer = BufWriter::new(writer);
+        let writer: Box<dyn Write> = match output {
+            Some(output) => Box::new(BufWriter::new(File::create(output)?)),
+            None => Box::new(BufWriter::new(io::stdout())),
+        };
         CollapseOptions::from_writer(writer, bam_file)
     }
 }
 
 impl<W: Write> CollapseOptions<W> {
-    fn new(writer: FileWriter<W>, strand_db: PlusStrandMap) -> Self {
+    fn new(writer: FileWriter<W>, strand_db: StrandMap) -> Self {
         Self {
             writer,
             strand_db,
             capacity: 2048,
-            progress: false,
+            progress: io::stderr().is_terminal(),
+            input_len: None,
+            min_read_length: None,
+            deduplicate: false,
+            seen_reads: FnvHashSet::default(),
+            n_duplicates: 0,
+            chrom_filter: None,
+            region_filter: (None, None),
         }
     }
 
@@ -150,16 +225,114 @@ impl<W: Write> CollapseOptions<W> {
         self
     }
 
+    /// Show a spinner with the amount of input read and the throughput.
+    /// Defaults to true if stderr is a terminal.
     pub fn progress(&mut self, progress: bool) -> &mut Self {
         self.progress = progress;
         self
     }
 
+    /// Size of the input in bytes, if known, so the progress bar can show
+    /// the ETA
+    pub fn input_len(&mut self, input_len: Option<u64>) -> &mut Self {
+        self.input_len = input_len;
+        self
+    }
+
+    /// Skip reads whose alignment spans fewer than this many bases
+    pub fn min_read_length(&mut self, min_read_length: Option<u64>) -> &mut Self {
+        self.min_read_length = min_read_length;
+        self
+    }
+
+    /// Skip reads with the same name as a read that was already written, ie
+    /// from multi-mapped reads. Read names are kept in memory.
+    pub fn deduplicate(&mut self, deduplicate: bool) -> &mut Self {
+        self.deduplicate = deduplicate;
+        self
+    }
+
+    /// Only keep reads aligned to this chromosome, matched case-sensitively.
+    /// Lines on other chromosomes are skipped before their samples are
+    /// parsed.
+    pub fn chrom_filter(&mut self, chrom: Option<String>) -> &mut Self {
+        self.chrom_filter = chrom;
+        self
+    }
+
+    /// Only keep reads overlapping the half-open interval from start to stop
+    /// on the chromosome given to [CollapseOptions::chrom_filter], either end
+    /// can be left open
+    pub fn region_filter(&mut self, start: Option<u64>, stop: Option<u64>) -> &mut Self {
+        self.region_filter = (start, stop);
+        self
+    }
+
+    /// Whether the read doesn't overlap the region, using the positions of
+    /// the first and last kmers
+    fn is_outside_region(&self, nprs: &[Npr]) -> bool {
+        let (Some(first), Some(last)) = (nprs.first(), nprs.last()) else {
+            return false;
+        };
+        match self.region_filter {
+            (Some(start), _) if last.position < start => true,
+            (_, Some(stop)) if first.position >= stop => true,
+            _ => false,
+        }
+    }
+
+    /// Whether a read with the same name was already written
+    fn is_duplicate(&mut self, nprs: &[Npr]) -> bool {
+        if !self.deduplicate {
+            return false;
+        }
+        let Some(first) = nprs.first() else {
+            return false;
+        };
+        if self.seen_reads.contains(first.read_name()) {
+            self.n_duplicates += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Whether the read is shorter than the minimum read length, using the
+    /// positions of the first and last kmers so the signal doesn't need to be
+    /// processed. Reads that can't be measured are kept and handled later.
+    fn is_too_short(&self, nprs: &[Npr]) -> bool {
+        let (Some(min_read_length), Some(first), Some(last)) =
+            (self.min_read_length, nprs.first(), nprs.last())
+        else {
+            return false;
+        };
+        match last.position.checked_sub(first.position) {
+            Some(span) => span + 1 < min_read_length,
+            None => false,
+        }
+    }
+
+    /// Convert the records of a read and add it to the buffer, unless it is
+    /// filtered out
+    fn push_read(&mut self, acc: &mut Vec<Npr>, flats: &mut Vec<Eventalign>) -> Result<()> {
+        if self.is_too_short(acc) || self.is_outside_region(acc) || self.is_duplicate(acc) {
+            acc.clear();
+            return Ok(());
+        }
+        if let Some(eventalign) = nprs_to_eventalign(acc.drain(..), &self.strand_db)? {
+            if self.deduplicate {
+                self.seen_reads.insert(eventalign.name().to_string());
+            }
+            flats.push(eventalign);
+        }
+        Ok(())
+    }
+
     pub fn from_writer<R>(writer: W, bam_file: R) -> Result<Self>
     where
         R: AsRef<Path>,
     {
-        let strand_db = PlusStrandMap::from_bam_file(bam_file)?;
+        let strand_db = StrandMap::from_bam_file(bam_file)?;
         let schema = Eventalign::schema();
         let writer = arrow_utils::wrap_writer(writer, &schema)?;
         Ok(CollapseOptions::new(writer, strand_db))
@@ -174,75 +347,123 @@ impl<W: Write> CollapseOptions<W> {
         Ok(())
     }
 
-    pub fn run<R>(&mut self, input: R) -> Result<()>
-    where
-        R: Read,
-    {
-        let file = spin_iter(input, self.progress);
-        let mut builder = csv::ReaderBuilder::new().delimiter(b'\t').from_reader(file);
-        let mut npr_iter = builder.deserialize();
-
-        let mut idx_diff = 1;
-        let npr: Npr = npr_iter.next().ok_or_else(|| {
-            eyre::eyre!(
-                "No data, check if eventalign has data; nanopolish eventalign may have failed"
-            )
-        })??;
-        let mut position = npr.position;
-
-        let mut acc = vec![npr];
-        let mut flats = Vec::with_capacity(self.capacity);
+    fn check_region_filter(&self) -> Result<()> {
+        if self.chrom_filter.is_none() && self.region_filter != (None, None) {
+            return Err(eyre::eyre!(
+                "Filtering on start or stop requires a chromosome"
+            ));
+        }
+        if let (Some(start), Some(stop)) = self.region_filter {
+            if start >= stop {
+                return Err(eyre::eyre!("Start {start} must be less than stop {stop}"));
+            }
+        }
+        Ok(())
+    }
 
-        for line in npr_iter {
-            if let Ok(mut next_npr) = line {
-                let last = acc.last().unwrap();
-                let read_name = last.read_name();
-                let event_idx = last.event_index();
-                if (next_npr.read_name() == read_name)
-                    && (next_npr.event_index().abs_diff(event_idx) == idx_diff)
-                {
-                    // Same read, possibly new kmer or same
-                    if next_npr.position == position {
-                        // Same read, same kmer
-                        let npr_mut = acc.last_mut().unwrap();
-                        npr_mut.samples.append(&mut next_npr.samples);
-                        npr_mut.event_length += next_npr.event_length;
-                        npr_mut.event_index = next_npr.event_index;
-                    } else {
-                        // Same read, different kmer
-                        position = next_npr.position;
-                        acc.push(next_npr);
-                    }
+    /// Merge a parsed line into the read being collapsed, writing out the
+    /// buffered reads once there are enough of them
+    fn add_line(&mut self, pending: &mut Pending, line: csv::Result<Npr>) -> Result<()> {
+        let Some(last) = pending.acc.last() else {
+            // First line of the input
+            let npr = line?;
+            pending.position = npr.position;
+            pending.idx_diff = 1;
+            pending.acc.push(npr);
+            return Ok(());
+        };
+        if let Ok(mut next_npr) = line {
+            let read_name = last.read_name();
+            let event_idx = last.event_index();
+            if (next_npr.read_name() == read_name)
+                && (next_npr.event_index().abs_diff(event_idx) == pending.idx_diff)
+            {
+                // Same read, possibly new kmer or same
+                if next_npr.position == pending.position {
+                    // Same read, same kmer
+                    let npr_mut = pending.acc.last_mut().unwrap();
+                    npr_mut.samples.append(&mut next_npr.samples);
+                    npr_mut.event_length += next_npr.event_length;
+                    npr_mut.event_index = next_npr.event_index;
                 } else {
-                    // New read, write data and move forward
-                    if let Some(eventalign) = nprs_to_eventalign(acc.drain(..), &self.strand_db)? {
-                        flats.push(eventalign);
-                    }
-
-                    if flats.len() >= self.capacity {
-                        self.save_eventalign(&flats)?;
-                        flats.clear();
-                    }
-                    acc.push(next_npr);
+                    // Same read, different kmer
+                    pending.position = next_npr.position;
+                    pending.acc.push(next_npr);
                 }
-                idx_diff = 1;
             } else {
-                log::warn!("Parsing failed: {line:?}");
-                idx_diff += 1;
+                // New read, write data and move forward
+                self.push_read(&mut pending.acc, &mut pending.flats)?;
+
+                if pending.flats.len() >= self.capacity {
+                    self.save_eventalign(&pending.flats)?;
+                    pending.flats.clear();
+                }
+                pending.acc.push(next_npr);
             }
+            pending.idx_diff = 1;
+        } else {
+            log::warn!("Parsing failed: {line:?}");
+            pending.idx_diff += 1;
         }
+        Ok(())
+    }
 
-        if !acc.is_empty() {
-            if let Some(eventalign) = nprs_to_eventalign(acc.drain(..), &self.strand_db)? {
-                flats.push(eventalign);
+    /// Write the remaining reads and finish the output
+    fn finish(&mut self, mut pending: Pending) -> Result<()> {
+        if pending.acc.is_empty() {
+            if let Some(chrom) = &self.chrom_filter {
+                log::warn!("No reads aligned to {chrom}");
+                return self.close();
             }
+            return Err(eyre::eyre!(
+                "No data, check if eventalign has data; nanopolish eventalign may have failed"
+            ));
         }
+        self.push_read(&mut pending.acc, &mut pending.flats)?;
         // If reads are left in the buffer, save those
-        if !flats.is_empty() {
-            self.save_eventalign(&flats)?;
+        if !pending.flats.is_empty() {
+            self.save_eventalign(&pending.flats)?;
+        }
+        if self.n_duplicates > 0 {
+            log::warn!("Skipped {} duplicate reads", self.n_duplicates);
         }
         self.close()
     }
+
+    pub fn run<R>(&mut self, input: R) -> Result<()>
+    where
+        R: Read,
+    {
+        self.check_region_filter()?;
+        let file = spin_iter(input, self.progress, self.input_len);
+        let mut reader = csv::ReaderBuilder::new().delimiter(b'\t').from_reader(file);
+        let headers = reader.headers()?.clone();
+        let contig_idx = headers.iter().position(|h| h == "contig");
+        let chrom_filter = self.chrom_filter.clone();
+        let npr_iter = reader
+            .into_records()
+            .filter(|record| match record {
+                Ok(record) => on_chrom(chrom_filter.as_deref(), contig_idx, record),
+                Err(_) => true,
+            })
+            .map(|record| record.and_then(|record| record.deserialize::<Npr>(Some(&headers))));
+
+        let mut pending = Pending {
+            flats: Vec::with_capacity(self.capacity),
+            ..Default::default()
+        };
+        for line in npr_iter {
+            self.add_line(&mut pending, line)?;
+        }
+        self.finish(pending)
+    }
+
+    /// Same as [CollapseOptions::run], but the file is read with tokio's
+    /// async file I/O on a single threaded runtime. Parsing and writing the
+    /// output are unchanged so the output is the same.
+    pub fn run_async<P: AsRef<Path>>(&mut self, input: P) -> Result<()> {
+        self.run(AsyncFileReader::open(input.as_ref())?)
+    }
 }
 
 #[serde_as]
@@ -324,8 +545,8 @@ mod test {
         let input = File::open(filepath)?;
         let bam_file = "extra/single_read.bam";
         let output = temp_dir.path().join("test");
-        let mut collapse = CollapseOptions::try_new(bam_file, &output)?;
-        collapse.run(input)?;
+        let mut collapse = CollapseOptions::try_new(bam_file, Some(&output))?;
+        collapse.progress(false).run(input)?;
 
         let output = File::open(output)?;
         let x = load_iter(output).next().unwrap().unwrap();
@@ -342,6 +563,37 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_collapse_min_read_length() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let input = File::open("extra/single_read.eventalign.txt")?;
+        let output = temp_dir.path().join("test");
+        let mut collapse = CollapseOptions::try_new("extra/single_read.bam", Some(&output))?;
+        collapse
+            .progress(false)
+            .min_read_length(Some(10_000))
+            .run(input)?;
+
+        let mut n_reads = 0;
+        load_apply(File::open(&output)?, |eventaligns: Vec<Eventalign>| {
+            n_reads += eventaligns.len();
+            Ok(())
+        })?;
+        assert_eq!(n_reads, 0);
+
+        // The read spans 178 bases, so it is kept
+        let input = File::open("extra/single_read.eventalign.txt")?;
+        let output = temp_dir.path().join("test_kept");
+        let mut collapse = CollapseOptions::try_new("extra/single_read.bam", Some(&output))?;
+        collapse
+            .progress(false)
+            .min_read_length(Some(178))
+            .run(input)?;
+        let x = load_iter(File::open(output)?).next().unwrap().unwrap();
+        assert_eq!(x.len(), 1);
+        Ok(())
+    }
+
     #[test]
     fn test_collapse_big() -> Result<()> {
         let temp_dir = TempDir::new()?;
@@ -349,8 +601,8 @@ mod test {
         let input = File::open(filepath)?;
         let bam_file = "extra/neg_control.bam";
         let output = temp_dir.path().join("test");
-        let mut collapse = CollapseOptions::try_new(bam_file, &output)?;
-        collapse.run(input)?;
+        let mut collapse = CollapseOptions::try_new(bam_file, Some(&output))?;
+        collapse.progress(false).run(input)?;
 
         let output = File::open(output)?;
         let mut loads = 0;
@@ -367,6 +619,72 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_run_async() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let filepath = "extra/neg_control.eventalign.txt";
+        let bam_file = "extra/neg_control.bam";
+        let sync_output = temp_dir.path().join("sync");
+        CollapseOptions::try_new(bam_file, Some(&sync_output))?
+            .progress(false)
+            .run(File::open(filepath)?)?;
+        let async_output = temp_dir.path().join("async");
+        CollapseOptions::try_new(bam_file, Some(&async_output))?
+            .progress(false)
+            .run_async(filepath)?;
+        assert_eq!(std::fs::read(sync_output)?, std::fs::read(async_output)?);
+
+        let sync_output = temp_dir.path().join("sync_chrom");
+        CollapseOptions::try_new(bam_file, Some(&sync_output))?
+            .progress(false)
+            .chrom_filter(Some("chrX".to_string()))
+            .run(File::open(filepath)?)?;
+        let async_output = temp_dir.path().join("async_chrom");
+        CollapseOptions::try_new(bam_file, Some(&async_output))?
+            .progress(false)
+            .chrom_filter(Some("chrX".to_string()))
+            .run_async(filepath)?;
+        assert_eq!(std::fs::read(sync_output)?, std::fs::read(async_output)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_async_quoted_crlf() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let bam_file = "extra/single_read.bam";
+        let eventalign = std::fs::read_to_string("extra/single_read.eventalign.txt")?;
+        // Quoted samples and CRLF line endings, as written by some tools
+        let quoted = eventalign
+            .lines()
+            .map(|line| match line.rsplit_once('\t') {
+                Some((fields, samples)) => format!("{fields}\t\"{samples}\"\r\n"),
+                None => format!("{line}\r\n"),
+            })
+            .collect::<String>();
+        let input = temp_dir.path().join("quoted.eventalign.txt");
+        std::fs::write(&input, quoted)?;
+
+        let collapse = |name: &str, quoted: bool, is_async: bool| -> Result<Vec<u8>> {
+            let output = temp_dir.path().join(name);
+            let mut collapse = CollapseOptions::try_new(bam_file, Some(&output))?;
+            collapse.progress(false);
+            match (quoted, is_async) {
+                (false, _) => collapse.run(eventalign.as_bytes())?,
+                (true, false) => collapse.run(File::open(&input)?)?,
+                (true, true) => collapse.run_async(&input)?,
+            }
+            Ok(std::fs::read(output)?)
+        };
+        let outputs = [
+            collapse("unquoted", false, false)?,
+            collapse("sync", true, false)?,
+            collapse("async", true, true)?,
+        ];
+        assert_eq!(outputs[0], outputs[1]);
+        assert_eq!(outputs[1], outputs[2]);
+        Ok(())
+    }
+
     #[test]
     fn test_malformed() {
         let lines: &[u8] = b"contig	position	reference_kmer	read_name	strand	event_index	event_level_mean	event_stdv	event_length	model_kmer	model_mean	model_stdv	standardized_level	samples
@@ -397,13 +715,16 @@ chr1	199403040	ATATAA	c25d27a8-0eec-4e7d-96f9-b8e730a25832	t	3918	87.01		72.4013
         assert_eq!(next.unwrap(), npr);
         assert!(iter.next().unwrap().is_err());
 
-        let mut strand_db = PlusStrandMap::default();
-        strand_db.insert(b"c25d27a8-0eec-4e7d-96f9-b8e730a25832" as &[u8], true);
+        let mut strand_db = StrandMap::default();
+        strand_db.insert(
+            b"c25d27a8-0eec-4e7d-96f9-b8e730a25832" as &[u8],
+            Strand::plus(),
+        );
 
         let schema = Eventalign::schema();
         let writer = wrap_writer(Vec::new(), &schema).unwrap();
         let mut opts = CollapseOptions::new(writer, strand_db);
-        let res = opts.run(lines);
+        let res = opts.progress(false).run(lines);
         assert!(res.is_ok());
 
         let reader = Cursor::new(opts.writer.into_inner());
@@ -430,6 +751,157 @@ chr1	199403040	ATATAA	c25d27a8-0eec-4e7d-96f9-b8e730a25832	t	3918	87.01		72.4013
         pretty_assertions::assert_eq!(x[0], target);
     }
 
+    #[test]
+    fn test_deduplicate() -> Result<()> {
+        let lines: &[u8] = b"contig	position	reference_kmer	read_name	strand	event_index	event_level_mean	event_stdv	event_length	model_kmer	model_mean	model_stdv	standardized_level	samples
+chr1	100	ATATAA	read_a	t	10	86.81	0.500	0.00100	TTATAT	87.94	1.88	-0.59	87.1186,87.4749
+chr1	101	TATAAG	read_a	t	11	86.81	0.500	0.00100	TTATAT	87.94	1.88	-0.59	87.1186,87.4749
+chr1	200	GATATA	read_b	t	10	106.85	4.255	0.00100	TATATC	107.52	3.75	-0.18	99.4103,108.674
+chr5	300	ATATAA	read_a	t	10	86.81	0.500	0.00100	TTATAT	87.94	1.88	-0.59	87.1186,87.4749
+";
+        let run = |deduplicate| -> Result<Vec<Eventalign>> {
+            let mut strand_db = StrandMap::default();
+            strand_db.insert(b"read_a" as &[u8], Strand::plus());
+            strand_db.insert(b"read_b" as &[u8], Strand::plus());
+            let writer = wrap_writer(Vec::new(), &Eventalign::schema())?;
+            let mut opts = CollapseOptions::new(writer, strand_db);
+            opts.progress(false).deduplicate(deduplicate).run(lines)?;
+            let reader = Cursor::new(opts.writer.into_inner());
+            Ok(load_iter(reader).next().unwrap()?)
+        };
+
+        let reads = run(false)?;
+        assert_eq!(reads.len(), 3);
+
+        let reads = run(true)?;
+        let names: Vec<&str> = reads.iter().map(|r| r.name()).collect();
+        assert_eq!(names, ["read_a", "read_b"]);
+        assert_eq!(reads[0].chrom(), "chr1");
+
+        // The first alignment of read_a runs backwards so it isn't written,
+        // and the next one is kept
+        let lines: &[u8] = b"contig	position	reference_kmer	read_name	strand	event_index	event_level_mean	event_stdv	event_length	model_kmer	model_mean	model_stdv	standardized_level	samples
+chr1	100	ATATAA	read_a	t	10	86.81	0.500	0.00100	TTATAT	87.94	1.88	-0.59	87.1186,87.4749
+chr1	99	TATAAG	read_a	t	11	86.81	0.500	0.00100	TTATAT	87.94	1.88	-0.59	87.1186,87.4749
+chr5	300	ATATAA	read_a	t	20	86.81	0.500	0.00100	TTATAT	87.94	1.88	-0.59	87.1186,87.4749
+chr5	301	TATAAG	read_a	t	21	86.81	0.500	0.00100	TTATAT	87.94	1.88	-0.59	87.1186,87.4749
+";
+        let mut strand_db = StrandMap::default();
+        strand_db.insert(b"read_a" as &[u8], Strand::plus());
+        let writer = wrap_writer(Vec::new(), &Eventalign::schema())?;
+        let mut opts = CollapseOptions::new(writer, strand_db);
+        opts.progress(false).deduplicate(true).run(lines)?;
+        let reader = Cursor::new(opts.writer.into_inner());
+        let reads = load_iter(reader).next().unwrap()?;
+        assert_eq!(reads.len(), 1);
+        assert_eq!(reads[0].chrom(), "chr5");
+        Ok(())
+    }
+
+    #[test]
+    fn test_chrom_filter() -> Result<()> {
+        let lines: &[u8] = b"contig	position	reference_kmer	read_name	strand	event_index	event_level_mean	event_stdv	event_length	model_kmer	model_mean	model_stdv	standardized_level	samples
+chrI	100	ATATAA	read_a	t	10	86.81	0.500	0.00100	TTATAT	87.94	1.88	-0.59	87.1186,87.4749
+chrI	101	TATAAG	read_a	t	11	86.81	0.500	0.00100	TTATAT	87.94	1.88	-0.59	87.1186,87.4749
+chrII	200	GATATA	read_b	t	10	106.85	4.255	0.00100	TATATC	107.52	3.75	-0.18	99.4103,108.674
+chrII	201	ATATAC	read_b	t	11	106.85	4.255	0.00100	TATATC	107.52	3.75	-0.18	not,samples
+chrI	300	ATATAA	read_c	t	10	86.81	0.500	0.00100	TTATAT	87.94	1.88	-0.59	87.1186,87.4749
+";
+        let run = |chrom: Option<&str>| -> Result<Vec<Eventalign>> {
+            let mut strand_db = StrandMap::default();
+            for name in [b"read_a" as &[u8], b"read_b", b"read_c"] {
+                strand_db.insert(name, Strand::plus());
+            }
+            let writer = wrap_writer(Vec::new(), &Eventalign::schema())?;
+            let mut opts = CollapseOptions::new(writer, strand_db);
+            opts.progress(false)
+                .chrom_filter(chrom.map(String::from))
+                .run(lines)?;
+            let reader = Cursor::new(opts.writer.into_inner());
+            let mut reads = Vec::new();
+            load_apply(reader, |mut xs: Vec<Eventalign>| {
+                reads.append(&mut xs);
+                Ok(())
+            })?;
+            Ok(reads)
+        };
+
+        let reads = run(Some("chrI"))?;
+        let names: Vec<&str> = reads.iter().map(|r| r.name()).collect();
+        assert_eq!(names, ["read_a", "read_c"]);
+        assert!(reads.iter().all(|r| r.chrom() == "chrI"));
+
+        // Case-sensitive like bam and fasta files
+        assert!(run(Some("chri"))?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_region_filter() -> Result<()> {
+        let lines: &[u8] = b"contig	position	reference_kmer	read_name	strand	event_index	event_level_mean	event_stdv	event_length	model_kmer	model_mean	model_stdv	standardized_level	samples
+chrI	100	ATATAA	read_a	t	10	86.81	0.500	0.00100	TTATAT	87.94	1.88	-0.59	87.1186,87.4749
+chrI	150	TATAAG	read_a	t	11	86.81	0.500	0.00100	TTATAT	87.94	1.88	-0.59	87.1186,87.4749
+chrI	180	ATATAA	read_b	t	10	86.81	0.500	0.00100	TTATAT	87.94	1.88	-0.59	87.1186,87.4749
+chrI	220	TATAAG	read_b	t	11	86.81	0.500	0.00100	TTATAT	87.94	1.88	-0.59	87.1186,87.4749
+chrI	240	ATATAA	read_c	t	10	86.81	0.500	0.00100	TTATAT	87.94	1.88	-0.59	87.1186,87.4749
+chrI	300	TATAAG	read_c	t	11	86.81	0.500	0.00100	TTATAT	87.94	1.88	-0.59	87.1186,87.4749
+chrI	250	ATATAA	read_d	t	10	86.81	0.500	0.00100	TTATAT	87.94	1.88	-0.59	87.1186,87.4749
+chrI	300	TATAAG	read_d	t	11	86.81	0.500	0.00100	TTATAT	87.94	1.88	-0.59	87.1186,87.4749
+chrI	100	ATATAA	read_e	t	10	86.81	0.500	0.00100	TTATAT	87.94	1.88	-0.59	87.1186,87.4749
+chrI	310	TATAAG	read_e	t	11	86.81	0.500	0.00100	TTATAT	87.94	1.88	-0.59	87.1186,87.4749
+chrII	210	ATATAA	read_f	t	10	86.81	0.500	0.00100	TTATAT	87.94	1.88	-0.59	87.1186,87.4749
+";
+        let run = |chrom: Option<&str>, start, stop| -> Result<Vec<String>> {
+            let mut strand_db = StrandMap::default();
+            for name in ["read_a", "read_b", "read_c", "read_d", "read_e", "read_f"] {
+                strand_db.insert(name.as_bytes(), Strand::plus());
+            }
+            let writer = wrap_writer(Vec::new(), &Eventalign::schema())?;
+            let mut opts = CollapseOptions::new(writer, strand_db);
+            opts.progress(false)
+                .chrom_filter(chrom.map(String::from))
+                .region_filter(start, stop)
+                .run(lines)?;
+            let reader = Cursor::new(opts.writer.into_inner());
+            let mut names = Vec::new();
+            load_apply(reader, |xs: Vec<Eventalign>| {
+                names.extend(xs.iter().map(|x| x.name().to_string()));
+                Ok(())
+            })?;
+            Ok(names)
+        };
+
+        // read_d starts at the end of the half-open interval
+        let names = run(Some("chrI"), Some(200), Some(250))?;
+        assert_eq!(names, ["read_b", "read_c", "read_e"]);
+
+        let names = run(Some("chrI"), Some(200), None)?;
+        assert_eq!(names, ["read_b", "read_c", "read_d", "read_e"]);
+
+        assert!(run(None, Some(200), Some(250)).is_err());
+        assert!(run(Some("chrI"), Some(250), Some(200)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_no_chrom_filter() -> Result<()> {
+        let lines: &[u8] = b"contig	position	reference_kmer	read_name	strand	event_index	event_level_mean	event_stdv	event_length	model_kmer	model_mean	model_stdv	standardized_level	samples
+chrI	100	ATATAA	read_a	t	10	86.81	0.500	0.00100	TTATAT	87.94	1.88	-0.59	87.1186,87.4749
+chrII	200	GATATA	read_b	t	10	106.85	4.255	0.00100	TATATC	107.52	3.75	-0.18	99.4103,108.674
+";
+        let mut strand_db = StrandMap::default();
+        strand_db.insert(b"read_a" as &[u8], Strand::plus());
+        strand_db.insert(b"read_b" as &[u8], Strand::plus());
+        let writer = wrap_writer(Vec::new(), &Eventalign::schema())?;
+        let mut opts = CollapseOptions::new(writer, strand_db);
+        opts.progress(false).chrom_filter(None).run(lines)?;
+        let reader = Cursor::new(opts.writer.into_inner());
+        let reads = load_iter(reader).next().unwrap()?;
+        let chroms: Vec<&str> = reads.iter().map(|r| r.chrom()).collect();
+        assert_eq!(chroms, ["chrI", "chrII"]);
+        Ok(())
+    }
+
     #[test]
     fn test_diff_idx() {
         let lines: &[u8] = b"contig	position	reference_kmer	read_name	strand	event_index	event_level_mean	event_stdv	event_length	model_kmer	model_mean	model_stdv	standardized_level	samples
@@ -437,13 +909,16 @@ chr1	199403040	ATATAA	c25d27a8-0eec-4e7d-96f9-b8e730a25832	t	3919	86.81	0.500	0.
 chr1	199403040	ATATAA	c25d27a8-0eec-4e7d-96f9-b8e730a25832	t	3918	87.01		72.4013,75.9601,78.395,77.6458
 chr1	199403041	GATATA	c25d27a8-0eec-4e7d-96f9-b8e730a25832	t	3917	106.85	4.255	0.00100	TATATC	107.52	3.75	-0.18	99.4103,108.674,110.277,109.03
 ";
-        let mut strand_db = PlusStrandMap::default();
-        strand_db.insert(b"c25d27a8-0eec-4e7d-96f9-b8e730a25832" as &[u8], true);
+        let mut strand_db = StrandMap::default();
+        strand_db.insert(
+            b"c25d27a8-0eec-4e7d-96f9-b8e730a25832" as &[u8],
+            Strand::plus(),
+        );
 
         let schema = Eventalign::schema();
         let writer = wrap_writer(Vec::new(), &schema).unwrap();
         let mut opts = CollapseOptions::new(writer, strand_db);
-        let res = opts.run(lines);
+        let res = opts.progress(false).run(lines);
         assert!(res.is_ok());
 
         let reader = Cursor::new(opts.writer.into_inner());
@@ -0,0 +1,191 @@
+use std::io::Write;
+
+use eyre::Result;
+use fnv::FnvHashSet;
+use rand::{prelude::SmallRng, SeedableRng};
+use rv::traits::{KlDivergence, Rv};
+
+use crate::{
+    score::choose_model,
+    train::{Model, ModelParams},
+};
+
+/// Distances between the parameters of a kmer in two models. Values are NaN
+/// if the kmer is only found in one of the models.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KmerDistance {
+    pub kmer: String,
+    pub kl_ab: f64,
+    pub kl_ba: f64,
+    pub mean_diff: f64,
+    pub wasserstein: f64,
+}
+
+impl KmerDistance {
+    fn missing(kmer: String) -> Self {
+        Self {
+            kmer,
+            kl_ab: f64::NAN,
+            kl_ba: f64::NAN,
+            mean_diff: f64::NAN,
+            wasserstein: f64::NAN,
+        }
+    }
+}
+
+pub struct CompareOptions {
+    seed: u64,
+    n_samples: usize,
+}
+
+impl Default for CompareOptions {
+    fn default() -> Self {
+        CompareOptions {
+            seed: 2456,
+            n_samples: 10_000,
+        }
+    }
+}
+
+impl CompareOptions {
+    pub fn new(seed: u64, n_samples: usize) -> Self {
+        CompareOptions { seed, n_samples }
+    }
+
+    /// Compare every kmer found in either model, sorted by kmer.
+    pub fn compare(&self, model_a: &Model, model_b: &Model) -> Vec<KmerDistance> {
+        let a_kmers = model_a.gmms().keys().collect::<FnvHashSet<&String>>();
+        let b_kmers = model_b.gmms().keys().collect::<FnvHashSet<&String>>();
+        let mut kmers = a_kmers.union(&b_kmers).cloned().collect::<Vec<_>>();
+        kmers.sort_unstable();
+
+        kmers
+            .into_iter()
+            .map(
+                |kmer| match (model_a.gmms().get(kmer), model_b.gmms().get(kmer)) {
+                    (Some(a), Some(b)) => self.distance(kmer.clone(), a, b),
+                    _ => KmerDistance::missing(kmer.clone()),
+                },
+            )
+            .collect()
+    }
+
+    fn distance(&self, kmer: String, a: &ModelParams, b: &ModelParams) -> KmerDistance {
+        let a_mix = a.mixture();
+        let b_mix = b.mixture();
+
+        let a_comp = choose_model(&a_mix);
+        let b_comp = choose_model(&b_mix);
+        let kl_ab = a_comp.kl(b_comp);
+        let kl_ba = b_comp.kl(a_comp);
+
+        let mean_diff = mixture_mean(a) - mixture_mean(b);
+        let wasserstein = self.wasserstein_approx(&a_mix, &b_mix);
+
+        KmerDistance {
+            kmer,
+            kl_ab,
+            kl_ba,
+            mean_diff,
+            wasserstein,
+        }
+    }
+
+    // 1-D Wasserstein distance is the mean difference between the quantiles of
+    // the two distributions, approximated here with sorted samples. Each side
+    // is sampled from the same seed so comparing a model to itself gives zero.
+    fn wasserstein_approx<M: Rv<f64>>(&self, a: &M, b: &M) -> f64 {
+        let mut a_samples: Vec<f64> = a.sample(self.n_samples, &mut self.rng());
+        let mut b_samples: Vec<f64> = b.sample(self.n_samples, &mut self.rng());
+        a_samples.sort_by(|x, y| x.partial_cmp(y).expect("NaN sampled from model"));
+        b_samples.sort_by(|x, y| x.partial_cmp(y).expect("NaN sampled from model"));
+        let total: f64 = a_samples
+            .into_iter()
+            .zip(b_samples)
+            .map(|(x, y)| (x - y).abs())
+            .sum();
+        total / self.n_samples as f64
+    }
+
+    fn rng(&self) -> SmallRng {
+        SmallRng::seed_from_u64(self.seed)
+    }
+
+    /// Write distances as a tsv with a header line.
+    pub fn write_tsv<W: Write>(distances: &[KmerDistance], writer: &mut W) -> Result<()> {
+        writeln!(writer, "kmer\tkl_ab\tkl_ba\tmean_diff\twasserstein")?;
+        for d in distances {
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{}\t{}",
+                d.kmer, d.kl_ab, d.kl_ba, d.mean_diff, d.wasserstein
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn mixture_mean(params: &ModelParams) -> f64 {
+    params
+        .components()
+        .into_iter()
+        .map(|(weight, mu, _)| weight * mu)
+        .sum()
+}
+
+#[cfg(test)]
+mod test {
+    use float_eq::assert_float_eq;
+    use rv::prelude::{Gaussian, Mixture};
+
+    use super::*;
+
+    fn model_with(kmers: &[(&str, f64)]) -> Model {
+        let mut model = Model::default();
+        for &(kmer, mu) in kmers {
+            let g1 = Gaussian::new_unchecked(mu, 2.);
+            let g2 = Gaussian::new_unchecked(mu + 10., 3.);
+            let mix = Mixture::new_unchecked(vec![0.7, 0.3], vec![g1, g2]);
+            model.insert_gmm(kmer.to_string(), mix);
+        }
+        model
+    }
+
+    #[test]
+    fn test_compare_self() {
+        let model = model_with(&[("AAAAAA", 90.), ("CCCCCC", 100.)]);
+        let distances = CompareOptions::default().compare(&model, &model);
+        assert_eq!(distances.len(), 2);
+        for d in distances {
+            assert_float_eq!(d.kl_ab, 0.0, abs <= 1e-9);
+            assert_float_eq!(d.kl_ba, 0.0, abs <= 1e-9);
+            assert_float_eq!(d.mean_diff, 0.0, abs <= 1e-9);
+            assert_float_eq!(d.wasserstein, 0.0, abs <= 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_compare_missing_and_shifted() {
+        let model_a = model_with(&[("AAAAAA", 90.), ("CCCCCC", 100.)]);
+        let model_b = model_with(&[("AAAAAA", 95.), ("GGGGGG", 100.)]);
+        let distances = CompareOptions::default().compare(&model_a, &model_b);
+        let kmers = distances
+            .iter()
+            .map(|d| d.kmer.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(kmers, vec!["AAAAAA", "CCCCCC", "GGGGGG"]);
+
+        let shifted = &distances[0];
+        assert_float_eq!(shifted.mean_diff, -5.0, abs <= 1e-9);
+        assert_float_eq!(shifted.wasserstein, 5.0, abs <= 0.5);
+        assert!(shifted.kl_ab > 0.0);
+        assert!(shifted.kl_ba > 0.0);
+
+        for d in &distances[1..] {
+            assert!(d.kl_ab.is_nan());
+            assert!(d.kl_ba.is_nan());
+            assert!(d.mean_diff.is_nan());
+            assert!(d.wasserstein.is_nan());
+        }
+    }
+}
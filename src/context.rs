@@ -4,17 +4,23 @@ use std::io::{Read, Seek};
 use bio::{alphabets::dna, io::fasta::IndexedReader};
 use eyre::Result;
 use fnv::FnvHashMap;
+use lru::LruCache;
 
 use crate::{arrow::metadata::MetadataExt, motif::Motif};
 
+/// Genome sequences fetched for reads, keyed by chromosome, start and stop
+pub(crate) type GenomeCache = LruCache<(String, u64, u64), Vec<u8>>;
+
 /// Contains the genomic bases for a given position including additional
 /// metadata to handle positions near the end of the genome.
 /// Represents the genomic sequence for a read.
-pub(crate) struct Context {
+pub struct Context {
     context: Vec<u8>,
     read_start: u64,
     start_slop: u64,
     end_slop: u64,
+    kmer_size: usize,
+    minus_strand: bool,
 }
 
 impl fmt::Debug for Context {
@@ -23,95 +29,211 @@ impl fmt::Debug for Context {
             .field("context", &std::str::from_utf8(&self.context).unwrap())
             .field("read_start", &self.read_start)
             .field("start_slop", &self.start_slop)
+            .field("end_slop", &self.end_slop)
+            .field("kmer_size", &self.kmer_size)
+            .field("minus_strand", &self.minus_strand)
             .finish()
     }
 }
 
+/// Genome fasta reader method makes clippy think its wrong but it still
+/// works correctly.
+#[allow(clippy::read_zero_byte_vec)]
+fn fetch_seq<R: Read + Seek>(
+    genome: &mut IndexedReader<R>,
+    chrom: &str,
+    start: u64,
+    stop: u64,
+) -> Result<Vec<u8>> {
+    genome.fetch(chrom, start, stop)?;
+    let mut seq = Vec::new();
+    genome.read(&mut seq)?;
+    Ok(seq)
+}
+
 impl Context {
-    pub(crate) fn new(context: Vec<u8>, read_start: u64, start_slop: u64, end_slop: u64) -> Self {
+    pub(crate) fn new(
+        context: Vec<u8>,
+        read_start: u64,
+        start_slop: u64,
+        end_slop: u64,
+        kmer_size: usize,
+    ) -> Self {
         Self {
             context,
             read_start,
             start_slop,
             end_slop,
+            kmer_size,
+            minus_strand: false,
         }
     }
 
-    /// Genome fasta reader method makes clippy think its wrong but it still
-    /// works correctly.
-    #[allow(clippy::read_zero_byte_vec)]
-    pub(crate) fn from_read<R>(
+    /// Fetch the genome sequence covering the read, with enough bases on
+    /// either side for every kmer overlapping the read
+    pub fn from_read<R>(
+        genome: &mut IndexedReader<R>,
+        chrom_lens: &FnvHashMap<String, u64>,
+        read: &impl MetadataExt,
+        kmer_size: usize,
+    ) -> Result<Self>
+    where
+        R: Read + Seek,
+    {
+        Self::from_read_cached(genome, None, chrom_lens, read, kmer_size)
+    }
+
+    /// Like [Context::from_read], checking the cache for the sequence before
+    /// fetching it from the genome
+    pub(crate) fn from_read_cached<R>(
         genome: &mut IndexedReader<R>,
-        _chrom_lens: &FnvHashMap<String, u64>,
+        cache: Option<&mut GenomeCache>,
+        chrom_lens: &FnvHashMap<String, u64>,
         read: &impl MetadataExt,
+        kmer_size: usize,
     ) -> Result<Self>
     where
         R: Read + Seek,
     {
         let chrom = read.chrom();
-        // let chrom_len = *chrom_lens
-        //     .get(chrom)
-        //     .expect("chromosome missing in chrom_lens, different genome used?");
-        let start_slop = read.start_0b().min(5);
+        let Some(&chrom_len) = chrom_lens.get(chrom) else {
+            return Err(eyre::eyre!(
+                "Read {} is on {chrom}, which is missing from the genome, different genome used?",
+                read.name()
+            ));
+        };
+        let slop = kmer_size as u64 - 1;
+        let start_slop = read.start_0b().min(slop);
 
-        let start = if read.start_0b() < 5 {
+        let start = if read.start_0b() < slop {
             0
         } else {
-            read.start_0b() - 5
+            read.start_0b() - slop
         };
 
-        let stop = read.seq_stop_1b_excl();
-        // let end_slop = if (stop + 1) > chrom_len {
-        //     0
-        // } else {
-        //     5.min(chrom_len - (stop + 1))
-        // };
-        // let stop = if (stop + 1) > chrom_len {
-        //     chrom_len
-        // } else {
-        //     stop + 1
-        // };
-        genome.fetch(chrom, start, stop)?;
-        let mut seq = Vec::new();
-
-        genome.read(&mut seq)?;
-
-        if read.strand().is_minus_strand() {
+        // Kmers overlapping the end of the chromosome are padded with N, so
+        // positions near the end still have a kmer and skipping scores from
+        // the kmers that are in the genome
+        let full_stop = read.start_0b() + read.np_length() + slop;
+        let stop = full_stop.min(chrom_len);
+        let end_slop = stop.saturating_sub(read.start_0b() + read.np_length());
+        let mut seq = match cache {
+            Some(cache) => {
+                let key = (chrom.to_string(), start, stop);
+                match cache.get(&key) {
+                    Some(seq) => seq.clone(),
+                    None => {
+                        log::trace!("Genome cache miss for {chrom}:{start}-{stop}");
+                        let seq = fetch_seq(genome, chrom, start, stop)?;
+                        cache.put(key, seq.clone());
+                        seq
+                    }
+                }
+            }
+            None => fetch_seq(genome, chrom, start, stop)?,
+        };
+        seq.resize(seq.len() + (full_stop - stop) as usize, b'N');
+
+        let minus_strand = read.strand().is_minus_strand();
+        if minus_strand {
             log::debug!("Read is on negative");
             seq = seq.into_iter().map(dna::complement).collect();
         }
 
-        Ok(Context::new(seq, read.start_0b(), start_slop, 0u64))
+        Ok(Context {
+            minus_strand,
+            ..Context::new(seq, read.start_0b(), start_slop, end_slop, kmer_size)
+        })
     }
 
-    pub(crate) fn surrounding(&self, pos: u64, motif: &Motif) -> Vec<&[u8]> {
-        let true_pos = (pos - self.read_start) + self.start_slop + motif.position_0b() as u64;
+    /// Whether the context is from a minus strand read, in which case the
+    /// bases are complemented
+    pub fn is_minus_strand(&self) -> bool {
+        self.minus_strand
+    }
 
-        let true_start = if true_pos < 5 { 0 } else { true_pos - 5 };
+    /// Kmers that contain the base at pos
+    pub(crate) fn surrounding(&self, pos: u64) -> Vec<&[u8]> {
+        let slop = self.kmer_size as u64 - 1;
+        let true_pos = (pos - self.read_start) + self.start_slop;
+
+        let true_start = if true_pos < slop { 0 } else { true_pos - slop };
 
         let mut acc = Vec::new();
         let ctxt_len = self.context.len() as u64;
         for base_pos in true_start..=true_pos {
-            if (base_pos + 5) < ctxt_len {
+            if (base_pos + slop) < ctxt_len {
                 let base_pos = base_pos as usize;
-                acc.push(&self.context[base_pos..=base_pos + 5]);
+                acc.push(&self.context[base_pos..base_pos + self.kmer_size]);
             }
         }
         acc
     }
 
-    /// Returns None if the position is near the end of the chromosome and it
-    /// would return a position with a kmer size less than six
-    pub(crate) fn sixmer_at(&self, pos: u64) -> Option<&[u8]> {
+    /// Whether the motif matches the read's strand with its modified base at
+    /// pos, see [Motif::position_0b]
+    pub(crate) fn motif_at(&self, pos: u64, motif: &Motif) -> bool {
+        self.motif_on_strand_at(pos, motif, self.minus_strand)
+    }
+
+    /// Whether the motif matches the plus or minus strand of the genome with
+    /// its modified base at pos. Minus strand motifs are read 5' to 3' on the
+    /// minus strand, so they appear reverse complemented on the plus strand.
+    pub(crate) fn motif_on_strand_at(&self, pos: u64, motif: &Motif, minus: bool) -> bool {
+        let motif_len = motif.len_motif() as u64;
+        let mod_offset = if minus {
+            motif_len - 1 - motif.position_0b() as u64
+        } else {
+            motif.position_0b() as u64
+        };
+        let true_pos = (pos - self.read_start) + self.start_slop;
+        let seq = match true_pos.checked_sub(mod_offset) {
+            Some(start) => self
+                .context
+                .get(start as usize..(start + motif_len) as usize),
+            None => None,
+        };
+        let seq = match seq {
+            Some(seq) => seq,
+            None => return false,
+        };
+
+        // Context of minus strand reads is complemented
+        let mut expected = motif.motif().as_bytes().to_vec();
+        if minus {
+            expected = dna::revcomp(expected);
+        }
+        if self.minus_strand {
+            expected = expected.into_iter().map(dna::complement).collect();
+        }
+        seq == expected.as_slice()
+    }
+
+    /// Kmer starting at the position, with N for any bases past the end of
+    /// the chromosome. Returns None if the kmer isn't covered by the context.
+    pub fn kmer_at(&self, pos: u64) -> Option<&[u8]> {
         let true_pos = (pos - self.read_start) + self.start_slop;
         let true_pos = true_pos as usize;
-        self.context.get(true_pos..=true_pos + 5)
+        self.context.get(true_pos..true_pos + self.kmer_size)
+    }
+
+    /// Reverse complement of the kmer at the position, see [Context::kmer_at]
+    pub fn kmer_at_revcomp(&self, pos: u64) -> Option<Vec<u8>> {
+        self.kmer_at(pos).map(dna::revcomp)
+    }
+
+    /// Same as [Context::kmer_at_revcomp], named for the default kmer size of
+    /// six
+    pub fn sixmer_at_revcomp(&self, pos: u64) -> Option<Vec<u8>> {
+        self.kmer_at_revcomp(pos)
     }
 
     pub(crate) fn start_slop(&self) -> u64 {
         self.start_slop
     }
 
+    /// Number of genome bases after the end of the read, less than the kmer
+    /// size minus one only if the read is near the end of the chromosome
     pub(crate) fn end_slop(&self) -> u64 {
         self.end_slop
     }
@@ -119,16 +241,172 @@ impl Context {
 
 #[cfg(test)]
 mod test {
-    // use std::io::Cursor;
-
-    // use super::*;
-    // use crate::{
-    //     arrow::{MetadataExt, Strand},
-    //     utils::chrom_lens,
-    // };
-
-    // #[test]
-    // fn test_context() -> Result<(), eyre::Error> {
-    //     u
-    // }
+    use bio::alphabets::dna::revcomp;
+
+    use super::*;
+    use crate::{
+        arrow::metadata::{Metadata, Strand},
+        utils::chrom_lens,
+    };
+
+    #[test]
+    fn test_kmer_at_revcomp() -> Result<()> {
+        let mut genome = IndexedReader::from_file(&"extra/sacCer3.fa")
+            .map_err(|_| eyre::eyre!("Failed to read genome file."))?;
+        let chrom_lens = chrom_lens(&genome);
+
+        let read = |strand| {
+            Metadata::new(
+                "read".to_string(),
+                "chrI".to_string(),
+                1000,
+                50,
+                strand,
+                String::new(),
+            )
+        };
+        let context = Context::from_read(&mut genome, &chrom_lens, &read(Strand::plus()), 6)?;
+        assert!(!context.is_minus_strand());
+        for pos in 1000..1050 {
+            let kmer = context.kmer_at(pos).unwrap();
+            assert_eq!(context.kmer_at_revcomp(pos), Some(revcomp(kmer)));
+            assert_eq!(context.sixmer_at_revcomp(pos), Some(revcomp(kmer)));
+        }
+
+        let minus = Context::from_read(&mut genome, &chrom_lens, &read(Strand::minus()), 6)?;
+        assert!(minus.is_minus_strand());
+        Ok(())
+    }
+
+    #[test]
+    fn test_motif_strands() -> Result<()> {
+        let mut genome = IndexedReader::from_file(&"extra/sacCer3.fa")
+            .map_err(|_| eyre::eyre!("Failed to read genome file."))?;
+        let chrom_lens = chrom_lens(&genome);
+        let motif = Motif::parse_from_str("2:CA")?;
+        let read = |strand| {
+            Metadata::new(
+                "read".to_string(),
+                "chrI".to_string(),
+                1000,
+                50,
+                strand,
+                String::new(),
+            )
+        };
+        let positions = |context: &Context, minus: bool| {
+            (1000..1050)
+                .filter(|&pos| context.motif_on_strand_at(pos, &motif, minus))
+                .collect::<Vec<_>>()
+        };
+
+        // chrI:1000-1050 is TACAATTATATCTTATTTCCATTCCCATATGCTAACCGCAATATCCTAAA
+        let plus = Context::from_read(&mut genome, &chrom_lens, &read(Strand::plus()), 6)?;
+        assert_eq!(positions(&plus, false), vec![1003, 1020, 1026, 1039]);
+        assert_eq!(positions(&plus, true), vec![1029]);
+
+        // Minus strand reads find the same motifs at the same coordinates
+        let minus = Context::from_read(&mut genome, &chrom_lens, &read(Strand::minus()), 6)?;
+        assert_eq!(positions(&minus, false), vec![1003, 1020, 1026, 1039]);
+        assert_eq!(positions(&minus, true), vec![1029]);
+        assert!((1000..1050).all(|pos| minus.motif_at(pos, &motif) == (pos == 1029)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_chrom_end() -> Result<()> {
+        let mut genome = IndexedReader::from_file(&"extra/sacCer3.fa")
+            .map_err(|_| eyre::eyre!("Failed to read genome file."))?;
+        let chrom_lens = chrom_lens(&genome);
+        let chrom_len = chrom_lens["chrI"];
+        let read = |start: u64, strand| {
+            Metadata::new(
+                "read".to_string(),
+                "chrI".to_string(),
+                start,
+                50,
+                strand,
+                String::new(),
+            )
+        };
+
+        // Read ends exactly at the end of the chromosome
+        let start = chrom_len - 50;
+        let context =
+            Context::from_read(&mut genome, &chrom_lens, &read(start, Strand::plus()), 6)?;
+        assert_eq!(context.start_slop(), 5);
+        assert_eq!(context.end_slop(), 0);
+        assert_eq!(context.context.len(), 60);
+        assert!(context.context.ends_with(b"NNNNN"));
+        for pos in start..chrom_len {
+            let kmer = context.kmer_at(pos).unwrap();
+            let n_padded = (pos + 6).saturating_sub(chrom_len) as usize;
+            assert_eq!(kmer.iter().filter(|&&b| b == b'N').count(), n_padded);
+        }
+        let last = chrom_len - 1;
+        assert_eq!(context.surrounding(last).len(), 6);
+        assert!(!context.surrounding(last)[0].contains(&b'N'));
+
+        // Motifs match if they are within the chromosome, but never match N
+        let last_base = context.kmer_at(last).unwrap()[0] as char;
+        let intact = Motif::new(&last_base.to_string(), 1);
+        assert!(context.motif_at(last, &intact));
+        let past_end = Motif::new(&format!("{last_base}A"), 1);
+        assert!(!context.motif_at(last, &past_end));
+
+        let minus = Context::from_read(&mut genome, &chrom_lens, &read(start, Strand::minus()), 6)?;
+        assert_eq!(minus.end_slop(), 0);
+        assert!(minus.context.ends_with(b"NNNNN"));
+
+        // Read ends 2 bases before the end of the chromosome
+        let start = chrom_len - 52;
+        let context =
+            Context::from_read(&mut genome, &chrom_lens, &read(start, Strand::plus()), 6)?;
+        assert_eq!(context.end_slop(), 2);
+        assert_eq!(context.context.len(), 60);
+        assert!(context.context.ends_with(b"NNN"));
+        assert_ne!(context.context[56], b'N');
+
+        // Reads away from the end aren't padded
+        let context = Context::from_read(&mut genome, &chrom_lens, &read(1000, Strand::plus()), 6)?;
+        assert_eq!(context.end_slop(), 5);
+        assert!(!context.context.contains(&b'N'));
+        Ok(())
+    }
+
+    #[test]
+    fn test_genome_cache() -> Result<()> {
+        let mut genome = IndexedReader::from_file(&"extra/sacCer3.fa")
+            .map_err(|_| eyre::eyre!("Failed to read genome file."))?;
+        let chrom_lens = chrom_lens(&genome);
+        let read = |name: &str| {
+            Metadata::new(
+                name.to_string(),
+                "chrI".to_string(),
+                1000,
+                50,
+                Strand::plus(),
+                String::new(),
+            )
+        };
+        let mut cache = GenomeCache::new(2);
+        let first =
+            Context::from_read_cached(&mut genome, Some(&mut cache), &chrom_lens, &read("a"), 6)?;
+        assert_eq!(cache.len(), 1);
+
+        // Replace the cached sequence, the second read only sees it if it
+        // doesn't fetch from the genome again
+        let key = ("chrI".to_string(), 995, 1055);
+        let seq = cache.get_mut(&key).unwrap();
+        assert_eq!(*seq, first.context);
+        seq.iter_mut().for_each(|b| *b = b'N');
+        let second =
+            Context::from_read_cached(&mut genome, Some(&mut cache), &chrom_lens, &read("b"), 6)?;
+        assert!(second.context.iter().all(|&b| b == b'N'));
+        assert_eq!(cache.len(), 1);
+
+        let uncached = Context::from_read(&mut genome, &chrom_lens, &read("b"), 6)?;
+        assert_eq!(uncached.context, first.context);
+        Ok(())
+    }
 }
@@ -0,0 +1,355 @@
+//! Import per-read modification calls from other tools as scored Arrow files,
+//! using the same schema as cawlr score
+use std::{
+    fmt::Display,
+    io::{BufRead, BufReader, Read, Write},
+    str::FromStr,
+};
+
+use arrow2::io::ipc::write::FileWriter;
+use eyre::Result;
+use serde::Deserialize;
+
+use crate::{
+    arrow::{
+        arrow_utils::{save, wrap_writer},
+        metadata::{Metadata, MetadataExt, Strand},
+        scored_read::{Score, ScoredRead},
+    },
+    strand_map::StrandMap,
+};
+
+/// Tools whose output can be converted with cawlr convert
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ConvertFormat {
+    /// per_read_modified_base_calls.txt from Megalodon
+    #[default]
+    Megalodon,
+    /// bedMethyl from modkit pileup, with each position as a read
+    Modkit,
+}
+
+impl Display for ConvertFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let res = match self {
+            Self::Megalodon => "megalodon",
+            Self::Modkit => "modkit",
+        };
+        write!(f, "{res}")
+    }
+}
+
+impl FromStr for ConvertFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "megalodon" => Ok(Self::Megalodon),
+            "modkit" => Ok(Self::Modkit),
+            _ => Err(format!(
+                "Invalid format {s}: either 'megalodon' or 'modkit'"
+            )),
+        }
+    }
+}
+
+/// Line of Megalodon per_read_modified_base_calls.txt, columns are matched
+/// by the header so their order doesn't matter
+#[derive(Debug, Deserialize)]
+struct MegalodonLine {
+    read_id: String,
+    chrm: String,
+    strand: String,
+    pos: u64,
+    #[serde(default)]
+    ref_motif: Option<String>,
+    mod_base: String,
+    mod_log_prob: f64,
+    can_log_prob: f64,
+}
+
+impl MegalodonLine {
+    /// Probability of modification, exp(mod) / (exp(mod) + exp(can))
+    fn final_score(&self) -> f64 {
+        1.0 / (1.0 + (self.can_log_prob - self.mod_log_prob).exp())
+    }
+
+    fn strand(&self) -> Strand {
+        match self.strand.as_str() {
+            "+" | "1" => Strand::plus(),
+            "-" | "-1" => Strand::minus(),
+            _ => Strand::unknown(),
+        }
+    }
+
+    fn to_score(&self) -> Score {
+        let kmer = self
+            .ref_motif
+            .clone()
+            .unwrap_or_else(|| self.mod_base.clone());
+        let score = self.final_score();
+        Score::new(self.pos, kmer, false, Some(score), 0.0, score)
+            .with_likelihoods(self.mod_log_prob, self.can_log_prob)
+    }
+}
+
+/// Position from modkit pileup as a read with a single score, named
+/// chrom:pos. The score is the fraction of reads modified at the position.
+fn modkit_to_read(line: &str) -> Result<ScoredRead> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if fields.len() < 11 {
+        return Err(eyre::eyre!(
+            "Expected at least 11 columns, found {}",
+            fields.len()
+        ));
+    }
+    let chrom = fields[0].to_string();
+    let pos: u64 = fields[1].parse()?;
+    let mod_code = fields[3].to_string();
+    let strand = match fields[5] {
+        "+" => Strand::plus(),
+        "-" => Strand::minus(),
+        _ => Strand::unknown(),
+    };
+    let percent_modified: f64 = fields[10].parse()?;
+    let fraction = percent_modified / 100.;
+
+    let meta = Metadata::new(
+        format!("{chrom}:{pos}"),
+        chrom,
+        pos,
+        1,
+        strand,
+        String::new(),
+    );
+    let score = Score::new(pos, mod_code, false, Some(fraction), 0.0, fraction);
+    Ok(ScoredRead::new(meta, vec![score]))
+}
+
+fn megalodon_to_read(mlines: &[MegalodonLine]) -> ScoredRead {
+    let start = mlines.iter().map(|mline| mline.pos).min().unwrap();
+    let end = mlines.iter().map(|mline| mline.pos).max().unwrap();
+    let meta = Metadata::new(
+        mlines[0].read_id.clone(),
+        mlines[0].chrm.clone(),
+        start,
+        end - start + 1,
+        mlines[0].strand(),
+        String::new(),
+    );
+    let mut scores: Vec<Score> = mlines.iter().map(MegalodonLine::to_score).collect();
+    scores.sort_by_key(|score| score.pos);
+    ScoredRead::new(meta, scores)
+}
+
+pub struct ConvertOptions {
+    format: ConvertFormat,
+    strand_map: Option<StrandMap>,
+}
+
+impl ConvertOptions {
+    pub fn new(format: ConvertFormat) -> Self {
+        Self {
+            format,
+            strand_map: None,
+        }
+    }
+
+    /// Strand of each read from the bam file, used instead of the strand
+    /// given by the tool
+    pub fn strand_map(&mut self, strand_map: Option<StrandMap>) -> &mut Self {
+        self.strand_map = strand_map;
+        self
+    }
+
+    /// Convert the calls into scored reads, returning the number of reads
+    /// written. Calls for a read are expected to be on consecutive lines.
+    pub fn run<R: Read, W: Write>(&self, reader: R, writer: W) -> Result<usize> {
+        match self.format {
+            ConvertFormat::Megalodon => self.run_megalodon(reader, writer),
+            ConvertFormat::Modkit => run_modkit(reader, writer),
+        }
+    }
+
+    fn run_megalodon<R: Read, W: Write>(&self, reader: R, writer: W) -> Result<usize> {
+        let schema = ScoredRead::schema();
+        let mut writer = wrap_writer(writer, &schema)?;
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(b'\t')
+            .from_reader(reader);
+
+        let mut n_reads = 0;
+        let mut acc: Vec<MegalodonLine> = Vec::new();
+        for mline in reader.deserialize() {
+            let mline: MegalodonLine = mline?;
+            if acc.last().is_some_and(|last| last.read_id != mline.read_id) {
+                self.save_read(&mut writer, &acc)?;
+                n_reads += 1;
+                acc.clear();
+            }
+            acc.push(mline);
+        }
+        if !acc.is_empty() {
+            self.save_read(&mut writer, &acc)?;
+            n_reads += 1;
+        }
+        writer.finish()?;
+        Ok(n_reads)
+    }
+
+    fn save_read<W: Write>(
+        &self,
+        writer: &mut FileWriter<W>,
+        mlines: &[MegalodonLine],
+    ) -> Result<()> {
+        let mut read = megalodon_to_read(mlines);
+        if let Some(plus_stranded) = self
+            .strand_map
+            .as_ref()
+            .and_then(|strand_map| strand_map.get_plus_stranded(read.name()))
+        {
+            read.metadata.strand = if plus_stranded {
+                Strand::plus()
+            } else {
+                Strand::minus()
+            };
+        }
+        save(writer, &[read])
+    }
+}
+
+/// Positions from modkit pileup written in each record batch
+const MODKIT_BATCH_SIZE: usize = 4096;
+
+fn run_modkit<R: Read, W: Write>(reader: R, writer: W) -> Result<usize> {
+    let schema = ScoredRead::schema();
+    let mut writer = wrap_writer(writer, &schema)?;
+    let mut n_reads = 0;
+    let mut acc = Vec::with_capacity(MODKIT_BATCH_SIZE);
+    for (idx, line) in BufReader::new(reader).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() || line.starts_with('#') || line.starts_with("track") {
+            continue;
+        }
+        let read = modkit_to_read(&line)
+            .map_err(|e| eyre::eyre!("Invalid modkit pileup line {}: {e}", idx + 1))?;
+        acc.push(read);
+        if acc.len() >= MODKIT_BATCH_SIZE {
+            save(&mut writer, &acc)?;
+            n_reads += acc.len();
+            acc.clear();
+        }
+    }
+    save(&mut writer, &acc)?;
+    n_reads += acc.len();
+    writer.finish()?;
+    Ok(n_reads)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use float_eq::assert_float_eq;
+
+    use super::*;
+    use crate::arrow::arrow_utils::load_apply;
+
+    #[test]
+    fn test_convert_megalodon() -> Result<()> {
+        let calls = "read_id\tchrm\tstrand\tpos\tmod_log_prob\tcan_log_prob\tmod_base\n\
+                     read1\tchrI\t+\t120\t-0.2231\t-1.6094\tm\n\
+                     read1\tchrI\t+\t100\t-2.3026\t-0.1054\tm\n\
+                     read2\tchrII\t-\t50\t-0.6931\t-0.6931\tm\n";
+        let mut output = Vec::new();
+        let n_reads =
+            ConvertOptions::new(ConvertFormat::Megalodon).run(calls.as_bytes(), &mut output)?;
+        assert_eq!(n_reads, 2);
+
+        let mut reads = Vec::new();
+        load_apply(Cursor::new(output), |mut xs: Vec<ScoredRead>| {
+            reads.append(&mut xs);
+            Ok(())
+        })?;
+        assert_eq!(reads.len(), 2);
+
+        let read = &reads[0];
+        assert_eq!(read.name(), "read1");
+        assert_eq!(read.chrom(), "chrI");
+        assert_eq!(read.start_0b(), 100);
+        assert_eq!(read.np_length(), 21);
+        assert_eq!(read.strand(), Strand::plus());
+        let scores = read.scores();
+        assert_eq!(scores[0].pos, 100);
+        assert_eq!(scores[0].kmer, "m");
+        assert_float_eq!(scores[0].score, 0.1, abs <= 1e-3);
+        assert_float_eq!(scores[1].score, 0.8, abs <= 1e-3);
+        assert_eq!(scores[1].signal_score, Some(scores[1].score));
+        assert_eq!(scores[1].pos_ln_f(), Some(-0.2231));
+        assert_eq!(scores[1].neg_ln_f(), Some(-1.6094));
+
+        let read = &reads[1];
+        assert_eq!(read.strand(), Strand::minus());
+        assert_float_eq!(read.scores()[0].score, 0.5, abs <= 1e-6);
+        Ok(())
+    }
+
+    #[test]
+    fn test_convert_strand_map() -> Result<()> {
+        let calls = "read_id\tchrm\tstrand\tpos\tref_motif\tmod_base\tmod_log_prob\tcan_log_prob\n\
+                     read1\tchrI\t+\t120\tCG\tm\t-0.2231\t-1.6094\n";
+        let mut strand_map = StrandMap::default();
+        strand_map.insert("read1", Strand::minus());
+        let mut output = Vec::new();
+        ConvertOptions::new(ConvertFormat::Megalodon)
+            .strand_map(Some(strand_map))
+            .run(calls.as_bytes(), &mut output)?;
+
+        load_apply(Cursor::new(output), |reads: Vec<ScoredRead>| {
+            assert_eq!(reads[0].strand(), Strand::minus());
+            assert_eq!(reads[0].scores()[0].kmer, "CG");
+            Ok(())
+        })?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_convert_modkit() -> Result<()> {
+        // Columns after the 9th are space separated in older modkit versions
+        let pileup = "chrI\t100\t101\ta\t10\t+\t100\t101\t255,0,0\t10 80.00 8 2 0 0 0 0 0\n\
+                      chrI\t102\t103\ta\t10\t-\t102\t103\t255,0,0\t10\t20.00\t2\t8\t0\t0\t0\t0\t0\n\
+                      chrI\t110\t111\ta\t4\t+\t110\t111\t255,0,0\t4\t50.00\t2\t2\t0\t0\t0\t0\t0\n\
+                      chrII\t5\t6\tm\t1\t.\t5\t6\t255,0,0\t1\t100.00\t1\t0\t0\t0\t0\t0\t0\n\
+                      chrII\t9\t10\tm\t3\t+\t9\t10\t255,0,0\t3\t0.00\t0\t3\t0\t0\t0\t0\t0\n";
+        let mut output = Vec::new();
+        let n_reads =
+            ConvertOptions::new(ConvertFormat::Modkit).run(pileup.as_bytes(), &mut output)?;
+        assert_eq!(n_reads, 5);
+
+        let mut reads = Vec::new();
+        load_apply(Cursor::new(output), |mut xs: Vec<ScoredRead>| {
+            reads.append(&mut xs);
+            Ok(())
+        })?;
+        assert_eq!(reads.len(), 5);
+        assert!(reads.iter().all(|read| read.scores().len() == 1));
+
+        assert_eq!(reads[0].name(), "chrI:100");
+        assert_eq!(reads[0].start_0b(), 100);
+        assert_eq!(reads[0].scores()[0].pos, 100);
+        assert_eq!(reads[0].scores()[0].kmer, "a");
+        assert_float_eq!(reads[0].scores()[0].score, 0.8, abs <= 1e-12);
+        assert_eq!(reads[0].strand(), Strand::plus());
+        assert_eq!(reads[1].strand(), Strand::minus());
+        assert_float_eq!(reads[1].scores()[0].score, 0.2, abs <= 1e-12);
+        assert_eq!(reads[3].name(), "chrII:5");
+        assert_eq!(reads[3].strand(), Strand::unknown());
+        assert_float_eq!(reads[4].scores()[0].score, 0.0, abs <= 1e-12);
+
+        let mut output = Vec::new();
+        assert!(ConvertOptions::new(ConvertFormat::Modkit)
+            .run("chrI\t100\t101\ta\n".as_bytes(), &mut output)
+            .is_err());
+        Ok(())
+    }
+}
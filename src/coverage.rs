@@ -0,0 +1,137 @@
+use std::{
+    fs::File,
+    io::{BufReader, Write},
+    path::Path,
+};
+
+use eyre::Result;
+use fnv::FnvHashMap;
+
+use crate::arrow::{arrow_utils::load_apply, metadata::MetadataExt, scored_read::ScoredRead};
+
+/// Number of plus and minus strand reads with a score at each position
+#[derive(Debug, Default, Clone)]
+pub struct CoverageMap {
+    counts: FnvHashMap<(String, u64), (usize, usize)>,
+}
+
+impl CoverageMap {
+    /// Count the reads in the output of cawlr score, streaming through the
+    /// file
+    pub fn from_arrow_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let reader = BufReader::new(File::open(path)?);
+        let mut coverage = Self::default();
+        load_apply(reader, |reads: Vec<ScoredRead>| {
+            reads.iter().for_each(|read| coverage.add_read(read));
+            Ok(())
+        })?;
+        Ok(coverage)
+    }
+
+    /// Count the read at each of its scored positions. Reads with an unknown
+    /// strand are skipped.
+    pub fn add_read(&mut self, read: &ScoredRead) {
+        let strand = read.strand();
+        if strand.is_unknown_strand() {
+            return;
+        }
+        for score in read.scores() {
+            let (plus, minus) = self
+                .counts
+                .entry((read.chrom().to_string(), score.pos))
+                .or_default();
+            if strand.is_minus_strand() {
+                *minus += 1;
+            } else {
+                *plus += 1;
+            }
+        }
+    }
+
+    /// Number of plus and minus strand reads scored at the position
+    pub fn coverage_at(&self, chrom: &str, pos: u64) -> (usize, usize) {
+        self.counts
+            .get(&(chrom.to_string(), pos))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+
+    /// Write a 6 column bed file, sorted by position, with a line for each
+    /// strand covering a position with the number of reads in the score
+    /// column
+    pub fn write_bed<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let mut positions: Vec<_> = self.counts.iter().collect();
+        positions.sort_unstable_by_key(|(pos, _)| *pos);
+        for ((chrom, pos), &(plus, minus)) in positions {
+            for (count, strand) in [(plus, "+"), (minus, "-")] {
+                if count > 0 {
+                    writeln!(writer, "{chrom}\t{pos}\t{}\t.\t{count}\t{strand}", pos + 1)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use assert_fs::TempDir;
+
+    use super::*;
+    use crate::arrow::{
+        arrow_utils::{save, wrap_writer},
+        metadata::{Metadata, Strand},
+        scored_read::Score,
+    };
+
+    fn read(start: u64, end: u64, strand: Strand) -> ScoredRead {
+        let metadata = Metadata::new(
+            "read".to_string(),
+            "chrI".to_string(),
+            start,
+            end - start + 1,
+            strand,
+            String::new(),
+        );
+        let scores = (start..=end)
+            .map(|pos| Score::new(pos, "AAAAAA".to_string(), false, Some(0.5), 0.0, 0.5))
+            .collect();
+        ScoredRead::new(metadata, scores)
+    }
+
+    #[test]
+    fn test_coverage_map() -> Result<()> {
+        let reads = vec![
+            read(100, 120, Strand::plus()),
+            read(110, 130, Strand::plus()),
+            read(115, 125, Strand::minus()),
+            read(100, 200, Strand::unknown()),
+        ];
+        let temp_dir = TempDir::new()?;
+        let path = temp_dir.path().join("scores.arrow");
+        let mut writer = wrap_writer(File::create(&path)?, &ScoredRead::schema())?;
+        save(&mut writer, &reads)?;
+        writer.finish()?;
+
+        let coverage = CoverageMap::from_arrow_file(&path)?;
+        assert_eq!(coverage.coverage_at("chrI", 100), (1, 0));
+        assert_eq!(coverage.coverage_at("chrI", 112), (2, 0));
+        assert_eq!(coverage.coverage_at("chrI", 118), (2, 1));
+        assert_eq!(coverage.coverage_at("chrI", 126), (1, 0));
+        assert_eq!(coverage.coverage_at("chrI", 150), (0, 0));
+        assert_eq!(coverage.coverage_at("chrII", 118), (0, 0));
+
+        let mut bed = Vec::new();
+        coverage.write_bed(&mut bed)?;
+        let bed = String::from_utf8(bed)?;
+        let lines: Vec<&str> = bed.lines().collect();
+        assert_eq!(lines.len(), 31 + 11);
+        assert_eq!(lines[0], "chrI\t100\t101\t.\t1\t+");
+        assert!(lines.contains(&"chrI\t118\t119\t.\t1\t-"));
+        Ok(())
+    }
+}
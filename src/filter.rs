@@ -1,4 +1,7 @@
-use crate::{arrow::metadata::MetadataExt, region::Region};
+use crate::{
+    arrow::{metadata::MetadataExt, scored_read::ScoredRead},
+    region::Region,
+};
 
 pub struct FilterOptions {
     regions: Vec<Region>,
@@ -12,4 +15,14 @@ impl FilterOptions {
     pub fn any_valid<M: MetadataExt + ?Sized>(&self, meta: &M) -> bool {
         self.regions.iter().any(|r| r.valid(meta))
     }
+
+    /// Copies of the read trimmed to each region it overlaps
+    pub fn trim_to_regions<'a>(
+        &'a self,
+        read: &'a ScoredRead,
+    ) -> impl Iterator<Item = ScoredRead> + 'a {
+        self.regions
+            .iter()
+            .filter_map(|r| read.trim_to_region(r.chrom(), r.start(), r.end()))
+    }
 }
@@ -1,56 +1,229 @@
 use std::{
+    fmt,
     fs::File,
-    io::{BufWriter, Write},
+    io::{Seek, Write},
     path::Path,
+    str::FromStr,
 };
 
+use arrow2::io::ipc::read::read_file_metadata;
+use arrow2_convert::{deserialize::ArrowDeserialize, field::ArrowField};
 use eyre::Result;
+use serde::Serialize;
 
-use crate::arrow::{arrow_utils::load_apply, eventalign::Eventalign, metadata::MetadataExt};
+use crate::{
+    arrow::{
+        arrow_utils::{arrow_type_tag, load_apply, SchemaExt},
+        eventalign::Eventalign,
+        metadata::MetadataExt,
+        scored_read::ScoredRead,
+    },
+    utils::AtomicFileWriter,
+};
+
+/// Reads that can be written as a line in the index bed file.
+trait Indexable: MetadataExt {
+    /// Number of positions with data, used as the bed score column
+    fn n_scored(&self) -> usize;
+}
+
+impl Indexable for Eventalign {
+    fn n_scored(&self) -> usize {
+        self.signal_iter().count()
+    }
+}
+
+impl Indexable for ScoredRead {
+    fn n_scored(&self) -> usize {
+        self.scores().len()
+    }
+}
+
+/// Number of bases on the genome covered by the read
+fn covered_bases<M: MetadataExt>(metadata: &M) -> u64 {
+    metadata.end_1b_excl() - metadata.start_0b()
+}
+
+/// File format of the index written by [IndexOptions::run]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IndexFormat {
+    /// Six column bed file: chrom, chromStart, chromEnd, name, score, strand
+    #[default]
+    Bed,
+    /// Array of objects with the read name, coordinates, strand, and offset
+    /// in the Arrow file
+    Json,
+}
+
+impl fmt::Display for IndexFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            IndexFormat::Bed => "bed",
+            IndexFormat::Json => "json",
+        };
+        write!(f, "{s}")
+    }
+}
+
+impl FromStr for IndexFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bed" => Ok(IndexFormat::Bed),
+            "json" => Ok(IndexFormat::Json),
+            _ => Err(format!("Invalid index format {s}, expected bed or json")),
+        }
+    }
+}
+
+/// Location of a read in the genome and in the Arrow file
+#[derive(Debug, Serialize)]
+struct IndexEntry {
+    read_name: String,
+    chrom: String,
+    start: u64,
+    end: u64,
+    strand: String,
+    /// Byte offset of the record batch containing the read
+    file_offset: i64,
+    /// Number of positions with data, used as the bed score column
+    #[serde(skip)]
+    n_scored: usize,
+}
+
+impl IndexEntry {
+    fn new<M: Indexable>(read: &M, file_offset: i64) -> Self {
+        Self {
+            read_name: read.name().to_string(),
+            chrom: read.chrom().to_string(),
+            start: read.start_0b(),
+            end: read.end_1b_excl(),
+            strand: read.strand().as_str().to_string(),
+            file_offset,
+            n_scored: read.n_scored(),
+        }
+    }
+
+    /// Six column bed line: chrom, chromStart, chromEnd, name, score, strand
+    fn to_bed_line(&self) -> String {
+        format!(
+            "{}\t{}\t{}\t{}\t{}\t{}",
+            self.chrom, self.start, self.end, self.read_name, self.n_scored, self.strand
+        )
+    }
+}
 
-fn to_bed_line<M: MetadataExt>(metadata: M, chunk_idx: usize, rec_idx: usize) -> String {
-    let chrom = metadata.chrom();
-    let start = metadata.start_0b();
-    let stop = metadata.end_1b_excl();
-    let read_name = metadata.name();
-    let strand = metadata.strand().as_str();
-    format!(
-        "{chrom}\t{start}\t{stop}\t{read_name}\t0\t{strand}\t{0}\t{1}",
-        chunk_idx, rec_idx
-    )
+#[derive(Default)]
+pub struct IndexOptions {
+    min_length: u64,
+    output_format: IndexFormat,
+    overwrite: bool,
+}
+
+impl IndexOptions {
+    /// Skip reads covering fewer than min_length bases
+    pub fn min_length(&mut self, min_length: u64) -> &mut Self {
+        self.min_length = min_length;
+        self
+    }
+
+    /// Write the index as a bed file, the default, or as JSON
+    pub fn output_format(&mut self, output_format: IndexFormat) -> &mut Self {
+        self.output_format = output_format;
+        self
+    }
+
+    /// Replace an existing index file instead of failing
+    pub fn overwrite(&mut self, overwrite: bool) -> &mut Self {
+        self.overwrite = overwrite;
+        self
+    }
+
+    /// Writes an index of the reads in the Arrow file to {filepath}.idx.bed,
+    /// or {filepath}.idx.json for the JSON format, sorted by chrom, start,
+    /// end and read name. Works for output from both cawlr collapse and cawlr
+    /// score.
+    pub fn run<P>(&self, filepath: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let output_filepath = filepath
+            .as_ref()
+            .to_str()
+            .ok_or_else(|| eyre::eyre!("Invalid unicode in path"))?;
+        let idx_filepath = format!("{}.idx.{}", output_filepath, self.output_format);
+
+        let tag = arrow_type_tag(&filepath)?;
+        let mut entries = if tag == ScoredRead::type_as_str() {
+            self.index_entries::<_, ScoredRead>(&filepath)?
+        } else if tag == Eventalign::type_as_str() {
+            self.index_entries::<_, Eventalign>(&filepath)?
+        } else {
+            return Err(eyre::eyre!("Unknown Arrow file type: {tag}"));
+        };
+        entries.sort_by(|a, b| {
+            (&a.chrom, a.start, a.end, &a.read_name).cmp(&(&b.chrom, b.start, b.end, &b.read_name))
+        });
+
+        let mut writer = AtomicFileWriter::create(idx_filepath, self.overwrite)?;
+        match self.output_format {
+            IndexFormat::Bed => {
+                for entry in entries.iter() {
+                    writeln!(writer, "{}", entry.to_bed_line())?;
+                }
+            }
+            IndexFormat::Json => {
+                serde_json::to_writer_pretty(&mut writer, &entries)?;
+                writeln!(writer)?;
+            }
+        }
+        writer.commit()
+    }
+
+    fn index_entries<P, T>(&self, filepath: P) -> Result<Vec<IndexEntry>>
+    where
+        P: AsRef<Path>,
+        T: Indexable + ArrowField<Type = T> + ArrowDeserialize + 'static,
+        for<'a> &'a <T as ArrowDeserialize>::ArrayType: IntoIterator,
+    {
+        let mut file = File::open(filepath)?;
+        let blocks = read_file_metadata(&mut file)?.blocks;
+        file.rewind()?;
+        let mut entries = Vec::new();
+        let mut batch_idx = 0;
+        load_apply(file, |chunk: Vec<T>| {
+            let file_offset = blocks.get(batch_idx).map(|b| b.offset).unwrap_or(-1);
+            batch_idx += 1;
+            for read in chunk.iter() {
+                if covered_bases(read) < self.min_length {
+                    log::debug!("Read {} shorter than min length, skipping", read.name());
+                    continue;
+                }
+                entries.push(IndexEntry::new(read, file_offset));
+            }
+            Ok(())
+        })?;
+        Ok(entries)
+    }
 }
 
 pub fn index<P>(filepath: P) -> Result<()>
 where
     P: AsRef<Path>,
 {
-    let file = File::open(&filepath)?;
-    let output_filepath = filepath
-        .as_ref()
-        .to_str()
-        .ok_or_else(|| eyre::eyre!("Invalid unicode in path"))?;
-    let idx_filepath = format!("{}.idx.bed", output_filepath);
-    let idx_filepath = Path::new(&idx_filepath);
-    let writer = File::create(idx_filepath)?;
-    let mut writer = BufWriter::new(writer);
-
-    let mut chunk_idx = 0usize;
-    load_apply(file, |chunk: Vec<Eventalign>| {
-        for (rec_idx, event) in chunk.into_iter().enumerate() {
-            let idx_rec = to_bed_line(event, chunk_idx, rec_idx);
-            writeln!(writer, "{}", idx_rec)?;
-        }
-        chunk_idx += 1;
-        Ok(())
-    })?;
-    writer.flush()?;
-    Ok(())
+    IndexOptions::default().run(filepath)
 }
 
 #[cfg(test)]
 mod test {
     use std::path::PathBuf;
 
+    use assert_fs::TempDir;
+
+    use super::*;
+    use crate::collapse::CollapseOptions;
+
     #[test]
     fn test_new_file_extension() {
         let path = PathBuf::from("test.output");
@@ -58,4 +231,153 @@ mod test {
 
         assert_eq!(x, String::from("test.output.extra.stuff"));
     }
+
+    fn collapse_to(temp_dir: &TempDir, eventalign: &str, bam: &str) -> Result<PathBuf> {
+        let input = File::open(eventalign)?;
+        let output = temp_dir.path().join("collapse.arrow");
+        let mut collapse = CollapseOptions::try_new(bam, Some(&output))?;
+        collapse.progress(false).run(input)?;
+        Ok(output)
+    }
+
+    fn read_bed(path: &Path) -> Result<Vec<csv::StringRecord>> {
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(b'\t')
+            .has_headers(false)
+            .from_path(path)?;
+        let recs = reader.records().collect::<Result<Vec<_>, _>>()?;
+        Ok(recs)
+    }
+
+    #[test]
+    fn test_index_bed() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let arrow_file = collapse_to(
+            &temp_dir,
+            "extra/neg_control.eventalign.txt",
+            "extra/neg_control.bam",
+        )?;
+        index(&arrow_file)?;
+
+        let bed_file = temp_dir.path().join("collapse.arrow.idx.bed");
+        let recs = read_bed(&bed_file)?;
+        assert_eq!(recs.len(), 98);
+        for rec in recs.iter() {
+            assert_eq!(rec.len(), 6);
+            assert!(!rec[0].is_empty());
+            let start: u64 = rec[1].parse()?;
+            let stop: u64 = rec[2].parse()?;
+            assert!(start < stop);
+            assert!(!rec[3].is_empty());
+            let score: usize = rec[4].parse()?;
+            assert!(score > 0);
+            assert!(["+", "-"].contains(&&rec[5]));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_index_min_length() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let arrow_file = collapse_to(
+            &temp_dir,
+            "extra/single_read.eventalign.txt",
+            "extra/single_read.bam",
+        )?;
+        let bed_file = temp_dir.path().join("collapse.arrow.idx.bed");
+
+        IndexOptions::default().min_length(178).run(&arrow_file)?;
+        let recs = read_bed(&bed_file)?;
+        assert_eq!(recs.len(), 1);
+        assert_eq!(&recs[0][0], "chrXIII");
+        assert_eq!(&recs[0][1], "182504");
+        assert_eq!(&recs[0][2], "182682");
+        assert_eq!(&recs[0][5], "+");
+
+        assert!(IndexOptions::default()
+            .min_length(179)
+            .run(&arrow_file)
+            .is_err());
+        IndexOptions::default()
+            .min_length(179)
+            .overwrite(true)
+            .run(&arrow_file)?;
+        let recs = read_bed(&bed_file)?;
+        assert!(recs.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_index_formats() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let arrow_file = collapse_to(
+            &temp_dir,
+            "extra/neg_control.eventalign.txt",
+            "extra/neg_control.bam",
+        )?;
+        let mut expected = Vec::new();
+        load_apply(File::open(&arrow_file)?, |reads: Vec<Eventalign>| {
+            for read in reads {
+                expected.push((
+                    read.chrom().to_string(),
+                    read.start_0b(),
+                    read.end_1b_excl(),
+                    read.name().to_string(),
+                ));
+            }
+            Ok(())
+        })?;
+        expected.sort();
+
+        IndexOptions::default()
+            .output_format(IndexFormat::Bed)
+            .run(&arrow_file)?;
+        let recs = read_bed(&temp_dir.path().join("collapse.arrow.idx.bed"))?;
+        let bed = recs
+            .iter()
+            .map(|rec| -> Result<_> {
+                Ok((
+                    rec[0].to_string(),
+                    rec[1].parse::<u64>()?,
+                    rec[2].parse::<u64>()?,
+                    rec[3].to_string(),
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(bed, expected);
+
+        IndexOptions::default()
+            .output_format(IndexFormat::Json)
+            .run(&arrow_file)?;
+        let json = std::fs::read_to_string(temp_dir.path().join("collapse.arrow.idx.json"))?;
+        let json: serde_json::Value = serde_json::from_str(&json)?;
+        let entries = json.as_array().unwrap();
+        assert_eq!(entries.len(), expected.len());
+        for (entry, (chrom, start, end, name)) in entries.iter().zip(expected.iter()) {
+            let entry = entry.as_object().unwrap();
+            let mut keys = entry.keys().map(String::as_str).collect::<Vec<_>>();
+            keys.sort_unstable();
+            assert_eq!(
+                keys,
+                vec![
+                    "chrom",
+                    "end",
+                    "file_offset",
+                    "read_name",
+                    "start",
+                    "strand"
+                ]
+            );
+            assert_eq!(entry["chrom"], chrom.as_str());
+            assert_eq!(entry["start"], *start);
+            assert_eq!(entry["end"], *end);
+            assert_eq!(entry["read_name"], name.as_str());
+            assert!(["+", "-"].contains(&entry["strand"].as_str().unwrap()));
+            assert!(entry["file_offset"].as_i64().unwrap() >= 0);
+        }
+
+        assert_eq!("json".parse(), Ok(IndexFormat::Json));
+        assert!("tsv".parse::<IndexFormat>().is_err());
+        Ok(())
+    }
 }
@@ -1,10 +1,16 @@
 pub mod agg_blocks;
 pub mod arrow;
+pub mod bedmethyl;
 pub mod bkde;
+pub mod calibrate;
 pub mod collapse;
+pub mod compare;
 pub mod context;
+pub mod convert;
+pub mod coverage;
 pub mod filter;
 pub mod index;
+pub mod modbam;
 pub mod motif;
 pub mod npsmlr;
 pub mod plus_strand_map;
@@ -13,7 +19,10 @@ pub mod region;
 pub mod score;
 pub mod score_model;
 pub mod sma;
-mod strand_map;
+pub mod stats;
+pub mod strand_map;
+#[cfg(test)]
+mod test_utils;
 pub mod train;
 pub mod utils;
 pub mod validated;
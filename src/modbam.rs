@@ -0,0 +1,323 @@
+//! Write the scores from cawlr score into the MM/ML tags of a bam file, for
+//! IGV's base modification coloring or modkit.
+use std::{
+    fmt,
+    io::{Read, Seek, Write},
+    path::Path,
+    str::FromStr,
+};
+
+use arrow2::io::ipc::read::read_file_metadata;
+use bam::{BamReader, BamWriter, Record, RecordWriter};
+use bio::alphabets::dna::revcomp;
+use eyre::Result;
+use fnv::FnvHashMap;
+
+use crate::{
+    arrow::{arrow_utils::load_apply, metadata::MetadataExt, scored_read::ScoredRead},
+    score::ScoringMethod,
+};
+
+/// Modification written in the MM tag, in the form of the tag, ie A+a for
+/// 6mA or C+m for 5mC. The modification is always on the strand that was
+/// sequenced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BaseMod {
+    canonical: u8,
+    code: String,
+}
+
+impl Default for BaseMod {
+    fn default() -> Self {
+        Self {
+            canonical: b'A',
+            code: "a".to_string(),
+        }
+    }
+}
+
+impl fmt::Display for BaseMod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}+{}", self.canonical as char, self.code)
+    }
+}
+
+impl FromStr for BaseMod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("Invalid base modification {s}: expected <base>+<code>, ie A+a");
+        let (base, code) = s.split_once('+').ok_or_else(err)?;
+        let canonical = match base.as_bytes() {
+            [b] if b"ACGTUN".contains(&b.to_ascii_uppercase()) => b.to_ascii_uppercase(),
+            _ => return Err(err()),
+        };
+        if code.is_empty() || !code.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(err());
+        }
+        Ok(Self {
+            canonical,
+            code: code.to_string(),
+        })
+    }
+}
+
+/// Options for adding MM/ML tags to a bam file from the scores of its reads
+#[derive(Debug, Clone, Default)]
+pub struct ModBamOptions {
+    base_mod: BaseMod,
+}
+
+impl ModBamOptions {
+    pub fn new(base_mod: BaseMod) -> Self {
+        Self { base_mod }
+    }
+
+    /// Copy the bam file to the writer, replacing the MM/ML tags of primary
+    /// alignments with the scores of the read. Reads without scores, and
+    /// secondary or supplementary alignments, are written unchanged. Returns
+    /// the number of reads tagged.
+    ///
+    /// The scores of every read are kept in memory while the bam file is read.
+    pub fn run<R, P, W>(&self, mut scores: R, bam_file: P, writer: W) -> Result<usize>
+    where
+        R: Read + Seek,
+        P: AsRef<Path>,
+        W: Write,
+    {
+        let metadata = read_file_metadata(&mut scores)?;
+        scores.rewind()?;
+        let scoring_method = ScoringMethod::from_schema(&metadata.schema)?;
+        let mut read_probs: FnvHashMap<String, FnvHashMap<u64, f64>> = FnvHashMap::default();
+        load_apply(scores, |reads: Vec<ScoredRead>| {
+            for read in reads {
+                let probs = read
+                    .scores()
+                    .iter()
+                    .filter(|score| !score.score.is_nan())
+                    .map(|score| (score.pos, scoring_method.probability(score.score)))
+                    .collect();
+                read_probs.insert(read.name().to_string(), probs);
+            }
+            Ok(())
+        })?;
+
+        let reader = BamReader::from_path(bam_file, 2u16)?;
+        let mut writer = BamWriter::from_stream(writer, reader.header().clone())?;
+        let mut n_tagged = 0;
+        for record in reader {
+            let mut record = record?;
+            let flag = record.flag();
+            let probs = std::str::from_utf8(record.name())
+                .ok()
+                .and_then(|name| read_probs.get(name));
+            if let Some(probs) = probs {
+                if !flag.is_secondary() && !flag.is_supplementary() {
+                    if let Some((mm, ml)) = self.mm_ml_tags(&record, probs) {
+                        let tags = record.tags_mut();
+                        for tag in [b"MM", b"Mm", b"ML", b"Ml"] {
+                            tags.remove(tag);
+                        }
+                        tags.push_string(b"MM", mm.as_bytes());
+                        tags.push_array(b"ML", &ml);
+                        n_tagged += 1;
+                    }
+                }
+            }
+            writer.write(&record)?;
+        }
+        writer.finish()?;
+        Ok(n_tagged)
+    }
+
+    /// MM tag string and ML probabilities for the scores at aligned bases
+    /// matching the canonical base of the modification, or None if there
+    /// aren't any. The MM tag uses `?` since bases without a score weren't
+    /// called, rather than called unmodified.
+    fn mm_ml_tags(
+        &self,
+        record: &Record,
+        probs: &FnvHashMap<u64, f64>,
+    ) -> Option<(String, Vec<u8>)> {
+        let seq = record.sequence().to_vec();
+        let reverse = record.flag().is_reverse_strand();
+        // Positions in the MM tag count bases of the read as sequenced
+        let read_seq = if reverse { revcomp(&seq) } else { seq };
+        let mut scored = record
+            .matching_pairs()
+            .filter_map(|(query_pos, ref_pos)| {
+                let query_pos = query_pos as usize;
+                let idx = if reverse {
+                    read_seq.len() - 1 - query_pos
+                } else {
+                    query_pos
+                };
+                probs.get(&(ref_pos as u64)).map(|&prob| (idx, prob))
+            })
+            .collect::<Vec<_>>();
+        scored.sort_by_key(|&(idx, _)| idx);
+
+        let mut mm = format!("{}?", self.base_mod);
+        let mut ml = Vec::new();
+        let mut n_skipped = 0;
+        let mut scored = scored.into_iter().peekable();
+        for (idx, base) in read_seq.iter().enumerate() {
+            if base.to_ascii_uppercase() != self.base_mod.canonical
+                && self.base_mod.canonical != b'N'
+            {
+                continue;
+            }
+            while scored
+                .next_if(|&(scored_idx, _)| scored_idx < idx)
+                .is_some()
+            {}
+            match scored.next_if(|&(scored_idx, _)| scored_idx == idx) {
+                Some((_, prob)) => {
+                    mm.push_str(&format!(",{n_skipped}"));
+                    ml.push(ml_value(prob));
+                    n_skipped = 0;
+                }
+                None => n_skipped += 1,
+            }
+        }
+        mm.push(';');
+        if ml.is_empty() {
+            log::debug!(
+                "No scores on {} bases of read {}",
+                self.base_mod.canonical as char,
+                String::from_utf8_lossy(record.name())
+            );
+            return None;
+        }
+        Some((mm, ml))
+    }
+}
+
+/// Probability encoded in the ML tag, where N is for probabilities between
+/// N/256 and (N+1)/256
+fn ml_value(prob: f64) -> u8 {
+    (prob * 256.).floor().clamp(0., 255.) as u8
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use assert_fs::TempDir;
+    use bam::{header::HeaderEntry, record::tags::TagValue, Header};
+
+    use super::*;
+    use crate::arrow::{
+        arrow_utils::{save, wrap_writer},
+        metadata::{Metadata, Strand},
+        scored_read::Score,
+    };
+
+    fn record(name: &[u8], seq: &[u8], cigar: &[u8], reverse: bool) -> Result<Record> {
+        let mut record = Record::new();
+        record.set_name(name.iter().copied());
+        record.set_ref_id(0);
+        record.set_start(100);
+        record
+            .set_seq_qual(seq.iter().copied(), std::iter::empty())
+            .map_err(|e| eyre::eyre!(e))?;
+        record
+            .set_cigar(cigar.iter().copied())
+            .map_err(|e| eyre::eyre!(e))?;
+        record.flag_mut().set_strand(!reverse);
+        Ok(record)
+    }
+
+    fn scored_read(name: &str, scores: &[(u64, f64)]) -> ScoredRead {
+        let metadata = Metadata::new(
+            name.to_string(),
+            "chrI".to_string(),
+            100,
+            10,
+            Strand::unknown(),
+            String::new(),
+        );
+        let scores = scores
+            .iter()
+            .map(|&(pos, score)| {
+                Score::new(pos, "AAAAAA".to_string(), false, Some(score), 0.0, score)
+            })
+            .collect();
+        ScoredRead::new(metadata, scores)
+    }
+
+    #[test]
+    fn test_base_mod() {
+        assert_eq!("A+a".parse::<BaseMod>(), Ok(BaseMod::default()));
+        assert_eq!("C+m".parse::<BaseMod>().unwrap().to_string(), "C+m");
+        assert!("A-a".parse::<BaseMod>().is_err());
+        assert!("X+a".parse::<BaseMod>().is_err());
+        assert!("A+".parse::<BaseMod>().is_err());
+    }
+
+    #[test]
+    fn test_modbam() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let bam_file = temp_dir.path().join("reads.bam");
+        let mut header = Header::new();
+        header
+            .push_entry(HeaderEntry::ref_sequence("chrI".to_string(), 1000))
+            .map_err(|e| eyre::eyre!(e))?;
+        let mut bam_writer = BamWriter::from_path(&bam_file, header)?;
+        // Reference positions 100 to 109, with 2 inserted bases after 103
+        let plus = record(b"plus", b"AACAGGTATTAC", b"4M2I6M", false)?;
+        // Sequenced as GTAATACCTGTT
+        let minus = record(b"minus", b"AACAGGTATTAC", b"4M2I6M", true)?;
+        let unscored = record(b"unscored", b"AACAGGTATTAC", b"12M", false)?;
+        for record in [&plus, &minus, &unscored] {
+            bam_writer.write(record)?;
+        }
+        bam_writer.finish()?;
+
+        let reads = vec![
+            // 104 is aligned to a T
+            scored_read("plus", &[(100, 0.9), (103, 0.1), (104, 0.5), (108, 1.0)]),
+            scored_read(
+                "minus",
+                &[(100, 0.8), (101, f64::NAN), (106, 0.6), (107, 0.2)],
+            ),
+        ];
+        let mut scores = Vec::new();
+        let mut writer = wrap_writer(&mut scores, &ScoringMethod::ProbabilityRatio.schema())?;
+        save(&mut writer, &reads)?;
+        writer.finish()?;
+
+        let mut output = Vec::new();
+        let n_tagged = ModBamOptions::default().run(Cursor::new(scores), &bam_file, &mut output)?;
+        assert_eq!(n_tagged, 2);
+
+        let tagged =
+            BamReader::from_stream(Cursor::new(output), 1u16)?.collect::<Result<Vec<_>, _>>()?;
+        let tags = |record: &Record| {
+            let mm = match record.tags().get(b"MM") {
+                Some(TagValue::String(mm, _)) => mm.to_vec(),
+                _ => return None,
+            };
+            let ml = match record.tags().get(b"ML") {
+                Some(TagValue::IntArray(ml)) => ml.raw().to_vec(),
+                _ => return None,
+            };
+            Some((String::from_utf8(mm).unwrap(), ml))
+        };
+        assert_eq!(tagged.len(), 3);
+        // A at read positions 0, 1, 3, 7 and 10, scored at 0, 3 and 10
+        assert_eq!(
+            tags(&tagged[0]),
+            Some(("A+a?,0,1,1;".to_string(), vec![230, 25, 255]))
+        );
+        // A in GTAATACCTGTT at read positions 2, 3 and 5, scored at 2 and 3.
+        // The score at 100 is on a T of the sequenced strand.
+        assert_eq!(
+            tags(&tagged[1]),
+            Some(("A+a?,0,0;".to_string(), vec![51, 153]))
+        );
+        assert_eq!(tags(&tagged[2]), None);
+        assert_eq!(tagged[2].sequence().to_vec(), b"AACAGGTATTAC".to_vec());
+        Ok(())
+    }
+}
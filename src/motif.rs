@@ -86,13 +86,69 @@ impl Motif {
         kmer.contains(self.motif())
     }
 
-    pub(crate) fn surrounding_idxs(&self, pos: u64) -> impl Iterator<Item = u64> {
+    /// Whether the kmer could cover the modified base of a match to the
+    /// motif, ie the modified base is in the kmer and the rest of the motif
+    /// agrees with the kmer where they overlap. These are the kmers cawlr
+    /// score looks at around each motif.
+    pub fn overlaps_kmer(&self, kmer: &str) -> bool {
+        let kmer = kmer.as_bytes();
+        let motif = self.motif.as_bytes();
+        (0..kmer.len()).any(|mod_idx| {
+            motif.iter().enumerate().all(|(motif_idx, base)| {
+                let kmer_idx = (mod_idx + motif_idx).checked_sub(self.position_0b());
+                match kmer_idx.and_then(|idx| kmer.get(idx)) {
+                    Some(kmer_base) => kmer_base == base,
+                    None => true,
+                }
+            })
+        })
+    }
+
+    /// Whether a kmer starting at the modified base agrees with the rest of
+    /// the motif, the part of the motif before the modified base isn't
+    /// checked
+    pub fn starts_kmer(&self, kmer: &str) -> bool {
+        let rest = &self.motif.as_bytes()[self.position_0b()..];
+        let kmer = kmer.as_bytes();
+        rest.iter()
+            .zip(kmer)
+            .all(|(base, kmer_base)| base == kmer_base)
+    }
+
+    /// CG context for plant methylation
+    pub fn all_cg() -> Vec<Motif> {
+        vec![Motif::new("CG", 1)]
+    }
+
+    /// CHG contexts for plant methylation, H is any base but G
+    pub fn all_chg() -> Vec<Motif> {
+        ["A", "C", "T"]
+            .into_iter()
+            .map(|h| Motif::new(format!("C{h}G"), 1))
+            .collect()
+    }
+
+    /// CHH contexts for plant methylation, H is any base but G
+    pub fn all_chh() -> Vec<Motif> {
+        let bases = ["A", "C", "T"];
+        bases
+            .into_iter()
+            .flat_map(|h1| {
+                bases
+                    .into_iter()
+                    .map(move |h2| Motif::new(format!("C{h1}{h2}"), 1))
+            })
+            .collect()
+    }
+
+    pub(crate) fn surrounding_idxs(&self, pos: u64, kmer_size: usize) -> impl Iterator<Item = u64> {
         let end_idx = pos + self.position_0b() as u64;
+        let slop = kmer_size as u64 - 1;
         let start = {
-            if end_idx < 5 {
+            if end_idx < slop {
                 0
             } else {
-                end_idx - 5
+                end_idx - slop
             }
         };
         start..=end_idx
@@ -112,6 +168,60 @@ impl fmt::Display for Motif {
     }
 }
 
+/// Cytosine methylation contexts, shorthand for their motifs
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MethylationContext {
+    Cg,
+    Chg,
+    Chh,
+    /// CG, CHG, and CHH
+    All,
+}
+
+impl MethylationContext {
+    pub fn motifs(self) -> Vec<Motif> {
+        match self {
+            MethylationContext::Cg => Motif::all_cg(),
+            MethylationContext::Chg => Motif::all_chg(),
+            MethylationContext::Chh => Motif::all_chh(),
+            MethylationContext::All => {
+                let mut motifs = Motif::all_cg();
+                motifs.extend(Motif::all_chg());
+                motifs.extend(Motif::all_chh());
+                motifs
+            }
+        }
+    }
+}
+
+impl fmt::Display for MethylationContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            MethylationContext::Cg => "CG",
+            MethylationContext::Chg => "CHG",
+            MethylationContext::Chh => "CHH",
+            MethylationContext::All => "all",
+        };
+        write!(f, "{s}")
+    }
+}
+
+impl FromStr for MethylationContext {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "CG" => Ok(MethylationContext::Cg),
+            "CHG" => Ok(MethylationContext::Chg),
+            "CHH" => Ok(MethylationContext::Chh),
+            "all" => Ok(MethylationContext::All),
+            _ => Err(format!(
+                "Invalid context {s}, expected CG, CHG, CHH, or all"
+            )),
+        }
+    }
+}
+
 pub fn all_bases() -> Vec<Motif> {
     vec![
         Motif::new("A", 1),
@@ -125,6 +235,22 @@ pub fn all_bases() -> Vec<Motif> {
 mod test {
     use super::*;
 
+    #[test]
+    fn test_overlaps_kmer() {
+        let gpc = Motif::parse_from_str("2:GC").unwrap();
+        assert!(gpc.overlaps_kmer("AAGCAA"));
+        // Modified C at the start, G just before the kmer
+        assert!(gpc.overlaps_kmer("CAAAAA"));
+        // G at the end, modified C just after the kmer
+        assert!(!gpc.overlaps_kmer("AAAAAG"));
+        assert!(!gpc.overlaps_kmer("ACAAAA"));
+        assert!(!gpc.overlaps_kmer("AAAAAA"));
+
+        let a = Motif::parse_from_str("1:A").unwrap();
+        assert!(a.overlaps_kmer("CCCACC"));
+        assert!(!a.overlaps_kmer("CCCCCC"));
+    }
+
     #[test]
     fn test_motif() {
         let m = Motif::parse_from_str("2:GC");
@@ -170,12 +296,38 @@ mod test {
         assert!(m.is_err());
     }
 
+    #[test]
+    fn test_plant_contexts() {
+        assert_eq!(Motif::all_cg().len(), 1);
+        assert_eq!(Motif::all_chg().len(), 3);
+        assert_eq!(Motif::all_chh().len(), 9);
+        assert_eq!(MethylationContext::All.motifs().len(), 13);
+
+        let chg: Vec<_> = Motif::all_chg().iter().map(|m| m.to_string()).collect();
+        assert_eq!(chg, vec!["1:CAG", "1:CCG", "1:CTG"]);
+        let chh: Vec<_> = Motif::all_chh().iter().map(|m| m.to_string()).collect();
+        assert_eq!(chh[..3], ["1:CAA", "1:CAC", "1:CAT"]);
+        assert!(Motif::all_chh().iter().all(|m| !m.motif().contains('G')));
+
+        let cag = Motif::from_str("1:CAG").unwrap();
+        assert!(cag.starts_kmer("CAGTTT"));
+        assert!(!cag.starts_kmer("CATTTT"));
+        let gpc = Motif::from_str("2:GC").unwrap();
+        assert!(gpc.starts_kmer("CAAAAA"));
+
+        assert_eq!(
+            "CHH".parse::<MethylationContext>(),
+            Ok(MethylationContext::Chh)
+        );
+        assert!("chh".parse::<MethylationContext>().is_err());
+    }
+
     #[test]
     fn test_surrounding_idxs() {
         let m = Motif::from_str("1:CG").unwrap();
         let pos = 504;
         assert_eq!(
-            m.surrounding_idxs(pos).collect::<Vec<_>>(),
+            m.surrounding_idxs(pos, 6).collect::<Vec<_>>(),
             (499..=504).collect::<Vec<_>>()
         );
 
@@ -183,8 +335,12 @@ mod test {
         let pos = 510;
         assert_eq!(pos + m.position_0b() as u64, 511);
         assert_eq!(
-            m.surrounding_idxs(pos).collect::<Vec<_>>(),
+            m.surrounding_idxs(pos, 6).collect::<Vec<_>>(),
             (506..=511).collect::<Vec<_>>()
         );
+        assert_eq!(
+            m.surrounding_idxs(pos, 5).collect::<Vec<_>>(),
+            (507..=511).collect::<Vec<_>>()
+        );
     }
 }
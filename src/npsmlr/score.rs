@@ -1,8 +1,12 @@
 use std::{
+    collections::BTreeMap,
+    fs::File,
     io::{Read, Seek, Write},
+    ops::RangeInclusive,
     path::Path,
 };
 
+use bio::io::fasta::IndexedReader;
 use eyre::Result;
 use fnv::FnvHashMap;
 
@@ -10,12 +14,17 @@ use crate::{
     arrow::{
         arrow_utils::load_read_write_arrow,
         eventalign::Eventalign,
+        metadata::MetadataExt,
         scored_read::{Score, ScoredRead},
         signal::Signal,
     },
+    context::Context,
     motif::{all_bases, Motif},
+    npsmlr::train::{DEFAULT_SIGNAL_MAX, DEFAULT_SIGNAL_MIN},
+    rank::load_ranks,
+    score::{choose_model, skipping_score, ScorePolicy},
     train::Model,
-    utils::CawlrIO,
+    utils::{chrom_lens, CawlrIO},
 };
 
 pub struct ScoreOptions {
@@ -25,6 +34,11 @@ pub struct ScoreOptions {
     freq_thresh: usize,
     cutoff: f64,
     motifs: Vec<Motif>,
+    signal_min: f64,
+    signal_max: f64,
+    genome: Option<IndexedReader<File>>,
+    chrom_lens: FnvHashMap<String, u64>,
+    score_policy: ScorePolicy,
 }
 
 impl std::fmt::Debug for ScoreOptions {
@@ -33,6 +47,10 @@ impl std::fmt::Debug for ScoreOptions {
             .field("freq_thresh", &self.freq_thresh)
             .field("cutoff", &self.cutoff)
             .field("motifs", &self.motifs)
+            .field("signal_min", &self.signal_min)
+            .field("signal_max", &self.signal_max)
+            .field("genome", &self.genome.is_some())
+            .field("score_policy", &self.score_policy)
             .finish_non_exhaustive()
     }
 }
@@ -59,6 +77,9 @@ impl<'a> SignalScore<'a> {
 }
 
 impl ScoreOptions {
+    #[deprecated(
+        note = "use ScoreOptions::from_models with the freq_thresh, cutoff and motifs setters"
+    )]
     pub fn new(
         pos_model: Model,
         neg_model: Model,
@@ -74,17 +95,43 @@ impl ScoreOptions {
             freq_thresh,
             cutoff,
             motifs,
+            signal_min: DEFAULT_SIGNAL_MIN,
+            signal_max: DEFAULT_SIGNAL_MAX,
+            genome: None,
+            chrom_lens: FnvHashMap::default(),
+            score_policy: ScorePolicy::default(),
         }
     }
 
+    /// Score with the control models and ranks, scoring every base with a
+    /// cutoff of 10, a frequency threshold of 10 and the default npsmlr
+    /// training signal range unless changed with the setters
+    pub fn from_models(pos_model: Model, neg_model: Model, ranks: FnvHashMap<String, f64>) -> Self {
+        Self {
+            pos_model,
+            neg_model,
+            ranks,
+            freq_thresh: 10,
+            cutoff: 10.0,
+            motifs: all_bases(),
+            signal_min: DEFAULT_SIGNAL_MIN,
+            signal_max: DEFAULT_SIGNAL_MAX,
+            genome: None,
+            chrom_lens: FnvHashMap::default(),
+            score_policy: ScorePolicy::default(),
+        }
+    }
+
+    /// Like [ScoreOptions::from_models], loading the models and ranks from
+    /// files
     pub fn load<P>(pos_model_filepath: P, neg_model_filepath: P, ranks_filepath: P) -> Result<Self>
     where
         P: AsRef<Path>,
     {
         let pos_model = Model::load(pos_model_filepath)?;
         let neg_model = Model::load(neg_model_filepath)?;
-        let ranks = FnvHashMap::load(ranks_filepath)?;
-        let score_options = ScoreOptions::new(pos_model, neg_model, ranks, 10, 10.0, all_bases());
+        let (ranks, _) = load_ranks(ranks_filepath)?;
+        let score_options = ScoreOptions::from_models(pos_model, neg_model, ranks);
         log::debug!("Score Options: {score_options:?}");
         Ok(score_options)
     }
@@ -104,98 +151,332 @@ impl ScoreOptions {
         self
     }
 
-    pub fn run<R, W>(&self, reader: R, writer: W) -> Result<()>
+    /// Signals with a mean current (pA) below this are treated as if there
+    /// is no model for them, should match cawlr npsmlr train --signal-min
+    pub fn signal_min(&mut self, signal_min: f64) -> &mut Self {
+        self.signal_min = signal_min;
+        self
+    }
+
+    /// Signals with a mean current (pA) above this are treated as if there
+    /// is no model for them, should match cawlr npsmlr train --signal-max
+    pub fn signal_max(&mut self, signal_max: f64) -> &mut Self {
+        self.signal_max = signal_max;
+        self
+    }
+
+    fn signal_range(&self) -> RangeInclusive<f64> {
+        self.signal_min..=self.signal_max
+    }
+
+    /// Also calculate skipping scores from the genome sequence around each
+    /// read, combined with the signal score by the score policy. The models
+    /// need skips, ie trained with cawlr npsmlr train --genome, otherwise
+    /// only signal scores are used.
+    pub fn genome(&mut self, genome: IndexedReader<File>) -> &mut Self {
+        self.chrom_lens = chrom_lens(&genome);
+        self.genome = Some(genome);
+        self
+    }
+
+    /// How the signal and skipping score of a position are combined, only
+    /// used with a genome, see [crate::score::ScoreOptions::score_policy]
+    pub fn score_policy(&mut self, score_policy: ScorePolicy) -> &mut Self {
+        self.score_policy = score_policy;
+        self
+    }
+
+    /// Score every read, writing them as an Arrow file with the same schema
+    /// as cawlr score. Positions are those of the modified base in each
+    /// motif, as in cawlr score.
+    pub fn run<R, W>(&mut self, reader: R, writer: W) -> Result<()>
     where
         R: Read + Seek,
         W: Write,
     {
+        if self.signal_min >= self.signal_max {
+            return Err(eyre::eyre!(
+                "Signal min {} must be less than signal max {}",
+                self.signal_min,
+                self.signal_max
+            ));
+        }
+        let kmer_size = self.pos_model.kmer_size();
+        if self.neg_model.kmer_size() != kmer_size {
+            return Err(eyre::eyre!(
+                "Positive model uses {}-mers but negative model uses {}-mers",
+                kmer_size,
+                self.neg_model.kmer_size()
+            ));
+        }
         load_read_write_arrow(reader, writer, |eventaligns: Vec<Eventalign>| {
             let mut scored_reads = Vec::new();
             for eventalign in eventaligns {
                 log::debug!("eventalign: {:?}", eventalign.metadata());
-                let mut scores = Vec::new();
-                let data_map = eventalign
-                    .signal_iter()
-                    .map(|s| (s.pos, s))
-                    .collect::<FnvHashMap<_, _>>();
-                for signal in eventalign.signal_iter() {
-                    log::debug!("signal {signal:?}");
-                    let kmer = &signal.kmer;
-                    if let Some(m) = self.motifs.iter().find(|m| kmer.starts_with(m.motif())) {
-                        log::debug!("Kmer motif matches {m:?}");
-                        let mut kmers = Vec::new();
-                        let surrounding = m.surrounding_idxs(signal.pos);
-                        for surr in surrounding {
-                            log::debug!("Surrounding idx {surr}");
-                            if let Some(&s) = data_map.get(&surr) {
-                                log::debug!("Surrounding signal: {s:?}");
-                                if signal.samples.len() > self.freq_thresh {
-                                    log::debug!(
-                                        "n samples greater than frequency threshold, skipping"
-                                    );
-                                    continue;
-                                }
-
-                                let kmer = &s.kmer;
-                                if count_motif_in_kmer(kmer, m) > 1 {
-                                    log::debug!("Count of motifs in kmer greater than 1, skipping");
-                                    continue;
-                                }
-                                let pm = self.pos_model.gmms().get(kmer);
-                                let nm = self.neg_model.gmms().get(kmer);
-                                if let (Some(pm), Some(nm)) = (pm, nm) {
-                                    let pos_model = pm.mixture();
-                                    let neg_model = nm.single();
-
-                                    if let Some((pos_sum, neg_sum)) =
-                                        s.score_lnsum(&pos_model, &neg_model)
-                                    {
-                                        kmers.push(SignalScore::new(s, pos_sum, neg_sum));
-                                    }
-                                }
+                eventalign.check_kmer_size(kmer_size)?;
+                let context = match self.genome.as_mut() {
+                    Some(genome) => {
+                        match Context::from_read(genome, &self.chrom_lens, &eventalign, kmer_size) {
+                            Ok(context) => Some(context),
+                            Err(e) => {
+                                log::warn!("No skipping scores for {}: {e}", eventalign.name());
+                                None
                             }
                         }
-                        let mut best_signal = None;
-                        let mut diff = f64::NEG_INFINITY;
-                        for ss in kmers.into_iter() {
-                            if let Some(&rank) = self.ranks.get(&ss.signal.kmer) {
-                                log::debug!("signal score: {ss:?}");
-                                if rank > diff {
-                                    diff = rank;
-                                    best_signal = Some(ss);
-                                }
-                            }
-                        }
-
-                        if let Some(best_signal) = best_signal {
-                            log::debug!("Best signal: {best_signal:?}");
+                    }
+                    None => None,
+                };
+                scored_reads.push(self.score_eventalign(eventalign, context.as_ref()));
+            }
+            Ok(scored_reads)
+        })?;
+        Ok(())
+    }
 
-                            let exp_me = best_signal.pos_sum.exp();
-                            let exp_un = best_signal.neg_sum.exp();
+    fn score_eventalign(&self, eventalign: Eventalign, context: Option<&Context>) -> ScoredRead {
+        let signal_range = self.signal_range();
+        let kmer_size = self.pos_model.kmer_size();
+        let mut scores = Vec::new();
+        let data_map = eventalign
+            .signal_iter()
+            .map(|s| (s.pos, s))
+            .collect::<FnvHashMap<_, _>>();
+        // Modified base of every motif match, a position matched by more than
+        // one motif is only scored for the first of them given
+        let mut targets: BTreeMap<u64, (usize, &Signal)> = BTreeMap::new();
+        for signal in eventalign.signal_iter() {
+            for (motif_idx, m) in self.motifs.iter().enumerate() {
+                if !signal.kmer.starts_with(m.motif()) {
+                    continue;
+                }
+                let mod_pos = signal.pos + m.position_0b() as u64;
+                let target = targets.entry(mod_pos).or_insert((motif_idx, signal));
+                if motif_idx < target.0 {
+                    *target = (motif_idx, signal);
+                }
+            }
+        }
+        for (mod_pos, (motif_idx, signal)) in targets {
+            log::debug!("signal {signal:?}");
+            let m = &self.motifs[motif_idx];
+            log::debug!("Kmer motif matches {m:?}");
+            let mut kmers = Vec::new();
+            let surrounding = m.surrounding_idxs(signal.pos, kmer_size);
+            for surr in surrounding {
+                log::debug!("Surrounding idx {surr}");
+                if let Some(&s) = data_map.get(&surr) {
+                    log::debug!("Surrounding signal: {s:?}");
+                    if signal.samples.len() > self.freq_thresh {
+                        log::debug!("n samples greater than frequency threshold, skipping");
+                        continue;
+                    }
 
-                            let rate = exp_me / (exp_me + exp_un);
+                    if !signal_range.contains(&s.signal_mean) {
+                        log::debug!("Signal mean outside of training range, skipping");
+                        continue;
+                    }
 
-                            log::debug!("exp_me: {exp_me}");
-                            log::debug!("exp_un: {exp_un}");
-                            log::debug!("rate: {rate}");
+                    let kmer = &s.kmer;
+                    if count_motif_in_kmer(kmer, m) > 1 {
+                        log::debug!("Count of motifs in kmer greater than 1, skipping");
+                        continue;
+                    }
+                    let pm = self.pos_model.gmm(kmer, eventalign.strand());
+                    let nm = self.neg_model.gmm(kmer, eventalign.strand());
+                    if let (Some(pm), Some(nm)) = (pm, nm) {
+                        let pos_model = pm.mixture();
+                        let neg_model = choose_model(&nm.mixture()).clone();
 
-                            let score = Score::new(
-                                signal.pos,
-                                signal.kmer.to_string(),
-                                false,
-                                Some(rate),
-                                0.0,
-                                rate,
-                            );
-                            scores.push(score);
+                        if let Some((pos_sum, neg_sum)) =
+                            s.score_lnsum_in(&pos_model, &neg_model, &signal_range)
+                        {
+                            kmers.push(SignalScore::new(s, pos_sum, neg_sum));
                         }
                     }
                 }
-                let scored = ScoredRead::from_read_with_scores(eventalign, scores);
-                scored_reads.push(scored);
             }
-            Ok(scored_reads)
+            let mut best_signal = None;
+            let mut diff = f64::NEG_INFINITY;
+            for ss in kmers.into_iter() {
+                if let Some(&rank) = self.ranks.get(&ss.signal.kmer) {
+                    log::debug!("signal score: {ss:?}");
+                    if rank > diff {
+                        diff = rank;
+                        best_signal = Some(ss);
+                    }
+                }
+            }
+
+            let signal_score = best_signal.as_ref().map(|best_signal| {
+                log::debug!("Best signal: {best_signal:?}");
+
+                let exp_me = best_signal.pos_sum.exp();
+                let exp_un = best_signal.neg_sum.exp();
+
+                let rate = exp_me / (exp_me + exp_un);
+
+                log::debug!("exp_me: {exp_me}");
+                log::debug!("exp_un: {exp_un}");
+                log::debug!("rate: {rate}");
+                rate
+            });
+
+            let skipping_score = context.and_then(|context| {
+                skipping_score(
+                    mod_pos,
+                    &data_map,
+                    context,
+                    &self.pos_model,
+                    &self.neg_model,
+                )
+                .map_err(|e| log::debug!("No skipping score at {mod_pos}: {e}"))
+                .ok()
+            });
+            let final_score = match skipping_score {
+                Some(skipping_score) => self.score_policy.combine(signal_score, skipping_score),
+                None => signal_score,
+            };
+            let Some(final_score) = final_score else {
+                continue;
+            };
+
+            let mut score = Score::new(
+                mod_pos,
+                signal.kmer.to_string(),
+                signal_score.is_none(),
+                signal_score,
+                skipping_score.unwrap_or(0.0),
+                final_score,
+            )
+            .with_motif(Some(m));
+            if let Some(best_signal) = best_signal {
+                score = score.with_likelihoods(best_signal.pos_sum, best_signal.neg_sum);
+            }
+            scores.push(score);
+        }
+        ScoredRead::from_read_with_scores(eventalign, scores)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use rv::prelude::{Gaussian, Mixture};
+
+    use super::*;
+    use crate::arrow::{
+        arrow_utils::{load_apply, save, wrap_writer},
+        metadata::{Metadata, Strand},
+    };
+
+    /// Options with a modified model at 100 pA and an unmodified one at
+    /// 104 pA for each kmer, all with the same rank
+    fn score_options(kmers: &[&str]) -> ScoreOptions {
+        let mixture =
+            |mu| Mixture::new_unchecked(vec![1.0], vec![Gaussian::new_unchecked(mu, 3.0)]);
+        let mut pos_model = Model::default();
+        let mut neg_model = Model::default();
+        let mut ranks = FnvHashMap::default();
+        for kmer in kmers {
+            pos_model.insert_gmm(kmer.to_string(), mixture(100.0));
+            neg_model.insert_gmm(kmer.to_string(), mixture(104.0));
+            ranks.insert(kmer.to_string(), 1.0);
+        }
+        ScoreOptions::from_models(pos_model, neg_model, ranks)
+    }
+
+    /// Arrow file with a single read on chrI, with a signal around 100 pA
+    /// for each kmer starting at position 100
+    fn single_read(kmers: &[&str]) -> Result<Vec<u8>> {
+        let metadata = Metadata::new(
+            "read".to_string(),
+            "chrI".to_string(),
+            100,
+            kmers.len() as u64,
+            Strand::plus(),
+            String::new(),
+        );
+        let signals = kmers
+            .iter()
+            .zip(100..)
+            .map(|(kmer, pos)| {
+                Signal::new(pos, kmer.to_string(), 100.0, 0.01, vec![99.0, 100.0, 101.0])
+            })
+            .collect();
+        let mut input = Vec::new();
+        let mut writer = wrap_writer(&mut input, &Eventalign::schema())?;
+        save(&mut writer, &[Eventalign::new(metadata, signals)])?;
+        writer.finish()?;
+        Ok(input)
+    }
+
+    /// Every score of the reads in the input
+    fn run_scores(opts: &mut ScoreOptions, input: &[u8]) -> Result<Vec<Score>> {
+        let mut output = Vec::new();
+        opts.run(Cursor::new(input), &mut output)?;
+        let mut scores = Vec::new();
+        load_apply(Cursor::new(output), |reads: Vec<ScoredRead>| {
+            scores.extend(reads.iter().flat_map(|r| r.scores().to_vec()));
+            Ok(())
         })?;
+        Ok(scores)
+    }
+
+    #[test]
+    fn test_signal_range() -> Result<()> {
+        let input = single_read(&["GAAAAA"])?;
+        let mut opts = score_options(&["GAAAAA"]);
+        opts.motifs(vec![Motif::new("G", 1)]);
+        assert_eq!(run_scores(&mut opts, &input)?.len(), 1);
+
+        // Signal mean above the training range, as if there is no model
+        opts.signal_max(90.0);
+        assert_eq!(run_scores(&mut opts, &input)?.len(), 0);
+
+        opts.signal_min(95.0);
+        assert!(opts.run(Cursor::new(&input), Vec::new()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_modified_base_position() -> Result<()> {
+        let input = single_read(&["GCAAAA"])?;
+        let mut opts = score_options(&["GCAAAA"]);
+        opts.motifs(vec![Motif::new("GC", 2)]);
+        let scores = run_scores(&mut opts, &input)?;
+        // Scored at the C, like cawlr score, not the start of the motif
+        assert_eq!(scores.len(), 1);
+        assert_eq!(scores[0].pos, 101);
+        assert!(!scores[0].skipped);
+        assert_eq!(scores[0].score, scores[0].signal_score.unwrap());
+        assert_eq!(scores[0].motif(), Some("2:GC"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_overlapping_motifs() -> Result<()> {
+        let kmers = ["GCAAAA", "CAAAAA"];
+        let input = single_read(&kmers)?;
+        let scores = |motifs: Vec<Motif>| {
+            let mut opts = score_options(&kmers);
+            opts.motifs(motifs);
+            run_scores(&mut opts, &input)
+        };
+
+        // Both motifs have their modified base at 101, from different kmers
+        let gc = Motif::new("GC", 2);
+        let c = Motif::new("C", 1);
+        let overlapping = scores(vec![gc.clone(), c.clone()])?;
+        assert_eq!(overlapping.len(), 1);
+        assert_eq!(overlapping[0].pos, 101);
+        assert_eq!(overlapping[0].motif(), Some("2:GC"));
+        assert_eq!(overlapping[0].kmer, "GCAAAA");
+
+        let overlapping = scores(vec![c, gc])?;
+        assert_eq!(overlapping.len(), 1);
+        assert_eq!(overlapping[0].motif(), Some("1:C"));
+        assert_eq!(overlapping[0].kmer, "CAAAAA");
         Ok(())
     }
 }
@@ -1,52 +1,301 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
+    fmt::{Debug, Display},
+    fs::File,
     io::{Read, Seek, Write},
+    ops::RangeInclusive,
     path::{Path, PathBuf},
+    str::FromStr,
 };
 
-use eyre::Result;
+use bio::io::fasta::IndexedReader;
+use eyre::{Context as _, Result};
+use fnv::FnvHashMap;
+use indicatif::ProgressBar;
 use linfa::{
     traits::{Fit, Transformer},
     DatasetBase, ParamGuard,
 };
 use linfa_clustering::{Dbscan, GaussianMixtureModel};
-use ndarray::Array;
-use rusqlite::{named_params, Connection};
-use rv::prelude::{Gaussian, Mixture};
+use ndarray::{Array, Array1, Array2};
+use rand::{prelude::SmallRng, seq::SliceRandom, Rng, SeedableRng};
+use rusqlite::{named_params, params_from_iter, types::ToSql, Connection, Transaction};
+use rv::{
+    prelude::{Gaussian, Mixture},
+    traits::Rv,
+};
 
 use crate::{
-    arrow::{arrow_utils::load_read_arrow_measured, eventalign::Eventalign, metadata::MetadataExt},
+    arrow::{
+        arrow_utils::load_read_arrow_measured,
+        eventalign::Eventalign,
+        metadata::{MetadataExt, Strand},
+    },
+    context::Context,
     motif::{all_bases, Motif},
-    train::{mix_to_mix, Model},
-    utils::CawlrIO,
-    validated::{self, ValidSampleData},
+    train::{
+        check_kmer_size, kmer_progress_bar, mix_to_mix, KmerSkips, Model, Provenance,
+        TrainProgress, DEFAULT_KMER_SIZE,
+    },
+    utils::{check_output, chrom_lens, open_genome_fasta, AtomicFileWriter, CawlrIO},
+    validated::{ValidSampleData, ValidationReport, MIN_FRACTION_VALID},
 };
 
+/// Lowest current (pA) used for training by default, suited to R9.4.1
+/// flowcells
+pub const DEFAULT_SIGNAL_MIN: f64 = 40.0;
+
+/// Highest current (pA) used for training by default, suited to R9.4.1
+/// flowcells
+pub const DEFAULT_SIGNAL_MAX: f64 = 170.0;
+
+/// Kmers whose average log-likelihood on held-out samples is lower than on
+/// the training samples by more than this are flagged as overfit
+pub const OVERFIT_LL_DIFF: f64 = 1.0;
+
+/// How to choose the number of components in each kmer's GMM
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ModelSelection {
+    /// Always use the same number of components, see [TrainOptions::single]
+    #[default]
+    Fixed,
+    /// Bayesian information criterion
+    Bic,
+    /// Akaike information criterion
+    Aic,
+}
+
+impl Display for ModelSelection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let res = match self {
+            Self::Fixed => "fixed",
+            Self::Bic => "bic",
+            Self::Aic => "aic",
+        };
+        write!(f, "{res}")
+    }
+}
+
+impl FromStr for ModelSelection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fixed" => Ok(Self::Fixed),
+            "bic" => Ok(Self::Bic),
+            "aic" => Ok(Self::Aic),
+            _ => Err(format!(
+                "Invalid model selection {s}: either 'fixed', 'bic' or 'aic'"
+            )),
+        }
+    }
+}
+
+impl ModelSelection {
+    /// Information criterion of the mixture fit to xs, lower is better. Always
+    /// 0 for fixed.
+    fn criterion(&self, mix: &Mixture<Gaussian>, xs: &[f64]) -> f64 {
+        let ln_likelihood: f64 = xs.iter().map(|x| mix.ln_f(x)).sum();
+        // Mean and std for each component, plus k - 1 free weights
+        let n_params = (3 * mix.k() - 1) as f64;
+        match self {
+            Self::Fixed => 0.0,
+            Self::Bic => n_params * (xs.len() as f64).ln() - 2.0 * ln_likelihood,
+            Self::Aic => 2.0 * n_params - 2.0 * ln_likelihood,
+        }
+    }
+}
+
+/// Diagnostics from training the GMM for a single kmer
+#[derive(Debug, Clone, PartialEq)]
+pub struct KmerReport {
+    pub kmer: String,
+    /// Strand of the reads used, None if reads from both strands were used
+    pub strand: Option<Strand>,
+    /// Number of valid samples used for training
+    pub n_samples: usize,
+    /// Number of samples removed as outliers by DBSCAN
+    pub n_outliers: usize,
+    /// Log-likelihood of the samples under the trained model
+    pub log_likelihood: Option<f64>,
+    /// Number of samples held out from training, see [TrainOptions::holdout]
+    pub n_holdout: usize,
+    /// Average log-likelihood of the held-out samples under the trained model
+    pub holdout_log_likelihood: Option<f64>,
+    /// Held-out samples fit much worse than the training samples, see
+    /// [OVERFIT_LL_DIFF]
+    pub overfit: bool,
+    /// Weight, mean, and standard deviation of each component
+    pub components: Vec<(f64, f64, f64)>,
+    /// Reason the kmer failed to train
+    pub error: Option<String>,
+}
+
+impl KmerReport {
+    fn new(kmer: &str, strand: Option<Strand>, n_samples: usize) -> Self {
+        KmerReport {
+            kmer: kmer.to_string(),
+            strand,
+            n_samples,
+            n_outliers: 0,
+            log_likelihood: None,
+            n_holdout: 0,
+            holdout_log_likelihood: None,
+            overfit: false,
+            components: Vec::new(),
+            error: None,
+        }
+    }
+}
+
+/// Diagnostics for every kmer with samples, see [TrainOptions::run_model]
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct TrainReport {
+    kmers: Vec<KmerReport>,
+    validation: ValidationReport,
+}
+
+impl TrainReport {
+    pub fn kmers(&self) -> &[KmerReport] {
+        &self.kmers
+    }
+
+    /// Number of samples kept by validation for each kmer
+    pub fn validation(&self) -> &ValidationReport {
+        &self.validation
+    }
+
+    /// Kmers that failed to train
+    pub fn failed(&self) -> impl Iterator<Item = &KmerReport> {
+        self.kmers.iter().filter(|k| k.error.is_some())
+    }
+
+    /// Write one line per kmer as a tsv with a header line. Components are
+    /// comma separated, missing values are NA.
+    pub fn write_tsv<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writeln!(
+            writer,
+            "kmer\tstrand\tn_samples\tn_outliers\tlog_likelihood\tn_holdout\t\
+             holdout_log_likelihood\toverfit\tweights\tmeans\tsigmas\terror"
+        )?;
+        for k in self.kmers.iter() {
+            let strand = k.strand.map(|s| s.as_str()).unwrap_or("both");
+            let log_likelihood = k
+                .log_likelihood
+                .map(|ll| ll.to_string())
+                .unwrap_or_else(|| "NA".to_string());
+            let holdout_log_likelihood = k
+                .holdout_log_likelihood
+                .map(|ll| ll.to_string())
+                .unwrap_or_else(|| "NA".to_string());
+            let join = |f: fn(&(f64, f64, f64)) -> f64| {
+                if k.components.is_empty() {
+                    "NA".to_string()
+                } else {
+                    k.components
+                        .iter()
+                        .map(|c| f(c).to_string())
+                        .collect::<Vec<_>>()
+                        .join(",")
+                }
+            };
+            let error = k.error.as_deref().unwrap_or("NA");
+            writeln!(
+                writer,
+                "{}\t{strand}\t{}\t{}\t{log_likelihood}\t{}\t{holdout_log_likelihood}\t{}\t{}\t{}\t{}\t{error}",
+                k.kmer,
+                k.n_samples,
+                k.n_outliers,
+                k.n_holdout,
+                k.overfit,
+                join(|c| c.0),
+                join(|c| c.1),
+                join(|c| c.2),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Fails if the path already exists and overwrite is false
+    pub fn save_as<P: AsRef<Path>>(&self, path: P, overwrite: bool) -> Result<()> {
+        let mut writer = AtomicFileWriter::create(path, overwrite)?;
+        self.write_tsv(&mut writer)?;
+        writer.commit()
+    }
+}
+
+/// Called with the progress after each kmer is trained
+pub struct ProgressCallback(Box<dyn Fn(TrainProgress)>);
+
+impl Debug for ProgressCallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ProgressCallback")
+    }
+}
+
 #[derive(Debug)]
 pub struct TrainOptions {
     n_samples: usize,
+    min_kmer_samples: usize,
     single: bool,
+    model_selection: ModelSelection,
     dbscan: bool,
+    winsorize: Option<(f64, f64)>,
+    holdout: f64,
+    holdout_seed: u64,
+    seed: u64,
     motifs: Vec<Motif>,
     db_path: Option<PathBuf>,
+    in_memory: bool,
+    kmer_size: usize,
+    checkpoint_path: Option<PathBuf>,
+    checkpoint_every: usize,
+    resume: bool,
+    report_path: Option<PathBuf>,
+    overwrite: bool,
+    stranded: bool,
+    signal_min: f64,
+    signal_max: f64,
+    genome: Option<PathBuf>,
+    progress: bool,
+    on_progress: Option<ProgressCallback>,
 }
 
 impl Default for TrainOptions {
     fn default() -> Self {
         TrainOptions {
             n_samples: 50000,
+            min_kmer_samples: 10,
             single: false,
+            model_selection: ModelSelection::default(),
             dbscan: false,
+            winsorize: None,
+            holdout: 0.0,
+            holdout_seed: 2456,
+            seed: 2456,
             motifs: all_bases(),
             db_path: None,
+            in_memory: false,
+            kmer_size: DEFAULT_KMER_SIZE,
+            checkpoint_path: None,
+            checkpoint_every: 100,
+            resume: false,
+            report_path: None,
+            overwrite: false,
+            stranded: false,
+            signal_min: DEFAULT_SIGNAL_MIN,
+            signal_max: DEFAULT_SIGNAL_MAX,
+            genome: None,
+            progress: false,
+            on_progress: None,
         }
     }
 }
 
-fn all_kmers() -> Vec<String> {
+pub(crate) fn all_kmers(kmer_size: usize) -> Vec<String> {
     let mut kmers: Vec<String> = vec![String::new()];
     let bases = ["A", "C", "G", "T"];
-    for _ in 0..6 {
+    for _ in 0..kmer_size {
         let mut acc = Vec::new();
         for base in bases {
             for s in kmers.iter() {
@@ -66,84 +315,568 @@ impl TrainOptions {
         self
     }
 
+    /// Kmers with fewer measurements than this are not fitted, since a GMM
+    /// from only a handful of samples is unreliable
+    pub fn min_kmer_samples(mut self, min_kmer_samples: usize) -> Self {
+        self.min_kmer_samples = min_kmer_samples;
+        self
+    }
+
     pub fn single(mut self, single: bool) -> Self {
         self.single = single;
         self
     }
 
+    /// Choose between one and two components for each kmer, instead of
+    /// using the same number for every kmer
+    pub fn model_selection(mut self, model_selection: ModelSelection) -> Self {
+        self.model_selection = model_selection;
+        self
+    }
+
     pub fn dbscan(mut self, dbscan: bool) -> Self {
         self.dbscan = dbscan;
         self
     }
 
+    /// Clamp samples below the lower quantile and above the upper quantile
+    /// before fitting, ie 0.005 and 0.995. Applied before DBSCAN filtering if
+    /// both are used.
+    pub fn winsorize(mut self, lower: f64, upper: f64) -> Self {
+        self.winsorize = Some((lower, upper));
+        self
+    }
+
+    /// Fraction of each kmer's samples to hold out from training, used to
+    /// report the held-out log-likelihood. Defaults to 0, using every sample
+    /// for training.
+    pub fn holdout(mut self, holdout: f64) -> Self {
+        self.holdout = holdout;
+        self
+    }
+
+    /// Seed for randomly choosing the held-out samples, so the split is the
+    /// same between runs
+    pub fn holdout_seed(mut self, holdout_seed: u64) -> Self {
+        self.holdout_seed = holdout_seed;
+        self
+    }
+
+    /// Seed for randomly choosing which measurements of each kmer are kept
+    /// while loading, so the training data is the same between runs
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
     pub fn motifs(mut self, motifs: Vec<Motif>) -> Self {
         self.motifs = motifs;
         self
     }
 
+    /// Write the training data to a SQLite database at db_path and keep it
+    /// after training, see [Db::init] for its schema. If None, a database in
+    /// the temporary directory is used and removed once training finishes.
     pub fn db_path(mut self, db_path: Option<PathBuf>) -> Self {
         self.db_path = db_path;
         self
     }
 
-    pub fn run<R, W>(self, input: R, mut writer: W) -> Result<()>
+    /// Keep a random sample of at most n_samples measurements per kmer in
+    /// memory instead of writing every measurement to a SQLite database.
+    /// Ignores db_path.
+    pub fn in_memory(mut self, in_memory: bool) -> Self {
+        self.in_memory = in_memory;
+        self
+    }
+
+    /// Length of kmers in the input, defaults to 6. Errors if it is 0.
+    pub fn kmer_size(mut self, kmer_size: usize) -> Result<Self> {
+        check_kmer_size(kmer_size)?;
+        self.kmer_size = kmer_size;
+        Ok(self)
+    }
+
+    /// Path where the partially trained model is periodically saved, usually
+    /// {output}.partial. No checkpoints are written if None.
+    pub fn checkpoint_path(mut self, checkpoint_path: Option<PathBuf>) -> Self {
+        self.checkpoint_path = checkpoint_path;
+        self
+    }
+
+    /// Number of kmers to train between each checkpoint
+    pub fn checkpoint_every(mut self, checkpoint_every: usize) -> Self {
+        self.checkpoint_every = checkpoint_every;
+        self
+    }
+
+    /// Resume from the model in the checkpoint path, skipping kmers that have
+    /// already been trained
+    pub fn resume(mut self, resume: bool) -> Self {
+        self.resume = resume;
+        self
+    }
+
+    /// Path where the training diagnostics for each kmer are written by
+    /// [TrainOptions::run], usually {output}.train_report.tsv
+    pub fn report_path(mut self, report_path: Option<PathBuf>) -> Self {
+        self.report_path = report_path;
+        self
+    }
+
+    /// Replace an existing training report instead of failing
+    pub fn overwrite(mut self, overwrite: bool) -> Self {
+        self.overwrite = overwrite;
+        self
+    }
+
+    /// Also train a separate model for each kmer on each strand
+    pub fn stranded(mut self, stranded: bool) -> Self {
+        self.stranded = stranded;
+        self
+    }
+
+    /// Skip measurements below this current (pA), defaults to 40
+    pub fn signal_min(mut self, signal_min: f64) -> Self {
+        self.signal_min = signal_min;
+        self
+    }
+
+    /// Skip measurements above this current (pA), defaults to 170
+    pub fn signal_max(mut self, signal_max: f64) -> Self {
+        self.signal_max = signal_max;
+        self
+    }
+
+    /// Genome fasta used to estimate how often each kmer is skipped, which
+    /// cawlr score needs for the skipping score. Skip frequencies are left
+    /// empty if None.
+    pub fn genome(mut self, genome: Option<PathBuf>) -> Self {
+        self.genome = genome;
+        self
+    }
+
+    /// Show a progress bar with the number of kmers trained, failures and
+    /// ETA. Ignored if a callback is set with [TrainOptions::on_progress].
+    pub fn progress(mut self, progress: bool) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    /// Call f with the progress after each kmer instead of showing a
+    /// progress bar
+    pub fn on_progress<F>(mut self, f: F) -> Self
+    where
+        F: Fn(TrainProgress) + 'static,
+    {
+        self.on_progress = Some(ProgressCallback(Box::new(f)));
+        self
+    }
+
+    fn update_progress(&self, progress: TrainProgress, pb: &ProgressBar) {
+        match &self.on_progress {
+            Some(ProgressCallback(f)) => f(progress),
+            None => progress.update_bar(pb),
+        }
+    }
+
+    fn signal_range(&self) -> RangeInclusive<f64> {
+        self.signal_min..=self.signal_max
+    }
+
+    pub fn run<R, W>(self, input: R, writer: W) -> Result<()>
+    where
+        R: Read + Seek,
+        W: Write,
+    {
+        self.run_many(vec![("input".to_string(), input)], writer)
+    }
+
+    /// Train on the samples from every input, each named by the path or
+    /// description used in log messages and errors
+    pub fn run_many<R, W>(self, inputs: Vec<(String, R)>, mut writer: W) -> Result<()>
     where
         R: Read + Seek,
         W: Write,
     {
-        let model = self.run_model(input)?;
+        let checkpoint_path = self.checkpoint_path.clone();
+        let report_path = self.report_path.clone();
+        let overwrite = self.overwrite;
+        if let Some(report_path) = &report_path {
+            check_output(report_path, overwrite)?;
+        }
+        let (model, report) = self.run_model_many(inputs)?;
         model.save(&mut writer)?;
+        if let Some(report_path) = report_path {
+            report.save_as(report_path, overwrite)?;
+        }
+        if let Some(checkpoint_path) = checkpoint_path {
+            if checkpoint_path.exists() {
+                std::fs::remove_file(checkpoint_path)?;
+            }
+        }
         Ok(())
     }
 
-    pub fn run_model<R>(self, input: R) -> Result<Model>
+    /// Train the model, along with diagnostics for each kmer
+    pub fn run_model<R>(self, input: R) -> Result<(Model, TrainReport)>
+    where
+        R: Read + Seek,
+    {
+        self.run_model_many(vec![("input".to_string(), input)])
+    }
+
+    /// Train the model on the samples from every input, see
+    /// [TrainOptions::run_many]
+    pub fn run_model_many<R>(self, inputs: Vec<(String, R)>) -> Result<(Model, TrainReport)>
     where
         R: Read + Seek,
     {
         log::info!("{self:?}");
+        if self.in_memory {
+            let db = MemoryDb::new(self.n_samples)
+                .seed(self.seed)
+                .stranded(self.stranded);
+            return self.load_and_train(inputs, db);
+        }
         let db_path = {
             match &self.db_path {
                 Some(db_path) => db_path.clone(),
-                None => std::env::temp_dir().join("npsmlr.db"),
+                None => std::env::temp_dir().join(format!("npsmlr-{}.db", std::process::id())),
             }
         };
-        let mut db = Db::open(db_path)?;
+        let mut db = Db::open(&db_path)?
+            .limit(self.n_samples)
+            .seed(self.seed)
+            .stranded(self.stranded)?;
         log::debug!("Database: {db:?}");
-        load_read_arrow_measured(input, |eventaligns: Vec<Eventalign>| {
-            db.add_reads(eventaligns, &self.motifs)?;
+        let trained = self.load_and_train(inputs, &mut db);
+        let closed = db.close().and_then(|()| {
+            if self.db_path.is_none() {
+                std::fs::remove_file(&db_path)?;
+            }
             Ok(())
-        })?;
+        });
+        let trained = trained?;
+        closed?;
+        Ok(trained)
+    }
+
+    fn load_and_train<R, S>(
+        &self,
+        inputs: Vec<(String, R)>,
+        mut db: S,
+    ) -> Result<(Model, TrainReport)>
+    where
+        R: Read + Seek,
+        S: SampleStore,
+    {
+        if self.signal_min >= self.signal_max {
+            return Err(eyre::eyre!(
+                "Signal min {} must be less than signal max {}",
+                self.signal_min,
+                self.signal_max
+            ));
+        }
+        let signal_range = self.signal_range();
+        let mut skips = match &self.genome {
+            Some(genome) => Some(SkipCounter::open(genome, self.kmer_size)?),
+            None => {
+                log::warn!("No genome given, skip frequencies will not be estimated");
+                None
+            }
+        };
+        let names = inputs.iter().map(|(name, _)| name.clone()).collect();
+        for (name, input) in inputs {
+            let mut n_reads = 0;
+            load_read_arrow_measured(input, |eventaligns: Vec<Eventalign>| {
+                for eventalign in eventaligns.iter() {
+                    eventalign.check_kmer_size(self.kmer_size)?;
+                }
+                n_reads += eventaligns.len();
+                if let Some(skips) = skips.as_mut() {
+                    skips.add_reads(&eventaligns)?;
+                }
+                db.add_reads(eventaligns, &self.motifs, &signal_range)?;
+                Ok(())
+            })
+            .wrap_err_with(|| format!("Failed to load training data from {name}"))?;
+            log::info!("Loaded {n_reads} reads from {name}");
+        }
+        db.build_index()?;
+
+        let (mut model, report) = self.train_gmms(db)?;
+        if let Some(skips) = skips {
+            model.set_n_skip_samples(skips.totals()?);
+            model.set_skips(skips.into_ratios()?);
+        }
+        model.set_provenance(Provenance::new(names, self.provenance_options()));
+        Ok((model, report))
+    }
+
+    /// Options that change the trained model, recorded in its provenance
+    fn provenance_options(&self) -> BTreeMap<String, String> {
+        let motifs = self
+            .motifs
+            .iter()
+            .map(|m| m.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+        let mut options = BTreeMap::from([
+            ("samples".to_string(), self.n_samples.to_string()),
+            ("single".to_string(), self.single.to_string()),
+            (
+                "model_selection".to_string(),
+                self.model_selection.to_string(),
+            ),
+            ("dbscan".to_string(), self.dbscan.to_string()),
+            ("motifs".to_string(), motifs),
+            ("kmer_size".to_string(), self.kmer_size.to_string()),
+            ("stranded".to_string(), self.stranded.to_string()),
+            ("signal_min".to_string(), self.signal_min.to_string()),
+            ("signal_max".to_string(), self.signal_max.to_string()),
+            ("seed".to_string(), self.seed.to_string()),
+        ]);
+        if let Some((lower, upper)) = self.winsorize {
+            options.insert("winsorize".to_string(), format!("{lower},{upper}"));
+        }
+        if self.holdout > 0.0 {
+            options.insert("holdout".to_string(), self.holdout.to_string());
+            options.insert("holdout_seed".to_string(), self.holdout_seed.to_string());
+        }
+        if let Some(genome) = &self.genome {
+            options.insert("genome".to_string(), genome.display().to_string());
+        }
+        options
+    }
+
+    /// Load the partially trained model if resuming, otherwise start from an
+    /// empty model.
+    fn initial_model(&self) -> Result<Model> {
+        match &self.checkpoint_path {
+            Some(checkpoint_path) if self.resume && checkpoint_path.exists() => {
+                let model = Model::load(checkpoint_path)?;
+                if model.kmer_size() != self.kmer_size {
+                    return Err(eyre::eyre!(
+                        "Checkpoint kmer size {} does not match kmer size {}",
+                        model.kmer_size(),
+                        self.kmer_size
+                    ));
+                }
+                log::info!(
+                    "Resuming from {} with {} kmers trained",
+                    checkpoint_path.display(),
+                    model.gmms().len()
+                );
+                Ok(model)
+            }
+            Some(checkpoint_path) if self.resume => {
+                log::warn!(
+                    "No checkpoint found at {}, training from scratch",
+                    checkpoint_path.display()
+                );
+                Ok(Model::with_kmer_size(self.kmer_size))
+            }
+            _ => Ok(Model::with_kmer_size(self.kmer_size)),
+        }
+    }
 
-        self.train_gmms(db)
+    /// Save the partially trained model, writing to a temporary file first so
+    /// a crash while saving doesn't corrupt the previous checkpoint.
+    fn save_checkpoint(&self, model: &Model) -> Result<()> {
+        if let Some(checkpoint_path) = &self.checkpoint_path {
+            log::info!("Saving checkpoint to {}", checkpoint_path.display());
+            let mut tmp_path = checkpoint_path.clone().into_os_string();
+            tmp_path.push(".tmp");
+            model.save_as(&tmp_path)?;
+            std::fs::rename(&tmp_path, checkpoint_path)?;
+        }
+        Ok(())
     }
 
-    fn train_gmms(&self, db: Db) -> Result<Model> {
-        let mut model = Model::default();
-        for kmer in all_kmers() {
+    fn train_gmms<S: SampleStore>(&self, mut db: S) -> Result<(Model, TrainReport)> {
+        if let Some((lower, upper)) = self.winsorize {
+            if !(0.0..=1.0).contains(&lower) || !(0.0..=1.0).contains(&upper) || lower >= upper {
+                return Err(eyre::eyre!(
+                    "Invalid winsorize quantiles {lower} and {upper}, must be between 0 and 1 with lower < upper"
+                ));
+            }
+        }
+        if !(0.0..1.0).contains(&self.holdout) {
+            return Err(eyre::eyre!(
+                "Invalid holdout fraction {}, must be at least 0 and less than 1",
+                self.holdout
+            ));
+        }
+        let mut model = self.initial_model()?;
+        let mut report = TrainReport::default();
+        let signal_range = self.signal_range();
+        let mut n_trained = 0;
+        let kmers = all_kmers(self.kmer_size);
+        let mut progress = TrainProgress {
+            n_kmers: kmers.len(),
+            ..Default::default()
+        };
+        let pb = kmer_progress_bar(kmers.len(), self.progress && self.on_progress.is_none());
+        for kmer in kmers {
+            progress.n_done += 1;
+            if model.gmms().contains_key(&kmer) {
+                log::debug!("Kmer {kmer} found in checkpoint, skipping");
+                self.update_progress(progress, &pb);
+                continue;
+            }
+
             log::info!("Training on kmer {kmer}");
-            let samples = db.get_kmer_samples(&kmer, self.n_samples)?;
-            log::info!("n samples: {}", samples.len());
-            if let Some(validated) = validated::ValidSampleData::validated(samples) {
-                match self.train_gmm(validated) {
-                    Ok(gmm) => {
-                        log::info!("Training successful!");
-                        model.insert_gmm(kmer, gmm);
-                    }
-                    Err(e) => {
-                        log::warn!("kmer {kmer} failed to train with error {e}");
+            let n_reported = report.kmers.len();
+            if self.stranded {
+                for strand in [Strand::plus(), Strand::minus()] {
+                    let samples = db.get_kmer_split(
+                        &kmer,
+                        Some(strand),
+                        self.n_samples,
+                        self.min_kmer_samples,
+                        self.holdout,
+                        self.holdout_seed,
+                    )?;
+                    log::info!("n samples on {strand} strand: {}", samples.len());
+                    let ingested = db.ingested(&kmer, Some(strand));
+                    if let Some(gmm) = self.train_kmer(
+                        &kmer,
+                        Some(strand),
+                        samples,
+                        ingested,
+                        &signal_range,
+                        &mut report,
+                    ) {
+                        model.insert_stranded_gmm(kmer.clone(), strand, gmm);
                     }
                 }
             }
+
+            let samples = db.get_kmer_split(
+                &kmer,
+                None,
+                self.n_samples,
+                self.min_kmer_samples,
+                self.holdout,
+                self.holdout_seed,
+            )?;
+            log::info!("n samples: {}", samples.len());
+            let ingested = db.ingested(&kmer, None);
+            if let Some(gmm) =
+                self.train_kmer(&kmer, None, samples, ingested, &signal_range, &mut report)
+            {
+                log::info!("Training successful!");
+                if let Some(kmer_report) = report.kmers.last() {
+                    model.set_n_samples(kmer.clone(), kmer_report.n_samples);
+                }
+                model.insert_gmm(kmer, gmm);
+            }
+            if report.kmers[n_reported..].iter().any(|k| k.error.is_some()) {
+                progress.n_failed += 1;
+            }
+            self.update_progress(progress, &pb);
+
+            n_trained += 1;
+            if self.checkpoint_every > 0 && n_trained % self.checkpoint_every == 0 {
+                self.save_checkpoint(&model)?;
+            }
         }
+        pb.finish();
         if model.gmms().is_empty() {
             Err(eyre::eyre!("Not gmms trained due to error. Check logs"))
         } else {
-            Ok(model)
+            Ok((model, report))
+        }
+    }
+
+    /// Train the GMM for the kmer, adding its diagnostics to the report.
+    /// Kmers with fewer than min_kmer_samples samples are not reported.
+    /// Whether too many measurements were discarded is checked with the
+    /// counts from when the reads were added, since the sampled measurements
+    /// are all within the signal range.
+    fn train_kmer(
+        &self,
+        kmer: &str,
+        strand: Option<Strand>,
+        samples: SplitSamples,
+        ingested: SampleCounts,
+        signal_range: &RangeInclusive<f64>,
+        report: &mut TrainReport,
+    ) -> Option<Mixture<Gaussian>> {
+        if samples.is_empty() {
+            return None;
+        }
+        if samples.len() < self.min_kmer_samples {
+            log::debug!(
+                "Skipping kmer {kmer}, only {} samples out of the minimum {}",
+                samples.len(),
+                self.min_kmer_samples
+            );
+            return None;
+        }
+        let SplitSamples {
+            train: samples,
+            holdout,
+        } = samples;
+        let n_samples = samples.len();
+        let validated = match ValidSampleData::validated_within(samples, signal_range) {
+            Some(validated) => validated,
+            None => {
+                let mut kmer_report = KmerReport::new(kmer, strand, n_samples);
+                kmer_report.error = Some("Fewer than 2 valid samples".to_string());
+                report.kmers.push(kmer_report);
+                return None;
+            }
+        };
+        report
+            .validation
+            .add(kmer, strand, ingested.n_read, ingested.n_kept);
+        if ingested.fraction_kept() < MIN_FRACTION_VALID {
+            log::warn!(
+                "Only {}/{} samples for kmer {kmer} are within the signal range, check the signal normalization",
+                ingested.n_kept,
+                ingested.n_read
+            );
         }
+        let mut kmer_report = KmerReport::new(kmer, strand, 0);
+        let gmm = match self.train_gmm(validated, &mut kmer_report) {
+            Ok(gmm) => {
+                score_holdout(&gmm, &holdout, &mut kmer_report);
+                Some(gmm)
+            }
+            Err(e) => {
+                match strand {
+                    Some(strand) => {
+                        log::warn!("kmer {kmer} on {strand} strand failed to train with error {e}")
+                    }
+                    None => log::warn!("kmer {kmer} failed to train with error {e}"),
+                }
+                kmer_report.error = Some(e.to_string());
+                None
+            }
+        };
+        report.kmers.push(kmer_report);
+        gmm
+    }
+
+    fn train_gmm(
+        &self,
+        samples: ValidSampleData,
+        report: &mut KmerReport,
+    ) -> Result<Mixture<Gaussian>> {
+        self.fit_samples(samples.inner(), report)
     }
 
-    fn train_gmm(&self, samples: ValidSampleData) -> Result<Mixture<Gaussian>> {
-        let samples = samples.inner();
+    fn fit_samples(
+        &self,
+        mut samples: Vec<f64>,
+        report: &mut KmerReport,
+    ) -> Result<Mixture<Gaussian>> {
+        report.n_samples = samples.len();
+        if let Some((lower, upper)) = self.winsorize {
+            winsorize(&mut samples, lower, upper);
+        }
         let len = samples.len();
         let shape = (len, 1);
         let means = Array::from_shape_vec(shape, samples).unwrap();
@@ -177,6 +910,7 @@ impl TrainOptions {
                     },
                 )
                 .collect();
+            report.n_outliers = len - filtered.len();
             if filtered.len() < 2 {
                 log::warn!("Not enough values left in observations");
                 return Err(eyre::eyre!("Not enough values after filtering"));
@@ -188,173 +922,955 @@ impl TrainOptions {
             data = DatasetBase::from(filtered_results);
         }
 
-        let n_clusters = if self.single { 1 } else { 2 };
-        let n_runs = 10;
-        let tolerance = 1e-4f64;
-        let gmm = GaussianMixtureModel::params(n_clusters)
-            .n_runs(n_runs)
-            .tolerance(tolerance)
-            .check()?
-            .fit(&data)?;
-        let mm = mix_to_mix(&gmm);
-        Ok(mm)
+        let gmm = self.select_gmm(&data)?;
+        let xs = data
+            .records()
+            .as_slice()
+            .expect("Getting records for log-likelihood failed");
+        report.log_likelihood = Some(xs.iter().map(|x| gmm.ln_f(x)).sum());
+        report.components = gmm
+            .weights()
+            .iter()
+            .zip(gmm.components())
+            .map(|(&w, g)| (w, g.mu(), g.sigma()))
+            .collect();
+        Ok(gmm)
     }
-}
-
-#[derive(Debug)]
-struct Db {
-    limit: usize,
-    connection: Connection,
-    counts: HashMap<String, usize>,
-}
 
-impl Db {
-    fn open<P: AsRef<Path>>(path: P) -> eyre::Result<Self> {
-        let path = path.as_ref();
-        if path.exists() {
-            std::fs::remove_file(path)?;
+    /// Fit the GMM, choosing the number of components with the model
+    /// selection criterion if it isn't fixed
+    fn select_gmm(&self, data: &DatasetBase<Array2<f64>, Array1<()>>) -> Result<Mixture<Gaussian>> {
+        if self.model_selection == ModelSelection::Fixed {
+            let n_clusters = if self.single { 1 } else { 2 };
+            return fit_gmm(data, n_clusters);
         }
-        let db = Db {
-            limit: 50000,
-            connection: Connection::open(path)?,
-            counts: Default::default(),
+
+        let single = fit_gmm(data, 1)?;
+        let double = match fit_gmm(data, 2) {
+            Ok(double) => double,
+            Err(e) => {
+                log::debug!("Two component GMM failed with {e}, using single component");
+                return Ok(single);
+            }
         };
-        db.init()?;
-        db.create_idx()?;
-        Ok(db)
+        let xs = data
+            .records()
+            .as_slice()
+            .expect("Getting records for model selection failed");
+        let single_score = self.model_selection.criterion(&single, xs);
+        let double_score = self.model_selection.criterion(&double, xs);
+        log::debug!(
+            "{}: single {single_score:.3}, double {double_score:.3}",
+            self.model_selection
+        );
+        if single_score <= double_score {
+            Ok(single)
+        } else {
+            Ok(double)
+        }
     }
+}
 
-    fn init(&self) -> eyre::Result<()> {
-        self.connection.execute(
-            "CREATE TABLE data (
-                id      INTEGER PRIMARY KEY,
-                kmer    TEXT NOT NULL,
-                sample  REAL NOT NULL
-            );",
-            (),
-        )?;
-        Ok(())
+/// Replace values below the lower quantile and above the upper quantile with
+/// the value at that quantile.
+fn winsorize(xs: &mut [f64], lower: f64, upper: f64) {
+    if xs.is_empty() {
+        return;
     }
+    let mut sorted = xs.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).expect("NaN in samples"));
+    let last = (sorted.len() - 1) as f64;
+    let lower = sorted[(lower * last).round() as usize];
+    let upper = sorted[(upper * last).round() as usize];
+    xs.iter_mut().for_each(|x| *x = x.clamp(lower, upper));
+}
 
-    fn create_idx(&self) -> eyre::Result<()> {
-        self.connection
-            .execute("CREATE INDEX kmer_idx on data (kmer)", ())?;
-        self.connection.pragma_update(None, "journal_mode", "WAL")?;
-        self.connection
-            .pragma_update(None, "synchronous", "NORMAL")?;
-        self.connection.pragma_update(None, "cache_size", -64000)?;
+fn fit_gmm(
+    data: &DatasetBase<Array2<f64>, Array1<()>>,
+    n_clusters: usize,
+) -> Result<Mixture<Gaussian>> {
+    let n_runs = 10;
+    let tolerance = 1e-4f64;
+    let gmm = GaussianMixtureModel::params(n_clusters)
+        .n_runs(n_runs)
+        .tolerance(tolerance)
+        .check()?
+        .fit(data)?;
+    let mm = mix_to_mix(&gmm);
+    Ok(mm)
+}
+
+/// Source of signal measurements used to train the GMM for each kmer
+trait SampleStore {
+    /// Add measurements from kmers matching the motifs that fall within the
+    /// signal range
+    fn add_reads(
+        &mut self,
+        es: Vec<Eventalign>,
+        motifs: &[Motif],
+        signal_range: &RangeInclusive<f64>,
+    ) -> Result<()>;
+
+    /// Called once after all reads have been added, before sampling.
+    fn build_index(&mut self) -> Result<()> {
         Ok(())
     }
 
-    fn add_reads(&mut self, es: Vec<Eventalign>, motifs: &[Motif]) -> eyre::Result<()> {
-        let tx = self.connection.transaction()?;
-        let mut stmt = tx.prepare("INSERT INTO data (kmer, sample) VALUES (?1, ?2)")?;
-        for eventalign in es.into_iter() {
-            log::info!("Processing Read: {}", eventalign.name());
-            for signal in eventalign.signal_iter() {
-                let kmer = &signal.kmer;
-                log::debug!("Processing signal kmer: {kmer}");
+    /// Number of measurements of the kmer read so far and how many of them
+    /// were kept, only counting reads on the given strand if it is Some
+    fn ingested(&self, kmer: &str, strand: Option<Strand>) -> SampleCounts;
 
-                // Skip if kmer doesn't match any of the kmers
-                if !motifs.iter().any(|m| kmer.starts_with(m.motif())) {
-                    log::debug!("Kmer skipped, doesn't match any motifs");
-                    continue;
-                }
+    /// Random sample of at most n_samples measurements for the kmer, only
+    /// from reads on the given strand if it is Some. Nothing is returned if
+    /// fewer than min_samples measurements are available.
+    fn get_kmer_samples(
+        &mut self,
+        kmer: &str,
+        strand: Option<Strand>,
+        n_samples: usize,
+        min_samples: usize,
+    ) -> Result<Vec<f64>>;
 
-                for sample in signal.samples.iter() {
-                    if !(40.0..=170.0).contains(sample) {
-                        log::debug!("Uncharacteristic signal measurement {sample}");
-                        continue;
-                    }
-                    if sample.is_finite() {
-                        stmt.execute((kmer, sample))?;
-                    }
-                }
-            }
+    /// Random sample of measurements like [SampleStore::get_kmer_samples],
+    /// with a holdout fraction of them held out from training. The split only
+    /// depends on the seed, so it is the same between runs.
+    fn get_kmer_split(
+        &mut self,
+        kmer: &str,
+        strand: Option<Strand>,
+        n_samples: usize,
+        min_samples: usize,
+        holdout: f64,
+        seed: u64,
+    ) -> Result<SplitSamples> {
+        let mut train = self.get_kmer_samples(kmer, strand, n_samples, min_samples)?;
+        if holdout <= 0.0 {
+            return Ok(SplitSamples {
+                train,
+                holdout: Vec::new(),
+            });
         }
-        stmt.finalize()?;
+        // Stores don't return samples in a fixed order, sort so the split
+        // only depends on the seed
+        train.sort_by(|a, b| a.total_cmp(b));
+        let mut rng = SmallRng::seed_from_u64(seed);
+        train.shuffle(&mut rng);
+        let n_holdout = (train.len() as f64 * holdout).round() as usize;
+        let holdout = train.split_off(train.len() - n_holdout);
+        Ok(SplitSamples { train, holdout })
+    }
+}
 
-        tx.commit()?;
-        Ok(())
+/// Lets a store be used for training and still be closed afterwards
+impl<S: SampleStore + ?Sized> SampleStore for &mut S {
+    fn add_reads(
+        &mut self,
+        es: Vec<Eventalign>,
+        motifs: &[Motif],
+        signal_range: &RangeInclusive<f64>,
+    ) -> Result<()> {
+        (**self).add_reads(es, motifs, signal_range)
     }
 
-    fn get_kmer_samples(&self, kmer: &str, n_samples: usize) -> eyre::Result<Vec<f64>> {
-        let mut stmt = self
-            .connection
-            .prepare("SELECT sample FROM data where kmer = :kmer ORDER BY RANDOM() LIMIT :n")?;
-        let rows = stmt.query_map(named_params! {":kmer": kmer, ":n": n_samples}, |row| {
-            row.get::<usize, f64>(0)
-        })?;
-        let mut samples = Vec::new();
-        for sample in rows {
-            samples.push(sample?)
-        }
-        Ok(samples)
+    fn build_index(&mut self) -> Result<()> {
+        (**self).build_index()
+    }
+
+    fn ingested(&self, kmer: &str, strand: Option<Strand>) -> SampleCounts {
+        (**self).ingested(kmer, strand)
+    }
+
+    fn get_kmer_samples(
+        &mut self,
+        kmer: &str,
+        strand: Option<Strand>,
+        n_samples: usize,
+        min_samples: usize,
+    ) -> Result<Vec<f64>> {
+        (**self).get_kmer_samples(kmer, strand, n_samples, min_samples)
     }
 }
 
-#[cfg(test)]
-mod test {
-    use assert_fs::TempDir;
+/// Samples for a kmer, split into ones used for training and ones held out to
+/// check how well the model generalizes
+struct SplitSamples {
+    train: Vec<f64>,
+    holdout: Vec<f64>,
+}
 
-    // use quickcheck::quickcheck;
-    use super::*;
-    use crate::arrow::signal::Signal;
+impl SplitSamples {
+    fn len(&self) -> usize {
+        self.train.len() + self.holdout.len()
+    }
 
-    #[test]
-    fn test_empty_model() {
-        let tmp_dir = TempDir::new().unwrap();
-        let db_path = tmp_dir.join("test.db");
-        let db = Db::open(db_path).expect("Failed to open database file");
-        let opts = TrainOptions::default();
-        assert!(opts.train_gmms(db).is_err());
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Report the average log-likelihood of the held-out samples, flagging the
+/// kmer if they fit much worse than the training samples
+fn score_holdout(gmm: &Mixture<Gaussian>, holdout: &[f64], report: &mut KmerReport) {
+    report.n_holdout = holdout.len();
+    if holdout.is_empty() {
+        return;
+    }
+    let holdout_ll = holdout.iter().map(|x| gmm.ln_f(x)).sum::<f64>() / holdout.len() as f64;
+    report.holdout_log_likelihood = Some(holdout_ll);
+    let n_train = report.n_samples - report.n_outliers;
+    if let Some(train_ll) = report.log_likelihood {
+        let train_ll = train_ll / n_train as f64;
+        if train_ll - holdout_ll > OVERFIT_LL_DIFF {
+            log::warn!(
+                "kmer {} may be overfit, held-out log-likelihood {holdout_ll:.3} is much lower than training log-likelihood {train_ll:.3}",
+                report.kmer
+            );
+            report.overfit = true;
+        }
+    }
+}
+
+/// Measurements from the read for kmers matching any of the motifs
+fn motif_samples<'a>(
+    eventalign: &'a Eventalign,
+    motifs: &'a [Motif],
+) -> impl Iterator<Item = (&'a str, f64)> + 'a {
+    eventalign
+        .signal_iter()
+        .filter(move |signal| {
+            let kmer = &signal.kmer;
+            log::debug!("Processing signal kmer: {kmer}");
+            let matches = motifs.iter().any(|m| kmer.starts_with(m.motif()));
+            if !matches {
+                log::debug!("Kmer skipped, doesn't match any motifs");
+            }
+            matches
+        })
+        .flat_map(|signal| {
+            signal
+                .samples
+                .iter()
+                .map(move |&sample| (signal.kmer.as_str(), sample))
+        })
+}
+
+/// Whether the measurement is finite and within the signal range
+fn is_training_sample(sample: f64, signal_range: &RangeInclusive<f64>) -> bool {
+    if !signal_range.contains(&sample) {
+        log::debug!("Uncharacteristic signal measurement {sample}");
+        return false;
+    }
+    sample.is_finite()
+}
+
+/// Measurements from the read that can be used for training, skipping kmers
+/// that don't match any of the motifs and measurements outside of the signal
+/// range.
+fn training_samples<'a>(
+    eventalign: &'a Eventalign,
+    motifs: &'a [Motif],
+    signal_range: &'a RangeInclusive<f64>,
+) -> impl Iterator<Item = (&'a str, f64)> + 'a {
+    motif_samples(eventalign, motifs)
+        .filter(move |&(_, sample)| is_training_sample(sample, signal_range))
+}
+
+/// Number of measurements of a kmer read from the inputs, and how many of
+/// them were kept for training by [is_training_sample]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct SampleCounts {
+    n_read: usize,
+    n_kept: usize,
+}
+
+impl SampleCounts {
+    fn fraction_kept(&self) -> f64 {
+        self.n_kept as f64 / self.n_read as f64
+    }
+}
+
+/// [SampleCounts] of each kmer, and of each kmer on each strand, counted as
+/// reads are added to a [SampleStore]
+#[derive(Debug, Default)]
+struct IngestCounts {
+    pooled: HashMap<String, SampleCounts>,
+    stranded: HashMap<(String, &'static str), SampleCounts>,
+}
+
+impl IngestCounts {
+    fn add_read(
+        &mut self,
+        eventalign: &Eventalign,
+        motifs: &[Motif],
+        signal_range: &RangeInclusive<f64>,
+    ) {
+        let strand = eventalign.strand().as_str();
+        for (kmer, sample) in motif_samples(eventalign, motifs) {
+            let n_kept = usize::from(is_training_sample(sample, signal_range));
+            for counts in [
+                self.pooled.entry(kmer.to_string()).or_default(),
+                self.stranded.entry((kmer.to_string(), strand)).or_default(),
+            ] {
+                counts.n_read += 1;
+                counts.n_kept += n_kept;
+            }
+        }
+    }
+
+    fn get(&self, kmer: &str, strand: Option<Strand>) -> SampleCounts {
+        let counts = match strand {
+            Some(strand) => self.stranded.get(&(kmer.to_string(), strand.as_str())),
+            None => self.pooled.get(kmer),
+        };
+        counts.copied().unwrap_or_default()
+    }
+}
+
+/// Counts how often each kmer in the reference has signal data, mirroring the
+/// skip frequencies of the classic trainer.
+struct SkipCounter {
+    genome: IndexedReader<File>,
+    chrom_lens: FnvHashMap<String, u64>,
+    kmer_size: usize,
+    skips: KmerSkips,
+}
+
+impl SkipCounter {
+    fn open(genome: &Path, kmer_size: usize) -> Result<Self> {
+        let genome = open_genome_fasta(genome)?;
+        let chrom_lens = chrom_lens(&genome);
+        Ok(Self {
+            genome,
+            chrom_lens,
+            kmer_size,
+            skips: KmerSkips::new(),
+        })
+    }
+
+    fn add_reads(&mut self, es: &[Eventalign]) -> Result<()> {
+        for eventalign in es.iter() {
+            let context = Context::from_read(
+                &mut self.genome,
+                &self.chrom_lens,
+                eventalign,
+                self.kmer_size,
+            )?;
+            self.skips.add_context(eventalign, &context);
+        }
+        Ok(())
+    }
+
+    fn totals(&self) -> Result<FnvHashMap<String, usize>> {
+        self.skips.totals()
+    }
+
+    fn into_ratios(self) -> Result<FnvHashMap<String, f64>> {
+        self.skips.into_ratios()
+    }
+}
+
+/// Number of rows in each multi-row INSERT
+const INSERT_BATCH_SIZE: usize = 1000;
+
+fn insert_sql(table: &str, n_rows: usize) -> String {
+    let values = vec!["(?, ?, ?, ?)"; n_rows].join(", ");
+    format!("INSERT INTO {table} (id, kmer, strand, sample) VALUES {values}")
+}
+
+/// Position in a reservoir of the given capacity to store the seen'th value
+/// added, or None if the value should be dropped. See Algorithm R.
+fn reservoir_slot<R: Rng>(seen: usize, capacity: usize, rng: &mut R) -> Option<usize> {
+    if seen <= capacity {
+        Some(seen - 1)
+    } else {
+        let idx = rng.gen_range(0..seen);
+        (idx < capacity).then_some(idx)
+    }
+}
+
+/// Rows to write to one of the tables of a [Db] for a batch of reads
+#[derive(Default)]
+struct TableWrites<'a> {
+    rows: Vec<(i64, &'a str, &'static str, f64)>,
+    pending: HashMap<i64, usize>,
+    updates: HashMap<i64, (&'static str, f64)>,
+}
+
+impl<'a> TableWrites<'a> {
+    /// New rows are inserted in batches, replacements of rows already in the
+    /// database are updated in place. Row ids always belong to the same kmer
+    /// so only the strand and sample need to be replaced.
+    fn add(
+        &mut self,
+        (id, is_empty): (i64, bool),
+        kmer: &'a str,
+        strand: &'static str,
+        sample: f64,
+    ) {
+        if is_empty {
+            self.pending.insert(id, self.rows.len());
+            self.rows.push((id, kmer, strand, sample));
+        } else if let Some(&row_idx) = self.pending.get(&id) {
+            self.rows[row_idx] = (id, kmer, strand, sample);
+        } else {
+            self.updates.insert(id, (strand, sample));
+        }
+    }
+
+    fn write(self, tx: &Transaction, table: &str) -> eyre::Result<()> {
+        let mut stmt = tx.prepare_cached(&insert_sql(table, INSERT_BATCH_SIZE))?;
+        for batch in self.rows.chunks(INSERT_BATCH_SIZE) {
+            let params = batch
+                .iter()
+                .flat_map(|(id, kmer, strand, sample)| [id as &dyn ToSql, kmer, strand, sample]);
+            if batch.len() == INSERT_BATCH_SIZE {
+                stmt.execute(params_from_iter(params))?;
+            } else {
+                tx.execute(&insert_sql(table, batch.len()), params_from_iter(params))?;
+            }
+        }
+        let mut stmt = tx.prepare_cached(&format!(
+            "UPDATE {table} SET strand = ?1, sample = ?2 WHERE id = ?3"
+        ))?;
+        for (id, (strand, sample)) in self.updates {
+            stmt.execute((strand, sample, id))?;
+        }
+        Ok(())
+    }
+}
+
+/// SQLite database holding a uniform random sample of at most limit
+/// measurements for each kmer, and for each kmer on each strand if
+/// [Db::stranded] is set, using reservoir sampling as reads are added.
+#[derive(Debug)]
+struct Db {
+    limit: usize,
+    stranded: bool,
+    connection: Connection,
+    rng: SmallRng,
+    /// Index used to assign row ids and number of measurements seen for each
+    /// kmer, and each kmer on a strand
+    counts: HashMap<(String, Option<&'static str>), (usize, usize)>,
+    ingested: IngestCounts,
+}
+
+impl Db {
+    fn open<P: AsRef<Path>>(path: P) -> eyre::Result<Self> {
+        let path = path.as_ref();
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+        let db = Db {
+            limit: 50000,
+            stranded: false,
+            connection: Connection::open(path)?,
+            rng: SmallRng::seed_from_u64(2456),
+            counts: Default::default(),
+            ingested: Default::default(),
+        };
+        db.init()?;
+        Ok(db)
+    }
+
+    /// Maximum number of measurements kept for each kmer
+    fn limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Seed for choosing which measurements are kept in each reservoir
+    fn seed(mut self, seed: u64) -> Self {
+        self.rng = SmallRng::seed_from_u64(seed);
+        self
+    }
+
+    /// Also keep the measurements of each kmer on each strand, in the
+    /// stranded table, for training stranded models
+    fn stranded(mut self, stranded: bool) -> eyre::Result<Self> {
+        if stranded && !self.stranded {
+            self.create_table("stranded")?;
+        }
+        self.stranded = stranded;
+        Ok(self)
+    }
+
+    /// Row id for the slot in the reservoir of the kmer, or of the kmer on
+    /// the strand, if the measurement should be kept, and whether the slot is
+    /// empty. Errors if the row id doesn't fit in an SQLite integer, with a
+    /// very large limit.
+    fn reserve(
+        &mut self,
+        kmer: &str,
+        strand: Option<&'static str>,
+    ) -> eyre::Result<Option<(i64, bool)>> {
+        let n_reservoirs = self.counts.len();
+        let (idx, seen) = self
+            .counts
+            .entry((kmer.to_string(), strand))
+            .or_insert((n_reservoirs, 0));
+        *seen += 1;
+        let Some(slot) = reservoir_slot(*seen, self.limit, &mut self.rng) else {
+            return Ok(None);
+        };
+        let id = idx
+            .checked_mul(self.limit)
+            .and_then(|start| start.checked_add(slot))
+            .and_then(|id| i64::try_from(id).ok())
+            .ok_or_else(|| {
+                eyre::eyre!(
+                    "Too many samples per kmer ({}) to store in the database",
+                    self.limit
+                )
+            })?;
+        Ok(Some((id, *seen <= self.limit)))
+    }
+
+    /// Number of measurements stored for the kmer, only on the strand if it
+    /// is Some
+    fn n_available(&self, kmer: &str, strand: Option<Strand>) -> usize {
+        self.counts
+            .get(&(kmer.to_string(), strand.map(|s| s.as_str())))
+            .map(|&(_, seen)| seen.min(self.limit))
+            .unwrap_or_default()
+    }
+
+    /// Checkpoint the write-ahead log into the database and switch back to a
+    /// rollback journal, so the -wal and -shm files are removed when the
+    /// connection is closed
+    fn close(self) -> eyre::Result<()> {
+        let busy: i64 = self
+            .connection
+            .query_row("PRAGMA wal_checkpoint(FULL)", (), |row| row.get(0))?;
+        if busy != 0 {
+            log::warn!("Database was busy, write-ahead log was not fully checkpointed");
+        }
+        self.connection
+            .pragma_update(None, "journal_mode", "DELETE")?;
+        self.connection.close().map_err(|(_, e)| e)?;
+        Ok(())
+    }
+
+    /// Create the table holding the measurements of each kmer:
+    ///
+    /// ```sql
+    /// CREATE TABLE data (
+    ///     id      INTEGER PRIMARY KEY, -- reservoir index * limit + reservoir slot
+    ///     kmer    TEXT NOT NULL,
+    ///     strand  TEXT NOT NULL,       -- +, - or . as in the read metadata
+    ///     sample  REAL NOT NULL        -- current in pA
+    /// );
+    /// ```
+    ///
+    /// The stranded table with the same columns, holding the measurements of
+    /// each kmer on each strand, is created by [Db::stranded]. The kmer_idx
+    /// and stranded_kmer_idx indexes on kmer are added by
+    /// [SampleStore::build_index] once all reads are loaded.
+    fn init(&self) -> eyre::Result<()> {
+        self.create_table("data")?;
+        self.connection.pragma_update(None, "journal_mode", "WAL")?;
+        self.connection
+            .pragma_update(None, "synchronous", "NORMAL")?;
+        self.connection.pragma_update(None, "cache_size", -64000)?;
+        Ok(())
+    }
+
+    fn create_table(&self, table: &str) -> eyre::Result<()> {
+        self.connection.execute(
+            &format!(
+                "CREATE TABLE {table} (
+                    id      INTEGER PRIMARY KEY,
+                    kmer    TEXT NOT NULL,
+                    strand  TEXT NOT NULL,
+                    sample  REAL NOT NULL
+                );"
+            ),
+            (),
+        )?;
+        Ok(())
+    }
+}
+
+impl SampleStore for Db {
+    fn add_reads(
+        &mut self,
+        es: Vec<Eventalign>,
+        motifs: &[Motif],
+        signal_range: &RangeInclusive<f64>,
+    ) -> eyre::Result<()> {
+        let mut pooled = TableWrites::default();
+        let mut stranded = TableWrites::default();
+        for eventalign in es.iter() {
+            log::info!("Processing Read: {}", eventalign.name());
+            self.ingested.add_read(eventalign, motifs, signal_range);
+            let strand = eventalign.strand().as_str();
+            for (kmer, sample) in training_samples(eventalign, motifs, signal_range) {
+                if let Some(slot) = self.reserve(kmer, None)? {
+                    pooled.add(slot, kmer, strand, sample);
+                }
+                if !self.stranded {
+                    continue;
+                }
+                if let Some(slot) = self.reserve(kmer, Some(strand))? {
+                    stranded.add(slot, kmer, strand, sample);
+                }
+            }
+        }
+
+        let tx = self.connection.transaction()?;
+        pooled.write(&tx, "data")?;
+        if self.stranded {
+            stranded.write(&tx, "stranded")?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Building the indexes as rows are inserted slows down inserts, so they
+    /// are only created once all of the data is loaded.
+    fn build_index(&mut self) -> eyre::Result<()> {
+        self.connection
+            .execute("CREATE INDEX IF NOT EXISTS kmer_idx on data (kmer)", ())?;
+        if self.stranded {
+            self.connection.execute(
+                "CREATE INDEX IF NOT EXISTS stranded_kmer_idx on stranded (kmer, strand)",
+                (),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn ingested(&self, kmer: &str, strand: Option<Strand>) -> SampleCounts {
+        self.ingested.get(kmer, strand)
+    }
+
+    fn get_kmer_samples(
+        &mut self,
+        kmer: &str,
+        strand: Option<Strand>,
+        n_samples: usize,
+        min_samples: usize,
+    ) -> eyre::Result<Vec<f64>> {
+        let n_available = self.n_available(kmer, strand);
+        if n_available == 0 || n_available < min_samples {
+            return Ok(Vec::new());
+        }
+        let mut samples = Vec::new();
+        match strand {
+            Some(strand) => {
+                let mut stmt = self.connection.prepare(
+                    "SELECT sample FROM stranded where kmer = :kmer AND strand = :strand",
+                )?;
+                let rows = stmt.query_map(
+                    named_params! {":kmer": kmer, ":strand": strand.as_str()},
+                    |row| row.get::<usize, f64>(0),
+                )?;
+                for sample in rows {
+                    samples.push(sample?)
+                }
+            }
+            None => {
+                let mut stmt = self
+                    .connection
+                    .prepare("SELECT sample FROM data where kmer = :kmer")?;
+                let rows = stmt.query_map(named_params! {":kmer": kmer}, |row| {
+                    row.get::<usize, f64>(0)
+                })?;
+                for sample in rows {
+                    samples.push(sample?)
+                }
+            }
+        }
+        // Already a random sample, only subsample if fewer are requested
+        if samples.len() > n_samples {
+            samples.shuffle(&mut self.rng);
+            samples.truncate(n_samples);
+        }
+        Ok(samples)
+    }
+}
+
+/// Uniform random sample of the values added so far, using reservoir
+/// sampling so memory is bounded by the capacity.
+#[derive(Debug, Default)]
+struct Reservoir {
+    seen: usize,
+    samples: Vec<f64>,
+}
+
+impl Reservoir {
+    fn add<R: Rng>(&mut self, sample: f64, capacity: usize, rng: &mut R) {
+        self.seen += 1;
+        match reservoir_slot(self.seen, capacity, rng) {
+            Some(idx) if idx == self.samples.len() => self.samples.push(sample),
+            Some(idx) => self.samples[idx] = sample,
+            None => (),
+        }
+    }
+}
+
+/// Keeps at most capacity measurements per kmer, and per kmer on each
+/// strand if [MemoryDb::stranded] is set, in memory.
+#[derive(Debug)]
+struct MemoryDb {
+    capacity: usize,
+    stranded: bool,
+    rng: SmallRng,
+    pooled: HashMap<String, Reservoir>,
+    by_strand: HashMap<(String, &'static str), Reservoir>,
+    ingested: IngestCounts,
+}
+
+impl MemoryDb {
+    fn new(capacity: usize) -> Self {
+        MemoryDb {
+            capacity,
+            stranded: false,
+            rng: SmallRng::seed_from_u64(2456),
+            pooled: HashMap::new(),
+            by_strand: HashMap::new(),
+            ingested: IngestCounts::default(),
+        }
+    }
+
+    /// Seed for choosing which measurements are kept in each reservoir
+    fn seed(mut self, seed: u64) -> Self {
+        self.rng = SmallRng::seed_from_u64(seed);
+        self
+    }
+
+    /// Also keep the measurements of each kmer on each strand, for training
+    /// stranded models
+    fn stranded(mut self, stranded: bool) -> Self {
+        self.stranded = stranded;
+        self
+    }
+}
+
+impl SampleStore for MemoryDb {
+    fn add_reads(
+        &mut self,
+        es: Vec<Eventalign>,
+        motifs: &[Motif],
+        signal_range: &RangeInclusive<f64>,
+    ) -> Result<()> {
+        for eventalign in es.into_iter() {
+            log::info!("Processing Read: {}", eventalign.name());
+            self.ingested.add_read(&eventalign, motifs, signal_range);
+            let strand = eventalign.strand().as_str();
+            for (kmer, sample) in training_samples(&eventalign, motifs, signal_range) {
+                self.pooled.entry(kmer.to_string()).or_default().add(
+                    sample,
+                    self.capacity,
+                    &mut self.rng,
+                );
+                if !self.stranded {
+                    continue;
+                }
+                self.by_strand
+                    .entry((kmer.to_string(), strand))
+                    .or_default()
+                    .add(sample, self.capacity, &mut self.rng);
+            }
+        }
+        Ok(())
+    }
+
+    fn get_kmer_samples(
+        &mut self,
+        kmer: &str,
+        strand: Option<Strand>,
+        n_samples: usize,
+        min_samples: usize,
+    ) -> Result<Vec<f64>> {
+        let reservoir = match strand {
+            Some(strand) => self.by_strand.get(&(kmer.to_string(), strand.as_str())),
+            None => self.pooled.get(kmer),
+        };
+        let samples = match reservoir {
+            Some(reservoir) => &reservoir.samples[..],
+            None => &[],
+        };
+        if samples.len() < min_samples {
+            return Ok(Vec::new());
+        }
+        Ok(samples
+            .choose_multiple(&mut self.rng, n_samples)
+            .cloned()
+            .collect())
+    }
+
+    fn ingested(&self, kmer: &str, strand: Option<Strand>) -> SampleCounts {
+        self.ingested.get(kmer, strand)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{cell::RefCell, rc::Rc};
+
+    use assert_fs::TempDir;
+    use float_eq::assert_float_eq;
+    use rand::{prelude::SmallRng, SeedableRng};
+
+    // use quickcheck::quickcheck;
+    use super::*;
+    use crate::{
+        arrow::{
+            arrow_utils::{save, wrap_writer},
+            signal::Signal,
+        },
+        test_utils::{collapse_single_read, TempIndexedGenome},
+    };
+
+    const SIGNAL_RANGE: RangeInclusive<f64> = DEFAULT_SIGNAL_MIN..=DEFAULT_SIGNAL_MAX;
+
+    impl SampleStore for Box<dyn SampleStore> {
+        fn add_reads(
+            &mut self,
+            es: Vec<Eventalign>,
+            motifs: &[Motif],
+            signal_range: &RangeInclusive<f64>,
+        ) -> Result<()> {
+            self.as_mut().add_reads(es, motifs, signal_range)
+        }
+
+        fn build_index(&mut self) -> Result<()> {
+            self.as_mut().build_index()
+        }
+
+        fn ingested(&self, kmer: &str, strand: Option<Strand>) -> SampleCounts {
+            self.as_ref().ingested(kmer, strand)
+        }
+
+        fn get_kmer_samples(
+            &mut self,
+            kmer: &str,
+            strand: Option<Strand>,
+            n_samples: usize,
+            min_samples: usize,
+        ) -> Result<Vec<f64>> {
+            self.as_mut()
+                .get_kmer_samples(kmer, strand, n_samples, min_samples)
+        }
+    }
+
+    /// Store that fails once samples have been taken for n_kmers kmers, as if
+    /// training was interrupted
+    struct InterruptedStore<S> {
+        store: S,
+        n_kmers: usize,
+    }
+
+    impl<S: SampleStore> SampleStore for InterruptedStore<S> {
+        fn add_reads(
+            &mut self,
+            es: Vec<Eventalign>,
+            motifs: &[Motif],
+            signal_range: &RangeInclusive<f64>,
+        ) -> Result<()> {
+            self.store.add_reads(es, motifs, signal_range)
+        }
+
+        fn build_index(&mut self) -> Result<()> {
+            self.store.build_index()
+        }
+
+        fn ingested(&self, kmer: &str, strand: Option<Strand>) -> SampleCounts {
+            self.store.ingested(kmer, strand)
+        }
+
+        fn get_kmer_samples(
+            &mut self,
+            kmer: &str,
+            strand: Option<Strand>,
+            n_samples: usize,
+            min_samples: usize,
+        ) -> Result<Vec<f64>> {
+            if self.n_kmers == 0 {
+                return Err(eyre::eyre!("Training interrupted"));
+            }
+            self.n_kmers -= 1;
+            self.store
+                .get_kmer_samples(kmer, strand, n_samples, min_samples)
+        }
+    }
+
+    fn kmer_report() -> KmerReport {
+        KmerReport::new("AAAAAA", None, 0)
+    }
+
+    /// SQLite and in-memory sample stores, so each test covers both
+    fn stores(tmp_dir: &TempDir) -> Vec<Box<dyn SampleStore>> {
+        let db = Db::open(tmp_dir.join("test.db")).expect("Failed to open database file");
+        vec![Box::new(db), Box::new(MemoryDb::new(50000))]
+    }
+
+    /// Like [stores], also keeping the samples of each kmer on each strand
+    fn stranded_stores(tmp_dir: &TempDir, limit: usize) -> Vec<Box<dyn SampleStore>> {
+        let db = Db::open(tmp_dir.join("test.db"))
+            .and_then(|db| db.limit(limit).stranded(true))
+            .expect("Failed to open database file");
+        vec![Box::new(db), Box::new(MemoryDb::new(limit).stranded(true))]
+    }
+
+    #[test]
+    fn test_empty_model() {
+        let tmp_dir = TempDir::new().unwrap();
+        for db in stores(&tmp_dir) {
+            let opts = TrainOptions::default();
+            assert!(opts.train_gmms(db).is_err());
+        }
     }
 
     #[test]
     fn test_all_kmers() {
-        let kmers = all_kmers();
+        let kmers = all_kmers(6);
         assert_eq!(kmers.len(), 4096);
+
+        let kmers = all_kmers(5);
+        assert_eq!(kmers.len(), 1024);
+        assert!(kmers.iter().all(|k| k.len() == 5));
     }
 
     #[test]
     fn test_db_no_kmer() {
         let tmp_dir = TempDir::new().unwrap();
-        let db_path = tmp_dir.join("test.db");
-        let mut db = Db::open(db_path).expect("Failed to open database file");
-        let eventalign = Eventalign::default();
-        db.add_reads(vec![eventalign], &all_bases())
-            .expect("Unable to add read");
-        let samples = db
-            .get_kmer_samples("ABCDEF", 5000)
-            .expect("Unable to get samples");
-        assert!(samples.is_empty());
+        for mut db in stores(&tmp_dir) {
+            let eventalign = Eventalign::default();
+            db.add_reads(vec![eventalign], &all_bases(), &SIGNAL_RANGE)
+                .expect("Unable to add read");
+            let samples = db
+                .get_kmer_samples("ABCDEF", None, 5000, 0)
+                .expect("Unable to get samples");
+            assert!(samples.is_empty());
+        }
     }
     #[test]
     fn test_db_motif() {
         let tmp_dir = TempDir::new().unwrap();
-        let db_path = tmp_dir.join("test.db");
         let test_cases = vec![
             ("AAAAAA", vec![100.0; 3], true),
             ("AACCCC", vec![100.0; 3], false),
         ];
-        let mut db = Db::open(db_path).expect("Failed to open database file");
         let signal_data = test_cases
             .iter()
             .enumerate()
             .map(|(i, (k, xs, _))| Signal::new(i as u64, k.to_string(), 1.0, 0.5, xs.clone()))
             .collect::<Vec<_>>();
-        let mut eventalign = Eventalign::default();
-        *eventalign.signal_data_mut() = signal_data;
-        db.add_reads(vec![eventalign], &[Motif::new("AAA", 2)])
-            .expect("Unable to add read");
+        for mut db in stores(&tmp_dir) {
+            let mut eventalign = Eventalign::default();
+            *eventalign.signal_data_mut() = signal_data.clone();
+            db.add_reads(vec![eventalign], &[Motif::new("AAA", 2)], &SIGNAL_RANGE)
+                .expect("Unable to add read");
 
-        for (k, xs, unfiltered) in test_cases.into_iter() {
-            let err_msg = format!("Unable to retrieve kmer values for {k}");
-            let samples = db.get_kmer_samples(k, 5000).expect(&err_msg);
-            if unfiltered {
-                assert_eq!(samples, xs);
-            } else {
-                assert!(samples.is_empty(), "{k}");
+            for &(k, ref xs, unfiltered) in test_cases.iter() {
+                let err_msg = format!("Unable to retrieve kmer values for {k}");
+                let samples = db.get_kmer_samples(k, None, 5000, 0).expect(&err_msg);
+                if unfiltered {
+                    assert_eq!(&samples, xs);
+                } else {
+                    assert!(samples.is_empty(), "{k}");
+                }
             }
         }
     }
@@ -376,7 +1892,7 @@ mod test {
             .collect::<Vec<_>>();
         let mut eventalign = Eventalign::default();
         *eventalign.signal_data_mut() = signal_data;
-        db.add_reads(vec![eventalign], &all_bases())
+        db.add_reads(vec![eventalign], &all_bases(), &SIGNAL_RANGE)
             .expect("Unable to add read");
         let mut stmt = db
             .connection
@@ -390,33 +1906,71 @@ mod test {
         assert_eq!(3, res.unwrap()[0])
     }
 
+    #[test]
+    fn test_db_batches() {
+        let tmp_dir = TempDir::new().unwrap();
+        let n_samples = INSERT_BATCH_SIZE * 2 + 7;
+        let samples = (0..n_samples)
+            .map(|i| 50.0 + (i % 100) as f64)
+            .collect::<Vec<_>>();
+        let mut eventalign = Eventalign::default();
+        *eventalign.signal_data_mut() = vec![
+            Signal::new(0, "AAAAAA".to_string(), 1.0, 0.5, samples.clone()),
+            Signal::new(1, "CCCCCC".to_string(), 1.0, 0.5, vec![100.0; 3]),
+        ];
+        let mut db = Db::open(tmp_dir.join("test.db")).unwrap();
+        db.add_reads(vec![eventalign], &all_bases(), &SIGNAL_RANGE)
+            .unwrap();
+        db.build_index().unwrap();
+
+        let mut stored = db.get_kmer_samples("AAAAAA", None, n_samples, 0).unwrap();
+        stored.sort_by(|a, b| a.total_cmp(b));
+        let mut expected = samples;
+        expected.sort_by(|a, b| a.total_cmp(b));
+        assert_eq!(stored, expected);
+        assert_eq!(
+            db.get_kmer_samples("CCCCCC", None, 5000, 0).unwrap(),
+            vec![100.0; 3]
+        );
+
+        let n_idx: usize = db
+            .connection
+            .query_row(
+                "SELECT COUNT(*) FROM sqlite_master WHERE type = 'index' AND name = 'kmer_idx'",
+                (),
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(n_idx, 1);
+    }
+
     #[test]
     fn test_db() {
         let tmp_dir = TempDir::new().unwrap();
-        let db_path = tmp_dir.join("test.db");
         let test_cases = vec![
             ("AAAAAA", vec![100.0; 3], true),
             ("GGGGGG", vec![20.0; 4], false),
             ("CCCCCC", vec![300.0; 2], false),
         ];
-        let mut db = Db::open(db_path).expect("Failed to open database file");
         let signal_data = test_cases
             .iter()
             .enumerate()
             .map(|(i, (k, xs, _))| Signal::new(i as u64, k.to_string(), 1.0, 0.5, xs.clone()))
             .collect::<Vec<_>>();
-        let mut eventalign = Eventalign::default();
-        *eventalign.signal_data_mut() = signal_data;
-        db.add_reads(vec![eventalign], &all_bases())
-            .expect("Unable to add read");
+        for mut db in stores(&tmp_dir) {
+            let mut eventalign = Eventalign::default();
+            *eventalign.signal_data_mut() = signal_data.clone();
+            db.add_reads(vec![eventalign], &all_bases(), &SIGNAL_RANGE)
+                .expect("Unable to add read");
 
-        for (k, xs, unfiltered) in test_cases.into_iter() {
-            let err_msg = format!("Unable to retrieve kmer values for {k}");
-            let samples = db.get_kmer_samples(k, 5000).expect(&err_msg);
-            if unfiltered {
-                assert_eq!(samples, xs);
-            } else {
-                assert!(samples.is_empty(), "{k}");
+            for &(k, ref xs, unfiltered) in test_cases.iter() {
+                let err_msg = format!("Unable to retrieve kmer values for {k}");
+                let samples = db.get_kmer_samples(k, None, 5000, 0).expect(&err_msg);
+                if unfiltered {
+                    assert_eq!(&samples, xs);
+                } else {
+                    assert!(samples.is_empty(), "{k}");
+                }
             }
         }
     }
@@ -440,12 +1994,855 @@ mod test {
         ];
         let opts = TrainOptions::default();
         let vs = ValidSampleData::validated(cases).unwrap();
-        let xs = opts.train_gmm(vs);
+        let xs = opts.train_gmm(vs, &mut kmer_report());
         assert!(xs.is_ok(), "first");
 
         let case = vec![100.0, 100.0, 0.0, -0.0];
         let vs = ValidSampleData::validated(case).unwrap();
-        let xs = opts.train_gmm(vs);
+        let xs = opts.train_gmm(vs, &mut kmer_report());
         assert!(xs.is_err(), "not enough different values");
     }
+
+    /// n samples alternating between clusters around low and high, with a
+    /// little jitter so they aren't all the same value
+    fn bimodal_samples(n: usize, low: f64, high: f64) -> Vec<f64> {
+        (0..n)
+            .map(|j| if j % 2 == 0 { low } else { high } + (j % 10) as f64 * 0.3)
+            .collect()
+    }
+
+    /// Read with two well separated clusters of samples for the first few
+    /// kmers trained
+    fn two_cluster_read() -> Eventalign {
+        let signal_data = ["AAAAAA", "CAAAAA", "GAAAAA", "TAAAAA", "ACAAAA"]
+            .into_iter()
+            .enumerate()
+            .map(|(i, k)| {
+                let offset = i as f64;
+                let samples = bimodal_samples(100, 80.0 + offset, 120.0 + offset);
+                Signal::new(i as u64, k.to_string(), 1.0, 0.5, samples)
+            })
+            .collect::<Vec<_>>();
+        let mut eventalign = Eventalign::default();
+        *eventalign.signal_data_mut() = signal_data;
+        eventalign
+    }
+
+    /// Database with the samples of [two_cluster_read]
+    fn checkpoint_db(db_path: &Path) -> Db {
+        let mut db = Db::open(db_path).expect("Failed to open database file");
+        db.add_reads(vec![two_cluster_read()], &all_bases(), &SIGNAL_RANGE)
+            .expect("Unable to add read");
+        db
+    }
+
+    /// Sorted (mean, std, weight) of each component, since component order
+    /// depends on initialization
+    fn sorted_components(model: &Model, kmer: &str) -> Vec<(f64, f64, f64)> {
+        let mix = model.gmms()[kmer].mixture();
+        let mut comps = mix
+            .components()
+            .iter()
+            .zip(mix.weights())
+            .map(|(g, &w)| (g.mu(), g.sigma(), w))
+            .collect::<Vec<_>>();
+        comps.sort_by(|a, b| a.0.total_cmp(&b.0));
+        comps
+    }
+
+    #[test]
+    fn test_checkpoint_resume() {
+        let tmp_dir = TempDir::new().unwrap();
+        let checkpoint = tmp_dir.join("model.partial");
+
+        let db = checkpoint_db(&tmp_dir.join("clean.db"));
+        let clean = TrainOptions::default().train_gmms(db).unwrap().0;
+        assert_eq!(clean.gmms().len(), 5);
+
+        let db = checkpoint_db(&tmp_dir.join("interrupted.db"));
+        let interrupted = InterruptedStore {
+            store: db,
+            n_kmers: 2,
+        };
+        let result = TrainOptions::default()
+            .checkpoint_path(Some(checkpoint.clone()))
+            .checkpoint_every(1)
+            .train_gmms(interrupted);
+        assert!(result.is_err());
+
+        let partial = Model::load(&checkpoint).unwrap();
+        assert_eq!(partial.gmms().len(), 2);
+        assert!(partial.gmms().contains_key("AAAAAA"));
+        assert!(partial.gmms().contains_key("CAAAAA"));
+
+        let db = checkpoint_db(&tmp_dir.join("resumed.db"));
+        let resumed = TrainOptions::default()
+            .checkpoint_path(Some(checkpoint))
+            .checkpoint_every(1)
+            .resume(true)
+            .train_gmms(db)
+            .unwrap()
+            .0;
+        assert_eq!(resumed.gmms().len(), clean.gmms().len());
+        for kmer in clean.gmms().keys() {
+            let expected = sorted_components(&clean, kmer);
+            let actual = sorted_components(&resumed, kmer);
+            for (e, a) in expected.into_iter().zip(actual) {
+                assert_float_eq!(e.0, a.0, abs <= 1e-6);
+                assert_float_eq!(e.1, a.1, abs <= 1e-6);
+                assert_float_eq!(e.2, a.2, abs <= 1e-6);
+            }
+        }
+    }
+
+    /// Read with two clusters of samples for a single kmer, centered around
+    /// center
+    fn stranded_read(kmer: &str, strand: Strand, center: f64) -> Eventalign {
+        let samples = bimodal_samples(100, center - 10.0, center + 10.0);
+        let mut eventalign = Eventalign::default();
+        eventalign.metadata.strand = strand;
+        *eventalign.signal_data_mut() = vec![Signal::new(0, kmer.to_string(), 1.0, 0.5, samples)];
+        eventalign
+    }
+
+    fn model_mean(model: &Model, kmer: &str, strand: Strand) -> f64 {
+        model
+            .gmm(kmer, strand)
+            .unwrap()
+            .components()
+            .into_iter()
+            .map(|(w, mu, _)| w * mu)
+            .sum()
+    }
+
+    #[test]
+    fn test_stranded() {
+        let tmp_dir = TempDir::new().unwrap();
+        for db in stranded_stores(&tmp_dir, 50000) {
+            check_stranded(db);
+        }
+    }
+
+    fn check_stranded(mut db: Box<dyn SampleStore>) {
+        let reads = vec![
+            stranded_read("AAAAAA", Strand::plus(), 80.0),
+            stranded_read("AAAAAA", Strand::minus(), 140.0),
+            stranded_read("CAAAAA", Strand::unknown(), 100.0),
+        ];
+        db.add_reads(reads, &all_bases(), &SIGNAL_RANGE).unwrap();
+
+        let plus = db
+            .get_kmer_samples("AAAAAA", Some(Strand::plus()), 5000, 0)
+            .unwrap();
+        assert_eq!(plus.len(), 100);
+        let both = db.get_kmer_samples("AAAAAA", None, 5000, 0).unwrap();
+        assert_eq!(both.len(), 200);
+
+        let model = TrainOptions::default()
+            .stranded(true)
+            .train_gmms(db)
+            .unwrap()
+            .0;
+        assert!(model.is_stranded());
+        assert_float_eq!(
+            model_mean(&model, "AAAAAA", Strand::plus()),
+            81.35,
+            abs <= 1.0
+        );
+        assert_float_eq!(
+            model_mean(&model, "AAAAAA", Strand::minus()),
+            141.35,
+            abs <= 1.0
+        );
+        assert_float_eq!(
+            model_mean(&model, "AAAAAA", Strand::unknown()),
+            111.35,
+            abs <= 1.0
+        );
+
+        // Only unstranded data, so falls back to the unstranded model
+        assert_float_eq!(
+            model_mean(&model, "CAAAAA", Strand::plus()),
+            101.35,
+            abs <= 1.0
+        );
+    }
+
+    #[test]
+    fn test_stranded_reservoirs() {
+        let tmp_dir = TempDir::new().unwrap();
+        let limit = 60;
+        let mut reports = Vec::new();
+        for mut db in stranded_stores(&tmp_dir, limit) {
+            let reads = vec![
+                stranded_read("AAAAAA", Strand::plus(), 80.0),
+                stranded_read("AAAAAA", Strand::minus(), 140.0),
+            ];
+            db.add_reads(reads, &all_bases(), &SIGNAL_RANGE).unwrap();
+            db.build_index().unwrap();
+            for strand in [None, Some(Strand::plus()), Some(Strand::minus())] {
+                let samples = db.get_kmer_samples("AAAAAA", strand, 5000, 0).unwrap();
+                assert_eq!(samples.len(), limit, "{strand:?}");
+            }
+
+            let report = TrainOptions::default()
+                .n_samples(limit)
+                .stranded(true)
+                .train_gmms(db)
+                .unwrap()
+                .1;
+            let n_samples = report
+                .kmers()
+                .iter()
+                .map(|k| (k.strand, k.n_samples))
+                .collect::<Vec<_>>();
+            reports.push(n_samples);
+        }
+        assert!(!reports[0].is_empty());
+        assert_eq!(reports[0], reports[1]);
+
+        // Samples aren't kept by strand unless asked for
+        for mut db in stores(&tmp_dir) {
+            let reads = vec![stranded_read("AAAAAA", Strand::plus(), 80.0)];
+            db.add_reads(reads, &all_bases(), &SIGNAL_RANGE).unwrap();
+            db.build_index().unwrap();
+            let samples = db
+                .get_kmer_samples("AAAAAA", Some(Strand::plus()), 5000, 0)
+                .unwrap();
+            assert!(samples.is_empty());
+            assert_eq!(
+                db.get_kmer_samples("AAAAAA", None, 5000, 0).unwrap().len(),
+                100
+            );
+        }
+        let db = Db::open(tmp_dir.join("test.db")).unwrap();
+        let n_tables: usize = db
+            .connection
+            .query_row(
+                "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = 'stranded'",
+                (),
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(n_tables, 0);
+    }
+
+    #[test]
+    fn test_signal_range_boundaries() {
+        let tmp_dir = TempDir::new().unwrap();
+        let below = |x: f64| f64::from_bits(x.to_bits() - 1);
+        let above = |x: f64| f64::from_bits(x.to_bits() + 1);
+        for (min, max) in [(DEFAULT_SIGNAL_MIN, DEFAULT_SIGNAL_MAX), (60.0, 250.0)] {
+            let samples = vec![below(min), min, max, above(max)];
+            for mut db in stores(&tmp_dir) {
+                let mut eventalign = Eventalign::default();
+                *eventalign.signal_data_mut() = vec![Signal::new(
+                    0,
+                    "AAAAAA".to_string(),
+                    1.0,
+                    0.5,
+                    samples.clone(),
+                )];
+                db.add_reads(vec![eventalign], &all_bases(), &(min..=max))
+                    .unwrap();
+                let mut kept = db.get_kmer_samples("AAAAAA", None, 5000, 0).unwrap();
+                kept.sort_by(|a, b| a.total_cmp(b));
+                assert_eq!(kept, vec![min, max]);
+            }
+        }
+    }
+
+    fn kmer_read(kmer: &str, samples: Vec<f64>) -> Eventalign {
+        let mut eventalign = Eventalign::default();
+        *eventalign.signal_data_mut() = vec![Signal::new(0, kmer.to_string(), 1.0, 0.5, samples)];
+        eventalign
+    }
+
+    fn n_rows(db: &Db, kmer: &str) -> usize {
+        db.connection
+            .query_row("SELECT COUNT(*) FROM data WHERE kmer = ?1", [kmer], |row| {
+                row.get(0)
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn test_db_reservoir() {
+        let tmp_dir = TempDir::new().unwrap();
+        let mut db = Db::open(tmp_dir.join("test.db"))
+            .and_then(|db| db.limit(10).stranded(true))
+            .unwrap();
+        let first = (0..60).map(|i| 50.0 + i as f64).collect::<Vec<_>>();
+        let second = (60..100).map(|i| 50.0 + i as f64).collect::<Vec<_>>();
+        let reads = vec![
+            kmer_read("AAAAAA", first.clone()),
+            kmer_read("CCCCCC", vec![100.0, 101.0, 102.0]),
+        ];
+        db.add_reads(reads, &all_bases(), &SIGNAL_RANGE).unwrap();
+        assert_eq!(n_rows(&db, "AAAAAA"), 10);
+
+        // Replacements in later batches update rows already in the database
+        db.add_reads(
+            vec![kmer_read("AAAAAA", second.clone())],
+            &all_bases(),
+            &SIGNAL_RANGE,
+        )
+        .unwrap();
+        db.build_index().unwrap();
+        assert_eq!(n_rows(&db, "AAAAAA"), 10);
+        assert_eq!(db.counts[&("AAAAAA".to_string(), None)].1, 100);
+
+        let kept = db.get_kmer_samples("AAAAAA", None, 5000, 0).unwrap();
+        assert_eq!(kept.len(), 10);
+        assert!(kept.iter().all(|x| first.contains(x) || second.contains(x)));
+        assert!(
+            kept.iter().any(|x| second.contains(x)),
+            "Reservoir never replaced"
+        );
+        assert_eq!(db.get_kmer_samples("AAAAAA", None, 3, 0).unwrap().len(), 3);
+        // Samples for a strand come from their own capped reservoir
+        let stranded = db
+            .get_kmer_samples("AAAAAA", Some(Strand::unknown()), 5000, 0)
+            .unwrap();
+        assert_eq!(stranded.len(), 10);
+
+        // Under the cap, every sample is kept
+        let mut under = db.get_kmer_samples("CCCCCC", None, 5000, 0).unwrap();
+        under.sort_by(|a, b| a.total_cmp(b));
+        assert_eq!(under, vec![100.0, 101.0, 102.0]);
+    }
+
+    #[test]
+    fn test_db_row_id_overflow() {
+        let tmp_dir = TempDir::new().unwrap();
+        let mut db = Db::open(tmp_dir.join("test.db")).unwrap().limit(usize::MAX);
+        assert_eq!(db.reserve("AAAAAA", None).unwrap(), Some((0, true)));
+        assert!(db.reserve("CCCCCC", None).is_err());
+        let reads = vec![
+            kmer_read("AAAAAA", vec![100.0]),
+            kmer_read("CCCCCC", vec![100.0]),
+        ];
+        assert!(db.add_reads(reads, &all_bases(), &SIGNAL_RANGE).is_err());
+    }
+
+    #[test]
+    fn test_train_signal_range() {
+        let tmp_dir = TempDir::new().unwrap();
+        let samples = bimodal_samples(100, 200.0, 220.0);
+        for mut db in stores(&tmp_dir) {
+            let signal_range = 60.0..=250.0;
+            db.add_reads(
+                vec![kmer_read("AAAAAA", samples.clone())],
+                &all_bases(),
+                &signal_range,
+            )
+            .unwrap();
+            let model = TrainOptions::default()
+                .signal_min(60.0)
+                .signal_max(250.0)
+                .train_gmms(db)
+                .unwrap()
+                .0;
+            assert_float_eq!(
+                model_mean(&model, "AAAAAA", Strand::unknown()),
+                211.35,
+                abs <= 1.0
+            );
+        }
+    }
+
+    #[test]
+    fn test_train_report() {
+        let tmp_dir = TempDir::new().unwrap();
+        let mut clustered = bimodal_samples(100, 80.0, 120.0);
+        clustered.push(160.0);
+        let reads = vec![
+            kmer_read("AAAAAA", clustered),
+            kmer_read("CAAAAA", vec![100.0; 50]),
+            kmer_read("GAAAAA", vec![100.0]),
+        ];
+        let mut db = Db::open(tmp_dir.join("test.db")).unwrap();
+        db.add_reads(reads, &all_bases(), &SIGNAL_RANGE).unwrap();
+        let (model, report) = TrainOptions::default()
+            .dbscan(true)
+            .min_kmer_samples(1)
+            .train_gmms(db)
+            .unwrap();
+        assert_eq!(model.gmms().len(), 1);
+
+        let kmers = report.kmers();
+        assert_eq!(kmers.len(), 3);
+        let trained = &kmers[0];
+        assert_eq!(trained.kmer, "AAAAAA");
+        assert_eq!(trained.strand, None);
+        assert_eq!(trained.n_samples, 101);
+        assert_eq!(trained.n_outliers, 1);
+        assert!(trained.log_likelihood.unwrap().is_finite());
+        assert_eq!(trained.components.len(), 2);
+        let total_weight: f64 = trained.components.iter().map(|c| c.0).sum();
+        assert_float_eq!(total_weight, 1.0, abs <= 1e-9);
+        assert!(trained.error.is_none());
+
+        let failed = report.failed().map(|k| k.kmer.as_str()).collect::<Vec<_>>();
+        assert_eq!(failed, vec!["CAAAAA", "GAAAAA"]);
+        assert_eq!(kmers[2].n_samples, 1);
+        assert!(kmers[2].log_likelihood.is_none());
+
+        let validated = report.validation().kmers();
+        assert_eq!(validated.len(), 2);
+        assert_eq!(validated[0].n_original, 101);
+        assert_eq!(validated[0].n_valid, 101);
+        assert_eq!(report.validation().mostly_invalid().count(), 0);
+
+        let mut output = Vec::new();
+        report.write_tsv(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let lines = output.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 4);
+        assert!(lines.iter().all(|l| l.split('\t').count() == 12));
+        assert!(lines[1].starts_with("AAAAAA\tboth\t101\t1\t"));
+        assert!(lines[3].ends_with("\tNA\t0\tNA\tfalse\tNA\tNA\tNA\tFewer than 2 valid samples"));
+
+        let report_path = tmp_dir.join("train_report.tsv");
+        report.save_as(&report_path, false).unwrap();
+        assert_eq!(std::fs::read_to_string(&report_path).unwrap(), output);
+        assert!(report.save_as(&report_path, false).is_err());
+        report.save_as(&report_path, true).unwrap();
+    }
+
+    #[test]
+    fn test_train_report_discarded() {
+        let tmp_dir = TempDir::new().unwrap();
+        let mut samples = bimodal_samples(100, 80.0, 120.0);
+        samples.extend([f64::NAN; 140]);
+        samples.extend([f64::INFINITY, f64::NEG_INFINITY, 0.0, 500.0]);
+        for mut db in stores(&tmp_dir) {
+            db.add_reads(
+                vec![kmer_read("AAAAAA", samples.clone())],
+                &all_bases(),
+                &SIGNAL_RANGE,
+            )
+            .unwrap();
+            let (model, report) = TrainOptions::default().train_gmms(db).unwrap();
+            assert_eq!(model.gmms().len(), 1);
+            assert_eq!(report.kmers()[0].n_samples, 100);
+
+            let validated = report.validation().kmers();
+            assert_eq!(validated.len(), 1);
+            assert_eq!(validated[0].n_original, 244);
+            assert_eq!(validated[0].n_valid, 100);
+            let mostly_invalid = report
+                .validation()
+                .mostly_invalid()
+                .map(|k| k.kmer.as_str())
+                .collect::<Vec<_>>();
+            assert_eq!(mostly_invalid, vec!["AAAAAA"]);
+        }
+    }
+
+    #[test]
+    fn test_min_kmer_samples() {
+        let tmp_dir = TempDir::new().unwrap();
+        for mut db in stores(&tmp_dir) {
+            let reads = vec![
+                kmer_read("AAAAAA", vec![80.0, 100.0, 120.0]),
+                kmer_read("CAAAAA", (0..15).map(|j| 80.0 + j as f64).collect()),
+            ];
+            db.add_reads(reads, &all_bases(), &SIGNAL_RANGE).unwrap();
+            assert!(db
+                .get_kmer_samples("AAAAAA", None, 5000, 5)
+                .unwrap()
+                .is_empty());
+            assert_eq!(
+                db.get_kmer_samples("CAAAAA", None, 5000, 5).unwrap().len(),
+                15
+            );
+
+            let (model, report) = TrainOptions::default()
+                .min_kmer_samples(5)
+                .train_gmms(db)
+                .unwrap();
+            let fitted = model.gmms().keys().collect::<Vec<_>>();
+            assert_eq!(fitted, vec!["CAAAAA"]);
+            assert_eq!(report.kmers().len(), 1);
+        }
+    }
+
+    #[test]
+    fn test_inspect_kmer() {
+        let tmp_dir = TempDir::new().unwrap();
+        let samples = bimodal_samples(100, 80.0, 120.0);
+        let mut db = Db::open(tmp_dir.join("test.db")).unwrap();
+        db.add_reads(
+            vec![kmer_read("AAAAAA", samples)],
+            &all_bases(),
+            &SIGNAL_RANGE,
+        )
+        .unwrap();
+        let model = TrainOptions::default().train_gmms(db).unwrap().0;
+
+        let kmer_model = model.inspect_kmer("AAAAAA").unwrap();
+        assert_eq!(kmer_model.kmer, "AAAAAA");
+        assert_eq!(kmer_model.skip_prob, None);
+        let mut means = kmer_model
+            .components
+            .iter()
+            .map(|c| c.mean)
+            .collect::<Vec<_>>();
+        means.sort_by(|a, b| a.total_cmp(b));
+        assert_eq!(means.len(), 2);
+        assert_float_eq!(means[0], 81.2, abs <= 0.1);
+        assert_float_eq!(means[1], 121.5, abs <= 0.1);
+        let total_weight: f64 = kmer_model.components.iter().map(|c| c.weight).sum();
+        assert_float_eq!(total_weight, 1.0, abs <= 1e-9);
+        assert!(kmer_model.to_string().starts_with("AAAAAA\t"));
+        assert!(model.inspect_kmer("CAAAAA").is_none());
+
+        let mut json = Vec::new();
+        model.write_kmer_json(&mut json, "AAAAAA").unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(json["kmer"], "AAAAAA");
+        assert_eq!(json["components"].as_array().unwrap().len(), 2);
+        assert!(json["skip_prob"].is_null());
+        assert!(model.write_kmer_json(&mut Vec::new(), "CAAAAA").is_err());
+    }
+
+    #[test]
+    fn test_train_holdout() {
+        let tmp_dir = TempDir::new().unwrap();
+        let samples = bimodal_samples(200, 80.0, 120.0);
+        for mut db in stores(&tmp_dir) {
+            db.add_reads(
+                vec![kmer_read("AAAAAA", samples.clone())],
+                &all_bases(),
+                &SIGNAL_RANGE,
+            )
+            .unwrap();
+            let first = db.get_kmer_split("AAAAAA", None, 1000, 0, 0.25, 1).unwrap();
+            assert_eq!(first.train.len(), 150);
+            assert_eq!(first.holdout.len(), 50);
+            let second = db.get_kmer_split("AAAAAA", None, 1000, 0, 0.25, 1).unwrap();
+            assert_eq!(first.holdout, second.holdout);
+
+            let (_, report) = TrainOptions::default()
+                .holdout(0.25)
+                .train_gmms(db)
+                .unwrap();
+            let kmer = &report.kmers()[0];
+            assert_eq!(kmer.n_samples, 150);
+            assert_eq!(kmer.n_holdout, 50);
+            assert!(kmer.holdout_log_likelihood.unwrap().is_finite());
+            assert!(!kmer.overfit);
+        }
+
+        let db = MemoryDb::new(50000);
+        assert!(TrainOptions::default().holdout(1.0).train_gmms(db).is_err());
+    }
+
+    #[test]
+    fn test_train_progress() {
+        let tmp_dir = TempDir::new().unwrap();
+        let reads = vec![
+            kmer_read("AAAAAA", (0..100).map(|j| 80.0 + (j % 10) as f64).collect()),
+            kmer_read("GAAAAA", vec![100.0]),
+        ];
+        let mut db = Db::open(tmp_dir.join("test.db")).unwrap();
+        db.add_reads(reads, &all_bases(), &SIGNAL_RANGE).unwrap();
+
+        let updates = Rc::new(RefCell::new(Vec::new()));
+        let recorded = Rc::clone(&updates);
+        // A single sample is enough to be trained on, and fails
+        TrainOptions::default()
+            .min_kmer_samples(1)
+            .on_progress(move |p| recorded.borrow_mut().push(p))
+            .train_gmms(db)
+            .unwrap();
+
+        let updates = updates.borrow();
+        assert_eq!(updates.len(), 4096);
+        assert!(updates.windows(2).all(|w| w[0].n_done + 1 == w[1].n_done));
+        let last = updates.last().unwrap();
+        assert_eq!(last.n_done, 4096);
+        assert_eq!(last.n_kmers, 4096);
+        assert_eq!(last.n_failed, 1);
+    }
+
+    #[test]
+    fn test_memory_db_reservoir() {
+        let samples = (0..100).map(|i| 50.0 + i as f64).collect::<Vec<_>>();
+        let mut eventalign = Eventalign::default();
+        *eventalign.signal_data_mut() = vec![Signal::new(
+            0,
+            "AAAAAA".to_string(),
+            1.0,
+            0.5,
+            samples.clone(),
+        )];
+        let mut db = MemoryDb::new(10).stranded(true);
+        db.add_reads(vec![eventalign.clone()], &all_bases(), &SIGNAL_RANGE)
+            .unwrap();
+        assert_eq!(db.pooled["AAAAAA"].seen, 100);
+
+        let kept = db.get_kmer_samples("AAAAAA", None, 5000, 0).unwrap();
+        assert_eq!(kept.len(), 10);
+        assert!(kept.iter().all(|x| samples.contains(x)));
+        assert!(kept.iter().any(|&x| x >= 60.0), "Reservoir never replaced");
+
+        let fewer = db.get_kmer_samples("AAAAAA", None, 3, 0).unwrap();
+        assert_eq!(fewer.len(), 3);
+        let unknown = db
+            .get_kmer_samples("AAAAAA", Some(Strand::unknown()), 5000, 0)
+            .unwrap();
+        assert_eq!(unknown.len(), 10);
+        assert!(db
+            .get_kmer_samples("AAAAAA", Some(Strand::plus()), 5000, 0)
+            .unwrap()
+            .is_empty());
+
+        let mut seeded = MemoryDb::new(10).seed(2456).stranded(true);
+        seeded
+            .add_reads(vec![eventalign.clone()], &all_bases(), &SIGNAL_RANGE)
+            .unwrap();
+        let mut resampled = MemoryDb::new(10).seed(1);
+        resampled
+            .add_reads(vec![eventalign], &all_bases(), &SIGNAL_RANGE)
+            .unwrap();
+        assert_eq!(seeded.pooled["AAAAAA"].samples, db.pooled["AAAAAA"].samples);
+        assert_ne!(
+            resampled.pooled["AAAAAA"].samples,
+            db.pooled["AAAAAA"].samples
+        );
+    }
+
+    #[test]
+    fn test_model_selection() {
+        let mut rng = SmallRng::seed_from_u64(1234);
+        let unimodal: Vec<f64> = Gaussian::new_unchecked(100.0, 2.0).sample(500, &mut rng);
+        let mut bimodal: Vec<f64> = Gaussian::new_unchecked(80.0, 2.0).sample(250, &mut rng);
+        let upper: Vec<f64> = Gaussian::new_unchecked(130.0, 2.0).sample(250, &mut rng);
+        bimodal.extend(upper);
+
+        for selection in [ModelSelection::Bic, ModelSelection::Aic] {
+            let opts = TrainOptions::default().model_selection(selection);
+            let single = opts
+                .train_gmm(
+                    ValidSampleData::validated(unimodal.clone()).unwrap(),
+                    &mut kmer_report(),
+                )
+                .unwrap();
+            assert_eq!(single.k(), 1, "{selection}");
+            let double = opts
+                .train_gmm(
+                    ValidSampleData::validated(bimodal.clone()).unwrap(),
+                    &mut kmer_report(),
+                )
+                .unwrap();
+            assert_eq!(double.k(), 2, "{selection}");
+        }
+
+        let opts = TrainOptions::default();
+        let fixed = opts
+            .train_gmm(
+                ValidSampleData::validated(unimodal).unwrap(),
+                &mut kmer_report(),
+            )
+            .unwrap();
+        assert_eq!(fixed.k(), 2);
+    }
+
+    #[test]
+    fn test_parse_model_selection() {
+        for selection in [
+            ModelSelection::Fixed,
+            ModelSelection::Bic,
+            ModelSelection::Aic,
+        ] {
+            assert_eq!(selection.to_string().parse(), Ok(selection));
+        }
+        assert!("quack".parse::<ModelSelection>().is_err());
+    }
+
+    #[test]
+    fn test_winsorize() {
+        let mut xs = (0..=100).map(|x| x as f64).collect::<Vec<_>>();
+        xs.reverse();
+        winsorize(&mut xs, 0.1, 0.9);
+        assert_eq!(xs[0], 90.0);
+        assert_eq!(xs[100], 10.0);
+        assert_eq!(xs[50], 50.0);
+
+        let mut empty: Vec<f64> = Vec::new();
+        winsorize(&mut empty, 0.1, 0.9);
+        assert!(empty.is_empty());
+    }
+
+    fn sorted_means(mix: &Mixture<Gaussian>) -> Vec<f64> {
+        let mut means = mix.components().iter().map(|g| g.mu()).collect::<Vec<_>>();
+        means.sort_by(|a, b| a.total_cmp(b));
+        means
+    }
+
+    #[test]
+    fn test_winsorize_outliers() {
+        let mut rng = SmallRng::seed_from_u64(1234);
+        let mut samples: Vec<f64> = Gaussian::new_unchecked(80.0, 2.0).sample(500, &mut rng);
+        let upper: Vec<f64> = Gaussian::new_unchecked(130.0, 2.0).sample(500, &mut rng);
+        samples.extend(upper);
+        let mut with_outliers = samples.clone();
+        with_outliers.extend([5000.0, 5000.0, 5000.0]);
+
+        let opts = TrainOptions::default().winsorize(0.005, 0.995);
+        let clean = sorted_means(&opts.fit_samples(samples, &mut kmer_report()).unwrap());
+        let outliers = sorted_means(&opts.fit_samples(with_outliers, &mut kmer_report()).unwrap());
+        for (c, o) in clean.into_iter().zip(outliers) {
+            assert_float_eq!(c, o, abs <= 1.0);
+        }
+
+        let tmp_dir = TempDir::new().unwrap();
+        let db = Db::open(tmp_dir.join("test.db")).unwrap();
+        let invalid = TrainOptions::default().winsorize(0.9, 0.1);
+        assert!(invalid.train_gmms(db).is_err());
+    }
+
+    #[test]
+    fn test_train_skips() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let collapse = collapse_single_read(&tmp_dir)?;
+
+        let opts = TrainOptions::default().in_memory(true).single(true);
+        let (model, _) = opts.run_model(File::open(&collapse)?)?;
+        assert!(model.skips().is_empty());
+
+        let opts = TrainOptions::default()
+            .in_memory(true)
+            .single(true)
+            .genome(Some(PathBuf::from("extra/sacCer3.fa")));
+        let (model, _) = opts.run_model(File::open(&collapse)?)?;
+        assert!(!model.skips().is_empty());
+        assert!(model.skips().keys().all(|kmer| kmer.len() == 6));
+        assert!(model.skips().values().all(|x| (0.0..=1.0).contains(x)));
+        // Not every position in a read has signal data
+        assert!(model.skips().values().any(|&x| x < 1.0));
+        assert!(model.skips().values().any(|&x| x > 0.0));
+
+        // Genome indexed in the temp directory instead of next to the fasta
+        let genome = TempIndexedGenome::new(&tmp_dir)?;
+        let opts = TrainOptions::default()
+            .in_memory(true)
+            .single(true)
+            .genome(Some(genome.path.clone()));
+        let (indexed, _) = opts.run_model(File::open(&collapse)?)?;
+        assert_eq!(indexed.skips(), model.skips());
+        Ok(())
+    }
+
+    #[test]
+    fn test_db_close() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let input = tmp_dir.join("input.arrow");
+        let mut writer = wrap_writer(File::create(&input)?, &Eventalign::schema())?;
+        save(&mut writer, &[two_cluster_read()])?;
+        writer.finish()?;
+        let sidecars = || -> Result<Vec<PathBuf>> {
+            let mut paths = Vec::new();
+            for entry in std::fs::read_dir(tmp_dir.path())? {
+                let path = entry?.path();
+                let name = path.to_string_lossy();
+                if name.ends_with("-wal") || name.ends_with("-shm") {
+                    paths.push(path);
+                }
+            }
+            Ok(paths)
+        };
+
+        let db_path = tmp_dir.join("train.db");
+        let (model, _) = TrainOptions::default()
+            .db_path(Some(db_path.clone()))
+            .run_model(File::open(&input)?)?;
+        assert_eq!(model.gmms().len(), 5);
+        assert!(db_path.exists());
+        let connection = Connection::open(&db_path)?;
+        let n_rows: usize = connection.query_row(
+            "SELECT COUNT(*) FROM data WHERE kmer = 'AAAAAA'",
+            (),
+            |row| row.get(0),
+        )?;
+        assert_eq!(n_rows, 100);
+        connection.close().map_err(|(_, e)| e)?;
+        assert!(sidecars()?.is_empty(), "{:?}", sidecars()?);
+
+        // Closed even if training fails after the reads are loaded
+        let failed = TrainOptions::default()
+            .db_path(Some(db_path))
+            .holdout(1.0)
+            .run_model(File::open(&input)?);
+        assert!(failed.is_err());
+        assert!(sidecars()?.is_empty(), "{:?}", sidecars()?);
+        Ok(())
+    }
+
+    /// Arrow file with each batch of reads saved as its own chunk
+    fn write_batches(path: &Path, batches: &[Vec<Eventalign>]) -> Result<()> {
+        let mut writer = wrap_writer(File::create(path)?, &Eventalign::schema())?;
+        for batch in batches {
+            save(&mut writer, batch)?;
+        }
+        writer.finish()?;
+        Ok(())
+    }
+
+    fn named_inputs(paths: &[&Path]) -> Result<Vec<(String, File)>> {
+        paths
+            .iter()
+            .map(|path| Ok((path.display().to_string(), File::open(path)?)))
+            .collect()
+    }
+
+    #[test]
+    fn test_train_many_inputs() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let first = tmp_dir.join("first.arrow");
+        let second = tmp_dir.join("second.arrow");
+        write_batches(&first, &[vec![two_cluster_read()]])?;
+        write_batches(&second, &[vec![two_cluster_read()]])?;
+
+        let (single, _) = TrainOptions::default()
+            .in_memory(true)
+            .run_model_many(named_inputs(&[&first])?)?;
+        let (both, _) = TrainOptions::default()
+            .in_memory(true)
+            .run_model_many(named_inputs(&[&first, &second])?)?;
+        assert_eq!(single.n_samples("AAAAAA"), Some(100));
+        assert_eq!(both.n_samples("AAAAAA"), Some(200));
+        assert_eq!(
+            both.provenance().unwrap().inputs,
+            [first.display().to_string(), second.display().to_string()]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_train_many_inputs_error() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let good = tmp_dir.join("good.arrow");
+        write_batches(&good, &[vec![two_cluster_read()]])?;
+        // The first chunk loads, then the second chunk has 5-mers
+        let bad = tmp_dir.join("bad.arrow");
+        write_batches(
+            &bad,
+            &[
+                vec![two_cluster_read()],
+                vec![kmer_read("AAAAA", vec![100.0; 10])],
+            ],
+        )?;
+
+        let err = TrainOptions::default()
+            .in_memory(true)
+            .run_model_many(named_inputs(&[&good, &bad])?)
+            .unwrap_err();
+        assert!(err.to_string().contains("bad.arrow"), "{err}");
+        assert!(!err.to_string().contains("good.arrow"), "{err}");
+        assert!(format!("{err:?}").contains("kmers of length 5"), "{err:?}");
+        Ok(())
+    }
 }
@@ -1,55 +1,40 @@
-use std::{collections::hash_map::Entry, path::Path, str::from_utf8};
+// Only PlusStrandMap itself is deprecated
+#![allow(deprecated)]
+
+use std::path::Path;
 
-use bam::BamReader;
 use eyre::Result;
-use fnv::FnvHashMap;
 
+use crate::{arrow::metadata::Strand, strand_map::StrandMap};
+
+/// Whether each read in a bam file is on the plus strand, now a wrapper
+/// around [StrandMap]. Reads aligned to both strands have no strand.
+#[deprecated(since = "0.4.0", note = "Use StrandMap instead")]
 #[derive(Default)]
-pub struct PlusStrandMap(FnvHashMap<Vec<u8>, bool>);
+pub struct PlusStrandMap(StrandMap);
 
 impl PlusStrandMap {
-    fn new(db: FnvHashMap<Vec<u8>, bool>) -> Self {
-        Self(db)
-    }
-
     pub fn from_bam_file<P: AsRef<Path>>(bam_file: P) -> Result<Self> {
-        let mut acc = FnvHashMap::default();
-        let reader = BamReader::from_path(bam_file, 2u16)?;
-        for record in reader {
-            let record = record?;
-            let read_name = record.name();
-
-            log::debug!("ReadName from bam: {:?}", from_utf8(read_name));
-
-            let plus_stranded = !record.flag().is_reverse_strand();
-            match acc.entry(read_name.to_owned()) {
-                Entry::Occupied(mut entry) => {
-                    let old_stranded = entry.insert(plus_stranded);
-                    if old_stranded != plus_stranded {
-                        log::debug!("Multimapped read has strand swap");
-                    }
-                }
-                Entry::Vacant(entry) => {
-                    entry.insert(plus_stranded);
-                }
-            }
-        }
-        Ok(PlusStrandMap::new(acc))
+        StrandMap::from_bam_file(bam_file).map(PlusStrandMap)
     }
 
     pub fn get<B>(&self, read_id: B) -> Option<bool>
     where
         B: AsRef<[u8]>,
     {
-        let read_id = read_id.as_ref();
-        self.0.get(read_id).cloned()
+        self.0.get_plus_stranded(read_id)
     }
 
     pub fn insert<B>(&mut self, read_id: B, plus_stranded: bool)
     where
         B: Into<Vec<u8>>,
     {
-        self.0.insert(read_id.into(), plus_stranded);
+        let strand = if plus_stranded {
+            Strand::plus()
+        } else {
+            Strand::minus()
+        };
+        self.0.insert(read_id, strand);
     }
 }
 
@@ -62,7 +47,6 @@ mod test {
         let filepath = "extra/single_read.bam";
         let psmap = PlusStrandMap::from_bam_file(filepath).unwrap();
         let read_id: &[u8] = b"20d1aac0-29de-43ae-a0ef-aa8a6766eb70";
-        assert!(psmap.0.contains_key(read_id));
         assert_eq!(psmap.get(read_id), Some(true));
     }
 
@@ -71,7 +55,6 @@ mod test {
         let filepath = "extra/pos_control.bam";
         let psmap = PlusStrandMap::from_bam_file(filepath).unwrap();
         let read_id: &[u8] = b"ca10c9e3-61d4-439b-abb3-078767d19f8c";
-        assert!(psmap.0.contains_key(read_id));
         assert_eq!(psmap.get(read_id), Some(false));
     }
 }
@@ -1,17 +1,420 @@
-use fnv::{FnvHashMap, FnvHashSet};
-use rand::{prelude::SmallRng, SeedableRng};
-use rv::traits::{ContinuousDistr, Rv};
+use std::{
+    cmp::Ordering,
+    fmt::Display,
+    fs::File,
+    hash::Hasher,
+    io::{BufReader, Write},
+    path::Path,
+    str::FromStr,
+};
+
+use eyre::Result;
+use fnv::{FnvHashMap, FnvHashSet, FnvHasher};
+use rand::{prelude::SmallRng, Rng, SeedableRng};
+use rayon::prelude::*;
+use rv::{
+    prelude::{Gaussian, Mixture},
+    traits::{Cdf, ContinuousDistr, Rv},
+};
+use serde::{Deserialize, Serialize};
+use serde_pickle::{HashableValue, Value};
 
 use crate::{
-    score::{choose_model, choose_pos_model},
+    motif::Motif,
+    score::{choose_model, choose_pos_model, ScoringMethod},
     train::Model,
 };
 
 pub type Ranks = FnvHashMap<String, f64>;
 
+/// How different the positive and negative control models are for a kmer
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum RankMetric {
+    /// Kullback-Leibler divergence of the negative control from the positive
+    /// control
+    #[default]
+    Kl,
+    /// Sum of the Kullback-Leibler divergences in both directions
+    SymmetricKl,
+    /// Jensen-Shannon divergence, between 0 and ln(2)
+    Js,
+    /// Hellinger distance, between 0 and 1
+    Hellinger,
+    /// First Wasserstein (earth mover's) distance, in pA
+    Wasserstein,
+    /// Area under the ROC curve when telling apart signal from the positive
+    /// and negative controls using the score from cawlr score, 0.5 if they
+    /// are indistinguishable and 1 if perfectly separated
+    Auc,
+}
+
+impl Display for RankMetric {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let res = match self {
+            Self::Kl => "kl",
+            Self::SymmetricKl => "symmetric-kl",
+            Self::Js => "js",
+            Self::Hellinger => "hellinger",
+            Self::Wasserstein => "wasserstein",
+            Self::Auc => "auc",
+        };
+        write!(f, "{res}")
+    }
+}
+
+impl FromStr for RankMetric {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "kl" => Ok(Self::Kl),
+            "symmetric-kl" => Ok(Self::SymmetricKl),
+            "js" => Ok(Self::Js),
+            "hellinger" => Ok(Self::Hellinger),
+            "wasserstein" => Ok(Self::Wasserstein),
+            "auc" => Ok(Self::Auc),
+            _ => Err(format!(
+                "Invalid metric {s}, expected kl, symmetric-kl, js, hellinger, wasserstein or auc"
+            )),
+        }
+    }
+}
+
+impl RankMetric {
+    /// Rank below which the controls for a kmer are considered
+    /// indistinguishable, unless set with [RankOptions::min_divergence]
+    pub fn default_min_divergence(&self) -> f64 {
+        match self {
+            Self::Auc => DEFAULT_MIN_AUC,
+            _ => DEFAULT_MIN_DIVERGENCE,
+        }
+    }
+
+    /// Metric from the means of the Monte Carlo draws in the order given by
+    /// [RankOptions::divergence_terms]
+    fn combine(&self, means: &[f64]) -> f64 {
+        match self {
+            Self::Kl | Self::Wasserstein | Self::Auc => means[0],
+            Self::SymmetricKl => means[0] + means[1],
+            // Bounded by ln 2, which rounding can overshoot for disjoint
+            // distributions
+            Self::Js => (0.5 * means[0] + 0.5 * means[1]).min(std::f64::consts::LN_2),
+            Self::Hellinger => (1.0 - means[0]).max(0.0).sqrt(),
+        }
+    }
+}
+
+/// Default divergence below which the controls for a kmer are considered
+/// indistinguishable
+pub const DEFAULT_MIN_DIVERGENCE: f64 = 0.05;
+
+/// Default AUC below which the controls for a kmer are considered
+/// indistinguishable
+pub const DEFAULT_MIN_AUC: f64 = 0.55;
+
+/// Kmers with a rank below the threshold, sorted
+pub fn indistinguishable_kmers(ranks: &Ranks, min_divergence: f64) -> Vec<String> {
+    let mut kmers: Vec<String> = ranks
+        .iter()
+        .filter(|(_, &rank)| rank < min_divergence)
+        .map(|(kmer, _)| kmer.clone())
+        .collect();
+    kmers.sort_unstable();
+    kmers
+}
+
+/// Default number of bootstrap resamples for [RankOptions::rank_with_stats]
+pub const DEFAULT_BOOTSTRAP: usize = 20;
+
+/// Rank of a kmer with the uncertainty from estimating it by sampling
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RankStats {
+    pub estimate: f64,
+    pub std_err: f64,
+    /// Lower bound of the 95% bootstrap percentile interval
+    pub ci_low: f64,
+    /// Upper bound of the 95% bootstrap percentile interval
+    pub ci_high: f64,
+}
+
+impl RankStats {
+    fn exact(estimate: f64) -> Self {
+        Self {
+            estimate,
+            std_err: 0.0,
+            ci_low: estimate,
+            ci_high: estimate,
+        }
+    }
+}
+
+pub type RanksWithStats = FnvHashMap<String, RankStats>;
+
+/// Point estimates of the ranks, to save with [save_ranks]
+pub fn estimates(ranks: &RanksWithStats) -> Ranks {
+    ranks
+        .iter()
+        .map(|(kmer, stats)| (kmer.clone(), stats.estimate))
+        .collect()
+}
+
+/// Number of standard deviations from the mean that a control's signal is
+/// within 99.9% of the time
+const CUTOFF_Z: f64 = 3.29;
+
+/// Smallest cutoff for cawlr score that still scores signal within
+/// [CUTOFF_Z] standard deviations of the mean of either control, which is
+/// larger for kmers with wider distributions
+pub fn suggested_cutoff(pos_ctrl: &Gaussian, neg_ctrl: &Gaussian) -> f64 {
+    [pos_ctrl, neg_ctrl]
+        .into_iter()
+        .map(|g| -g.ln_f(&(g.mu() + CUTOFF_Z * g.sigma())))
+        .fold(f64::NEG_INFINITY, f64::max)
+}
+
+/// Write the ranks as a tsv sorted from most to least different kmer, with
+/// the position of each kmer in that order starting at 1, and the suggested
+/// cutoff for each kmer from [RankOptions::suggested_cutoffs] if it has one.
+/// The column of ranks is named after the metric they were computed with,
+/// ie kl or symmetric_kl.
+pub fn write_ranks_tsv<W: Write>(
+    ranks: &RanksWithStats,
+    metric: RankMetric,
+    cutoffs: &FnvHashMap<String, f64>,
+    writer: &mut W,
+) -> Result<()> {
+    let mut sorted: Vec<_> = ranks.iter().collect();
+    sorted.sort_by(|(a_kmer, a), (b_kmer, b)| {
+        b.estimate
+            .partial_cmp(&a.estimate)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| a_kmer.cmp(b_kmer))
+    });
+    writeln!(
+        writer,
+        "kmer\t{}\trank\tstd_err\tci_low\tci_high\tsuggested_cutoff",
+        metric.to_string().replace('-', "_")
+    )?;
+    for (idx, (kmer, stats)) in sorted.into_iter().enumerate() {
+        let cutoff = cutoffs
+            .get(kmer)
+            .map(|cutoff| cutoff.to_string())
+            .unwrap_or_default();
+        writeln!(
+            writer,
+            "{kmer}\t{}\t{}\t{}\t{}\t{}\t{cutoff}",
+            stats.estimate,
+            idx + 1,
+            stats.std_err,
+            stats.ci_low,
+            stats.ci_high
+        )?;
+    }
+    Ok(())
+}
+
+/// Write the ranks as a csv with a header, sorted from most to least
+/// different kmer, for loading into R or pandas
+pub fn write_ranks_csv<W: Write>(ranks: &Ranks, writer: W) -> Result<()> {
+    let mut sorted: Vec<_> = ranks.iter().collect();
+    sorted.sort_by(|(a_kmer, a), (b_kmer, b)| {
+        b.partial_cmp(a)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| a_kmer.cmp(b_kmer))
+    });
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record(["kmer", "rank_score"])?;
+    for (kmer, rank) in sorted {
+        writer.write_record([kmer.as_str(), &rank.to_string()])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Format version of ranks files written by [save_ranks], files saved before
+/// the version was recorded are plain dictionaries from kmer to rank
+const RANKS_VERSION: i64 = 2;
+
+/// Ranks file written by [save_ranks]
+#[derive(Serialize, Deserialize)]
+struct SavedRanks {
+    version: i64,
+    metric: String,
+    ranks: Ranks,
+}
+
+/// Pickle the ranks along with the metric used and the format version
+pub fn save_ranks<W: Write>(ranks: &Ranks, metric: RankMetric, writer: &mut W) -> Result<()> {
+    let saved = SavedRanks {
+        version: RANKS_VERSION,
+        metric: metric.to_string(),
+        ranks: ranks.clone(),
+    };
+    serde_pickle::to_writer(writer, &saved, Default::default())?;
+    Ok(())
+}
+
+/// Load ranks saved by cawlr rank, along with the metric used. Ranks saved
+/// before the metric was recorded used KL divergence.
+pub fn load_ranks<P: AsRef<Path>>(filename: P) -> Result<(Ranks, RankMetric)> {
+    let file = BufReader::new(File::open(filename)?);
+    let value = serde_pickle::value_from_reader(file, Default::default())?;
+    let version = match &value {
+        Value::Dict(dict) => match dict.get(&HashableValue::String("version".to_string())) {
+            Some(Value::I64(n)) => *n,
+            Some(v) => return Err(eyre::eyre!("Invalid ranks format version {v}")),
+            None => 1,
+        },
+        _ => return Err(eyre::eyre!("Invalid ranks file, expected a dictionary")),
+    };
+    match version {
+        1 => Ok((serde_pickle::from_value(value)?, RankMetric::Kl)),
+        RANKS_VERSION => {
+            let saved: SavedRanks = serde_pickle::from_value(value)?;
+            let metric = saved.metric.parse().map_err(|e: String| eyre::eyre!(e))?;
+            Ok((saved.ranks, metric))
+        }
+        n if n > RANKS_VERSION => Err(eyre::eyre!(
+            "Ranks format version {n} is newer than the latest version supported by this \
+             version of cawlr ({RANKS_VERSION}), upgrade cawlr to load it"
+        )),
+        n => Err(eyre::eyre!("Invalid ranks format version {n}")),
+    }
+}
+
+/// Number of Gauss-Hermite nodes used for each Gaussian component when
+/// computing divergences exactly
+const N_QUADRATURE_NODES: usize = 64;
+
+/// Number of intervals used to integrate the difference between CDFs for the
+/// exact Wasserstein distance
+const N_CDF_INTERVALS: usize = 4096;
+
+/// Largest number of components in each mixture for which divergences are
+/// computed exactly, larger mixtures are sampled
+const MAX_EXACT_COMPONENTS: usize = 2;
+
+/// Gauss-Hermite quadrature rule, for integrals of f(x) exp(-x^2)
+#[derive(Clone)]
+struct Quadrature {
+    nodes: Vec<f64>,
+    weights: Vec<f64>,
+}
+
+impl Quadrature {
+    /// Nodes and weights for n points, found with Newton's method from
+    /// initial guesses as in gauher from Numerical Recipes
+    fn gauss_hermite(n: usize) -> Self {
+        // pi^(-1/4)
+        const PIM4: f64 = 0.751_125_544_464_942_5;
+        let mut nodes = vec![0.0; n];
+        let mut weights = vec![0.0; n];
+        let nf = n as f64;
+        let mut z = 0.0;
+        for i in 0..(n + 1) / 2 {
+            z = match i {
+                0 => (2.0 * nf + 1.0).sqrt() - 1.85575 * (2.0 * nf + 1.0).powf(-0.16667),
+                1 => z - 1.14 * nf.powf(0.426) / z,
+                2 => 1.86 * z - 0.86 * nodes[0],
+                3 => 1.91 * z - 0.91 * nodes[1],
+                _ => 2.0 * z - nodes[i - 2],
+            };
+            let mut pp = 0.0;
+            for _ in 0..100 {
+                // Normalized Hermite polynomials at z by recurrence
+                let mut p1 = PIM4;
+                let mut p2 = 0.0;
+                for j in 0..n {
+                    let p3 = p2;
+                    p2 = p1;
+                    let j = j as f64;
+                    p1 = z * (2.0 / (j + 1.0)).sqrt() * p2 - (j / (j + 1.0)).sqrt() * p3;
+                }
+                pp = (2.0 * nf).sqrt() * p2;
+                let z1 = z;
+                z = z1 - p1 / pp;
+                if (z - z1).abs() <= 1e-14 {
+                    break;
+                }
+            }
+            nodes[i] = z;
+            nodes[n - 1 - i] = -z;
+            weights[i] = 2.0 / (pp * pp);
+            weights[n - 1 - i] = weights[i];
+        }
+        Self { nodes, weights }
+    }
+
+    /// Expected value of f(x) with x drawn from the mixture
+    fn expectation<F: Fn(f64) -> f64>(&self, mix: &Mixture<Gaussian>, f: F) -> f64 {
+        let norm = std::f64::consts::PI.sqrt();
+        mix.weights()
+            .iter()
+            .zip(mix.components())
+            .map(|(w, g)| {
+                let total: f64 = self
+                    .nodes
+                    .iter()
+                    .zip(self.weights.iter())
+                    .map(|(x, wx)| wx * f(g.mu() + std::f64::consts::SQRT_2 * g.sigma() * x))
+                    .sum();
+                w * total / norm
+            })
+            .sum()
+    }
+}
+
+/// Mixture with a single component
+fn single_mixture(g: &Gaussian) -> Mixture<Gaussian> {
+    Mixture::new_unchecked(vec![1.0], vec![g.clone()])
+}
+
+/// Equal mixture of p and q
+fn midpoint(p: &Mixture<Gaussian>, q: &Mixture<Gaussian>) -> Mixture<Gaussian> {
+    let weights = p
+        .weights()
+        .iter()
+        .chain(q.weights())
+        .map(|w| 0.5 * w)
+        .collect();
+    let components = p
+        .components()
+        .iter()
+        .chain(q.components())
+        .cloned()
+        .collect();
+    Mixture::new_unchecked(weights, components)
+}
+
+/// Integral of |F_p(x) - F_q(x)| over x by the trapezoid rule, covering 10
+/// standard deviations around every component
+fn wasserstein_exact(p: &Mixture<Gaussian>, q: &Mixture<Gaussian>) -> f64 {
+    let components = || p.components().iter().chain(q.components());
+    let lo = components()
+        .map(|g| g.mu() - 10.0 * g.sigma())
+        .fold(f64::INFINITY, f64::min);
+    let hi = components()
+        .map(|g| g.mu() + 10.0 * g.sigma())
+        .fold(f64::NEG_INFINITY, f64::max);
+    let step = (hi - lo) / N_CDF_INTERVALS as f64;
+    let diff = |x: f64| (p.cdf(&x) - q.cdf(&x)).abs();
+    let inner: f64 = (1..N_CDF_INTERVALS)
+        .map(|i| diff(lo + i as f64 * step))
+        .sum();
+    step * (inner + 0.5 * (diff(lo) + diff(hi)))
+}
+
+#[derive(Clone)]
 pub struct RankOptions {
     rng: SmallRng,
+    seed: u64,
     n_samples: usize,
+    n_bootstrap: usize,
+    metric: RankMetric,
+    quadrature: Option<Quadrature>,
+    motifs: Option<Vec<Motif>>,
+    min_divergence: Option<f64>,
+    top_n: Option<usize>,
 }
 
 impl Default for RankOptions {
@@ -19,7 +422,14 @@ impl Default for RankOptions {
         let rng = SmallRng::seed_from_u64(2456);
         RankOptions {
             rng,
+            seed: 2456,
             n_samples: 10_000,
+            n_bootstrap: DEFAULT_BOOTSTRAP,
+            metric: RankMetric::default(),
+            quadrature: None,
+            motifs: None,
+            min_divergence: None,
+            top_n: None,
         }
     }
 }
@@ -27,7 +437,98 @@ impl Default for RankOptions {
 impl RankOptions {
     pub fn new(seed: u64, n_samples: usize) -> Self {
         let rng = SmallRng::seed_from_u64(seed);
-        RankOptions { rng, n_samples }
+        RankOptions {
+            rng,
+            seed,
+            n_samples,
+            n_bootstrap: DEFAULT_BOOTSTRAP,
+            metric: RankMetric::default(),
+            quadrature: None,
+            motifs: None,
+            min_divergence: None,
+            top_n: None,
+        }
+    }
+
+    /// Metric used to compare the control models, defaults to KL divergence
+    pub fn metric(&mut self, metric: RankMetric) -> &mut Self {
+        self.metric = metric;
+        self
+    }
+
+    /// Number of times the Monte Carlo draws are resampled to estimate the
+    /// uncertainty of each rank in [RankOptions::rank_with_stats]
+    pub fn bootstrap_samples(&mut self, n_bootstrap: usize) -> &mut Self {
+        self.n_bootstrap = n_bootstrap;
+        self
+    }
+
+    /// Only rank kmers that cawlr score would look at for these motifs, other
+    /// kmers are left out of the ranks
+    pub fn motifs(&mut self, motifs: Option<Vec<Motif>>) -> &mut Self {
+        self.motifs = motifs;
+        self
+    }
+
+    /// Only keep the n kmers with the highest ranks, since cawlr score only
+    /// uses the highest ranked kmer around each position. Kmers with
+    /// indistinguishable controls are still reported before they're dropped.
+    pub fn top_n(&mut self, top_n: Option<usize>) -> &mut Self {
+        self.top_n = top_n;
+        self
+    }
+
+    /// Keep the top_n kmers by rank, ties are broken by kmer
+    fn keep_top_n<V>(
+        &self,
+        ranks: FnvHashMap<String, V>,
+        rank: fn(&V) -> f64,
+    ) -> FnvHashMap<String, V> {
+        let n = match self.top_n {
+            Some(n) if n < ranks.len() => n,
+            _ => return ranks,
+        };
+        let mut sorted: Vec<_> = ranks.into_iter().collect();
+        sorted.sort_by(|(a_kmer, a), (b_kmer, b)| {
+            rank(b)
+                .partial_cmp(&rank(a))
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| a_kmer.cmp(b_kmer))
+        });
+        sorted.truncate(n);
+        sorted.into_iter().collect()
+    }
+
+    /// Divergence below which the controls for a kmer are reported as
+    /// indistinguishable, by default [RankMetric::default_min_divergence]
+    pub fn min_divergence(&mut self, min_divergence: Option<f64>) -> &mut Self {
+        self.min_divergence = min_divergence;
+        self
+    }
+
+    /// Kmers whose controls are indistinguishable, logging how many there are
+    pub fn indistinguishable(&self, ranks: &Ranks) -> Vec<String> {
+        let min_divergence = self
+            .min_divergence
+            .unwrap_or_else(|| self.metric.default_min_divergence());
+        let kmers = indistinguishable_kmers(ranks, min_divergence);
+        if !kmers.is_empty() {
+            log::warn!(
+                "{}/{} kmers have {} < {min_divergence}, signal scores for them are unreliable",
+                kmers.len(),
+                ranks.len(),
+                self.metric,
+            );
+        }
+        kmers
+    }
+
+    /// Compute divergences with numerical integration instead of sampling,
+    /// which is faster and deterministic. Only used when both models have at
+    /// most two components, AUC is always sampled.
+    pub fn exact(&mut self, exact: bool) -> &mut Self {
+        self.quadrature = exact.then(|| Quadrature::gauss_hermite(N_QUADRATURE_NODES));
+        self
     }
 
     // Approximate the Kulback-Leibler Divergence for the two GMMs as mentioned in
@@ -37,56 +538,800 @@ impl RankOptions {
     // IV-317-IV-320, doi: 10.1109/ICASSP.2007.366913.
     //
     // TODO: Check if some normalization is required for this
-    fn kl_approx<M, N>(&mut self, pos_ctrl: &M, neg_ctrl: &N) -> f64
+    fn kl_terms<M, N>(&mut self, pos_ctrl: &M, neg_ctrl: &N) -> Vec<f64>
     where
         M: Rv<f64> + ContinuousDistr<f64>,
         N: Rv<f64> + ContinuousDistr<f64>,
     {
         let samples: Vec<f64> = pos_ctrl.sample(self.n_samples, &mut self.rng);
-        let total: f64 = samples
+        samples
             .into_iter()
             .map(|sample| {
                 let p = pos_ctrl.ln_f(&sample);
                 let n = neg_ctrl.ln_f(&sample);
                 p - n
             })
-            .sum();
-        total / self.count()
+            .collect()
     }
 
-    fn count(&self) -> f64 {
-        self.n_samples as f64
+    /// Terms for the KL divergence of the equal mixture of p and q from p,
+    /// sampling from p
+    fn kl_to_midpoint_terms<M, N>(&mut self, p: &M, q: &N) -> Vec<f64>
+    where
+        M: Rv<f64> + ContinuousDistr<f64>,
+        N: Rv<f64> + ContinuousDistr<f64>,
+    {
+        let samples: Vec<f64> = p.sample(self.n_samples, &mut self.rng);
+        samples
+            .into_iter()
+            .map(|sample| {
+                let ln_p = p.ln_f(&sample);
+                let ln_m = 0.5f64.ln() + ln_add_exp(ln_p, q.ln_f(&sample));
+                ln_p - ln_m
+            })
+            .collect()
     }
 
-    pub fn rank(&mut self, pos_ctrl: &Model, neg_ctrl: &Model) -> Ranks {
-        let mut kmer_ranks = FnvHashMap::default();
-        let pos_ctrl_kmers = pos_ctrl.gmms().keys().collect::<FnvHashSet<&String>>();
-        let neg_ctrl_kmers = neg_ctrl.gmms().keys().collect::<FnvHashSet<&String>>();
-        let kmers = pos_ctrl_kmers.intersection(&neg_ctrl_kmers);
-        for &kmer in kmers {
-            let neg_ctrl_model = &neg_ctrl.gmms()[kmer].mixture();
-            let pos_ctrl_model = &pos_ctrl.gmms()[kmer].mixture();
+    /// Terms for the Bhattacharyya coefficient, sqrt(q(x) / p(x)) with x
+    /// sampled from p
+    fn bhattacharyya_terms<M, N>(&mut self, pos_ctrl: &M, neg_ctrl: &N) -> Vec<f64>
+    where
+        M: Rv<f64> + ContinuousDistr<f64>,
+        N: Rv<f64> + ContinuousDistr<f64>,
+    {
+        let samples: Vec<f64> = pos_ctrl.sample(self.n_samples, &mut self.rng);
+        samples
+            .into_iter()
+            .map(|sample| (0.5 * (neg_ctrl.ln_f(&sample) - pos_ctrl.ln_f(&sample))).exp())
+            .collect()
+    }
+
+    /// In one dimension the optimal coupling matches quantiles, so the
+    /// distance is the mean difference between the sorted samples
+    fn wasserstein_terms<M, N>(&mut self, pos_ctrl: &M, neg_ctrl: &N) -> Vec<f64>
+    where
+        M: Rv<f64>,
+        N: Rv<f64>,
+    {
+        let mut pos_samples: Vec<f64> = pos_ctrl.sample(self.n_samples, &mut self.rng);
+        let mut neg_samples: Vec<f64> = neg_ctrl.sample(self.n_samples, &mut self.rng);
+        pos_samples.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+        neg_samples.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+        pos_samples
+            .iter()
+            .zip(neg_samples.iter())
+            .map(|(p, n)| (p - n).abs())
+            .collect()
+    }
 
-            let neg_ctrl_model = choose_model(neg_ctrl_model);
-            let pos_ctrl_model = choose_pos_model(neg_ctrl_model, pos_ctrl_model);
+    /// Score samples from both controls the way cawlr score does, and for
+    /// each positive control sample find the fraction of negative control
+    /// samples it beats, counting ties as half. The mean is the AUC.
+    fn auc_terms<M, N>(&mut self, pos_ctrl: &M, neg_ctrl: &N) -> Vec<f64>
+    where
+        M: Rv<f64> + ContinuousDistr<f64>,
+        N: Rv<f64> + ContinuousDistr<f64>,
+    {
+        // AUC only depends on the order of the scores, so any scoring method
+        // gives the same result
+        let score = |x: &f64| ScoringMethod::LogOdds.score(pos_ctrl.ln_f(x), neg_ctrl.ln_f(x));
+        let pos_samples: Vec<f64> = pos_ctrl.sample(self.n_samples, &mut self.rng);
+        let neg_samples: Vec<f64> = neg_ctrl.sample(self.n_samples, &mut self.rng);
+        let mut neg_scores: Vec<f64> = neg_samples.iter().map(score).collect();
+        neg_scores.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+        let n_neg = neg_scores.len() as f64;
+        pos_samples
+            .iter()
+            .map(|x| {
+                let s = score(x);
+                let below = neg_scores.partition_point(|&n| n < s);
+                let below_or_tied = neg_scores.partition_point(|&n| n <= s);
+                (below as f64 + 0.5 * (below_or_tied - below) as f64) / n_neg
+            })
+            .collect()
+    }
 
-            let kl = self.kl_approx(pos_ctrl_model, neg_ctrl_model);
-            kmer_ranks.insert(kmer.clone(), kl);
+    /// Monte Carlo draws for the chosen metric, which is combined from the
+    /// means of each set of draws by [RankMetric::combine]
+    fn divergence_terms<M, N>(&mut self, pos_ctrl: &M, neg_ctrl: &N) -> Vec<Vec<f64>>
+    where
+        M: Rv<f64> + ContinuousDistr<f64>,
+        N: Rv<f64> + ContinuousDistr<f64>,
+    {
+        match self.metric {
+            RankMetric::Kl => vec![self.kl_terms(pos_ctrl, neg_ctrl)],
+            RankMetric::SymmetricKl => vec![
+                self.kl_terms(pos_ctrl, neg_ctrl),
+                self.kl_terms(neg_ctrl, pos_ctrl),
+            ],
+            RankMetric::Js => vec![
+                self.kl_to_midpoint_terms(pos_ctrl, neg_ctrl),
+                self.kl_to_midpoint_terms(neg_ctrl, pos_ctrl),
+            ],
+            RankMetric::Hellinger => vec![self.bhattacharyya_terms(pos_ctrl, neg_ctrl)],
+            RankMetric::Wasserstein => vec![self.wasserstein_terms(pos_ctrl, neg_ctrl)],
+            RankMetric::Auc => vec![self.auc_terms(pos_ctrl, neg_ctrl)],
         }
-        kmer_ranks
     }
 
-    pub fn rank_npsmlr(&mut self, pos_ctrl: &Model, neg_ctrl: &Model) -> Ranks {
-        let mut kmer_ranks = FnvHashMap::default();
-        let pos_ctrl_kmers = pos_ctrl.gmms().keys().collect::<FnvHashSet<&String>>();
+    /// Compare the models with the chosen metric by sampling
+    fn divergence<M, N>(&mut self, pos_ctrl: &M, neg_ctrl: &N) -> f64
+    where
+        M: Rv<f64> + ContinuousDistr<f64>,
+        N: Rv<f64> + ContinuousDistr<f64>,
+    {
+        let terms = self.divergence_terms(pos_ctrl, neg_ctrl);
+        let means: Vec<f64> = terms.iter().map(|t| mean(t)).collect();
+        self.metric.combine(&means)
+    }
+
+    /// Estimate the divergence along with its uncertainty, by recomputing it
+    /// from the Monte Carlo draws resampled with replacement
+    fn bootstrap(&mut self, terms: &[Vec<f64>]) -> RankStats {
+        let means: Vec<f64> = terms.iter().map(|t| mean(t)).collect();
+        let estimate = self.metric.combine(&means);
+        if self.n_bootstrap < 2 {
+            return RankStats::exact(estimate);
+        }
+
+        let mut resampled: Vec<f64> = (0..self.n_bootstrap)
+            .map(|_| {
+                let means: Vec<f64> = terms
+                    .iter()
+                    .map(|t| {
+                        let total: f64 = (0..t.len())
+                            .map(|_| t[self.rng.gen_range(0..t.len())])
+                            .sum();
+                        total / t.len() as f64
+                    })
+                    .collect();
+                self.metric.combine(&means)
+            })
+            .collect();
+        resampled.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+
+        let n = resampled.len() as f64;
+        let resampled_mean = resampled.iter().sum::<f64>() / n;
+        let var = resampled
+            .iter()
+            .map(|x| (x - resampled_mean).powi(2))
+            .sum::<f64>()
+            / (n - 1.0);
+        let quantile = |q: f64| resampled[((n - 1.0) * q).round() as usize];
+        RankStats {
+            estimate,
+            std_err: var.sqrt(),
+            ci_low: quantile(0.025),
+            ci_high: quantile(0.975),
+        }
+    }
+
+    /// Like [RankOptions::divergence], but Hellinger distance is computed
+    /// exactly since it has a closed form for two Gaussians
+    fn gaussian_divergence(&mut self, pos_ctrl: &Gaussian, neg_ctrl: &Gaussian) -> f64 {
+        match self.metric {
+            RankMetric::Hellinger => gaussian_hellinger(pos_ctrl, neg_ctrl),
+            _ => self.divergence(pos_ctrl, neg_ctrl),
+        }
+    }
+
+    /// Compare the models with the chosen metric by numerical integration,
+    /// or by sampling if exact divergences are off or a model has too many
+    /// components
+    fn mixture_divergence(&mut self, p: &Mixture<Gaussian>, q: &Mixture<Gaussian>) -> f64 {
+        let quad = match &self.quadrature {
+            Some(quad)
+                if p.k() <= MAX_EXACT_COMPONENTS
+                    && q.k() <= MAX_EXACT_COMPONENTS
+                    && self.metric != RankMetric::Auc =>
+            {
+                quad
+            }
+            _ => return self.divergence(p, q),
+        };
+        let kl = |a: &Mixture<Gaussian>, b: &Mixture<Gaussian>| {
+            quad.expectation(a, |x| a.ln_f(&x) - b.ln_f(&x))
+        };
+        match self.metric {
+            RankMetric::Kl => kl(p, q),
+            RankMetric::SymmetricKl => kl(p, q) + kl(q, p),
+            RankMetric::Js => {
+                let m = midpoint(p, q);
+                0.5 * kl(p, &m) + 0.5 * kl(q, &m)
+            }
+            RankMetric::Hellinger => {
+                // Integrate sqrt(pq) against the midpoint, so the integrand
+                // stays between 0 and 1
+                let m = midpoint(p, q);
+                let bc = quad.expectation(&m, |x| {
+                    let (ln_p, ln_q) = (p.ln_f(&x), q.ln_f(&x));
+                    (0.5 * (ln_p + ln_q) - m.ln_f(&x)).exp()
+                });
+                (1.0 - bc).max(0.0).sqrt()
+            }
+            RankMetric::Wasserstein => wasserstein_exact(p, q),
+            RankMetric::Auc => unreachable!("AUC is always sampled"),
+        }
+    }
+
+    /// Options for ranking the kmer, with its own RNG seeded from the base
+    /// seed and the kmer so ranks don't depend on the number of threads or
+    /// which other kmers are ranked
+    fn for_kmer(&self, kmer: &str) -> Self {
+        let mut hasher = FnvHasher::default();
+        hasher.write(kmer.as_bytes());
+        let mut opts = self.clone();
+        opts.rng = SmallRng::seed_from_u64(self.seed ^ hasher.finish());
+        opts
+    }
+
+    /// Kmers found in both models that overlap any of the motifs, if given
+    fn shared_kmers<'a>(&self, pos_ctrl: &'a Model, neg_ctrl: &Model) -> Vec<&'a String> {
         let neg_ctrl_kmers = neg_ctrl.gmms().keys().collect::<FnvHashSet<&String>>();
-        let kmers = pos_ctrl_kmers.intersection(&neg_ctrl_kmers);
-        for &kmer in kmers {
-            let pos_ctrl_model = &pos_ctrl.gmms()[kmer].mixture();
-            let neg_ctrl_model = &neg_ctrl.gmms()[kmer].single();
-            let kl = self.kl_approx(pos_ctrl_model, neg_ctrl_model);
-            kmer_ranks.insert(kmer.clone(), kl);
-        }
-        kmer_ranks
+        pos_ctrl
+            .gmms()
+            .keys()
+            .filter(|kmer| neg_ctrl_kmers.contains(kmer))
+            .filter(|kmer| match &self.motifs {
+                Some(motifs) => motifs.iter().any(|m| m.overlaps_kmer(kmer)),
+                None => true,
+            })
+            .collect()
+    }
+
+    /// Kmers found in both models, with the component of each model that
+    /// cawlr score compares
+    fn kmer_models(&self, pos_ctrl: &Model, neg_ctrl: &Model) -> Vec<(String, Gaussian, Gaussian)> {
+        self.shared_kmers(pos_ctrl, neg_ctrl)
+            .into_iter()
+            .map(|kmer| {
+                let neg_ctrl_model = &neg_ctrl.gmms()[kmer].mixture();
+                let pos_ctrl_model = &pos_ctrl.gmms()[kmer].mixture();
+
+                let neg_ctrl_model = choose_model(neg_ctrl_model);
+                let pos_ctrl_model = choose_pos_model(neg_ctrl_model, pos_ctrl_model);
+                (kmer.clone(), pos_ctrl_model.clone(), neg_ctrl_model.clone())
+            })
+            .collect()
+    }
+
+    /// Ranks are computed in parallel over the kmers using the rayon thread
+    /// pool, and are the same for any number of threads
+    ///
+    /// Logs a warning with the number of kmers whose controls are
+    /// indistinguishable, see [RankOptions::indistinguishable].
+    pub fn rank(&mut self, pos_ctrl: &Model, neg_ctrl: &Model) -> Ranks {
+        let this = &*self;
+        let ranks = this
+            .kmer_models(pos_ctrl, neg_ctrl)
+            .into_par_iter()
+            .map(|(kmer, pos_ctrl_model, neg_ctrl_model)| {
+                let mut opts = this.for_kmer(&kmer);
+                let rank = if opts.quadrature.is_some() {
+                    opts.mixture_divergence(
+                        &single_mixture(&pos_ctrl_model),
+                        &single_mixture(&neg_ctrl_model),
+                    )
+                } else {
+                    opts.gaussian_divergence(&pos_ctrl_model, &neg_ctrl_model)
+                };
+                (kmer, rank)
+            })
+            .collect();
+        self.indistinguishable(&ranks);
+        self.keep_top_n(ranks, |&rank| rank)
+    }
+
+    /// Like [RankOptions::rank], with a bootstrap standard error and 95%
+    /// confidence interval for each rank. Ranks that aren't estimated by
+    /// sampling, with --exact or Hellinger distance, have no uncertainty.
+    /// Estimates are the same as from [RankOptions::rank] with the same seed.
+    pub fn rank_with_stats(&mut self, pos_ctrl: &Model, neg_ctrl: &Model) -> RanksWithStats {
+        let this = &*self;
+        let ranks = this
+            .kmer_models(pos_ctrl, neg_ctrl)
+            .into_par_iter()
+            .map(|(kmer, pos_ctrl_model, neg_ctrl_model)| {
+                let mut opts = this.for_kmer(&kmer);
+                let stats = if opts.quadrature.is_some() {
+                    RankStats::exact(opts.mixture_divergence(
+                        &single_mixture(&pos_ctrl_model),
+                        &single_mixture(&neg_ctrl_model),
+                    ))
+                } else if opts.metric == RankMetric::Hellinger {
+                    RankStats::exact(gaussian_hellinger(&pos_ctrl_model, &neg_ctrl_model))
+                } else {
+                    let terms = opts.divergence_terms(&pos_ctrl_model, &neg_ctrl_model);
+                    opts.bootstrap(&terms)
+                };
+                (kmer, stats)
+            })
+            .collect();
+        self.indistinguishable(&estimates(&ranks));
+        self.keep_top_n(ranks, |stats| stats.estimate)
+    }
+
+    /// Suggested cutoff for cawlr score for each kmer found in both models,
+    /// see [suggested_cutoff]
+    pub fn suggested_cutoffs(&self, pos_ctrl: &Model, neg_ctrl: &Model) -> FnvHashMap<String, f64> {
+        self.kmer_models(pos_ctrl, neg_ctrl)
+            .into_iter()
+            .map(|(kmer, pos_ctrl_model, neg_ctrl_model)| {
+                let cutoff = suggested_cutoff(&pos_ctrl_model, &neg_ctrl_model);
+                (kmer, cutoff)
+            })
+            .collect()
+    }
+
+    /// Ranks for cawlr npsmlr score, which compares the whole positive control
+    /// mixture against the negative control component picked by
+    /// [choose_model]
+    pub fn rank_npsmlr(&mut self, pos_ctrl: &Model, neg_ctrl: &Model) -> Ranks {
+        let this = &*self;
+        let ranks = this
+            .shared_kmers(pos_ctrl, neg_ctrl)
+            .into_par_iter()
+            .map(|kmer| {
+                let mut opts = this.for_kmer(kmer);
+                let pos_ctrl_model = &pos_ctrl.gmms()[kmer].mixture();
+                let neg_ctrl_model = &neg_ctrl.gmms()[kmer].mixture();
+                let neg_ctrl_model = choose_model(neg_ctrl_model);
+                let rank = if opts.quadrature.is_some() {
+                    opts.mixture_divergence(pos_ctrl_model, &single_mixture(neg_ctrl_model))
+                } else {
+                    opts.divergence(pos_ctrl_model, neg_ctrl_model)
+                };
+                (kmer.clone(), rank)
+            })
+            .collect();
+        self.indistinguishable(&ranks);
+        self.keep_top_n(ranks, |&rank| rank)
+    }
+}
+
+fn mean(xs: &[f64]) -> f64 {
+    xs.iter().sum::<f64>() / xs.len() as f64
+}
+
+/// ln(exp(a) + exp(b)) without underflow
+fn ln_add_exp(a: f64, b: f64) -> f64 {
+    let max = a.max(b);
+    if max == f64::NEG_INFINITY {
+        return max;
+    }
+    max + ((a - max).exp() + (b - max).exp()).ln()
+}
+
+/// Closed form Hellinger distance between two Gaussians
+fn gaussian_hellinger(p: &Gaussian, q: &Gaussian) -> f64 {
+    let var_sum = p.sigma().powi(2) + q.sigma().powi(2);
+    let bc = (2.0 * p.sigma() * q.sigma() / var_sum).sqrt()
+        * (-(p.mu() - q.mu()).powi(2) / (4.0 * var_sum)).exp();
+    (1.0 - bc).max(0.0).sqrt()
+}
+
+#[cfg(test)]
+mod test {
+    use assert_fs::TempDir;
+    use float_eq::assert_float_eq;
+
+    use super::*;
+    use crate::utils::CawlrIO;
+
+    /// Positive and negative control models with a single Gaussian for each
+    /// kmer, from the kmer and the means of the positive and negative controls
+    fn ctrl_models<K: AsRef<str>>(kmers: &[(K, f64, f64)]) -> (Model, Model) {
+        let mut pos_ctrl = Model::default();
+        let mut neg_ctrl = Model::default();
+        for (kmer, pos_mu, neg_mu) in kmers {
+            let kmer = kmer.as_ref().to_string();
+            let pos = Gaussian::new_unchecked(*pos_mu, 2.0);
+            let neg = Gaussian::new_unchecked(*neg_mu, 2.0);
+            pos_ctrl.insert_gmm(kmer.clone(), single_mixture(&pos));
+            neg_ctrl.insert_gmm(kmer, single_mixture(&neg));
+        }
+        (pos_ctrl, neg_ctrl)
+    }
+
+    #[test]
+    fn test_js() {
+        let p = Gaussian::new_unchecked(80.0, 2.0);
+        let q = Mixture::new_unchecked(
+            vec![0.5, 0.5],
+            vec![
+                Gaussian::new_unchecked(82.0, 3.0),
+                Gaussian::new_unchecked(90.0, 2.0),
+            ],
+        );
+        let mut opts = RankOptions::default();
+        opts.metric(RankMetric::Js);
+        let pq = opts.divergence(&p, &q);
+        let qp = opts.divergence(&q, &p);
+        assert_float_eq!(pq, qp, abs <= 0.01);
+        assert!(pq > 0.0 && pq <= 2f64.ln());
+
+        // Far apart distributions have the largest possible divergence
+        let far = Gaussian::new_unchecked(200.0, 1.0);
+        let js = opts.divergence(&p, &far);
+        assert!(js <= 2f64.ln());
+        assert_float_eq!(js, 2f64.ln(), abs <= 1e-6);
+        assert_float_eq!(opts.divergence(&p, &p), 0.0, abs <= 1e-9);
+    }
+
+    #[test]
+    fn test_hellinger() {
+        let p = Gaussian::new_unchecked(80.0, 2.0);
+        let q = Gaussian::new_unchecked(83.0, 3.0);
+        let exact = gaussian_hellinger(&p, &q);
+        assert!(exact > 0.0 && exact < 1.0);
+        assert_float_eq!(exact, gaussian_hellinger(&q, &p), abs <= 1e-12);
+        assert_float_eq!(gaussian_hellinger(&p, &p), 0.0, abs <= 1e-6);
+
+        let mut opts = RankOptions::new(1234, 100_000);
+        opts.metric(RankMetric::Hellinger);
+        assert_float_eq!(opts.divergence(&p, &q), exact, abs <= 0.02);
+    }
+
+    #[test]
+    fn test_wasserstein() {
+        // Shifting a distribution moves it by the size of the shift
+        let p = Gaussian::new_unchecked(80.0, 2.0);
+        let q = Gaussian::new_unchecked(85.0, 2.0);
+        let mut opts = RankOptions::default();
+        opts.metric(RankMetric::Wasserstein);
+        assert_float_eq!(opts.divergence(&p, &q), 5.0, abs <= 0.1);
+    }
+
+    #[test]
+    fn test_auc() {
+        let mut opts = RankOptions::new(1234, 20_000);
+        opts.metric(RankMetric::Auc);
+
+        let p = Gaussian::new_unchecked(80.0, 2.0);
+        assert_float_eq!(opts.divergence(&p, &p), 0.5, abs <= 0.01);
+
+        let far = Gaussian::new_unchecked(120.0, 2.0);
+        assert_float_eq!(opts.divergence(&far, &p), 1.0, abs <= 1e-6);
+
+        // Equal variances, the score is monotonic in the signal so the AUC is
+        // Phi(d / sqrt(2)) for means d standard deviations apart
+        let q = Gaussian::new_unchecked(82.0, 2.0);
+        let expected = Gaussian::standard().cdf(&(1.0 / 2f64.sqrt()));
+        assert_float_eq!(opts.divergence(&q, &p), expected, abs <= 0.01);
+    }
+
+    #[test]
+    fn test_rank_with_stats() {
+        let (pos_ctrl, neg_ctrl) = ctrl_models(&[
+            ("AAAAAA", 90.0, 80.0),
+            ("CCCCCC", 84.0, 80.0),
+            ("GGGGGG", 80.5, 80.0),
+        ]);
+
+        let ranks = RankOptions::new(1234, 10_000).rank(&pos_ctrl, &neg_ctrl);
+        let stats = RankOptions::new(1234, 10_000).rank_with_stats(&pos_ctrl, &neg_ctrl);
+        for (kmer, rank) in ranks.iter() {
+            let kmer_stats = stats[kmer];
+            assert_eq!(kmer_stats.estimate, *rank);
+            assert!(kmer_stats.std_err > 0.0);
+            assert!(kmer_stats.ci_low <= kmer_stats.ci_high);
+        }
+        assert_eq!(estimates(&stats), ranks);
+
+        let cutoffs = RankOptions::default().suggested_cutoffs(&pos_ctrl, &neg_ctrl);
+        let mut tsv = Vec::new();
+        write_ranks_tsv(&stats, RankMetric::Kl, &cutoffs, &mut tsv).unwrap();
+        let tsv = String::from_utf8(tsv).unwrap();
+        assert!(tsv.starts_with("kmer\tkl\trank\t"));
+        let kmers: Vec<&str> = tsv
+            .lines()
+            .skip(1)
+            .map(|line| line.split('\t').next().unwrap())
+            .collect();
+        assert_eq!(kmers, ["AAAAAA", "CCCCCC", "GGGGGG"]);
+        assert!(tsv.lines().nth(1).unwrap().contains("\t1\t"));
+        for line in tsv.lines().skip(1) {
+            let cutoff: f64 = line.split('\t').nth(6).unwrap().parse().unwrap();
+            assert!(cutoff > 0.0);
+        }
+
+        // No sampling, no uncertainty
+        let exact = RankOptions::default()
+            .exact(true)
+            .rank_with_stats(&pos_ctrl, &neg_ctrl);
+        assert!(exact.values().all(|s| s.std_err == 0.0));
+
+        for (metric, column) in [
+            (RankMetric::Auc, "auc"),
+            (RankMetric::SymmetricKl, "symmetric_kl"),
+        ] {
+            let mut tsv = Vec::new();
+            write_ranks_tsv(&exact, metric, &cutoffs, &mut tsv).unwrap();
+            let header = String::from_utf8(tsv).unwrap();
+            let header = header
+                .lines()
+                .next()
+                .unwrap()
+                .split('\t')
+                .collect::<Vec<_>>();
+            assert_eq!(header[1], column);
+        }
+    }
+
+    #[test]
+    fn test_suggested_cutoff() {
+        let narrow = Gaussian::new_unchecked(80.0, 1.0);
+        let wide = Gaussian::new_unchecked(90.0, 5.0);
+        let expected = (5.0 * (2.0 * std::f64::consts::PI).sqrt()).ln() + CUTOFF_Z.powi(2) / 2.0;
+        assert_float_eq!(suggested_cutoff(&narrow, &wide), expected, abs <= 1e-9);
+        assert_float_eq!(suggested_cutoff(&wide, &narrow), expected, abs <= 1e-9);
+        assert!(suggested_cutoff(&narrow, &narrow) < expected);
+
+        // Signal at the edge of the wider control is just kept
+        let signal = 90.0 + CUTOFF_Z * 5.0;
+        assert_float_eq!(-wide.ln_f(&signal), expected, abs <= 1e-9);
+    }
+
+    #[test]
+    fn test_rank_thread_invariance() {
+        let kmers = (0..64)
+            .map(|i| (format!("{i:06}"), 80.0 + i as f64 * 0.25, 80.0))
+            .collect::<Vec<_>>();
+        let (pos_ctrl, neg_ctrl) = ctrl_models(&kmers);
+
+        let rank_with_threads = |n_threads| {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(n_threads)
+                .build()
+                .unwrap()
+                .install(|| {
+                    let mut opts = RankOptions::new(1234, 1_000);
+                    let ranks = opts.rank(&pos_ctrl, &neg_ctrl);
+                    let npsmlr_ranks = opts.rank_npsmlr(&pos_ctrl, &neg_ctrl);
+                    let stats = opts.rank_with_stats(&pos_ctrl, &neg_ctrl);
+                    (ranks, npsmlr_ranks, stats)
+                })
+        };
+        let single = rank_with_threads(1);
+        assert_eq!(single.0.len(), 64);
+        assert_eq!(single, rank_with_threads(4));
+    }
+
+    #[test]
+    fn test_rank_motifs() {
+        let (pos_ctrl, neg_ctrl) = ctrl_models(&[
+            ("AAGCAA", 82.0, 80.0),
+            ("CAAAAA", 83.0, 80.0),
+            ("AAAAAG", 84.0, 80.0),
+            ("TTTTTT", 85.0, 80.0),
+            ("GCGCGC", 86.0, 80.0),
+        ]);
+
+        let full = RankOptions::new(1234, 1_000).rank(&pos_ctrl, &neg_ctrl);
+        let motifs = vec![Motif::parse_from_str("2:GC").unwrap()];
+        let restricted = RankOptions::new(1234, 1_000)
+            .motifs(Some(motifs))
+            .rank(&pos_ctrl, &neg_ctrl);
+
+        let mut kmers: Vec<&String> = restricted.keys().collect();
+        kmers.sort();
+        assert_eq!(kmers, ["AAGCAA", "CAAAAA", "GCGCGC"]);
+        for (kmer, rank) in restricted.iter() {
+            assert_eq!(full[kmer], *rank);
+        }
+    }
+
+    #[test]
+    fn test_top_n() {
+        let (pos_ctrl, neg_ctrl) = ctrl_models(&[
+            ("AAAAAA", 82.0, 80.0),
+            ("CAAAAA", 84.0, 80.0),
+            ("GAAAAA", 86.0, 80.0),
+            ("TAAAAA", 88.0, 80.0),
+        ]);
+
+        let full = RankOptions::new(1234, 1_000).rank(&pos_ctrl, &neg_ctrl);
+        let top = RankOptions::new(1234, 1_000)
+            .top_n(Some(2))
+            .rank(&pos_ctrl, &neg_ctrl);
+        assert_eq!(top.len(), 2);
+        for kmer in ["GAAAAA", "TAAAAA"] {
+            assert_eq!(top[kmer], full[kmer]);
+        }
+
+        let stats = RankOptions::new(1234, 1_000)
+            .top_n(Some(3))
+            .rank_with_stats(&pos_ctrl, &neg_ctrl);
+        assert_eq!(stats.len(), 3);
+        assert!(!stats.contains_key("AAAAAA"));
+
+        let all = RankOptions::new(1234, 1_000)
+            .top_n(Some(10))
+            .rank(&pos_ctrl, &neg_ctrl);
+        assert_eq!(all, full);
+    }
+
+    #[test]
+    fn test_indistinguishable() {
+        let kmers = ["AAAAAA", "CCCCCC", "GGGGGG"];
+        let (pos_ctrl, neg_ctrl) = ctrl_models(&kmers.map(|kmer| (kmer, 80.0, 80.0)));
+
+        for metric in [
+            RankMetric::Kl,
+            RankMetric::Js,
+            RankMetric::Hellinger,
+            RankMetric::Auc,
+        ] {
+            let mut opts = RankOptions::new(1234, 1_000);
+            opts.metric(metric);
+            let ranks = opts.rank(&pos_ctrl, &neg_ctrl);
+            assert_eq!(opts.indistinguishable(&ranks), kmers);
+        }
+
+        let mut ranks = Ranks::default();
+        ranks.insert("AAAAAA".to_string(), 0.01);
+        ranks.insert("CCCCCC".to_string(), 0.5);
+        assert_eq!(indistinguishable_kmers(&ranks, 0.05), ["AAAAAA"]);
+        assert!(indistinguishable_kmers(&ranks, 0.0).is_empty());
+    }
+
+    #[test]
+    fn test_gauss_hermite() {
+        let quad = Quadrature::gauss_hermite(N_QUADRATURE_NODES);
+        let total: f64 = quad.weights.iter().sum();
+        assert_float_eq!(total, std::f64::consts::PI.sqrt(), abs <= 1e-10);
+
+        // Mean and variance of a mixture
+        let mix = Mixture::new_unchecked(
+            vec![0.3, 0.7],
+            vec![
+                Gaussian::new_unchecked(80.0, 2.0),
+                Gaussian::new_unchecked(90.0, 3.0),
+            ],
+        );
+        let mean = quad.expectation(&mix, |x| x);
+        assert_float_eq!(mean, 87.0, abs <= 1e-9);
+        let var = quad.expectation(&mix, |x| (x - mean).powi(2));
+        assert_float_eq!(var, 0.3 * 4.0 + 0.7 * 9.0 + 0.3 * 0.7 * 100.0, abs <= 1e-8);
+    }
+
+    #[test]
+    fn test_exact_matches_sampled() {
+        let mut pos_ctrl = Model::default();
+        let mut neg_ctrl = Model::default();
+        let pos = Mixture::new_unchecked(
+            vec![0.4, 0.6],
+            vec![
+                Gaussian::new_unchecked(82.0, 2.5),
+                Gaussian::new_unchecked(95.0, 3.0),
+            ],
+        );
+        let neg = Mixture::new_unchecked(
+            vec![0.8, 0.2],
+            vec![
+                Gaussian::new_unchecked(80.0, 2.0),
+                Gaussian::new_unchecked(88.0, 4.0),
+            ],
+        );
+        pos_ctrl.insert_gmm("AAAAAA".to_string(), pos);
+        neg_ctrl.insert_gmm("AAAAAA".to_string(), neg);
+
+        for metric in [
+            RankMetric::Kl,
+            RankMetric::SymmetricKl,
+            RankMetric::Js,
+            RankMetric::Hellinger,
+            RankMetric::Wasserstein,
+        ] {
+            for rank in [RankOptions::rank, RankOptions::rank_npsmlr] {
+                let mut sampled = RankOptions::new(2456, 200_000);
+                sampled.metric(metric);
+                let sampled = rank(&mut sampled, &pos_ctrl, &neg_ctrl)["AAAAAA"];
+
+                let mut exact = RankOptions::default();
+                exact.metric(metric).exact(true);
+                let exact = rank(&mut exact, &pos_ctrl, &neg_ctrl)["AAAAAA"];
+                assert_float_eq!(exact, sampled, r2nd <= 0.05);
+            }
+        }
+    }
+
+    #[test]
+    fn test_rank_npsmlr_scored_component() {
+        // Equally weighted negative control, the first component is the one
+        // cawlr npsmlr score compares against
+        let neg = Mixture::new_unchecked(
+            vec![0.5, 0.5],
+            vec![
+                Gaussian::new_unchecked(80.0, 2.0),
+                Gaussian::new_unchecked(100.0, 2.0),
+            ],
+        );
+        let pos = single_mixture(&Gaussian::new_unchecked(80.0, 2.0));
+        let mut pos_ctrl = Model::default();
+        let mut neg_ctrl = Model::default();
+        pos_ctrl.insert_gmm("AAAAAA".to_string(), pos.clone());
+        neg_ctrl.insert_gmm("AAAAAA".to_string(), neg.clone());
+
+        let mut opts = RankOptions::default();
+        opts.exact(true);
+        let rank = opts.rank_npsmlr(&pos_ctrl, &neg_ctrl)["AAAAAA"];
+        let scored = single_mixture(choose_model(&neg));
+        assert_float_eq!(rank, opts.mixture_divergence(&pos, &scored), abs <= 1e-9);
+        assert_float_eq!(rank, 0.0, abs <= 1e-6);
+    }
+
+    #[test]
+    fn test_metric_from_str() {
+        for metric in [
+            RankMetric::Kl,
+            RankMetric::SymmetricKl,
+            RankMetric::Js,
+            RankMetric::Hellinger,
+            RankMetric::Wasserstein,
+            RankMetric::Auc,
+        ] {
+            assert_eq!(metric.to_string().parse::<RankMetric>(), Ok(metric));
+        }
+        assert!("euclidean".parse::<RankMetric>().is_err());
+    }
+
+    #[test]
+    fn test_save_load_ranks() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let mut ranks = Ranks::default();
+        ranks.insert("AAAAAA".to_string(), 0.5);
+        ranks.insert("CAAAAA".to_string(), 0.25);
+
+        let path = temp_dir.path().join("ranks.pickle");
+        let mut file = File::create(&path)?;
+        save_ranks(&ranks, RankMetric::Js, &mut file)?;
+        assert_eq!(load_ranks(&path)?, (ranks.clone(), RankMetric::Js));
+
+        // Saved before the metric was recorded
+        let old_path = temp_dir.path().join("old_ranks.pickle");
+        ranks.save_as(&old_path)?;
+        assert_eq!(load_ranks(&old_path)?, (ranks.clone(), RankMetric::Kl));
+
+        let newer_path = temp_dir.path().join("newer_ranks.pickle");
+        let newer = SavedRanks {
+            version: RANKS_VERSION + 1,
+            metric: RankMetric::Kl.to_string(),
+            ranks,
+        };
+        serde_pickle::to_writer(&mut File::create(&newer_path)?, &newer, Default::default())?;
+        assert!(load_ranks(&newer_path).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_ranks_csv() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let mut ranks = Ranks::default();
+        ranks.insert("AAAAAA".to_string(), 0.25);
+        ranks.insert("CAAAAA".to_string(), 1.5);
+        ranks.insert("GAAAAA".to_string(), 0.75);
+
+        let path = temp_dir.path().join("ranks.pickle");
+        save_ranks(&ranks, RankMetric::Kl, &mut File::create(&path)?)?;
+        let csv_path = temp_dir.path().join("ranks.csv");
+        write_ranks_csv(&ranks, File::create(&csv_path)?)?;
+
+        let (saved, _) = load_ranks(&path)?;
+        let mut reader = csv::Reader::from_path(&csv_path)?;
+        assert_eq!(reader.headers()?, vec!["kmer", "rank_score"]);
+        let rows = reader
+            .records()
+            .map(|record| {
+                let record = record?;
+                Ok((record[0].to_string(), record[1].parse::<f64>()?))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let kmers: Vec<_> = rows.iter().map(|(kmer, _)| kmer.as_str()).collect();
+        assert_eq!(kmers, ["CAAAAA", "GAAAAA", "AAAAAA"]);
+        for (kmer, rank) in rows {
+            assert_eq!(saved[&kmer], rank);
+        }
+        Ok(())
     }
 }
@@ -1,4 +1,10 @@
-use std::{fmt::Display, str::FromStr};
+use std::{
+    fmt::Display,
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+    str::FromStr,
+};
 
 use thiserror::Error;
 
@@ -33,6 +39,9 @@ impl Region {
             return Err(FilterError::EmptyRegionError);
         }
         let spliter: Vec<_> = bed_line.split('\t').collect();
+        if spliter.len() < 3 {
+            return Err(FilterError::ParseError);
+        }
         let chrom = spliter[0].to_string();
         let start = spliter[1]
             .parse()
@@ -47,6 +56,32 @@ impl Region {
     }
 }
 
+/// Regions from the first three columns of each line of a bed file, skipping
+/// blank, comment, track and browser lines
+pub fn load_bed_regions<P: AsRef<Path>>(path: P) -> eyre::Result<Vec<Region>> {
+    let reader = BufReader::new(File::open(&path)?);
+    let mut regions = Vec::new();
+    for (idx, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty()
+            || line.starts_with('#')
+            || line.starts_with("track")
+            || line.starts_with("browser")
+        {
+            continue;
+        }
+        let region = Region::from_bed_line(&line).map_err(|e| {
+            eyre::eyre!(
+                "Invalid region on line {} of {}: {e}",
+                idx + 1,
+                path.as_ref().display()
+            )
+        })?;
+        regions.push(region);
+    }
+    Ok(regions)
+}
+
 fn overlaps(a_start: u64, a_end: u64, b_start: u64, b_end: u64) -> bool {
     ((b_start <= a_start) && (a_start <= b_end)) || // End overlaps
         ((b_start <= a_end) && (a_end <= b_end)) || // Other end overlaps
@@ -112,4 +147,22 @@ mod test {
         let outside_a = (9, 16);
         assert!(overlaps(a.0, a.1, outside_a.0, outside_a.1));
     }
+
+    #[test]
+    fn test_load_bed_regions() -> eyre::Result<()> {
+        let temp_dir = assert_fs::TempDir::new()?;
+        let bed = temp_dir.path().join("regions.bed");
+        std::fs::write(
+            &bed,
+            "track name=test\nchrI\t100\t200\tpromoter\n\nchrXIII\t5\t10\n",
+        )?;
+        let regions = load_bed_regions(&bed)?;
+        assert_eq!(regions.len(), 2);
+        assert_eq!(regions[0].to_string(), "chrI:100-200");
+        assert_eq!(regions[1].to_string(), "chrXIII:5-10");
+
+        std::fs::write(&bed, "chrI\t100\n")?;
+        assert!(load_bed_regions(&bed).is_err());
+        Ok(())
+    }
 }
@@ -1,44 +1,438 @@
 use std::{
-    collections::HashMap, fmt::Debug, fs::File, hash::BuildHasher, ops::RangeInclusive, path::Path,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt::{self, Debug},
+    fs::File,
+    hash::BuildHasher,
+    io::{BufWriter, Read, Seek, Write},
+    ops::{Range, RangeInclusive},
+    path::Path,
+    str::FromStr,
 };
 
-use arrow2::io::ipc::write::FileWriter;
+use arrow2::{
+    datatypes::Schema,
+    io::ipc::{read::read_file_metadata, write::FileWriter},
+};
 use bio::io::fasta::IndexedReader;
-use eyre::Result;
-use fnv::FnvHashMap;
+use eyre::{Context as _, Result};
+use fnv::{FnvHashMap, FnvHashSet};
+use rayon::{prelude::*, ThreadPool, ThreadPoolBuilder};
 use rv::{
     prelude::{Gaussian, Mixture},
     traits::{Cdf, KlDivergence, Rv},
 };
+use serde::{Deserialize, Serialize};
 use statrs::statistics::Statistics;
 
 use crate::{
     arrow::{
-        arrow_utils::{load_apply, save, wrap_writer},
+        arrow_utils::{load, load_apply, load_apply_batched, save, try_into_vec, wrap_writer},
         eventalign::Eventalign,
-        metadata::MetadataExt,
-        scored_read::{Score, ScoredRead},
+        metadata::{Metadata, MetadataExt, Strand},
+        scored_read::{ReadQuality, Score, ScoredRead},
         signal::Signal,
     },
-    context,
+    calibrate::Calibration,
+    context::{self, GenomeCache},
+    filter::FilterOptions,
     motif::{all_bases, Motif},
-    train::{Model, ModelDB},
-    utils::{chrom_lens, CawlrIO},
+    rank::load_ranks,
+    region::Region,
+    train::{check_kmer_size, Model},
+    utils::{chrom_lens, open_genome_fasta, CawlrIO},
 };
 
-pub struct ScoreOptions {
+/// Key in the scored Arrow file schema metadata recording how the scores
+/// were calculated.
+pub const SCORING_METHOD_KEY: &str = "scoring_method";
+
+/// Skipping score ratios are kept this far from 0 and 1 before converting
+/// them to log odds, which are infinite at either end
+const RATIO_EPSILON: f64 = 1e-6;
+
+/// How the positive and negative control likelihoods of a signal are combined
+/// into a score.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize, Default)]
+pub enum ScoringMethod {
+    /// pos / (pos + neg), bounded between 0 and 1
+    #[default]
+    ProbabilityRatio,
+    /// ln(pos / neg), unbounded and centered on 0
+    LogOdds,
+}
+
+impl fmt::Display for ScoringMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            ScoringMethod::ProbabilityRatio => "ratio",
+            ScoringMethod::LogOdds => "log-odds",
+        };
+        write!(f, "{s}")
+    }
+}
+
+impl FromStr for ScoringMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ratio" => Ok(ScoringMethod::ProbabilityRatio),
+            "log-odds" => Ok(ScoringMethod::LogOdds),
+            _ => Err(format!(
+                "Invalid scoring method {s}, expected ratio or log-odds"
+            )),
+        }
+    }
+}
+
+impl ScoringMethod {
+    /// Schema for scored Arrow files, with the scoring method in the metadata.
+    pub fn schema(self) -> Schema {
+        let mut metadata = BTreeMap::new();
+        metadata.insert(SCORING_METHOD_KEY.to_string(), self.to_string());
+        ScoredRead::schema().with_metadata(metadata)
+    }
+
+    /// Scoring method recorded in a schema, files without one were scored
+    /// before the method was recorded and use the probability ratio.
+    pub fn from_schema(schema: &Schema) -> Result<Self> {
+        match schema.metadata.get(SCORING_METHOD_KEY) {
+            Some(method) => method.parse().map_err(|e: String| eyre::eyre!(e)),
+            None => Ok(ScoringMethod::default()),
+        }
+    }
+
+    /// Combine the likelihoods of the signal under the positive and negative
+    /// control, given as log probabilities.
+    pub(crate) fn score(self, pos_log_proba: f64, neg_log_proba: f64) -> f64 {
+        match self {
+            ScoringMethod::ProbabilityRatio => {
+                let pos_proba = pos_log_proba.exp();
+                let neg_proba = neg_log_proba.exp();
+                pos_proba / (pos_proba + neg_proba)
+            }
+            ScoringMethod::LogOdds => pos_log_proba - neg_log_proba,
+        }
+    }
+
+    /// Probability of modification for a score of this method, the inverse of
+    /// [ScoringMethod::convert_ratio]
+    pub fn probability(self, score: f64) -> f64 {
+        match self {
+            ScoringMethod::ProbabilityRatio => score,
+            ScoringMethod::LogOdds => 1. / (1. + (-score).exp()),
+        }
+    }
+
+    /// Convert a skipping score, a probability ratio, to this scoring method
+    /// so it can be compared with the signal score.
+    fn convert_ratio(self, ratio: f64) -> f64 {
+        match self {
+            ScoringMethod::ProbabilityRatio => ratio,
+            ScoringMethod::LogOdds => {
+                let ratio = ratio.clamp(RATIO_EPSILON, 1. - RATIO_EPSILON);
+                (ratio / (1. - ratio)).ln()
+            }
+        }
+    }
+}
+
+/// How the signal score and skipping score of a position are combined into its
+/// final score. Both are still stored in each [Score] so other policies can
+/// be applied later.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum ScorePolicy {
+    /// Larger of the signal and skipping score, or the skipping score without
+    /// a signal score
+    #[default]
+    Max,
+    /// Only the signal score, positions without one aren't scored
+    SignalOnly,
+    /// Only the skipping score
+    SkipOnly,
+    /// alpha * signal + (1 - alpha) * skipping, or the skipping score without
+    /// a signal score
+    WeightedMean { alpha: f64 },
+}
+
+impl fmt::Display for ScorePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScorePolicy::Max => write!(f, "max"),
+            ScorePolicy::SignalOnly => write!(f, "signal-only"),
+            ScorePolicy::SkipOnly => write!(f, "skip-only"),
+            ScorePolicy::WeightedMean { alpha } => write!(f, "weighted-mean:{alpha}"),
+        }
+    }
+}
+
+impl FromStr for ScorePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "max" => Ok(ScorePolicy::Max),
+            "signal-only" => Ok(ScorePolicy::SignalOnly),
+            "skip-only" => Ok(ScorePolicy::SkipOnly),
+            _ => match s.strip_prefix("weighted-mean:") {
+                Some(alpha) => {
+                    let alpha: f64 = alpha
+                        .parse()
+                        .map_err(|e| format!("Invalid weight {alpha}: {e}"))?;
+                    if !(0.0..=1.0).contains(&alpha) {
+                        return Err(format!("Weight {alpha} must be between 0 and 1"));
+                    }
+                    Ok(ScorePolicy::WeightedMean { alpha })
+                }
+                None => Err(format!(
+                    "Invalid score policy {s}, expected max, signal-only, skip-only, or \
+                     weighted-mean:<alpha>"
+                )),
+            },
+        }
+    }
+}
+
+impl ScorePolicy {
+    /// Final score of a position, None if the position shouldn't be scored
+    pub(crate) fn combine(self, signal_score: Option<f64>, skipping_score: f64) -> Option<f64> {
+        match self {
+            ScorePolicy::Max => {
+                Some(signal_score.map_or(skipping_score, |x| x.max(skipping_score)))
+            }
+            ScorePolicy::SignalOnly => signal_score,
+            ScorePolicy::SkipOnly => Some(skipping_score),
+            ScorePolicy::WeightedMean { alpha } => Some(signal_score.map_or(skipping_score, |x| {
+                alpha * x + (1. - alpha) * skipping_score
+            })),
+        }
+    }
+}
+
+/// Number of kmers or contigs listed in each problem of a
+/// [CompatibilityReport]
+const MAX_LISTED: usize = 10;
+
+/// Mismatches between the models, ranks, genome and input given to cawlr
+/// score, which would otherwise only show up as missing or wrong scores. See
+/// [ScoreOptions::compatibility_report].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompatibilityReport {
+    /// Ranked kmers missing from the positive or negative control model,
+    /// usually because the ranks were computed from other models
+    pub ranked_without_model: Vec<String>,
+    /// Ranked kmers with a different length than the kmers of the models
+    pub wrong_kmer_size: Vec<String>,
+    /// Contigs of reads in the first chunk of the input that aren't in the
+    /// genome
+    pub missing_contigs: Vec<String>,
+}
+
+impl CompatibilityReport {
+    /// Check that every ranked kmer has a model in both controls, with the
+    /// same kmer size
+    fn from_models(
+        pos_ctrl: &Model,
+        neg_ctrl: &Model,
+        ranks: &FnvHashMap<String, f64>,
+        kmer_size: usize,
+    ) -> Self {
+        let mut report = CompatibilityReport::default();
+        for kmer in ranks.keys() {
+            if kmer.len() != kmer_size {
+                report.wrong_kmer_size.push(kmer.clone());
+            } else if !pos_ctrl.gmms().contains_key(kmer) || !neg_ctrl.gmms().contains_key(kmer) {
+                report.ranked_without_model.push(kmer.clone());
+            }
+        }
+        report.wrong_kmer_size.sort();
+        report.ranked_without_model.sort();
+        report
+    }
+
+    /// True if no problems were found
+    pub fn is_compatible(&self) -> bool {
+        self.ranked_without_model.is_empty()
+            && self.wrong_kmer_size.is_empty()
+            && self.missing_contigs.is_empty()
+    }
+
+    /// Description of each problem found
+    pub fn problems(&self) -> Vec<String> {
+        let listed = |xs: &[String]| {
+            let mut listed = xs
+                .iter()
+                .take(MAX_LISTED)
+                .cloned()
+                .collect::<Vec<_>>()
+                .join(", ");
+            if xs.len() > MAX_LISTED {
+                listed.push_str(", ...");
+            }
+            listed
+        };
+        let mut problems = Vec::new();
+        if !self.ranked_without_model.is_empty() {
+            problems.push(format!(
+                "{} ranked kmers have no model in one of the controls, were the ranks computed \
+                 from these models? {}",
+                self.ranked_without_model.len(),
+                listed(&self.ranked_without_model)
+            ));
+        }
+        if !self.wrong_kmer_size.is_empty() {
+            problems.push(format!(
+                "{} ranked kmers have a different length than the models' kmers: {}",
+                self.wrong_kmer_size.len(),
+                listed(&self.wrong_kmer_size)
+            ));
+        }
+        if !self.missing_contigs.is_empty() {
+            problems.push(format!(
+                "Reads are aligned to contigs missing from the genome, different genome used? {}",
+                listed(&self.missing_contigs)
+            ));
+        }
+        problems
+    }
+}
+
+/// Contigs of the reads in the first chunk of an Arrow file from cawlr
+/// collapse
+fn first_chunk_contigs<R: Read + Seek>(reader: R) -> Result<BTreeSet<String>> {
+    let mut contigs = BTreeSet::new();
+    if let Some(chunk) = load(reader)?.next() {
+        for arr in chunk?.into_arrays() {
+            let reads: Vec<Eventalign> = try_into_vec(arr)?;
+            contigs.extend(reads.iter().map(|read| read.chrom().to_string()));
+        }
+    }
+    Ok(contigs)
+}
+
+/// Write the scored reads in record batches of [OUTPUT_BATCH_SIZE], leaving
+/// the remainder for the next call unless this is the last one
+fn write_output_batches<W: Write>(
+    writer: &mut FileWriter<W>,
+    scored: &mut Vec<ScoredRead>,
+    last: bool,
+) -> Result<()> {
+    while scored.len() >= OUTPUT_BATCH_SIZE {
+        let batch: Vec<_> = scored.drain(..OUTPUT_BATCH_SIZE).collect();
+        save(writer, &batch)?;
+    }
+    if last && !scored.is_empty() {
+        save(writer, scored)?;
+        scored.clear();
+    }
+    Ok(())
+}
+
+/// Number of genome sequences kept by default, see
+/// [ScoreOptions::genome_cache_size]
+pub const DEFAULT_GENOME_CACHE_SIZE: usize = 128;
+
+/// Number of bases of a read scored at a time by default, see
+/// [ScoreOptions::window_size]
+pub const DEFAULT_WINDOW_SIZE: u64 = 100_000;
+
+/// Number of reads scored at a time by default, see
+/// [ScoreOptions::batch_reads]
+pub const DEFAULT_BATCH_READS: usize = 1000;
+
+/// Scored reads are written in record batches of this many reads, so the
+/// output doesn't depend on how many reads are scored at a time
+const OUTPUT_BATCH_SIZE: usize = 1000;
+
+/// Options for scoring reads from cawlr collapse. Created with the required
+/// inputs by [ScoreOptions::load], other settings are set with the builder
+/// methods before calling [ScoreOptions::run].
+pub struct ScoreOptions<'a> {
+    output: Option<Box<dyn Write + Send + Sync + 'a>>,
     pos_ctrl: Model,
     neg_ctrl: Model,
     genome: IndexedReader<File>,
     chrom_lens: FnvHashMap<String, u64>,
     rank: FnvHashMap<String, f64>,
-    writer: FileWriter<File>,
+    scoring_method: ScoringMethod,
+    score_policy: ScorePolicy,
+    use_skipping_score: bool,
+    strict: bool,
     cutoff: f64,
+    per_kmer_cutoffs: Option<FnvHashMap<String, f64>>,
     p_value_threshold: f64,
     motifs: Vec<Motif>,
+    kmer_size: usize,
+    batch_reads: usize,
+    min_rank: Option<f64>,
+    both_strand_motifs: bool,
+    output_missing_kmers: bool,
+    output_all_positions: bool,
+    threads: Option<usize>,
+    window_size: u64,
+    genome_cache: Option<GenomeCache>,
+    regions: Option<FilterOptions>,
+    read_names: Option<FnvHashSet<String>>,
+    calibration: Option<Calibration>,
+    compatibility: CompatibilityReport,
 }
 
-impl ScoreOptions {
+impl<'a> ScoreOptions<'a> {
+    /// Load the control models from cawlr train, the genome and the ranks
+    /// from cawlr rank, with the scored reads written to output as an Arrow
+    /// file by [ScoreOptions::run]
+    pub fn load<P, W>(
+        pos_ctrl_filepath: P,
+        neg_ctrl_filepath: P,
+        genome_filepath: P,
+        rank_filepath: P,
+        output: W,
+    ) -> Result<Self>
+    where
+        P: AsRef<Path> + Debug,
+        W: Write + Send + Sync + 'a,
+    {
+        let mut options = ScoreOptions::load_models(
+            pos_ctrl_filepath,
+            neg_ctrl_filepath,
+            genome_filepath,
+            rank_filepath,
+        )?;
+        options.output(output);
+        Ok(options)
+    }
+
+    /// Like [ScoreOptions::load] without the output, so the options can be
+    /// checked before creating it. Set it with [ScoreOptions::output] before
+    /// running, or pass it to [ScoreOptions::run_with_writer].
+    pub fn load_models<P>(
+        pos_ctrl_filepath: P,
+        neg_ctrl_filepath: P,
+        genome_filepath: P,
+        rank_filepath: P,
+    ) -> Result<Self>
+    where
+        P: AsRef<Path> + Debug,
+    {
+        let genome = open_genome_fasta(genome_filepath.as_ref())?;
+        let (kmer_ranks, metric) = load_ranks(rank_filepath)?;
+        log::debug!("Ranks computed with {metric}");
+        let pos_ctrl_db = Model::load(&pos_ctrl_filepath)?;
+        let neg_ctrl_db = Model::load(&neg_ctrl_filepath)?;
+        ScoreOptions::from_parts(pos_ctrl_db, neg_ctrl_db, genome, kmer_ranks)
+    }
+
+    /// Where [ScoreOptions::run] writes the scored reads as an Arrow file
+    pub fn output<W>(&mut self, output: W) -> &mut Self
+    where
+        W: Write + Send + Sync + 'a,
+    {
+        self.output = Some(Box::new(output));
+        self
+    }
+
+    /// Like [ScoreOptions::load], creating the output file at the path
+    #[deprecated(note = "use ScoreOptions::load, which takes any writer as the output")]
     pub fn try_new<P>(
         pos_ctrl_filepath: P,
         neg_ctrl_filepath: P,
@@ -49,33 +443,109 @@ impl ScoreOptions {
     where
         P: AsRef<Path> + Debug,
     {
-        let schema = ScoredRead::schema();
-        let writer = File::create(output)?;
-        let writer = wrap_writer(writer, &schema)?;
-        let kmer_ranks = FnvHashMap::load(rank_filepath)?;
-        let genome = IndexedReader::from_file(&genome_filepath)
-            .map_err(|_| eyre::eyre!("Failed to read genome file"))?;
+        let writer = BufWriter::new(File::create(output)?);
+        ScoreOptions::load(
+            pos_ctrl_filepath,
+            neg_ctrl_filepath,
+            genome_filepath,
+            rank_filepath,
+            writer,
+        )
+    }
+
+    fn from_parts(
+        pos_ctrl_db: Model,
+        neg_ctrl_db: Model,
+        genome: IndexedReader<File>,
+        kmer_ranks: FnvHashMap<String, f64>,
+    ) -> Result<Self> {
         let chrom_lens = chrom_lens(&genome);
-        let pos_ctrl_db = Model::load(&pos_ctrl_filepath)?;
-        let neg_ctrl_db = Model::load(&neg_ctrl_filepath)?;
+        let kmer_size = pos_ctrl_db.kmer_size();
+        check_kmer_size(kmer_size)?;
+        if neg_ctrl_db.kmer_size() != kmer_size {
+            return Err(eyre::eyre!(
+                "Positive control model uses {}-mers but negative control model uses {}-mers",
+                kmer_size,
+                neg_ctrl_db.kmer_size()
+            ));
+        }
+        let compatibility =
+            CompatibilityReport::from_models(&pos_ctrl_db, &neg_ctrl_db, &kmer_ranks, kmer_size);
+        for problem in compatibility.problems() {
+            log::warn!("{problem}");
+        }
         Ok(ScoreOptions {
+            output: None,
             pos_ctrl: pos_ctrl_db,
             neg_ctrl: neg_ctrl_db,
             genome,
             chrom_lens,
             rank: kmer_ranks,
-            writer,
+            scoring_method: ScoringMethod::default(),
+            score_policy: ScorePolicy::default(),
+            use_skipping_score: true,
+            strict: false,
             cutoff: 10.0,
+            per_kmer_cutoffs: None,
             p_value_threshold: 0.05,
             motifs: all_bases(),
+            kmer_size,
+            batch_reads: DEFAULT_BATCH_READS,
+            min_rank: None,
+            both_strand_motifs: false,
+            output_missing_kmers: false,
+            output_all_positions: false,
+            threads: None,
+            window_size: DEFAULT_WINDOW_SIZE,
+            genome_cache: Some(GenomeCache::new(DEFAULT_GENOME_CACHE_SIZE)),
+            regions: None,
+            read_names: None,
+            calibration: None,
+            compatibility,
         })
     }
 
+    /// Kmer size of the models used for scoring
+    pub fn kmer_size(&self) -> usize {
+        self.kmer_size
+    }
+
+    /// How signal likelihoods are combined into a score, recorded in the
+    /// output schema so later steps can check for it.
+    pub fn scoring_method(&mut self, scoring_method: ScoringMethod) -> &mut Self {
+        self.scoring_method = scoring_method;
+        self
+    }
+
+    /// How the signal and skipping score of a position are combined into its
+    /// final score, by default the larger of the two.
+    pub fn score_policy(&mut self, score_policy: ScorePolicy) -> &mut Self {
+        self.score_policy = score_policy;
+        self
+    }
+
+    /// Calculate the skipping score of each position, on by default. Without
+    /// it the final score is the signal score, or NaN for positions without
+    /// one, and the score policy isn't used.
+    pub fn use_skipping_score(&mut self, use_skipping_score: bool) -> &mut Self {
+        self.use_skipping_score = use_skipping_score;
+        self
+    }
+
     pub fn cutoff(&mut self, cutoff: f64) -> &mut Self {
         self.cutoff = cutoff;
         self
     }
 
+    /// Cutoffs for specific kmers, kmers not in the map use the global cutoff
+    pub fn per_kmer_cutoffs(
+        &mut self,
+        per_kmer_cutoffs: Option<FnvHashMap<String, f64>>,
+    ) -> &mut Self {
+        self.per_kmer_cutoffs = per_kmer_cutoffs;
+        self
+    }
+
     pub fn p_value_threshold(&mut self, p_value_threshold: f64) -> &mut Self {
         self.p_value_threshold = p_value_threshold;
         self
@@ -86,77 +556,472 @@ impl ScoreOptions {
         self
     }
 
-    fn close(mut self) -> Result<()> {
-        self.writer.finish()?;
-        Ok(())
+    /// Don't use the signal from kmers ranked below min_rank, whose controls
+    /// are indistinguishable, so positions only covered by them get the
+    /// skipping score. Kmers missing from the ranks are still used.
+    pub fn min_rank(&mut self, min_rank: Option<f64>) -> &mut Self {
+        self.min_rank = min_rank;
+        self
+    }
+
+    /// Also score motifs on the minus strand of plus strand reads, for motifs
+    /// that aren't their own reverse complement
+    pub fn both_strand_motifs(&mut self, both_strand_motifs: bool) -> &mut Self {
+        self.both_strand_motifs = both_strand_motifs;
+        self
+    }
+
+    /// Write positions whose kmer is in neither control model with NaN scores
+    /// and [Score::missing_model] set, instead of scoring them from the
+    /// surrounding kmers
+    pub fn output_missing_kmers(&mut self, output_missing_kmers: bool) -> &mut Self {
+        self.output_missing_kmers = output_missing_kmers;
+        self
+    }
+
+    /// Also score positions that aren't the modified base of any motif, with
+    /// [Score::matches_motif] unset, so motif and non-motif positions can be
+    /// compared
+    pub fn output_all_positions(&mut self, output_all_positions: bool) -> &mut Self {
+        self.output_all_positions = output_all_positions;
+        self
+    }
+
+    /// Number of threads to score reads on, by default the number of cpus
+    pub fn threads(&mut self, threads: Option<usize>) -> &mut Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Score reads longer than this many bases one window at a time, only
+    /// fetching the genome sequence and indexing the signal around the
+    /// window, so memory doesn't grow with the length of ultra-long reads.
+    /// Windows are scored on the calling thread after the rest of the batch.
+    pub fn window_size(&mut self, window_size: u64) -> &mut Self {
+        self.window_size = window_size.max(1);
+        self
+    }
+
+    /// Keep the genome sequence fetched for up to this many reads, so reads
+    /// covering the same positions don't fetch it from the fasta again. A
+    /// size of 0 turns off the cache.
+    pub fn genome_cache_size(&mut self, size: usize) -> &mut Self {
+        self.genome_cache = (size > 0).then(|| GenomeCache::new(size));
+        self
     }
 
-    /// For every read in the input file, try to calculate scores for each base
-    /// position and write to file.
-    pub fn run<P>(mut self, input: P) -> Result<()>
+    /// Only score reads overlapping any of the regions, skipping the rest
+    /// before fetching their context. Reads are scored in full if they
+    /// overlap at all. By default every read is scored.
+    pub fn regions(&mut self, regions: Vec<Region>) -> &mut Self {
+        self.regions = (!regions.is_empty()).then(|| FilterOptions::new(regions));
+        self
+    }
+
+    /// Only score reads with these names, skipping the rest before fetching
+    /// their context. By default every read is scored.
+    pub fn read_names<I>(&mut self, read_names: I) -> &mut Self
     where
-        P: AsRef<Path>,
+        I: IntoIterator<Item = String>,
     {
-        let file = File::open(input)?;
-        load_apply(file, |eventaligns| {
-            let scored = eventaligns
-                .into_iter()
-                .flat_map(|e| self.score_eventalign(e))
-                .collect();
-            self.save(scored)
+        let read_names: FnvHashSet<String> = read_names.into_iter().collect();
+        self.read_names = (!read_names.is_empty()).then_some(read_names);
+        self
+    }
+
+    /// Also store the score of each position mapped to a probability of
+    /// modification by the calibration, see [Score::calibrated_score]
+    pub fn calibration(&mut self, calibration: Calibration) -> &mut Self {
+        self.calibration = Some(calibration);
+        self
+    }
+
+    /// Stop with an error on reads aligned to contigs missing from the genome
+    /// or that fail to score, instead of skipping them with a warning, and on
+    /// any other problem in the [ScoreOptions::compatibility_report]
+    pub fn strict(&mut self, strict: bool) -> &mut Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Score this many reads at a time, whatever the size of the record
+    /// batches in the input, to limit the reads kept in memory. Reads are
+    /// written in the same record batches for any batch size. Must be
+    /// greater than 0.
+    pub fn batch_reads(&mut self, batch_reads: usize) -> &mut Self {
+        self.batch_reads = batch_reads;
+        self
+    }
+
+    /// Check that the ranks were computed from the models and that the reads
+    /// in the first chunk of the input are on contigs in the genome. Problems
+    /// with the models and ranks are also logged as warnings when the options
+    /// are created.
+    pub fn compatibility_report<R: Read + Seek>(&self, input: R) -> Result<CompatibilityReport> {
+        let mut report = self.compatibility.clone();
+        report.missing_contigs = first_chunk_contigs(input)?
+            .into_iter()
+            .filter(|contig| !self.chrom_lens.contains_key(contig))
+            .collect();
+        Ok(report)
+    }
+
+    /// For every read in the input, usually from cawlr collapse, try to
+    /// calculate scores for each base position and write to the writer as an
+    /// Arrow file.
+    ///
+    /// Reads are scored [ScoreOptions::batch_reads] at a time, in parallel,
+    /// keeping their order in the output. The genome is only read from the
+    /// calling thread, which fetches the context of every read in the batch
+    /// first. Reads longer than the [ScoreOptions::window_size] are scored
+    /// after the rest of the batch, one window at a time.
+    ///
+    /// With [ScoreOptions::strict], any problem in the
+    /// [ScoreOptions::compatibility_report] is an error before scoring starts.
+    /// Reads with kmers of a different length than the models are always an
+    /// error.
+    pub fn run<R>(mut self, mut reader: R) -> Result<()>
+    where
+        R: Read + Seek,
+    {
+        let writer = self.output.take().ok_or_else(|| {
+            eyre::eyre!("No output to write scores to, set it with ScoreOptions::output")
         })?;
-        self.close()
+        let report = self.compatibility_report(&mut reader)?;
+        reader.rewind()?;
+        if self.strict && !report.is_compatible() {
+            return Err(eyre::eyre!(
+                "Inputs are incompatible: {}",
+                report.problems().join("; ")
+            ));
+        }
+        if !report.missing_contigs.is_empty() {
+            log::warn!(
+                "Reads on contigs missing from the genome will be skipped: {:?}",
+                report.missing_contigs
+            );
+        }
+        let mut writer = wrap_writer(writer, &self.scoring_method.schema())?;
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(self.threads.unwrap_or(0))
+            .build()?;
+        let batch_reads = self.batch_reads;
+        let mut found_names = FnvHashSet::default();
+        let mut unknown_contigs: BTreeMap<String, usize> = BTreeMap::new();
+        let mut n_failed = 0;
+        let mut pending: Vec<Eventalign> = Vec::new();
+        let mut scored: Vec<ScoredRead> = Vec::new();
+        load_apply_batched(reader, batch_reads, |eventaligns: Vec<Eventalign>| {
+            for read in eventaligns {
+                if !self.keep_read(&read) {
+                    continue;
+                }
+                if self.read_names.is_some() {
+                    found_names.insert(read.name().to_string());
+                }
+                read.check_kmer_size(self.kmer_size)?;
+                if self.chrom_lens.contains_key(read.chrom()) {
+                    pending.push(read);
+                } else if self.strict {
+                    return Err(eyre::eyre!(
+                        "Read {} is on {}, which is missing from the genome",
+                        read.name(),
+                        read.chrom()
+                    ));
+                } else {
+                    log::warn!(
+                        "Skipping read {} on {}, which is missing from the genome",
+                        read.name(),
+                        read.chrom()
+                    );
+                    *unknown_contigs.entry(read.chrom().to_string()).or_default() += 1;
+                }
+            }
+            while pending.len() >= batch_reads {
+                let batch = pending.drain(..batch_reads).collect();
+                scored.extend(self.score_batch(&pool, batch, &mut n_failed)?);
+                write_output_batches(&mut writer, &mut scored, false)?;
+            }
+            Ok(())
+        })?;
+        if !pending.is_empty() {
+            scored.extend(self.score_batch(&pool, pending, &mut n_failed)?);
+        }
+        write_output_batches(&mut writer, &mut scored, true)?;
+        writer.finish()?;
+        if let Some(read_names) = &self.read_names {
+            log::info!(
+                "Found {} of {} requested reads",
+                found_names.len(),
+                read_names.len()
+            );
+            let mut missing: Vec<_> = read_names.difference(&found_names).collect();
+            if !missing.is_empty() {
+                missing.sort();
+                log::warn!("{} requested reads not found: {missing:?}", missing.len());
+            }
+        }
+        if !unknown_contigs.is_empty() {
+            let n_skipped: usize = unknown_contigs.values().sum();
+            let contigs = unknown_contigs
+                .iter()
+                .map(|(contig, n)| format!("{contig} ({n} reads)"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            log::warn!("Skipped {n_skipped} reads on contigs missing from the genome: {contigs}");
+        }
+        if n_failed > 0 {
+            log::warn!("Skipped {n_failed} reads that failed to score");
+        }
+        Ok(())
     }
 
-    /// Write batch of scored reads to the writer.
-    pub(crate) fn save(&mut self, scored: Vec<ScoredRead>) -> Result<()> {
-        save(&mut self.writer, &scored)
+    /// Like [ScoreOptions::run], writing the scores to writer instead of the
+    /// output the options were created with. The writer is only borrowed for
+    /// the run, so it can be passed by reference and finished afterwards.
+    pub fn run_with_writer<R, W>(mut self, reader: R, writer: W) -> Result<()>
+    where
+        R: Read + Seek,
+        W: Write + Send + Sync + 'a,
+    {
+        self.output = Some(Box::new(writer));
+        self.run(reader)
     }
 
-    /// Scores a single Eventalign read. For each read, loop over each base pair
-    /// position, and if the kmer at the position matches the motif attempt to
-    /// score it.
-    fn score_eventalign(&mut self, read: Eventalign) -> Result<ScoredRead> {
-        let mut acc = Vec::new();
-        let context = context::Context::from_read(&mut self.genome, &self.chrom_lens, &read)?;
+    /// Score the reads in parallel, keeping their order. Reads that fail to
+    /// score are logged, counted in n_failed and dropped, or an error with
+    /// [ScoreOptions::strict]. Reads longer than the window size are scored
+    /// after the rest, one window at a time.
+    fn score_batch(
+        &mut self,
+        pool: &ThreadPool,
+        reads: Vec<Eventalign>,
+        n_failed: &mut usize,
+    ) -> Result<Vec<ScoredRead>> {
+        let (long_reads, eventaligns): (Vec<_>, Vec<_>) = reads
+            .into_iter()
+            .enumerate()
+            .partition(|(_, read)| read.np_length() > self.window_size);
+        let contexts: Vec<_> = eventaligns
+            .iter()
+            .map(|(_, read)| self.read_context(read))
+            .collect();
+        let this = &*self;
+        let mut results: Vec<(usize, Result<ScoredRead>)> = pool.install(|| {
+            eventaligns
+                .into_par_iter()
+                .zip(contexts)
+                .map(|((idx, read), context)| {
+                    let name = read.name().to_string();
+                    let scored = context
+                        .and_then(|context| this.score_eventalign(read, &context))
+                        .wrap_err_with(|| format!("Failed to score read {name}"));
+                    (idx, scored)
+                })
+                .collect()
+        });
+        for (idx, read) in long_reads {
+            let name = read.name().to_string();
+            let scored = self
+                .score_long_read(read)
+                .wrap_err_with(|| format!("Failed to score read {name}"));
+            results.push((idx, scored));
+        }
+        results.sort_by_key(|(idx, _)| *idx);
+        let mut scored = Vec::with_capacity(results.len());
+        for (_, result) in results {
+            match result {
+                Ok(read) => scored.push(read),
+                Err(e) if self.strict => return Err(e),
+                Err(e) => {
+                    log::warn!("Skipping read: {e:#}");
+                    *n_failed += 1;
+                }
+            }
+        }
+        if let Some(calibration) = &self.calibration {
+            for read in scored.iter_mut() {
+                calibration.calibrate_read(read);
+            }
+        }
+        Ok(scored)
+    }
+
+    /// Whether the read is in the requested regions and read names, if any
+    fn keep_read(&self, read: &Eventalign) -> bool {
+        let in_regions = self
+            .regions
+            .as_ref()
+            .map_or(true, |regions| regions.any_valid(read));
+        let in_names = self
+            .read_names
+            .as_ref()
+            .map_or(true, |names| names.contains(read.name()));
+        in_regions && in_names
+    }
+
+    /// Fetch the genome sequence around the read, or reuse it from the cache
+    fn read_context(&mut self, read: &Eventalign) -> Result<context::Context> {
+        context::Context::from_read_cached(
+            &mut self.genome,
+            self.genome_cache.as_mut(),
+            &self.chrom_lens,
+            read,
+            self.kmer_size,
+        )
+    }
 
+    /// Scores a single Eventalign read with the context of the whole read
+    fn score_eventalign(&self, read: Eventalign, context: &context::Context) -> Result<ScoredRead> {
         log::debug!("{:?}", read.metadata());
         log::debug!("{context:.3?}");
 
         let data_pos = pos_with_data(&read);
-        for pos in read.start_1b()..read.end_1b_excl() {
-            // Get kmer and check if kmer matches the motifs, if there are any supplied
-            let pos_kmer: Option<(&[u8], &Motif)> = context.sixmer_at(pos).and_then(|k| {
-                self.motifs
-                    .iter()
-                    .find(|m| {
-                        let m = m.motif().as_bytes();
-                        k.starts_with(m)
-                    })
-                    .map(|m| (k, m))
-            });
-
-            if let Some((kmer, motif)) = pos_kmer {
+        let acc = self.score_positions(
+            &read,
+            &data_pos,
+            context,
+            read.start_1b()..read.end_1b_excl(),
+        )?;
+        Ok(self.finish_read(read, acc))
+    }
+
+    /// Scores a read longer than the window size one window at a time, with
+    /// the context and signal of the window and the kmers overlapping it.
+    /// The scores are the same as with the context of the whole read.
+    fn score_long_read(&mut self, read: Eventalign) -> Result<ScoredRead> {
+        log::debug!("Scoring {} in windows", read.name());
+        let slop = self.kmer_size as u64 - 1;
+        let read_end = read.end_1b_excl();
+        let mut signals = read.signal_iter().collect::<Vec<_>>();
+        signals.sort_by_key(|signal| signal.pos);
+        // First signal that can be around the current window, windows only
+        // move forward so earlier signals are never needed again
+        let mut cursor = 0;
+        let mut acc = Vec::new();
+        let mut window_start = read.start_0b();
+        while window_start < read_end {
+            let window_end = (window_start + self.window_size).min(read_end);
+            // Extended by a kmer so positions at the end of the window have
+            // the bases after them, but not past the end of the read
+            let context_len =
+                (window_end - window_start + self.kmer_size as u64).min(read_end - window_start);
+            let window = Metadata::new(
+                read.name().to_string(),
+                read.chrom().to_string(),
+                window_start,
+                context_len,
+                read.strand(),
+                String::new(),
+            );
+            let context = context::Context::from_read(
+                &mut self.genome,
+                &self.chrom_lens,
+                &window,
+                self.kmer_size,
+            )?;
+            let signal_start = (window_start + 1).saturating_sub(slop);
+            while signals.get(cursor).is_some_and(|s| s.pos < signal_start) {
+                cursor += 1;
+            }
+            let data_pos = signals[cursor..]
+                .iter()
+                .take_while(|signal| signal.pos <= window_end + slop)
+                .map(|&signal| (signal.pos, signal))
+                .collect::<FnvHashMap<_, _>>();
+            let positions = window_start + 1..(window_end + 1).min(read_end);
+            acc.extend(self.score_positions(&read, &data_pos, &context, positions)?);
+            window_start = window_end;
+        }
+        Ok(self.finish_read(read, acc))
+    }
+
+    fn finish_read(&self, read: Eventalign, scores: Vec<Score>) -> ScoredRead {
+        let quality = ReadQuality::from_scores(&scores, self.scoring_method);
+        ScoredRead::from_read_with_scores(read, scores).with_quality(quality)
+    }
+
+    /// Loop over each base pair position, and if it is the modified base of a
+    /// match to one of the motifs attempt to score it. Motifs are matched on
+    /// the read's strand, so minus strand hits are scored at the genomic
+    /// coordinate of their modified base. Each position is scored once, for
+    /// the first motif given whose modified base is there.
+    fn score_positions(
+        &self,
+        read: &Eventalign,
+        data_pos: &FnvHashMap<u64, &Signal>,
+        context: &context::Context,
+        positions: Range<u64>,
+    ) -> Result<Vec<Score>> {
+        let mut acc = Vec::new();
+        for pos in positions {
+            // Get kmer and check if the position is the modified base of any motifs
+            let motif = self.motifs.iter().find(|m| self.motif_at(context, pos, m));
+            let matches_motif = motif.is_some();
+            let pos_kmer = context
+                .kmer_at(pos)
+                .filter(|_| matches_motif || self.output_all_positions);
+
+            if let Some(kmer) = pos_kmer {
                 let kmer = std::str::from_utf8(kmer).unwrap().to_string();
                 log::debug!("Position {pos} kmer: {kmer}");
 
-                let signal_score = self.calc_signal_score(pos, &data_pos);
-                let skipping_score = self.calc_skipping_score(pos, &data_pos, &context, motif)?;
-                let final_score = signal_score.map_or(skipping_score, |x| x.max(skipping_score));
-                let score = Score::new(
+                if self.output_missing_kmers && self.is_missing_model(&kmer, read.strand()) {
+                    log::debug!("No model for kmer {kmer}");
+                    acc.push(
+                        Score::missing_model(pos, kmer)
+                            .with_matches_motif(matches_motif)
+                            .with_motif(motif),
+                    );
+                    continue;
+                }
+
+                let scored_signal = self.calc_signal_score(pos, data_pos, read.strand());
+                let signal_score = scored_signal.map(|s| s.score);
+                let (skipping_score, final_score) = if self.use_skipping_score {
+                    let skipping_score = self.calc_skipping_score(pos, data_pos, context)?;
+                    let skipping_score = self.scoring_method.convert_ratio(skipping_score);
+                    let Some(final_score) = self.score_policy.combine(signal_score, skipping_score)
+                    else {
+                        log::debug!("No score for position {pos} with {}", self.score_policy);
+                        continue;
+                    };
+                    (skipping_score, final_score)
+                } else {
+                    (f64::NAN, signal_score.unwrap_or(f64::NAN))
+                };
+                let mut score = Score::new(
                     pos,
                     kmer,
                     signal_score.is_none(),
                     signal_score,
                     skipping_score,
                     final_score,
-                );
+                )
+                .with_matches_motif(matches_motif)
+                .with_motif(motif);
+                if let Some(s) = scored_signal {
+                    score = score.with_likelihoods(s.pos_ln_f, s.neg_ln_f);
+                }
                 log::debug!("final score: {score:.3?}");
                 acc.push(score)
             }
         }
-        let scored_read = ScoredRead::from_read_with_scores(read, acc);
-        Ok(scored_read)
+        Ok(acc)
+    }
+
+    /// Whether the motif is at the position on the read's strand, or on
+    /// either strand of plus strand reads if both_strand_motifs is set
+    fn motif_at(&self, context: &context::Context, pos: u64, motif: &Motif) -> bool {
+        context.motif_at(pos, motif)
+            || (self.both_strand_motifs
+                && !context.is_minus_strand()
+                && context.motif_on_strand_at(pos, motif, true))
+    }
+
+    /// Neither control has a model for the kmer
+    fn is_missing_model(&self, kmer: &str, strand: Strand) -> bool {
+        self.pos_ctrl.gmm(kmer, strand).is_none() && self.neg_ctrl.gmm(kmer, strand).is_none()
     }
 
     fn calc_skipping_score(
@@ -164,53 +1029,30 @@ impl ScoreOptions {
         pos: u64,
         data_pos: &FnvHashMap<u64, &Signal>,
         context: &context::Context,
-        motif: &Motif,
     ) -> Result<f64> {
-        let sur_kmers = context.surrounding(pos, motif);
-        let sur_has_data = surround_has_data(pos, data_pos);
-        let skipping_scores = sur_kmers
-            .into_iter()
-            .zip(sur_has_data.into_iter())
-            .flat_map(|(kmer, has_data)| {
-                let kmer = std::str::from_utf8(kmer).expect("Invalid kmer");
-                let pos_presence = self.pos_ctrl.skips().get(kmer);
-                let neg_presence = self.neg_ctrl.skips().get(kmer);
-                match (pos_presence, neg_presence) {
-                    (Some(&pos_presence), Some(&neg_presence)) => {
-                        if has_data {
-                            Some(pos_presence / (pos_presence + neg_presence))
-                        } else {
-                            let pos_absent = 1. - pos_presence;
-                            let neg_absent = 1. - neg_presence;
-                            Some(pos_absent / (pos_absent + neg_absent))
-                        }
-                    }
-                    _ => None,
-                }
-            })
-            .collect::<Vec<_>>();
-
-        // TODO: Switch to median when it can be correctly handled
-        let skip_score = skipping_scores.mean();
-        if skip_score.is_nan() {
-            Err(eyre::eyre!("No data for calculating median"))
-        } else {
-            Ok(skip_score)
-        }
+        skipping_score(pos, data_pos, context, &self.pos_ctrl, &self.neg_ctrl)
     }
 
     /// For a given position, get the values for the position and surrounding
     /// kmers. Filter for the best kmer model, if there is confidence in the
-    /// model, otherwise return None.
-    fn calc_signal_score(&self, pos: u64, data_pos: &FnvHashMap<u64, &Signal>) -> Option<f64> {
+    /// model, otherwise return None. Stranded models are queried with the
+    /// read's strand.
+    fn calc_signal_score(
+        &self,
+        pos: u64,
+        data_pos: &FnvHashMap<u64, &Signal>,
+        strand: Strand,
+    ) -> Option<ScoredSignal> {
         log::debug!("Calculating signal score");
-        let sur_signals = surrounding_signal(pos, data_pos);
+        let sur_signals = surrounding_signal(pos, data_pos, self.kmer_size)
+            .and_then(|signals| drop_low_rank(signals, &self.rank, self.min_rank));
         log::debug!("surrounding signals: {sur_signals:.3?}");
         let best_signal = best_surrounding_signal(
             sur_signals,
             &self.rank,
-            self.pos_ctrl.gmms(),
-            self.neg_ctrl.gmms(),
+            &self.pos_ctrl,
+            &self.neg_ctrl,
+            strand,
             self.p_value_threshold,
         );
 
@@ -219,13 +1061,14 @@ impl ScoreOptions {
         best_signal.and_then(|sig| {
             let mean = sig.signal_mean;
             let kmer = &sig.kmer;
-            let pos_mix = self.pos_ctrl.gmms().get(kmer);
-            let neg_mix = self.neg_ctrl.gmms().get(kmer);
+            let pos_mix = self.pos_ctrl.gmm(kmer, strand);
+            let neg_mix = self.neg_ctrl.gmm(kmer, strand);
             match (pos_mix, neg_mix) {
                 (Some(pos_gmm), Some(neg_gmm)) => {
                     let neg_mix = neg_gmm.mixture();
                     let pos_mix = pos_gmm.mixture();
-                    score_signal(mean, &pos_mix, &neg_mix, self.cutoff)
+                    let cutoff = kmer_cutoff(self.per_kmer_cutoffs.as_ref(), kmer, self.cutoff);
+                    score_signal(mean, &pos_mix, &neg_mix, cutoff, self.scoring_method)
                 }
                 _ => {
                     log::debug!("Missing kmer, unable to score signal.");
@@ -236,18 +1079,157 @@ impl ScoreOptions {
     }
 }
 
-fn surrounding_pos(pos: u64) -> RangeInclusive<u64> {
-    let start = if pos < 5 { 0 } else { pos - 5 };
-    start..=pos
+/// Load per kmer cutoffs from a two column tsv of kmer and cutoff, without a
+/// header.
+pub fn load_kmer_cutoffs<P: AsRef<Path>>(path: P) -> Result<FnvHashMap<String, f64>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(b'\t')
+        .has_headers(false)
+        .from_path(path)?;
+    let mut cutoffs = FnvHashMap::default();
+    for rec in reader.records() {
+        let rec = rec?;
+        if rec.len() != 2 {
+            return Err(eyre::eyre!(
+                "Expected kmer and cutoff columns, found {} columns",
+                rec.len()
+            ));
+        }
+        let kmer = rec[0].to_string();
+        let cutoff = rec[1]
+            .parse::<f64>()
+            .map_err(|e| eyre::eyre!("Invalid cutoff {} for kmer {kmer}: {e}", &rec[1]))?;
+        cutoffs.insert(kmer, cutoff);
+    }
+    Ok(cutoffs)
+}
+
+/// Read names given either directly or as files with one name per line,
+/// values that are paths to existing files are read as files.
+pub fn load_read_names(values: Vec<String>) -> Result<Vec<String>> {
+    let mut read_names = Vec::new();
+    for value in values {
+        if Path::new(&value).is_file() {
+            let contents = std::fs::read_to_string(&value)?;
+            read_names.extend(
+                contents
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty())
+                    .map(String::from),
+            );
+        } else {
+            read_names.push(value);
+        }
+    }
+    Ok(read_names)
+}
+
+/// Write a tsv with the [ReadQuality] of each read in an Arrow file from
+/// cawlr score, computed from the scores for reads without one. Returns the
+/// number of reads written.
+pub fn write_read_report<R, W>(mut reader: R, mut writer: W) -> Result<usize>
+where
+    R: Read + Seek,
+    W: Write,
+{
+    let metadata = read_file_metadata(&mut reader)?;
+    reader.rewind()?;
+    let scoring_method = ScoringMethod::from_schema(&metadata.schema)?;
+    writeln!(
+        writer,
+        "read_name\tchrom\tstart\tend\tstrand\tn_motif_positions\tfrac_signal\tmean_confidence"
+    )?;
+    let mut n_reads = 0;
+    load_apply(reader, |reads: Vec<ScoredRead>| {
+        for read in reads.iter() {
+            let quality = read.quality_or_compute(scoring_method);
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{:.4}\t{:.4}",
+                read.name(),
+                read.chrom(),
+                read.start_0b(),
+                read.end_1b_excl(),
+                read.strand(),
+                quality.n_motif_positions,
+                quality.frac_signal,
+                quality.mean_confidence
+            )?;
+        }
+        n_reads += reads.len();
+        Ok(())
+    })?;
+    Ok(n_reads)
+}
+
+/// Cutoff for the kmer if it has one, otherwise the global cutoff
+fn kmer_cutoff(per_kmer_cutoffs: Option<&FnvHashMap<String, f64>>, kmer: &str, cutoff: f64) -> f64 {
+    per_kmer_cutoffs
+        .and_then(|cutoffs| cutoffs.get(kmer))
+        .copied()
+        .unwrap_or(cutoff)
+}
+
+fn surrounding_pos(pos: u64, kmer_size: usize) -> RangeInclusive<u64> {
+    let slop = kmer_size as u64 - 1;
+    pos.saturating_sub(slop)..=pos
+}
+
+/// Probability ratio of the position being modified from whether each kmer
+/// around it has signal data, using how often each kmer is skipped in the
+/// controls. Kmers missing from either control's skips are left out.
+pub(crate) fn skipping_score(
+    pos: u64,
+    data_pos: &FnvHashMap<u64, &Signal>,
+    context: &context::Context,
+    pos_ctrl: &Model,
+    neg_ctrl: &Model,
+) -> Result<f64> {
+    let sur_kmers = context.surrounding(pos);
+    let sur_has_data = surround_has_data(pos, data_pos, pos_ctrl.kmer_size());
+    let skipping_scores = sur_kmers
+        .into_iter()
+        .zip(sur_has_data.into_iter())
+        .flat_map(|(kmer, has_data)| {
+            let kmer = std::str::from_utf8(kmer).expect("Invalid kmer");
+            let pos_presence = pos_ctrl.skips().get(kmer);
+            let neg_presence = neg_ctrl.skips().get(kmer);
+            match (pos_presence, neg_presence) {
+                (Some(&pos_presence), Some(&neg_presence)) => {
+                    if has_data {
+                        Some(pos_presence / (pos_presence + neg_presence))
+                    } else {
+                        let pos_absent = 1. - pos_presence;
+                        let neg_absent = 1. - neg_presence;
+                        Some(pos_absent / (pos_absent + neg_absent))
+                    }
+                }
+                _ => None,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // TODO: Switch to median when it can be correctly handled
+    let skip_score = skipping_scores.mean();
+    if skip_score.is_nan() {
+        Err(eyre::eyre!("No data for calculating median"))
+    } else {
+        Ok(skip_score)
+    }
 }
 
 /// Return list of kmer positions around a given position pos contain signal
 /// current data
-fn surround_has_data<S>(pos: u64, signal_map: &HashMap<u64, &Signal, S>) -> Vec<bool>
+fn surround_has_data<S>(
+    pos: u64,
+    signal_map: &HashMap<u64, &Signal, S>,
+    kmer_size: usize,
+) -> Vec<bool>
 where
     S: BuildHasher,
 {
-    let positions = surrounding_pos(pos);
+    let positions = surrounding_pos(pos, kmer_size);
     positions.map(|p| signal_map.get(&p).is_some()).collect()
 }
 
@@ -256,11 +1238,12 @@ where
 fn surrounding_signal<'a, S>(
     pos: u64,
     signal_map: &HashMap<u64, &'a Signal, S>,
+    kmer_size: usize,
 ) -> Option<Vec<&'a Signal>>
 where
     S: BuildHasher,
 {
-    let positions = surrounding_pos(pos);
+    let positions = surrounding_pos(pos, kmer_size);
     let acc = positions
         .flat_map(|p| signal_map.get(&p))
         .cloned()
@@ -272,6 +1255,30 @@ where
     }
 }
 
+/// Remove signals from kmers ranked below min_rank, returning None if there
+/// are none left
+fn drop_low_rank<'a, S>(
+    signals: Vec<&'a Signal>,
+    ranks: &HashMap<String, f64, S>,
+    min_rank: Option<f64>,
+) -> Option<Vec<&'a Signal>>
+where
+    S: BuildHasher,
+{
+    let Some(min_rank) = min_rank else {
+        return Some(signals);
+    };
+    let signals = signals
+        .into_iter()
+        .filter(|s| ranks.get(&s.kmer).map_or(true, |&rank| rank >= min_rank))
+        .collect::<Vec<_>>();
+    if signals.is_empty() {
+        None
+    } else {
+        Some(signals)
+    }
+}
+
 /// Return mu and sigma from a Gaussian distribution.
 fn extract_components(gauss: &Gaussian) -> (f64, f64) {
     let mu = gauss.mu();
@@ -300,8 +1307,9 @@ fn zscore_to_tt_pvalue(zscore: f64) -> f64 {
 fn best_surrounding_signal<'a, S>(
     surrounding: Option<Vec<&'a Signal>>,
     ranks: &HashMap<String, f64, S>,
-    pos_gmms: &ModelDB,
-    neg_gmms: &ModelDB,
+    pos_ctrl: &Model,
+    neg_ctrl: &Model,
+    strand: Strand,
     p_value_threshold: f64,
 ) -> Option<&'a Signal>
 where
@@ -315,16 +1323,18 @@ where
             .filter(|&s| {
                 log::debug!("Signal: {s:.3?}");
                 let kmer = &s.kmer;
-                if !neg_gmms.contains_key(kmer) || !pos_gmms.contains_key(kmer) {
-                    false
-                } else {
-                    let neg_mix = neg_gmms[kmer].mixture();
-                    let pos_mix = pos_gmms[kmer].mixture();
+                if let (Some(pos_gmm), Some(neg_gmm)) =
+                    (pos_ctrl.gmm(kmer, strand), neg_ctrl.gmm(kmer, strand))
+                {
+                    let neg_mix = neg_gmm.mixture();
+                    let pos_mix = pos_gmm.mixture();
                     let neg_model = choose_model(&neg_mix);
                     let pos_model = choose_pos_model(neg_model, &pos_mix);
                     let pvalue = gauss_to_pvalue(pos_model, neg_model);
                     log::debug!("p-value: {pvalue:.3?}");
                     pvalue < p_value_threshold
+                } else {
+                    false
                 }
             })
             // Of the ones the best, choose the one with the best ranking
@@ -367,11 +1377,12 @@ pub(crate) fn choose_model(neg_mix: &Mixture<Gaussian>) -> &Gaussian {
     true_neg
 }
 
-/// Given a Gaussian, and a mixture model containing two gaussians, find the
-/// Gaussian in the mixture model that is most disimilar based on KL divergence
-/// and return it.
+/// Given a Gaussian, and a mixture model containing one or two gaussians, find
+/// the Gaussian in the mixture model that is most disimilar based on KL
+/// divergence and return it. If the mixture only has a single component, that
+/// component is returned.
 /// Should not fail because train should always produce a Mixture model
-/// containing two gaussians
+/// containing at least one gaussian
 pub(crate) fn choose_pos_model<'a>(
     neg_comp: &Gaussian,
     pos_mix: &'a Mixture<Gaussian>,
@@ -391,35 +1402,46 @@ pub(crate) fn choose_pos_model<'a>(
         .unwrap()
 }
 
+/// Score of a signal along with the log likelihoods of the signal under each
+/// control model it was computed from
+#[derive(Debug, Clone, Copy)]
+struct ScoredSignal {
+    score: f64,
+    pos_ln_f: f64,
+    neg_ln_f: f64,
+}
+
 /// Score given signal based on GMM from a positive and negative control.
 /// Scoring function based on:
 ///  Wang, Y. et al. Single-molecule long-read sequencing reveals the chromatin
 /// basis of gene expression. Genome Res. 29, 1329–1342 (2019).
-/// We don't take the ln(score) for now, only after the probability from the Kde
-/// later in cawlr sma
+/// With the probability ratio we don't take the ln(score) for now, only after
+/// the probability from the Kde later in cawlr sma
 fn score_signal(
     signal: f64,
     pos_mix: &Mixture<Gaussian>,
     neg_mix: &Mixture<Gaussian>,
     cutoff: f64,
-) -> Option<f64> {
+    method: ScoringMethod,
+) -> Option<ScoredSignal> {
     log::debug!("Scoring signal: {signal}");
     let neg_mix = choose_model(neg_mix);
     let pos_mix = choose_pos_model(neg_mix, pos_mix);
-    let pos_proba = pos_mix.f(&signal);
-    let neg_proba = neg_mix.f(&signal);
-    let score = pos_proba / (pos_proba + neg_proba);
-    log::debug!("Score: {score:.3}");
-
     let pos_log_proba = pos_mix.ln_f(&signal);
     let neg_log_proba = neg_mix.ln_f(&signal);
+    let score = method.score(pos_log_proba, neg_log_proba);
+    log::debug!("Score: {score:.3}");
 
     log::debug!("+ Gaussian log proba: {pos_log_proba}");
     log::debug!("- Gaussian log proba: {neg_log_proba}");
 
     if (pos_log_proba > -cutoff) || (neg_log_proba > -cutoff) {
         log::debug!("Valid score");
-        Some(score)
+        Some(ScoredSignal {
+            score,
+            pos_ln_f: pos_log_proba,
+            neg_ln_f: neg_log_proba,
+        })
     } else {
         log::debug!("Below cutoff, not scoring.");
         None
@@ -428,11 +1450,32 @@ fn score_signal(
 
 #[cfg(test)]
 mod test {
+    use std::path::PathBuf;
+
     use assert_fs::TempDir;
     use float_eq::assert_float_eq;
 
     use super::*;
-    use crate::{arrow::arrow_utils::load_iter, collapse::CollapseOptions, motif::Motif};
+    use crate::{
+        arrow::arrow_utils::{load_apply, load_iter},
+        collapse::CollapseOptions,
+        motif::Motif,
+        rank::{save_ranks, RankMetric},
+        test_utils::collapse_single_read,
+        train::ModelParams,
+    };
+
+    #[test]
+    fn test_choose_pos_model_single() {
+        let neg = Gaussian::new_unchecked(80.0, 2.0);
+        let pos = Gaussian::new_unchecked(90.0, 3.0);
+        let pos_mix = Mixture::new_unchecked(vec![1.0], vec![pos.clone()]);
+        assert_eq!(choose_pos_model(&neg, &pos_mix), &pos);
+
+        let params = ModelParams::from(&pos_mix);
+        assert_eq!(params.mixture().k(), 1);
+        assert_eq!(choose_pos_model(&neg, &params.mixture()), &pos);
+    }
 
     #[test]
     fn test_score_signal() {
@@ -456,13 +1499,189 @@ mod test {
         )
         .unwrap();
 
-        let result = score_signal(signal, &pos_mix, &neg_mix, cutoff);
+        let result = score_signal(signal, &pos_mix, &neg_mix, cutoff, ScoringMethod::default());
         assert!(result.is_some());
 
-        let result = score_signal(1000.0, &pos_mix, &neg_mix, cutoff);
+        let result = score_signal(1000.0, &pos_mix, &neg_mix, cutoff, ScoringMethod::default());
         assert!(result.is_none());
     }
 
+    #[test]
+    fn test_stored_likelihoods() -> Result<()> {
+        let neg_mix = Mixture::new_unchecked(vec![1.0], vec![Gaussian::new_unchecked(100.0, 2.0)]);
+        let pos_mix = Mixture::new_unchecked(vec![1.0], vec![Gaussian::new_unchecked(90.0, 2.0)]);
+        let scores = [88.0, 93.0, 95.0, 97.5, 101.0]
+            .into_iter()
+            .enumerate()
+            .map(|(i, signal)| {
+                let s = score_signal(signal, &pos_mix, &neg_mix, 10.0, ScoringMethod::default())
+                    .unwrap();
+                Score::new(
+                    i as u64,
+                    "AAAAAA".to_string(),
+                    false,
+                    Some(s.score),
+                    0.0,
+                    s.score,
+                )
+                .with_likelihoods(s.pos_ln_f, s.neg_ln_f)
+            })
+            .collect();
+        let metadata = Metadata::new(
+            "read".to_string(),
+            "chrI".to_string(),
+            0,
+            5,
+            Strand::plus(),
+            String::new(),
+        );
+        let mut output = Vec::new();
+        let mut writer = wrap_writer(&mut output, &ScoredRead::schema())?;
+        save(&mut writer, &[ScoredRead::new(metadata, scores)])?;
+        writer.finish()?;
+
+        let mut reads = Vec::new();
+        load_apply(std::io::Cursor::new(output), |xs: Vec<ScoredRead>| {
+            reads.extend(xs);
+            Ok(())
+        })?;
+        for score in reads[0].scores() {
+            let (pos_ln_f, neg_ln_f) = (score.pos_ln_f().unwrap(), score.neg_ln_f().unwrap());
+            let ratio = ScoringMethod::ProbabilityRatio.score(pos_ln_f, neg_ln_f);
+            assert_float_eq!(ratio, score.signal_score.unwrap(), abs <= 1e-12);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_log_odds_symmetric() {
+        let cutoff = 10.0;
+        let neg_mix = Mixture::new_unchecked(vec![1.0], vec![Gaussian::new_unchecked(100.0, 2.0)]);
+        let pos_mix = Mixture::new_unchecked(vec![1.0], vec![Gaussian::new_unchecked(90.0, 2.0)]);
+        let log_odds = |signal| {
+            score_signal(signal, &pos_mix, &neg_mix, cutoff, ScoringMethod::LogOdds)
+                .unwrap()
+                .score
+        };
+
+        // Halfway between the models both are equally likely
+        assert_float_eq!(log_odds(95.0), 0.0, abs <= 1e-9);
+        for d in [0.5, 1.0, 2.5, 4.0] {
+            let pos_side = log_odds(95.0 - d);
+            let neg_side = log_odds(95.0 + d);
+            assert!(pos_side > 0.0);
+            assert_float_eq!(pos_side, -neg_side, abs <= 1e-9);
+        }
+
+        let ratio = score_signal(
+            95.0,
+            &pos_mix,
+            &neg_mix,
+            cutoff,
+            ScoringMethod::ProbabilityRatio,
+        );
+        assert_float_eq!(ratio.unwrap().score, 0.5, abs <= 1e-9);
+        assert_float_eq!(ScoringMethod::LogOdds.convert_ratio(0.5), 0.0, abs <= 1e-9);
+
+        // Certain skipping scores stay finite
+        let never = ScoringMethod::LogOdds.convert_ratio(0.0);
+        let always = ScoringMethod::LogOdds.convert_ratio(1.0);
+        assert!(never.is_finite() && never < 0.0);
+        assert!(always.is_finite() && always > 0.0);
+        assert_float_eq!(never, -always, abs <= 1e-6);
+        assert_eq!(ScoringMethod::ProbabilityRatio.convert_ratio(0.0), 0.0);
+    }
+
+    #[test]
+    fn test_scoring_method_schema() -> Result<()> {
+        for method in [ScoringMethod::ProbabilityRatio, ScoringMethod::LogOdds] {
+            assert_eq!(method.to_string().parse::<ScoringMethod>(), Ok(method));
+            assert_eq!(ScoringMethod::from_schema(&method.schema())?, method);
+        }
+        assert_eq!(
+            ScoringMethod::from_schema(&ScoredRead::schema())?,
+            ScoringMethod::ProbabilityRatio
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_per_kmer_cutoffs() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let cutoffs_path = temp_dir.path().join("cutoffs.tsv");
+        std::fs::write(&cutoffs_path, "AAAAAA\t2.0\nCCCCCC\t10.0\n")?;
+        let cutoffs = load_kmer_cutoffs(&cutoffs_path)?;
+        assert_eq!(cutoffs.len(), 2);
+        assert_float_eq!(kmer_cutoff(Some(&cutoffs), "AAAAAA", 5.0), 2.0, abs <= 1e-9);
+        assert_float_eq!(kmer_cutoff(Some(&cutoffs), "GGGGGG", 5.0), 5.0, abs <= 1e-9);
+        assert_float_eq!(kmer_cutoff(None, "AAAAAA", 5.0), 5.0, abs <= 1e-9);
+
+        // Same models and signal for both kmers, only the cutoff differs
+        let neg_mix = Mixture::new_unchecked(
+            vec![0.9, 0.1],
+            vec![
+                Gaussian::new_unchecked(100.0, 2.0),
+                Gaussian::new_unchecked(110.0, 2.0),
+            ],
+        );
+        let pos_mix = Mixture::new_unchecked(
+            vec![0.9, 0.1],
+            vec![
+                Gaussian::new_unchecked(90.0, 2.0),
+                Gaussian::new_unchecked(100.0, 2.0),
+            ],
+        );
+        let signals = (0..100).map(|i| 70.0 + i as f64 * 0.5).collect::<Vec<_>>();
+        let n_unscored = |kmer: &str| {
+            let cutoff = kmer_cutoff(Some(&cutoffs), kmer, 5.0);
+            signals
+                .iter()
+                .filter(|&&signal| {
+                    score_signal(signal, &pos_mix, &neg_mix, cutoff, ScoringMethod::default())
+                        .is_none()
+                })
+                .count()
+        };
+        let tight = n_unscored("AAAAAA");
+        let loose = n_unscored("CCCCCC");
+        assert!(tight > loose, "{tight} <= {loose}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_kmer_cutoffs_invalid() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let cutoffs_path = temp_dir.path().join("cutoffs.tsv");
+        std::fs::write(&cutoffs_path, "AAAAAA\tten\n")?;
+        assert!(load_kmer_cutoffs(&cutoffs_path).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_drop_low_rank() {
+        let signal = |pos, kmer: &str| Signal::new(pos, kmer.to_string(), 80.0, 0.01, Vec::new());
+        let signals = [
+            signal(1, "AAAAAA"),
+            signal(2, "CCCCCC"),
+            signal(3, "GGGGGG"),
+        ];
+        let signals = signals.iter().collect::<Vec<_>>();
+        let mut ranks = FnvHashMap::default();
+        ranks.insert("AAAAAA".to_string(), 0.01);
+        ranks.insert("CCCCCC".to_string(), 0.5);
+
+        let kept = drop_low_rank(signals.clone(), &ranks, Some(0.05)).unwrap();
+        let kept = kept.iter().map(|s| s.kmer.as_str()).collect::<Vec<_>>();
+        assert_eq!(kept, ["CCCCCC", "GGGGGG"]);
+        assert_eq!(
+            drop_low_rank(signals.clone(), &ranks, None).unwrap().len(),
+            3
+        );
+
+        // Only indistinguishable kmers, fall back to the skipping score
+        assert!(drop_low_rank(signals[..1].to_vec(), &ranks, Some(0.05)).is_none());
+    }
+
     #[test]
     fn test_zscore_to_tt_pvalue() {
         assert_float_eq!(zscore_to_tt_pvalue(2.9), 0.003_732, abs <= 0.000_001);
@@ -484,28 +1703,27 @@ mod test {
         let input = File::open(filepath)?;
         let bam_file = "extra/single_read.bam";
         let output = temp_dir.path().join("test");
-        let mut collapse = CollapseOptions::try_new(bam_file, &output)?;
-        collapse.run(input)?;
+        let mut collapse = CollapseOptions::try_new(bam_file, Some(&output))?;
+        collapse.progress(false).run(input)?;
 
         let output = File::open(output)?;
         let reads = load_iter(output).next().unwrap().unwrap();
         let read = &reads[0];
 
-        let genome_file = "extra/sacCer3.fa";
-        let mut genome = IndexedReader::from_file(&genome_file)
-            .map_err(|_| eyre::eyre!("Failed to read genome file."))?;
+        let mut genome = sac_cer3()?;
 
         let chrom_lens = chrom_lens(&genome);
 
-        let context = context::Context::from_read(&mut genome, &chrom_lens, read)?;
+        let context = context::Context::from_read(&mut genome, &chrom_lens, read, 6)?;
         assert_eq!(context.start_slop(), 5);
         // assert_eq!(context.end_slop(), 5);
 
         let m = Motif::new("AT", 2);
         assert_eq!(m.position_0b(), 1);
+        assert!(context.motif_at(182523, &m));
         assert_eq!(
             context
-                .surrounding(182522, &m)
+                .surrounding(182523)
                 .into_iter()
                 .flat_map(std::str::from_utf8)
                 .collect::<Vec<_>>(),
@@ -514,4 +1732,681 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn test_score_policy() {
+        let both = (Some(0.8), 0.4);
+        let skip_only = (None, 0.4);
+
+        let policy = ScorePolicy::Max;
+        assert_eq!(policy.combine(both.0, both.1), Some(0.8));
+        assert_eq!(policy.combine(Some(0.2), 0.4), Some(0.4));
+        assert_eq!(policy.combine(skip_only.0, skip_only.1), Some(0.4));
+
+        let policy = ScorePolicy::SignalOnly;
+        assert_eq!(policy.combine(both.0, both.1), Some(0.8));
+        assert_eq!(policy.combine(skip_only.0, skip_only.1), None);
+
+        let policy = ScorePolicy::SkipOnly;
+        assert_eq!(policy.combine(both.0, both.1), Some(0.4));
+        assert_eq!(policy.combine(skip_only.0, skip_only.1), Some(0.4));
+
+        let policy = ScorePolicy::WeightedMean { alpha: 0.25 };
+        assert_float_eq!(policy.combine(both.0, both.1).unwrap(), 0.5, abs <= 1e-12);
+        assert_eq!(policy.combine(skip_only.0, skip_only.1), Some(0.4));
+
+        for policy in [
+            ScorePolicy::Max,
+            ScorePolicy::SignalOnly,
+            ScorePolicy::SkipOnly,
+            ScorePolicy::WeightedMean { alpha: 0.25 },
+        ] {
+            assert_eq!(policy.to_string().parse::<ScorePolicy>(), Ok(policy));
+        }
+        assert!("weighted-mean:1.5".parse::<ScorePolicy>().is_err());
+        assert!("weighted-mean".parse::<ScorePolicy>().is_err());
+    }
+
+    #[test]
+    fn test_output_missing_kmers() -> Result<()> {
+        let mut scoring = ScoreOptions::from_parts(
+            Model::default(),
+            Model::default(),
+            sac_cer3()?,
+            FnvHashMap::default(),
+        )?;
+        let metadata = Metadata::new(
+            "read".to_string(),
+            "chrI".to_string(),
+            100,
+            4,
+            Strand::plus(),
+            String::new(),
+        );
+        let read = Eventalign::new(metadata, Vec::new());
+        let context = context::Context::new(b"ACGTACGTACGT".to_vec(), 100, 0, 0, 6);
+
+        // Without any models there is nothing to score from
+        assert!(scoring.score_eventalign(read.clone(), &context).is_err());
+
+        scoring.output_missing_kmers(true);
+        let scored = scoring.score_eventalign(read, &context)?;
+        assert_eq!(scored.scores().len(), 3);
+        for score in scored.scores() {
+            assert!(score.missing_model);
+            assert!(score.score.is_nan());
+            assert!(score.signal_score.is_none());
+        }
+        assert_eq!(scored.scores()[0].kmer, "CGTACG");
+        Ok(())
+    }
+
+    /// Genome the test reads are aligned to
+    fn sac_cer3() -> Result<IndexedReader<File>> {
+        IndexedReader::from_file(&"extra/sacCer3.fa")
+            .map_err(|_| eyre::eyre!("Failed to read genome file."))
+    }
+
+    /// Score the reads in the input with the control models, returning every
+    /// scored read
+    fn score_with_models<R: Read + Seek>(
+        input: R,
+        pos_ctrl: Model,
+        neg_ctrl: Model,
+        configure: impl FnOnce(&mut ScoreOptions),
+    ) -> Result<Vec<ScoredRead>> {
+        let mut output = Vec::new();
+        let mut scoring =
+            ScoreOptions::from_parts(pos_ctrl, neg_ctrl, sac_cer3()?, FnvHashMap::default())?;
+        configure(&mut scoring);
+        scoring.output = Some(Box::new(&mut output));
+        scoring.run(input)?;
+        let mut acc = Vec::new();
+        load_apply(
+            std::io::Cursor::new(output),
+            |mut reads: Vec<ScoredRead>| {
+                acc.append(&mut reads);
+                Ok(())
+            },
+        )?;
+        Ok(acc)
+    }
+
+    /// Score the collapsed reads without any models, so every read that is
+    /// scored is in the output with only missing positions
+    fn score_without_models(
+        collapsed: &Path,
+        configure: impl FnOnce(&mut ScoreOptions),
+    ) -> Result<Vec<ScoredRead>> {
+        score_with_models(
+            File::open(collapsed)?,
+            Model::default(),
+            Model::default(),
+            |scoring| {
+                scoring.output_missing_kmers(true);
+                configure(scoring);
+            },
+        )
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_try_new_output_path() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let collapsed = collapse_single_read(&temp_dir)?;
+        let model_path = temp_dir.path().join("model.pickle");
+        Model::default().save_as(&model_path)?;
+        let ranks_path = temp_dir.path().join("ranks.pickle");
+        save_ranks(
+            &FnvHashMap::default(),
+            RankMetric::Kl,
+            &mut File::create(&ranks_path)?,
+        )?;
+        let output = temp_dir.path().join("scores.arrow");
+        let genome = PathBuf::from("extra/sacCer3.fa");
+
+        let mut scoring =
+            ScoreOptions::try_new(&model_path, &model_path, &genome, &ranks_path, &output)?;
+        scoring.output_missing_kmers(true);
+        scoring.run(File::open(&collapsed)?)?;
+        let mut reads = Vec::new();
+        load_apply(File::open(&output)?, |xs: Vec<ScoredRead>| {
+            reads.extend(xs);
+            Ok(())
+        })?;
+        assert_eq!(reads.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_no_skipping_score() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let collapsed = collapse_single_read(&temp_dir)?;
+
+        // Models with skips for every kmer but no signal models, so no
+        // position has a signal score
+        let skips: FnvHashMap<String, f64> = crate::npsmlr::train::all_kmers(6)
+            .into_iter()
+            .map(|kmer| (kmer, 0.5))
+            .collect();
+        let pos_ctrl = Model::new(Default::default(), skips.clone());
+        let neg_ctrl = Model::new(
+            Default::default(),
+            skips.into_iter().map(|(kmer, _)| (kmer, 0.25)).collect(),
+        );
+        let score = |use_skipping_score: bool| {
+            score_with_models(
+                File::open(&collapsed)?,
+                pos_ctrl.clone(),
+                neg_ctrl.clone(),
+                |scoring| {
+                    scoring.use_skipping_score(use_skipping_score);
+                },
+            )
+        };
+
+        let with_skips = score(true)?;
+        assert_eq!(with_skips.len(), 1);
+        let scores = with_skips[0].scores();
+        assert!(!scores.is_empty());
+        assert!(scores
+            .iter()
+            .all(|s| s.signal_score.is_none() && s.skip_score.is_finite() && s.score.is_finite()));
+
+        let without_skips = score(false)?;
+        assert_eq!(without_skips.len(), 1);
+        let scores = without_skips[0].scores();
+        assert_eq!(scores.len(), with_skips[0].scores().len());
+        assert!(scores
+            .iter()
+            .all(|s| s.signal_score.is_none() && s.skip_score.is_nan() && s.score.is_nan()));
+        Ok(())
+    }
+
+    /// Reads written as an Arrow file like the output of cawlr collapse
+    fn eventalign_input(reads: &[Eventalign]) -> Result<Vec<u8>> {
+        let mut input = Vec::new();
+        let mut writer = wrap_writer(&mut input, &Eventalign::schema())?;
+        save(&mut writer, reads)?;
+        writer.finish()?;
+        Ok(input)
+    }
+
+    #[test]
+    fn test_kmer_size_mismatch() -> Result<()> {
+        let metadata = Metadata::new(
+            "read".to_string(),
+            "chrI".to_string(),
+            100,
+            4,
+            Strand::plus(),
+            String::new(),
+        );
+        let signal = Signal::new(101, "ACGTA".to_string(), 80.0, 0.01, Vec::new());
+        let input = eventalign_input(&[Eventalign::new(metadata, vec![signal])])?;
+
+        let res = score_with_models(
+            std::io::Cursor::new(&input),
+            Model::default(),
+            Model::default(),
+            |scoring| {
+                assert_eq!(scoring.kmer_size(), 6);
+                scoring.output_missing_kmers(true);
+            },
+        );
+        let err = res.unwrap_err().to_string();
+        assert!(err.contains("kmers of length 5"), "{err}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_contig() -> Result<()> {
+        let metadata = Metadata::new(
+            "read".to_string(),
+            "chrDoesNotExist".to_string(),
+            100,
+            4,
+            Strand::plus(),
+            String::new(),
+        );
+        let input = eventalign_input(&[Eventalign::new(metadata, Vec::new())])?;
+
+        let score = |strict: bool| {
+            score_with_models(
+                std::io::Cursor::new(&input),
+                Model::default(),
+                Model::default(),
+                |scoring| {
+                    scoring.output_missing_kmers(true).strict(strict);
+                },
+            )
+        };
+
+        assert!(score(false)?.is_empty());
+        assert!(score(true).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_failed_reads() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let collapsed = collapse_single_read(&temp_dir)?;
+
+        // Without any models or missing positions the skipping score fails
+        for window_size in [DEFAULT_WINDOW_SIZE, 1000] {
+            let score = |strict: bool| {
+                score_with_models(
+                    File::open(&collapsed)?,
+                    Model::default(),
+                    Model::default(),
+                    |scoring| {
+                        scoring.window_size(window_size).strict(strict);
+                    },
+                )
+            };
+            assert!(score(false)?.is_empty());
+            let err = format!("{:#}", score(true).unwrap_err());
+            assert!(err.contains("Failed to score read"), "{err}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_compatibility_report() -> Result<()> {
+        let mixture =
+            |mean: f64| Mixture::new_unchecked(vec![1.0], vec![Gaussian::new_unchecked(mean, 2.0)]);
+        let mut pos_ctrl = Model::default();
+        let mut neg_ctrl = Model::default();
+        pos_ctrl.insert_gmm("AAAAAA".to_string(), mixture(100.0));
+        neg_ctrl.insert_gmm("AAAAAA".to_string(), mixture(104.0));
+        pos_ctrl.insert_gmm("CCCCCC".to_string(), mixture(100.0));
+        let ranks = [
+            ("AAAAAA", 0.5),
+            ("CCCCCC", 0.4),
+            ("GGGGGG", 0.3),
+            ("AAAAA", 0.2),
+        ]
+        .into_iter()
+        .map(|(kmer, rank)| (kmer.to_string(), rank))
+        .collect::<FnvHashMap<_, _>>();
+        let mut scoring = ScoreOptions::from_parts(pos_ctrl, neg_ctrl, sac_cer3()?, ranks)?;
+
+        let metadata = Metadata::new(
+            "read".to_string(),
+            "chrDoesNotExist".to_string(),
+            100,
+            4,
+            Strand::plus(),
+            String::new(),
+        );
+        let mut input = Vec::new();
+        let mut writer = wrap_writer(&mut input, &Eventalign::schema())?;
+        save(&mut writer, &[Eventalign::new(metadata, Vec::new())])?;
+        writer.finish()?;
+
+        let report = scoring.compatibility_report(std::io::Cursor::new(&input))?;
+        assert_eq!(
+            report,
+            CompatibilityReport {
+                ranked_without_model: vec!["CCCCCC".to_string(), "GGGGGG".to_string()],
+                wrong_kmer_size: vec!["AAAAA".to_string()],
+                missing_contigs: vec!["chrDoesNotExist".to_string()],
+            }
+        );
+        assert!(!report.is_compatible());
+        assert_eq!(report.problems().len(), 3);
+
+        scoring.strict(true);
+        scoring.output = Some(Box::new(Vec::new()));
+        let err = scoring.run(std::io::Cursor::new(&input)).unwrap_err();
+        assert!(err.to_string().starts_with("Inputs are incompatible"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_regions() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let collapsed = collapse_single_read(&temp_dir)?;
+        let n_reads = |regions: Vec<Region>| -> Result<usize> {
+            let reads = score_without_models(&collapsed, |scoring| {
+                scoring.regions(regions);
+            })?;
+            Ok(reads.len())
+        };
+
+        // The read is at chrXIII:182504-182681
+        assert_eq!(n_reads(Vec::new())?, 1);
+        assert_eq!(n_reads(vec!["chrXIII:182600-183000".parse()?])?, 1);
+        assert_eq!(
+            n_reads(vec![
+                "chrI:1000-2000".parse()?,
+                "chrXIII:190000-191000".parse()?
+            ])?,
+            0
+        );
+        assert_eq!(
+            n_reads(vec![
+                "chrI:1000-2000".parse()?,
+                "chrXIII:182000-182510".parse()?
+            ])?,
+            1
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_names() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let collapsed = collapse_single_read(&temp_dir)?;
+        let reads = score_without_models(&collapsed, |_| ())?;
+        let name = reads[0].name().to_string();
+
+        let names_file = temp_dir.path().join("names.txt");
+        std::fs::write(&names_file, format!("{name}\nother_read\n\n"))?;
+        let read_names = load_read_names(vec![
+            names_file.to_string_lossy().to_string(),
+            "another_read".to_string(),
+        ])?;
+        assert_eq!(
+            read_names,
+            vec![name.clone(), "other_read".into(), "another_read".into()]
+        );
+
+        let reads = score_without_models(&collapsed, |scoring| {
+            scoring.read_names(read_names);
+        })?;
+        assert_eq!(reads.len(), 1);
+        assert_eq!(reads[0].name(), name);
+
+        let reads = score_without_models(&collapsed, |scoring| {
+            scoring.read_names(vec!["other_read".to_string()]);
+        })?;
+        assert!(reads.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_output_all_positions() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let collapsed = collapse_single_read(&temp_dir)?;
+        let motif = Motif::parse_from_str("1:TA")?;
+
+        let reads = score_without_models(&collapsed, |scoring| {
+            scoring
+                .motifs(vec![motif.clone()])
+                .output_all_positions(true);
+        })?;
+        assert_eq!(reads.len(), 1);
+        assert!(!reads[0].strand().is_minus_strand());
+        let all_positions = reads[0].scores();
+        assert!(all_positions.iter().any(|s| s.matches_motif));
+        assert!(all_positions.iter().any(|s| !s.matches_motif));
+        for score in all_positions {
+            assert_eq!(
+                score.matches_motif,
+                score.kmer.starts_with("TA"),
+                "{score:?}"
+            );
+        }
+
+        let reads = score_without_models(&collapsed, |scoring| {
+            scoring.motifs(vec![motif]);
+        })?;
+        let motif_positions = reads[0].scores();
+        assert!(motif_positions.iter().all(|s| s.matches_motif));
+        assert_eq!(
+            motif_positions.len(),
+            all_positions.iter().filter(|s| s.matches_motif).count()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_overlapping_motifs() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let collapsed = collapse_single_read(&temp_dir)?;
+        let score_with = |motifs: &[&str]| -> Result<Vec<Score>> {
+            let motifs = motifs
+                .iter()
+                .map(Motif::parse_from_str)
+                .collect::<Result<Vec<_>, _>>()?;
+            let reads = score_without_models(&collapsed, |scoring| {
+                scoring.motifs(motifs);
+            })?;
+            Ok(reads[0].scores().to_vec())
+        };
+        let positions = |scores: &[Score]| scores.iter().map(|s| s.pos).collect::<Vec<_>>();
+
+        // Same modified base, the first motif given takes precedence
+        let g_only = score_with(&["1:G"])?;
+        let scores = score_with(&["1:GC", "1:G"])?;
+        assert_eq!(positions(&scores), positions(&g_only));
+        assert!(scores.iter().any(|s| s.motif() == Some("1:GC")));
+        for score in scores.iter() {
+            let expected = if score.kmer.starts_with("GC") {
+                "1:GC"
+            } else {
+                "1:G"
+            };
+            assert_eq!(score.motif(), Some(expected), "{score:?}");
+        }
+        let scores = score_with(&["1:G", "1:GC"])?;
+        assert!(scores.iter().all(|s| s.motif() == Some("1:G")));
+
+        // Different modified bases of the same match are each scored once
+        let scores = score_with(&["1:GC", "2:GCG"])?;
+        let mut expected = positions(&score_with(&["1:GC"])?);
+        expected.extend(positions(&score_with(&["2:GCG"])?));
+        expected.sort_unstable();
+        expected.dedup();
+        assert_eq!(positions(&scores), expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_window_size() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        // Synthetic 20 kb read on a random genome, with signal every 7 bases
+        let chrom_len = 20_100;
+        let mut state: u64 = 42;
+        let bases = (0..chrom_len)
+            .map(|_| {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+                b"ACGT"[(state >> 62) as usize]
+            })
+            .collect::<Vec<_>>();
+        let genome_path = temp_dir.path().join("synthetic.fa");
+        let mut fasta = b">chrSynth\n".to_vec();
+        for line in bases.chunks(60) {
+            fasta.extend_from_slice(line);
+            fasta.push(b'\n');
+        }
+        std::fs::write(&genome_path, fasta)?;
+        crate::utils::index_genome_fasta(&genome_path)?;
+
+        let (start, length) = (50, 20_000);
+        let signals = (start..start + length)
+            .step_by(7)
+            .map(|pos| {
+                let kmer = String::from_utf8(bases[pos as usize..pos as usize + 6].to_vec());
+                Signal::new(pos, kmer.unwrap(), 90.0, 0.01, Vec::new())
+            })
+            .collect();
+        let metadata = Metadata::new(
+            "long_read".to_string(),
+            "chrSynth".to_string(),
+            start,
+            length,
+            Strand::plus(),
+            String::new(),
+        );
+        let input = temp_dir.path().join("long_read.arrow");
+        let mut writer = wrap_writer(File::create(&input)?, &Eventalign::schema())?;
+        save(&mut writer, &[Eventalign::new(metadata, signals)])?;
+        writer.finish()?;
+
+        // Skips that differ between kmers, so every window needs the right
+        // context and signal around it
+        let kmers = crate::npsmlr::train::all_kmers(6);
+        let pos_ctrl = Model::new(
+            Default::default(),
+            kmers
+                .iter()
+                .enumerate()
+                .map(|(i, kmer)| (kmer.clone(), 0.1 + 0.08 * (i % 10) as f64))
+                .collect(),
+        );
+        let neg_ctrl = Model::new(
+            Default::default(),
+            kmers.into_iter().map(|kmer| (kmer, 0.5)).collect(),
+        );
+        let score = |window_size: u64| -> Result<Vec<(u64, String, u64)>> {
+            let genome = IndexedReader::from_file(&genome_path)
+                .map_err(|_| eyre::eyre!("Failed to read genome file."))?;
+            let mut output = Vec::new();
+            let mut scoring = ScoreOptions::from_parts(
+                pos_ctrl.clone(),
+                neg_ctrl.clone(),
+                genome,
+                FnvHashMap::default(),
+            )?;
+            scoring
+                .motifs(vec![Motif::parse_from_str("1:GC")?])
+                .window_size(window_size);
+            scoring.output = Some(Box::new(&mut output));
+            scoring.run(File::open(&input)?)?;
+            let mut reads = Vec::new();
+            load_apply(std::io::Cursor::new(output), |xs: Vec<ScoredRead>| {
+                reads.extend(xs);
+                Ok(())
+            })?;
+            assert_eq!(reads.len(), 1);
+            Ok(reads[0]
+                .scores()
+                .iter()
+                .map(|s| (s.pos, s.kmer.clone(), s.score.to_bits()))
+                .collect())
+        };
+
+        let whole = score(u64::MAX)?;
+        assert!(whole.len() > 1_000);
+        assert!(whole.windows(2).all(|w| w[0].0 < w[1].0));
+        assert_eq!(score(1_000)?, whole);
+        // Windows that don't line up with the signal or the kmers
+        assert_eq!(score(991)?, whole);
+        Ok(())
+    }
+
+    #[test]
+    fn test_batch_reads() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        // Input written in record batches of 700 reads, which don't line up
+        // with the reads scored at a time or the output batches
+        let n_reads = 2100;
+        let input = temp_dir.path().join("reads.arrow");
+        let mut writer = wrap_writer(File::create(&input)?, &Eventalign::schema())?;
+        let reads = (0..n_reads)
+            .map(|i| {
+                let metadata = Metadata::new(
+                    format!("read_{i}"),
+                    "chrI".to_string(),
+                    100 + 10 * i,
+                    20,
+                    Strand::plus(),
+                    String::new(),
+                );
+                Eventalign::new(metadata, Vec::new())
+            })
+            .collect::<Vec<_>>();
+        for chunk in reads.chunks(700) {
+            save(&mut writer, chunk)?;
+        }
+        writer.finish()?;
+
+        let score = |batch_reads: usize| -> Result<String> {
+            let reads = score_without_models(&input, |s| {
+                s.batch_reads(batch_reads);
+            })?;
+            Ok(format!("{reads:?}"))
+        };
+
+        let reads = score_without_models(&input, |_| ())?;
+        let names = reads
+            .iter()
+            .map(|read| read.name().to_string())
+            .collect::<Vec<_>>();
+        let expected = (0..n_reads)
+            .map(|i| format!("read_{i}"))
+            .collect::<Vec<_>>();
+        assert_eq!(names, expected);
+        // Scores are NaN for missing positions, so compare the debug output
+        let output = format!("{reads:?}");
+        assert_eq!(score(1)?, output);
+        assert_eq!(score(7)?, output);
+        assert!(score(0).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_quality() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let collapsed = collapse_single_read(&temp_dir)?;
+        // Models with only skips, so every position only has a skipping score
+        let skips: FnvHashMap<String, f64> = crate::npsmlr::train::all_kmers(6)
+            .into_iter()
+            .map(|kmer| (kmer, 0.5))
+            .collect();
+        let reads = score_with_models(
+            File::open(&collapsed)?,
+            Model::new(Default::default(), skips.clone()),
+            Model::new(
+                Default::default(),
+                skips.into_iter().map(|(kmer, _)| (kmer, 0.25)).collect(),
+            ),
+            |_| (),
+        )?;
+        let quality = reads[0].quality.expect("Quality set by cawlr score");
+        let n_scored = reads[0]
+            .scores()
+            .iter()
+            .filter(|s| !s.score.is_nan())
+            .count();
+        assert!(n_scored > 0);
+        assert_eq!(quality.n_motif_positions, n_scored as u64);
+        assert_eq!(quality.frac_signal, 0.0);
+        assert!((0.0..=0.5).contains(&quality.mean_confidence));
+
+        let mut input = Vec::new();
+        let mut writer = wrap_writer(&mut input, &ScoringMethod::ProbabilityRatio.schema())?;
+        save(&mut writer, &reads)?;
+        writer.finish()?;
+        let mut report = Vec::new();
+        assert_eq!(
+            write_read_report(std::io::Cursor::new(input), &mut report)?,
+            1
+        );
+        let report = String::from_utf8(report)?;
+        let lines = report.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("read_name\t"));
+        let fields = lines[1].split('\t').collect::<Vec<_>>();
+        assert_eq!(fields[0], reads[0].name());
+        assert_eq!(fields[5], n_scored.to_string());
+        assert_eq!(fields[6], "0.0000");
+        Ok(())
+    }
+
+    #[test]
+    fn test_motif_position() {
+        let context = context::Context::new(b"AACGTTCGAA".to_vec(), 100, 0, 0, 2);
+        let positions = |motif: &str| {
+            let motif = Motif::parse_from_str(motif).unwrap();
+            (100..108)
+                .filter(|&pos| context.motif_at(pos, &motif))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(positions("1:CG"), vec![102, 106]);
+        assert_eq!(positions("2:CG"), vec![103, 107]);
+        assert_eq!(positions("1:A"), vec![100, 101]);
+    }
 }
@@ -1,5 +1,7 @@
 use std::io::{Read, Seek};
 
+use arrow2::io::ipc::read::read_file_metadata;
+
 use criterion_stats::univariate::{
     kde::{kernel::Gaussian, Bandwidth, Kde},
     Sample,
@@ -14,6 +16,7 @@ use crate::{
         scored_read::ScoredRead,
     },
     bkde::BinnedKde,
+    score::ScoringMethod,
 };
 
 pub struct Options {
@@ -50,56 +53,84 @@ impl Options {
         self
     }
 
-    pub fn run_modfile(&mut self, mod_file: ModFile) -> Result<BinnedKde> {
+    pub fn run_modfile(&mut self, mut mod_file: ModFile) -> Result<BinnedKde> {
+        let scoring_method = mod_file.scoring_method()?;
         let scores = extract_samples_from_modfile(mod_file)?;
         let scores: Vec<f64> = scores
             .choose_multiple(&mut self.rng, self.samples)
             .cloned()
             .collect();
-        let kde = sample_kde(&scores)?;
-        let bkde = BinnedKde::from_kde(self.bins as i32, &kde);
-        Ok(bkde)
+        self.bin_scores(&scores, scoring_method)
     }
-    pub fn run_modfile_with<F>(&mut self, mod_file: ModFile, extractor: F) -> Result<BinnedKde>
+    pub fn run_modfile_with<F>(&mut self, mut mod_file: ModFile, extractor: F) -> Result<BinnedKde>
     where
         F: Fn(&[ScoredRead]) -> Vec<f64>,
     {
+        let scoring_method = mod_file.scoring_method()?;
         let scores = extract_samples_from_modfile_with(mod_file, extractor)?;
         let scores: Vec<f64> = scores
             .choose_multiple(&mut self.rng, self.samples)
             .cloned()
             .collect();
-        let kde = sample_kde(&scores)?;
-        let bkde = BinnedKde::from_kde(self.bins as i32, &kde);
-        Ok(bkde)
+        self.bin_scores(&scores, scoring_method)
     }
 
-    pub fn run_modfile_max(&mut self, mod_file: ModFile) -> Result<BinnedKde> {
+    pub fn run_modfile_max(&mut self, mut mod_file: ModFile) -> Result<BinnedKde> {
+        let scoring_method = mod_file.scoring_method()?;
         let scores = extract_max_samples_from_scored_read(mod_file)?;
         let scores: Vec<f64> = scores
             .choose_multiple(&mut self.rng, self.samples)
             .cloned()
             .collect();
-        let kde = sample_kde(&scores)?;
-        let bkde = BinnedKde::from_kde(self.bins as i32, &kde);
-        Ok(bkde)
+        self.bin_scores(&scores, scoring_method)
     }
 
-    pub fn run<R>(&mut self, reader: R) -> Result<BinnedKde>
+    pub fn run<R>(&mut self, mut reader: R) -> Result<BinnedKde>
     where
         R: Read + Seek,
     {
+        let metadata = read_file_metadata(&mut reader)?;
+        reader.rewind()?;
+        let scoring_method = ScoringMethod::from_schema(&metadata.schema)?;
         let scores = extract_samples_from_reader(reader)?;
         let scores: Vec<f64> = scores
             .choose_multiple(&mut self.rng, self.samples)
             .cloned()
             .collect();
-        let kde = sample_kde(&scores)?;
-        let bkde = BinnedKde::from_kde(self.bins as i32, &kde);
+        self.bin_scores(&scores, scoring_method)
+    }
+
+    fn bin_scores(&self, scores: &[f64], scoring_method: ScoringMethod) -> Result<BinnedKde> {
+        let kde = sample_kde(scores)?;
+        let bkde = match scoring_method {
+            ScoringMethod::ProbabilityRatio => BinnedKde::from_kde(self.bins as i32, &kde),
+            ScoringMethod::LogOdds => {
+                let range = score_range(scores);
+                BinnedKde::from_kde_with(self.bins as i32, &kde, scoring_method, range)
+            }
+        };
         Ok(bkde)
     }
 }
 
+/// Range of the finite scores, log-odds aren't bounded so the bins have to
+/// cover the scores that were sampled.
+fn score_range(scores: &[f64]) -> (f64, f64) {
+    let (lo, hi) = scores
+        .iter()
+        .filter(|x| x.is_finite())
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), &x| {
+            (lo.min(x), hi.max(x))
+        });
+    if lo < hi {
+        (lo, hi)
+    } else if lo.is_finite() {
+        (lo - 0.5, lo + 0.5)
+    } else {
+        (-0.5, 0.5)
+    }
+}
+
 fn sample_kde(samples: &[f64]) -> Result<Kde<f64, Gaussian>> {
     if samples.is_empty() {
         eyre::bail!("Score file does not contain any values.");
@@ -195,7 +226,18 @@ pub fn extract_max_samples(reads: &[ScoredRead]) -> Vec<f64> {
 
 #[cfg(test)]
 mod test {
+    use std::fs::File;
+
+    use assert_fs::TempDir;
+    use float_eq::assert_float_eq;
+    use rand::Rng;
+
     use super::*;
+    use crate::arrow::{
+        arrow_utils::{save, wrap_writer},
+        metadata::{Metadata, Strand},
+        scored_read::Score,
+    };
 
     #[test]
     fn test_extract_samples() {
@@ -203,4 +245,40 @@ mod test {
         let samples = extract_samples_from_modfile(modfile).unwrap();
         assert_eq!(samples.len(), 15);
     }
+
+    #[test]
+    fn test_percentiles_uniform() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let scores_path = temp_dir.path().join("scores.arrow");
+        let mut rng = SmallRng::seed_from_u64(1234);
+        let reads = (0..100)
+            .map(|i| {
+                let metadata = Metadata::new(
+                    format!("read{i}"),
+                    "chrI".to_string(),
+                    0,
+                    1000,
+                    Strand::plus(),
+                    String::new(),
+                );
+                let scores = (0..1000)
+                    .map(|pos| {
+                        let x = rng.gen::<f64>();
+                        Score::new(pos, "AAAAAA".to_string(), false, Some(x), 0.0, x)
+                    })
+                    .collect();
+                ScoredRead::new(metadata, scores)
+            })
+            .collect::<Vec<_>>();
+        let mut writer = wrap_writer(File::create(&scores_path)?, &ScoredRead::schema())?;
+        save(&mut writer, &reads)?;
+        writer.finish()?;
+
+        let bkde = Options::default()
+            .bins(1000)
+            .run(File::open(&scores_path)?)?;
+        let median = bkde.percentiles(&[0.5])[0];
+        assert_float_eq!(median, 0.5, abs <= 0.01);
+        Ok(())
+    }
 }
@@ -1,41 +1,217 @@
 use std::{
-    fs::File,
+    collections::BTreeMap,
+    fmt::Display,
+    fs::{File, OpenOptions},
     io::{BufWriter, Write},
-    path::Path,
+    path::{Path, PathBuf},
+    str::FromStr,
 };
 
+use arrow2::io::ipc::{read::read_file_metadata, write::FileWriter};
 use eyre::Result;
+use fnv::FnvHashMap;
 use itertools::Itertools;
+use rayon::{prelude::*, ThreadPool, ThreadPoolBuilder};
 
 use crate::{
     arrow::{
-        arrow_utils::load_apply,
+        arrow_utils::{load_apply_batched, save, wrap_writer},
         io::{read_mod_bam_or_arrow, ModFile},
-        metadata::MetadataExt,
-        scored_read::ScoredRead,
+        metadata::{MetadataExt, Strand},
+        posterior::{Posterior, ReadPosteriors},
+        scored_read::{Score, ScoredRead},
     },
     bkde::BinnedKde,
     motif::Motif,
-    utils::CawlrIO,
+    score::ScoringMethod,
+    utils::{check_output, CawlrIO},
 };
 
-fn make_scoring_vec(read: &ScoredRead) -> Vec<f64> {
-    let mut calling_vec = Vec::new();
-    (0..=(read.end_1b_excl() - read.start_0b() + 1)).for_each(|_| calling_vec.push(-1.0));
-    (0..read.scores().len()).for_each(|i| {
-        let idx = read.scores()[i].pos - read.start_0b() + 1;
-        calling_vec[idx as usize] = read.scores()[i].score;
-    });
-    calling_vec
+/// Number of reads with a score at each position, for each chromosome
+type Coverage = FnvHashMap<String, FnvHashMap<u64, usize>>;
+
+fn add_coverage(coverage: &mut Coverage, read: &ScoredRead) {
+    let chrom_coverage = coverage.entry(read.chrom().to_string()).or_default();
+    for score in read.scores() {
+        *chrom_coverage.entry(score.pos).or_default() += 1;
+    }
 }
 
-fn sma<W: Write>(
-    writer: &mut W,
-    pos_scores: &BinnedKde,
-    neg_scores: &BinnedKde,
-    read: &ScoredRead,
-) -> Result<()> {
-    let calling_vec = make_scoring_vec(read);
+/// File format for cawlr sma output
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum SmaOutputFormat {
+    /// BED12 line per read, with nucleosomes as blocks
+    #[default]
+    Bed,
+    /// Variable step wiggle of the fraction of reads accessible at each
+    /// scored position
+    Wig,
+    /// Fraction of reads accessible at each scored position as bedGraph
+    /// intervals
+    BedGraph,
+}
+
+impl Display for SmaOutputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let res = match self {
+            Self::Bed => "bed",
+            Self::Wig => "wig",
+            Self::BedGraph => "bedgraph",
+        };
+        write!(f, "{res}")
+    }
+}
+
+impl FromStr for SmaOutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bed" => Ok(Self::Bed),
+            "wig" => Ok(Self::Wig),
+            "bedgraph" => Ok(Self::BedGraph),
+            _ => Err(format!(
+                "Invalid output format {s}: either 'bed', 'wig', or 'bedgraph'"
+            )),
+        }
+    }
+}
+
+/// Length of DNA wrapped around a nucleosome
+const NUCLEOSOME_LENGTH: u64 = 147;
+
+/// Probability of staying in the same state at the next base in the two
+/// state model, so footprints are a nucleosome long on average
+pub const DEFAULT_P_STAY: f64 = 1. - 1. / NUCLEOSOME_LENGTH as f64;
+
+/// How reads are split into accessible and footprinted blocks
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Segmentation {
+    /// Positions are called independently, then nucleosomes of exactly
+    /// 147bp are placed between linkers of any length
+    #[default]
+    Independent,
+    /// Two state HMM of accessible and footprinted bases, with footprints of
+    /// any length decoded with Viterbi
+    Hmm,
+}
+
+impl Display for Segmentation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let res = match self {
+            Self::Independent => "independent",
+            Self::Hmm => "hmm",
+        };
+        write!(f, "{res}")
+    }
+}
+
+impl FromStr for Segmentation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "independent" | "nucleosome" => Ok(Self::Independent),
+            "hmm" => Ok(Self::Hmm),
+            _ => Err(format!(
+                "Invalid segmentation {s}: either 'independent' or 'hmm'"
+            )),
+        }
+    }
+}
+
+/// Number of reads accessible and total number of reads at each scored
+/// position, sorted by chromosome and position
+#[derive(Default)]
+struct Accessibility(BTreeMap<String, BTreeMap<u64, (usize, usize)>>);
+
+impl Accessibility {
+    fn add_read(&mut self, read: &ScoredRead, nucs: &[(usize, usize)]) {
+        let chrom_acc = self.0.entry(read.chrom().to_string()).or_default();
+        for score in read.scores() {
+            let pos = score.pos as usize;
+            let in_nucleosome = nucs.iter().any(|&(s, e)| s <= pos && pos < e);
+            let (n_accessible, total) = chrom_acc.entry(score.pos).or_default();
+            if !in_nucleosome {
+                *n_accessible += 1;
+            }
+            *total += 1;
+        }
+    }
+
+    /// Fraction of reads accessible at each position of the chromosome. If
+    /// smoothing_window is set, each fraction is replaced by the mean of the
+    /// fractions at the smoothing_window / 2 scored positions on each side,
+    /// using fewer positions near the ends of the chromosome.
+    fn fractions(
+        chrom_acc: &BTreeMap<u64, (usize, usize)>,
+        smoothing_window: Option<usize>,
+    ) -> Vec<(u64, f64)> {
+        let fracs = chrom_acc
+            .iter()
+            .map(|(&pos, &(n_accessible, total))| (pos, n_accessible as f64 / total as f64))
+            .collect::<Vec<_>>();
+        let half = match smoothing_window {
+            Some(window) => window / 2,
+            None => return fracs,
+        };
+        (0..fracs.len())
+            .map(|i| {
+                let window = &fracs[i.saturating_sub(half)..(i + half + 1).min(fracs.len())];
+                let mean = window.iter().map(|(_, frac)| frac).sum::<f64>() / window.len() as f64;
+                (fracs[i].0, mean)
+            })
+            .collect()
+    }
+
+    /// Write variableStep blocks, starting a new block at each chromosome.
+    /// Positions are converted to 1-based.
+    fn write_wig<W: Write>(&self, writer: &mut W, smoothing_window: Option<usize>) -> Result<()> {
+        for (chrom, chrom_acc) in self.0.iter() {
+            writeln!(writer, "variableStep chrom={chrom} span=1")?;
+            for (pos, frac) in Self::fractions(chrom_acc, smoothing_window) {
+                writeln!(writer, "{}\t{frac}", pos + 1)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write bedGraph intervals for each position. If run_length_encode is
+    /// set, adjacent positions with the same value are merged into a single
+    /// interval.
+    fn write_bedgraph<W: Write>(
+        &self,
+        writer: &mut W,
+        run_length_encode: bool,
+        smoothing_window: Option<usize>,
+    ) -> Result<()> {
+        for (chrom, chrom_acc) in self.0.iter() {
+            let mut interval: Option<(u64, u64, f64)> = None;
+            for (pos, frac) in Self::fractions(chrom_acc, smoothing_window) {
+                match interval.as_mut() {
+                    Some((_, end, value)) if run_length_encode && *end == pos && *value == frac => {
+                        *end = pos + 1;
+                    }
+                    _ => {
+                        if let Some((start, end, value)) = interval {
+                            writeln!(writer, "{chrom}\t{start}\t{end}\t{value}")?;
+                        }
+                        interval = Some((pos, pos + 1, frac));
+                    }
+                }
+            }
+            if let Some((start, end, value)) = interval {
+                writeln!(writer, "{chrom}\t{start}\t{end}\t{value}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Infer nucleosome positions along the read, returned as start and end
+/// positions of each nucleosome
+fn call_nucleosomes(models: &ScoreModels, read: &ScoredRead) -> Vec<(usize, usize)> {
+    let emissions = models.emission_vec(read);
     let base_num = read.end_1b_excl() - read.start_0b() + 1;
 
     // Build matrix
@@ -60,20 +236,18 @@ fn sma<W: Write>(
         let within_linker;
         let mut back_frm_ncls = 0.0;
 
-        if calling_vec[i] == -1. {
-            within_linker = prob_mat[i - 1][0];
-            if prob_mat[i - 1][147] != 0.0 {
-                back_frm_ncls = prob_mat[i - 1][147];
+        match emissions[i] {
+            None => {
+                within_linker = prob_mat[i - 1][0];
+                if prob_mat[i - 1][147] != 0.0 {
+                    back_frm_ncls = prob_mat[i - 1][147];
+                }
             }
-        } else {
-            // let k = (calling_vec[i] * 1000.) as usize;
-            // within_linker = EMISSION_PGC_ARRAY[k].ln() + prob_mat[i - 1][0];
-            within_linker = pos_scores.pmf_from_score(calling_vec[i]).ln() + prob_mat[i - 1][0];
-
-            if prob_mat[i - 1][147] != 0.0 {
-                // back_frm_ncls = EMISSION_PGC_ARRAY[k].ln() + prob_mat[i - 1][147];
-                back_frm_ncls =
-                    pos_scores.pmf_from_score(calling_vec[i]).ln() + prob_mat[i - 1][147];
+            Some([pos_ln, _]) => {
+                within_linker = pos_ln + prob_mat[i - 1][0];
+                if prob_mat[i - 1][147] != 0.0 {
+                    back_frm_ncls = pos_ln + prob_mat[i - 1][147];
+                }
             }
         }
 
@@ -85,25 +259,21 @@ fn sma<W: Write>(
             ptr_mat[i][0] = 0;
         }
 
-        if calling_vec[i] == -1. {
-            prob_mat[i][1] = prob_mat[i - 1][0];
-        } else {
-            // let k = (calling_vec[i] * 1000.) as usize;
-            // prob_mat[i][1] = EMISSION_NEG_ARRAY[k].ln() + prob_mat[i - 1][0];
-            prob_mat[i][1] = neg_scores.pmf_from_score(calling_vec[i]).ln() + prob_mat[i - 1][0];
-        }
+        prob_mat[i][1] = match emissions[i] {
+            None => prob_mat[i - 1][0],
+            Some([_, neg_ln]) => neg_ln + prob_mat[i - 1][0],
+        };
         ptr_mat[i][1] = 0;
 
         for j in 2..=147 {
-            if calling_vec[i] == -1. && prob_mat[i - 1][j - 1] != 0.0 {
-                prob_mat[i][j] = prob_mat[i - 1][j - 1];
-            } else {
-                // let k = (calling_vec[i] * 1000.) as usize;
-                if prob_mat[i - 1][j - 1] != 0. {
-                    // prob_mat[i][j] = EMISSION_NEG_ARRAY[k].ln() + prob_mat[i - 1][j - 1];
-                    prob_mat[i][j] =
-                        neg_scores.pmf_from_score(calling_vec[i]).ln() + prob_mat[i - 1][j - 1];
+            match emissions[i] {
+                None if prob_mat[i - 1][j - 1] != 0.0 => {
+                    prob_mat[i][j] = prob_mat[i - 1][j - 1];
+                }
+                Some([_, neg_ln]) if prob_mat[i - 1][j - 1] != 0. => {
+                    prob_mat[i][j] = neg_ln + prob_mat[i - 1][j - 1];
                 }
+                _ => (),
             }
 
             if prob_mat[i][j] != 0. {
@@ -148,7 +318,110 @@ fn sma<W: Write>(
     if in_nucleosome {
         nucs.push((ncls_start, read.end_1b_excl() as usize));
     }
+    nucs
+}
 
+/// Footprints along the read from the Viterbi path of a two state HMM, where
+/// accessible bases emit scores like the positive control and footprinted
+/// bases like the negative control. Both states stay the same at the next
+/// base with probability p_stay. Bases without a score don't emit anything.
+/// Returned as start and end positions of each footprint, like
+/// [call_nucleosomes].
+fn call_footprints(models: &ScoreModels, read: &ScoredRead, p_stay: f64) -> Vec<(usize, usize)> {
+    let start = read.start_0b();
+    let length = read.end_1b_excl().saturating_sub(start) as usize;
+    if length == 0 {
+        return Vec::new();
+    }
+    let mut emissions = vec![[0.0; 2]; length];
+    for score in read.scores() {
+        if score.score.is_nan() || score.pos < start || score.pos >= start + length as u64 {
+            continue;
+        }
+        emissions[(score.pos - start) as usize] = models.ln_likelihoods(read, score);
+    }
+
+    // State 0 is accessible and 1 is footprinted, both equally likely at the
+    // start of the read
+    let log_stay = p_stay.ln();
+    let log_switch = (1. - p_stay).ln();
+    let log_initial = 0.5f64.ln();
+    let mut prob = [log_initial + emissions[0][0], log_initial + emissions[0][1]];
+    // Previous state on the best path to each state at each base
+    let mut ptrs = Vec::with_capacity(length - 1);
+    for emission in emissions[1..].iter() {
+        let mut next = [0.0; 2];
+        let mut ptr = [0u8; 2];
+        for state in 0..2 {
+            let stay = prob[state] + log_stay;
+            let switch = prob[1 - state] + log_switch;
+            if switch > stay {
+                next[state] = switch + emission[state];
+                ptr[state] = (1 - state) as u8;
+            } else {
+                next[state] = stay + emission[state];
+                ptr[state] = state as u8;
+            }
+        }
+        prob = next;
+        ptrs.push(ptr);
+    }
+
+    let mut state = (prob[1] > prob[0]) as u8;
+    let mut states = vec![0u8; length];
+    for i in (0..length).rev() {
+        states[i] = state;
+        if i > 0 {
+            state = ptrs[i - 1][state as usize];
+        }
+    }
+
+    let mut footprints = Vec::new();
+    let mut footprint_start = None;
+    for (i, &state) in states.iter().enumerate() {
+        let pos = start as usize + i;
+        match (state, footprint_start) {
+            (1, None) => footprint_start = Some(pos),
+            (0, Some(s)) => {
+                footprints.push((s, pos));
+                footprint_start = None;
+            }
+            _ => (),
+        }
+    }
+    if let Some(s) = footprint_start {
+        footprints.push((s, start as usize + length));
+    }
+    footprints
+}
+
+/// Fuse sorted blocks separated by fewer than merge_gap bases, then drop
+/// the ones shorter than min_block_len
+fn clean_blocks(
+    blocks: Vec<(usize, usize)>,
+    min_block_len: usize,
+    merge_gap: usize,
+) -> Vec<(usize, usize)> {
+    let mut merged: Vec<(usize, usize)> = Vec::with_capacity(blocks.len());
+    for (start, end) in blocks {
+        match merged.last_mut() {
+            Some((_, last_end)) if start.saturating_sub(*last_end) < merge_gap => {
+                *last_end = (*last_end).max(end);
+            }
+            _ => merged.push((start, end)),
+        }
+    }
+    merged.retain(|&(start, end)| end - start >= min_block_len);
+    merged
+}
+
+/// Write a BED12 line for the read with the footprints as blocks
+fn sma<W: Write>(
+    writer: &mut W,
+    read: &ScoredRead,
+    name: &str,
+    mut nucs: Vec<(usize, usize)>,
+) -> Result<()> {
     // Add pseudo block at start if read doesn't start with a nucleosome
     if nucs.is_empty() || nucs[0].0 != read.start_0b() as usize {
         nucs.insert(0, (read.start_0b() as usize, read.start_0b() as usize + 1));
@@ -171,7 +444,7 @@ fn sma<W: Write>(
         read.chrom(),
         read.start_0b(),
         read.end_1b_excl(),
-        read.name(),
+        name,
         read.strand(),
         read.start_0b(),
         read.end_1b_excl(),
@@ -183,27 +456,452 @@ fn sma<W: Write>(
     Ok(())
 }
 
-pub struct SmaOptions {
-    track_name: Option<String>,
+/// Positions with a probability of modification above this are counted as
+/// modified in the per-read output
+const PER_READ_THRESHOLD: f64 = 0.5;
+
+/// Write a BED12 line for the read with each scored position as a block and
+/// the fraction of positions with a probability of modification above
+/// [PER_READ_THRESHOLD], scaled to 0-1000, as the score. Positions without a
+/// model are ignored, only the first score of a position is counted, and
+/// reads without any scores are skipped.
+fn per_read<W: Write>(
+    writer: &mut W,
+    read: &ScoredRead,
+    scoring_method: ScoringMethod,
+) -> Result<()> {
+    let scores = read
+        .scores()
+        .iter()
+        .filter(|score| !score.missing_model)
+        .sorted_by_key(|score| score.pos)
+        .dedup_by(|a, b| a.pos == b.pos)
+        .collect::<Vec<_>>();
+    let positions = scores.iter().map(|score| score.pos).collect::<Vec<_>>();
+    let (start, end) = match (positions.first(), positions.last()) {
+        (Some(&start), Some(&end)) => (start, end + 1),
+        _ => return Ok(()),
+    };
+    let n_modified = scores
+        .iter()
+        .filter(|score| scoring_method.probability(score.score) > PER_READ_THRESHOLD)
+        .count();
+    let frac = n_modified as f64 / positions.len() as f64;
+    let bed_score = (frac * 1000.).round() as u64;
+    writeln!(
+        writer,
+        "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+        read.chrom(),
+        start,
+        end,
+        read.name(),
+        bed_score,
+        read.strand(),
+        start,
+        end,
+        read.strand().rgb_str(),
+        positions.len(),
+        positions.iter().map(|_| 1).join(","),
+        positions.iter().map(|pos| pos - start).join(","),
+    )?;
+    Ok(())
+}
+
+/// Names of the strands in the paths and track names of the BED output split
+/// by strand, see [strand_bed_path]
+const STRAND_NAMES: [&str; 3] = ["plus", "minus", "unknown"];
+
+/// Path of the BED output of the reads on one strand, named after the BED
+/// output of all reads, ie sma.plus.bed for sma.bed
+pub fn strand_bed_path<P: AsRef<Path>>(bed: P, strand_name: &str) -> PathBuf {
+    let bed = bed.as_ref();
+    let stem = bed.file_stem().unwrap_or_default().to_string_lossy();
+    bed.with_file_name(format!("{stem}.{strand_name}.bed"))
+}
+
+/// Path of the BED output of the reads on a chromosome, after the prefix,
+/// ie sma.chrI.bed for the prefix sma
+pub fn chrom_bed_path<P: AsRef<Path>>(output_prefix: P, chrom: &str) -> PathBuf {
+    let mut path = output_prefix.as_ref().as_os_str().to_owned();
+    path.push(format!(".{chrom}.bed"));
+    PathBuf::from(path)
+}
+
+/// BED files of each chromosome with [SmaOptions::per_chromosome]. Like
+/// [AtomicFileWriter](crate::utils::AtomicFileWriter) each file is written to
+/// {path}.tmp and only moved to its path by [ChromWriters::commit], and the
+/// temporary files are removed if it is dropped before then.
+#[derive(Default)]
+struct ChromWriters {
+    open: Option<(String, BufWriter<File>)>,
+    /// Temporary file of each chromosome written so far and its final path
+    files: FnvHashMap<String, (PathBuf, PathBuf)>,
+}
+
+impl ChromWriters {
+    /// Writer of the chromosome's file, closing the file of the previous
+    /// chromosome, and whether the file was just created. Files of
+    /// chromosomes that were already written are appended to.
+    fn get(
+        &mut self,
+        output_prefix: &Path,
+        chrom: &str,
+        overwrite: bool,
+    ) -> Result<(&mut BufWriter<File>, bool)> {
+        let is_open = matches!(&self.open, Some((open_chrom, _)) if open_chrom == chrom);
+        let mut created = false;
+        if !is_open {
+            if let Some((_, mut writer)) = self.open.take() {
+                writer.flush()?;
+            }
+            let file = match self.files.get(chrom) {
+                Some((tmp_path, path)) => {
+                    log::warn!(
+                        "Reads on {chrom} aren't next to each other in the input, appending to {}",
+                        path.display()
+                    );
+                    OpenOptions::new().append(true).open(tmp_path)?
+                }
+                None => {
+                    let path = chrom_bed_path(output_prefix, chrom);
+                    check_output(&path, overwrite)?;
+                    let mut tmp_path = path.clone().into_os_string();
+                    tmp_path.push(".tmp");
+                    let tmp_path = PathBuf::from(tmp_path);
+                    let file = File::create(&tmp_path)?;
+                    self.files.insert(chrom.to_string(), (tmp_path, path));
+                    created = true;
+                    file
+                }
+            };
+            self.open = Some((chrom.to_string(), BufWriter::new(file)));
+        }
+        let (_, writer) = self.open.as_mut().expect("Writer was just opened");
+        Ok((writer, created))
+    }
+
+    /// Flush the open file and move every file to its final path
+    fn commit(&mut self) -> Result<()> {
+        if let Some((_, writer)) = self.open.take() {
+            let file = writer.into_inner().map_err(|e| e.into_error())?;
+            file.sync_all()?;
+        }
+        for (_, (tmp_path, path)) in self.files.drain() {
+            std::fs::rename(tmp_path, path)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ChromWriters {
+    fn drop(&mut self) {
+        self.open.take();
+        for (tmp_path, _) in self.files.values() {
+            let _ = std::fs::remove_file(tmp_path);
+        }
+    }
+}
+
+/// Writers for the BED lines of plus, minus and unknown strand reads, in the
+/// order of [STRAND_NAMES]
+pub struct StrandWriters<'a> {
+    pub plus: Box<dyn Write + 'a>,
+    pub minus: Box<dyn Write + 'a>,
+    pub unknown: Box<dyn Write + 'a>,
+}
+
+impl<'a> StrandWriters<'a> {
+    pub fn new(
+        plus: Box<dyn Write + 'a>,
+        minus: Box<dyn Write + 'a>,
+        unknown: Box<dyn Write + 'a>,
+    ) -> Self {
+        Self {
+            plus,
+            minus,
+            unknown,
+        }
+    }
+
+    fn get_mut(&mut self, strand: Strand) -> &mut Box<dyn Write + 'a> {
+        if strand.is_unknown_strand() {
+            &mut self.unknown
+        } else if strand.is_minus_strand() {
+            &mut self.minus
+        } else {
+            &mut self.plus
+        }
+    }
+
+    fn iter_mut(&mut self) -> impl Iterator<Item = &mut Box<dyn Write + 'a>> {
+        [&mut self.plus, &mut self.minus, &mut self.unknown].into_iter()
+    }
+}
+
+/// Number of reads of posteriors kept in memory before they are written
+const POSTERIORS_BATCH_SIZE: usize = 1024;
+
+/// Number of reads from a bam file processed in parallel at a time
+const READ_BATCH_SIZE: usize = 1024;
+
+/// Apply func to batches of the aligned reads in the file. Arrow files are
+/// read at most batch_size reads at a time, see [load_apply_batched], and
+/// reads from a bam file [READ_BATCH_SIZE] at a time.
+fn apply_read_batches<F>(mod_file: ModFile, batch_size: Option<usize>, mut func: F) -> Result<()>
+where
+    F: FnMut(Vec<ScoredRead>) -> Result<()>,
+{
+    let is_aligned = |read: &ScoredRead| {
+        if read.is_unaligned() {
+            log::debug!("Read {} is unaligned, skipping...", read.name());
+        }
+        !read.is_unaligned()
+    };
+    match mod_file {
+        ModFile::Arrow(file) => {
+            let batch_size = batch_size.unwrap_or(usize::MAX);
+            load_apply_batched(file, batch_size, |reads: Vec<ScoredRead>| {
+                func(reads.into_iter().filter(is_aligned).collect())
+            })
+        }
+        mod_file => {
+            let mut reads = Vec::new();
+            read_mod_bam_or_arrow(mod_file, |read| {
+                if is_aligned(&read) {
+                    reads.push(read);
+                    if reads.len() >= READ_BATCH_SIZE {
+                        func(std::mem::take(&mut reads))?;
+                    }
+                }
+                Ok(())
+            })?;
+            func(reads)
+        }
+    }
+}
+
+/// Posterior of each scored position of the read being accessible, using the
+/// same emission probabilities as the linker and nucleosome states of
+/// [call_nucleosomes]. Positions without a score or model are skipped.
+fn read_posteriors(models: &ScoreModels, read: &ScoredRead) -> ReadPosteriors {
+    let posteriors = read
+        .scores()
+        .iter()
+        .filter(|score| !score.missing_model && !score.score.is_nan())
+        .map(|score| {
+            let [pos_ln, neg_ln] = models.ln_likelihoods(read, score);
+            Posterior::new(score.pos, score.score, pos_ln, neg_ln)
+        })
+        .collect();
+    ReadPosteriors::new(read.metadata.clone(), posteriors)
+}
+
+/// Whether name is the motif as written in [Score::motif], ie 2:GC
+fn is_motif_named(motif: &Motif, name: &str) -> bool {
+    match name.split_once(':') {
+        Some((position, bases)) => {
+            position.parse() == Ok(motif.position_1b()) && bases == motif.motif()
+        }
+        None => false,
+    }
+}
+
+/// Control score models of a motif, given with `--motif-ctrl-scores` as the
+/// motif and the paths to the positive and negative control scores from
+/// cawlr model-scores, separated by commas, ie 1:GC,gc_pos.pickle,gc_neg.pickle
+#[derive(Debug, Clone)]
+pub struct MotifCtrlScores {
+    pub motif: Motif,
+    pub pos_ctrl_scores: PathBuf,
+    pub neg_ctrl_scores: PathBuf,
+}
+
+impl FromStr for MotifCtrlScores {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("Invalid motif control scores {s}: expected <motif>,<pos>,<neg>");
+        let (motif, pos_ctrl_scores, neg_ctrl_scores) =
+            s.split(',').collect_tuple().ok_or_else(err)?;
+        if pos_ctrl_scores.is_empty() || neg_ctrl_scores.is_empty() {
+            return Err(err());
+        }
+        let motif = Motif::parse_from_str(motif).map_err(|e| format!("{}: {e}", err()))?;
+        Ok(Self {
+            motif,
+            pos_ctrl_scores: pos_ctrl_scores.into(),
+            neg_ctrl_scores: neg_ctrl_scores.into(),
+        })
+    }
+}
+
+/// Positive and negative control score models giving the emission
+/// probabilities of scores, with models for specific motifs used for the
+/// scores from them
+struct ScoreModels {
     pos_bkde: BinnedKde,
     neg_bkde: BinnedKde,
+    motif_models: Vec<(Motif, BinnedKde, BinnedKde)>,
+}
+
+impl ScoreModels {
+    fn new(pos_bkde: BinnedKde, neg_bkde: BinnedKde) -> Self {
+        Self {
+            pos_bkde,
+            neg_bkde,
+            motif_models: Vec::new(),
+        }
+    }
+
+    /// Index of the motif with its own models that the score was scored for.
+    /// Scores from files written before the motif was recorded fall back to
+    /// the first motif matching the kmer of the score. Only the kmer after
+    /// the modified base is known then, so scores of minus strand reads and
+    /// scores without a kmer use the default models.
+    fn motif_idx(&self, read: &ScoredRead, score: &Score) -> Option<usize> {
+        if let Some(name) = score.motif() {
+            return self
+                .motif_models
+                .iter()
+                .position(|(motif, _, _)| is_motif_named(motif, name));
+        }
+        if !score.matches_motif || read.strand().is_minus_strand() || score.kmer.is_empty() {
+            return None;
+        }
+        self.motif_models
+            .iter()
+            .position(|(motif, _, _)| motif.starts_kmer(&score.kmer))
+    }
+
+    /// Log-likelihoods of the score under the positive and negative control
+    /// models for it
+    fn ln_likelihoods(&self, read: &ScoredRead, score: &Score) -> [f64; 2] {
+        let (pos_bkde, neg_bkde) = match self.motif_idx(read, score) {
+            Some(idx) => (&self.motif_models[idx].1, &self.motif_models[idx].2),
+            None => (&self.pos_bkde, &self.neg_bkde),
+        };
+        [
+            pos_bkde.pmf_from_score(score.score).ln(),
+            neg_bkde.pmf_from_score(score.score).ln(),
+        ]
+    }
+
+    /// Log-likelihoods at each base of the read, indexed from the base before
+    /// the start of the read, or None for bases without a score. The last
+    /// score at a position is used.
+    fn emission_vec(&self, read: &ScoredRead) -> Vec<Option<[f64; 2]>> {
+        let length = (read.end_1b_excl() - read.start_0b() + 2) as usize;
+        let mut emissions = vec![None; length];
+        for score in read.scores() {
+            let idx = (score.pos + 1).checked_sub(read.start_0b());
+            if let Some(emission) = idx.and_then(|idx| emissions.get_mut(idx as usize)) {
+                *emission = Some(self.ln_likelihoods(read, score));
+            }
+        }
+        emissions
+    }
+
+    /// Name of the read in BED output, followed by the motifs whose models
+    /// were used for any of its scores, ie read|1:GC,2:CG
+    fn bed_name(&self, read: &ScoredRead) -> String {
+        let motifs = read
+            .scores()
+            .iter()
+            .filter_map(|score| self.motif_idx(read, score))
+            .sorted()
+            .dedup()
+            .map(|idx| self.motif_models[idx].0.to_string())
+            .join(",");
+        if motifs.is_empty() {
+            read.name().to_string()
+        } else {
+            format!("{}|{motifs}", read.name())
+        }
+    }
+}
+
+/// Control score models and settings used to find the footprints of a read,
+/// shared between the threads processing reads
+struct Segmenter {
+    models: ScoreModels,
+    segmentation: Segmentation,
+    p_stay: f64,
+    min_block_len: usize,
+    merge_gap: usize,
+}
+
+impl Segmenter {
+    fn new(pos_bkde: BinnedKde, neg_bkde: BinnedKde) -> Self {
+        Self {
+            models: ScoreModels::new(pos_bkde, neg_bkde),
+            segmentation: Segmentation::default(),
+            p_stay: DEFAULT_P_STAY,
+            min_block_len: 0,
+            merge_gap: 0,
+        }
+    }
+
+    /// Footprints of the read after merging and dropping short blocks
+    fn footprints(&self, read: &ScoredRead) -> Vec<(usize, usize)> {
+        let nucs = match self.segmentation {
+            Segmentation::Independent => call_nucleosomes(&self.models, read),
+            Segmentation::Hmm => call_footprints(&self.models, read, self.p_stay),
+        };
+        clean_blocks(nucs, self.min_block_len, self.merge_gap)
+    }
+}
+
+pub struct SmaOptions<'a> {
+    track_name: Option<String>,
+    segmenter: Segmenter,
     motifs: Vec<Motif>,
-    writer: Box<dyn Write>,
+    writer: Box<dyn Write + 'a>,
+    per_read_writer: Option<Box<dyn Write + 'a>>,
+    strand_writers: Option<StrandWriters<'a>>,
+    per_chromosome: Option<PathBuf>,
+    chrom_writers: ChromWriters,
+    overwrite: bool,
+    posteriors_output: Option<Box<dyn Write + 'a>>,
+    posteriors_writer: Option<FileWriter<Box<dyn Write + 'a>>>,
+    posteriors: Vec<ReadPosteriors>,
+    min_coverage: usize,
+    output_format: SmaOutputFormat,
+    run_length_encode: bool,
+    smoothing_window: Option<usize>,
+    accessibility: Accessibility,
+    batch_size: Option<usize>,
+    threads: Option<usize>,
+    min_scored_positions: u64,
 }
 
-impl SmaOptions {
+impl<'a> SmaOptions<'a> {
     pub fn new(
         pos_bkde: BinnedKde,
         neg_bkde: BinnedKde,
         motifs: Vec<Motif>,
-        writer: Box<dyn Write>,
+        writer: Box<dyn Write + 'a>,
     ) -> Self {
         Self {
             track_name: None,
-            pos_bkde,
-            neg_bkde,
+            segmenter: Segmenter::new(pos_bkde, neg_bkde),
             motifs,
             writer,
+            per_read_writer: None,
+            strand_writers: None,
+            per_chromosome: None,
+            chrom_writers: ChromWriters::default(),
+            overwrite: false,
+            posteriors_output: None,
+            posteriors_writer: None,
+            posteriors: Vec::new(),
+            min_coverage: 1,
+            output_format: SmaOutputFormat::default(),
+            run_length_encode: true,
+            smoothing_window: None,
+            accessibility: Accessibility::default(),
+            batch_size: None,
+            threads: None,
+            min_scored_positions: 0,
         }
     }
 
@@ -224,47 +922,1414 @@ impl SmaOptions {
         self.track_name = Some(track_name.into());
         self
     }
-    pub fn run_modfile(mut self, mod_file: ModFile) -> Result<()> {
-        let track_name = self
-            .track_name
+
+    /// Ignore scores at positions covered by fewer than min_coverage reads,
+    /// reads left without any scores are not written
+    pub fn min_coverage(&mut self, min_coverage: usize) -> &mut Self {
+        self.min_coverage = min_coverage;
+        self
+    }
+
+    pub fn output_format(&mut self, output_format: SmaOutputFormat) -> &mut Self {
+        self.output_format = output_format;
+        self
+    }
+
+    /// How reads are split into accessible and footprinted blocks, by
+    /// default [Segmentation::Independent]
+    pub fn segmentation(&mut self, segmentation: Segmentation) -> &mut Self {
+        self.segmenter.segmentation = segmentation;
+        self
+    }
+
+    /// Probability of staying in the same state at the next base with
+    /// [Segmentation::Hmm], higher values give fewer and longer blocks.
+    /// Must be between 0 and 1 exclusive.
+    pub fn p_stay(&mut self, p_stay: f64) -> &mut Self {
+        self.segmenter.p_stay = p_stay;
+        self
+    }
+
+    /// Set [SmaOptions::p_stay] so footprints with [Segmentation::Hmm] are
+    /// this many bases long on average
+    pub fn footprint_length(&mut self, footprint_length: u64) -> &mut Self {
+        self.segmenter.p_stay = 1. - 1. / footprint_length.max(1) as f64;
+        self
+    }
+
+    /// Use these control score models for scores of the motif, instead of
+    /// the ones the options were created with. Scores from files without the
+    /// motif of each score use the models of the first motif added that
+    /// starts their kmer, or the default models for minus strand reads. With
+    /// any motif models, the motifs used for a read are added to its name in
+    /// BED output, ie read|1:GC,2:CG.
+    pub fn motif_models(
+        &mut self,
+        motif: Motif,
+        pos_bkde: BinnedKde,
+        neg_bkde: BinnedKde,
+    ) -> &mut Self {
+        self.segmenter
+            .models
+            .motif_models
+            .push((motif, pos_bkde, neg_bkde));
+        self
+    }
+
+    /// Drop footprints shorter than this many bases, after merging them with
+    /// [SmaOptions::merge_gap]. The bases are called accessible instead.
+    pub fn min_block_len(&mut self, min_block_len: usize) -> &mut Self {
+        self.segmenter.min_block_len = min_block_len;
+        self
+    }
+
+    /// Fuse footprints separated by fewer than this many accessible bases
+    pub fn merge_gap(&mut self, merge_gap: usize) -> &mut Self {
+        self.segmenter.merge_gap = merge_gap;
+        self
+    }
+
+    /// Merge adjacent positions with the same value into one interval in
+    /// bedGraph output, on by default
+    pub fn run_length_encode(&mut self, run_length_encode: bool) -> &mut Self {
+        self.run_length_encode = run_length_encode;
+        self
+    }
+
+    /// Smooth the fraction of reads accessible in wig and bedGraph output with
+    /// a centered rolling mean over this many scored positions of each
+    /// chromosome, off by default
+    pub fn smoothing_window(&mut self, smoothing_window: Option<usize>) -> &mut Self {
+        self.smoothing_window = smoothing_window;
+        self
+    }
+
+    /// Read at most batch_size reads from an Arrow file at a time in
+    /// [SmaOptions::run] and [SmaOptions::run_modfile], splitting larger
+    /// record batches to limit memory use. By default record batches are read
+    /// whole.
+    pub fn batch_size(&mut self, batch_size: Option<usize>) -> &mut Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// Number of threads to find the footprints of reads on, by default the
+    /// number of cpus. Output is the same for any number of threads.
+    pub fn threads(&mut self, threads: Option<usize>) -> &mut Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Skip reads with fewer scored motif positions than this, see
+    /// [crate::arrow::scored_read::ReadQuality]
+    pub fn min_scored_positions(&mut self, min_scored_positions: u64) -> &mut Self {
+        self.min_scored_positions = min_scored_positions;
+        self
+    }
+
+    /// Also write a BED12 line per read, with each scored position as a block
+    /// and the fraction of positions scored above 0.5 as the score
+    pub fn per_read_output(&mut self, per_read_writer: Option<Box<dyn Write + 'a>>) -> &mut Self {
+        self.per_read_writer = per_read_writer;
+        self
+    }
+
+    /// Also write the BED line of each read to the writer for its strand,
+    /// with a track line of their own. Only used with BED output.
+    pub fn split_by_strand(&mut self, strand_writers: Option<StrandWriters<'a>>) -> &mut Self {
+        self.strand_writers = strand_writers;
+        self
+    }
+
+    /// Write the BED lines of the reads on each chromosome to their own file
+    /// after the output prefix instead of the writer, see [chrom_bed_path].
+    /// Each file has its own track line. A file is closed when the next read
+    /// is on another chromosome, so input sorted by chromosome only keeps
+    /// one open. Only used with BED output. The files are written to
+    /// {path}.tmp and moved to their path once all reads are processed.
+    pub fn per_chromosome(&mut self, output_prefix: Option<PathBuf>) -> &mut Self {
+        self.per_chromosome = output_prefix;
+        self
+    }
+
+    /// Replace existing files with [SmaOptions::per_chromosome] instead of
+    /// failing
+    pub fn overwrite(&mut self, overwrite: bool) -> &mut Self {
+        self.overwrite = overwrite;
+        self
+    }
+
+    /// Also write the log-likelihood of each scored position under both
+    /// control score models and the posterior of it being accessible to an
+    /// Arrow file, see [ReadPosteriors]. The BED, wig or bedGraph output is
+    /// unchanged.
+    pub fn posteriors_output(
+        &mut self,
+        posteriors_output: Option<Box<dyn Write + 'a>>,
+    ) -> &mut Self {
+        self.posteriors_output = posteriors_output;
+        self
+    }
+
+    /// Write the track line and the Arrow header of the posteriors output
+    fn start(&mut self) -> Result<()> {
+        let p_stay = self.segmenter.p_stay;
+        if self.segmenter.segmentation == Segmentation::Hmm && !(p_stay > 0. && p_stay < 1.) {
+            return Err(eyre::eyre!(
+                "Probability of staying in the same state must be between 0 and 1, got {p_stay}"
+            ));
+        }
+        if self.strand_writers.is_some() && self.output_format != SmaOutputFormat::Bed {
+            return Err(eyre::eyre!(
+                "Splitting by strand is only supported with bed output, not {}",
+                self.output_format
+            ));
+        }
+        if self.per_chromosome.is_some() {
+            if self.output_format != SmaOutputFormat::Bed {
+                return Err(eyre::eyre!(
+                    "Output per chromosome is only supported with bed output, not {}",
+                    self.output_format
+                ));
+            }
+            if self.strand_writers.is_some() {
+                return Err(eyre::eyre!(
+                    "Output per chromosome can't be split by strand"
+                ));
+            }
+        }
+        self.write_track_line()?;
+        if let Some(output) = self.posteriors_output.take() {
+            self.posteriors_writer = Some(wrap_writer(output, &ReadPosteriors::schema())?);
+        }
+        Ok(())
+    }
+
+    fn flush_posteriors(&mut self) -> Result<()> {
+        if let Some(writer) = self.posteriors_writer.as_mut() {
+            if !self.posteriors.is_empty() {
+                save(writer, &self.posteriors)?;
+                self.posteriors.clear();
+            }
+        }
+        Ok(())
+    }
+
+    fn track_name_or_default(&self) -> String {
+        self.track_name
             .clone()
-            .unwrap_or_else(|| "cawlr_sma".to_string());
-        writeln!(
-            &mut self.writer,
-            "track name=\"{track_name}\" itemRgb=\"on\" visibility=2"
-        )?;
+            .unwrap_or_else(|| "cawlr_sma".to_string())
+    }
+
+    /// Track line of the output, the files per chromosome get theirs when
+    /// they are opened
+    fn write_track_line(&mut self) -> Result<()> {
+        if self.per_chromosome.is_some() {
+            return Ok(());
+        }
+        let track_name = self.track_name_or_default();
+        match self.output_format {
+            SmaOutputFormat::Bed => writeln!(
+                &mut self.writer,
+                "track name=\"{track_name}\" itemRgb=\"on\" visibility=2"
+            )?,
+            SmaOutputFormat::Wig => writeln!(
+                &mut self.writer,
+                "track type=wiggle_0 name=\"{track_name}\" visibility=2"
+            )?,
+            SmaOutputFormat::BedGraph => writeln!(
+                &mut self.writer,
+                "track type=bedGraph name=\"{track_name}\" visibility=2"
+            )?,
+        }
+        if let Some(strand_writers) = self.strand_writers.as_mut() {
+            for (writer, strand_name) in strand_writers.iter_mut().zip(STRAND_NAMES) {
+                writeln!(
+                    writer,
+                    "track name=\"{track_name}.{strand_name}\" itemRgb=\"on\" visibility=2"
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Scores are only comparable to control score models built with the same
+    /// scoring method.
+    fn check_scoring_method(&self, scoring_method: ScoringMethod) -> Result<()> {
+        let models = &self.segmenter.models;
+        let pos_method = models.pos_bkde.scoring_method();
+        let neg_method = models.neg_bkde.scoring_method();
+        if pos_method != neg_method {
+            return Err(eyre::eyre!(
+                "Positive control scores use {pos_method} scoring but negative control scores use {neg_method}"
+            ));
+        }
+        for (motif, pos_bkde, neg_bkde) in models.motif_models.iter() {
+            if pos_bkde.scoring_method() != pos_method || neg_bkde.scoring_method() != pos_method {
+                return Err(eyre::eyre!(
+                    "Control scores of motif {motif} don't use {pos_method} scoring like the rest"
+                ));
+            }
+        }
+        if pos_method != scoring_method {
+            return Err(eyre::eyre!(
+                "Input was scored with {scoring_method} scoring but control scores use {pos_method}, rerun cawlr model-scores on controls scored with {scoring_method}"
+            ));
+        }
+        Ok(())
+    }
 
-        read_mod_bam_or_arrow(mod_file, |read| {
-            if !read.is_unaligned() {
+    /// Write any output that is only available after all reads are processed
+    fn finish(&mut self) -> Result<()> {
+        match self.output_format {
+            SmaOutputFormat::Bed => (),
+            SmaOutputFormat::Wig => self
+                .accessibility
+                .write_wig(&mut self.writer, self.smoothing_window)?,
+            SmaOutputFormat::BedGraph => self.accessibility.write_bedgraph(
+                &mut self.writer,
+                self.run_length_encode,
+                self.smoothing_window,
+            )?,
+        }
+        self.writer.flush()?;
+        if let Some(per_read_writer) = self.per_read_writer.as_mut() {
+            per_read_writer.flush()?;
+        }
+        if let Some(strand_writers) = self.strand_writers.as_mut() {
+            for writer in strand_writers.iter_mut() {
+                writer.flush()?;
+            }
+        }
+        self.chrom_writers.commit()?;
+        self.flush_posteriors()?;
+        if let Some(mut writer) = self.posteriors_writer.take() {
+            writer.finish()?;
+        }
+        Ok(())
+    }
+
+    /// Remove scores at low coverage positions, returns None if no scores are
+    /// left.
+    fn filter_low_coverage(&self, coverage: &Coverage, mut read: ScoredRead) -> Option<ScoredRead> {
+        let chrom_coverage = coverage.get(read.chrom())?;
+        read.scores.retain(|score| {
+            chrom_coverage.get(&score.pos).copied().unwrap_or_default() >= self.min_coverage
+        });
+        if read.scores.is_empty() {
+            log::debug!("Read {} has no positions with enough coverage", read.name());
+            None
+        } else {
+            Some(read)
+        }
+    }
+
+    /// Drop scores at positions not matching any of the motifs, if any are
+    /// given. Only the kmer after the modified base is known, so minus strand
+    /// reads and scores without a kmer are kept as is.
+    fn filter_motifs(&self, mut read: ScoredRead) -> ScoredRead {
+        if self.motifs.is_empty() || read.strand().is_minus_strand() {
+            return read;
+        }
+        read.scores.retain(|score| {
+            score.kmer.is_empty() || self.motifs.iter().any(|m| m.starts_kmer(&score.kmer))
+        });
+        read
+    }
+
+    /// Skip reads with too few scored positions, then drop scores at low
+    /// coverage positions if coverage is given and ones not matching the
+    /// motifs. Returns None if the read is skipped.
+    fn filter_read(&self, coverage: Option<&Coverage>, read: ScoredRead) -> Option<ScoredRead> {
+        let quality = read.quality_or_compute(self.segmenter.models.pos_bkde.scoring_method());
+        if quality.n_motif_positions < self.min_scored_positions {
+            log::debug!(
+                "Read {} has only {} scored positions, skipping",
+                read.name(),
+                quality.n_motif_positions
+            );
+            return None;
+        }
+        let read = match coverage {
+            Some(coverage) => self.filter_low_coverage(coverage, read)?,
+            None => read,
+        };
+        Some(self.filter_motifs(read))
+    }
+
+    /// Find the footprints, and posteriors if they are written, of the reads
+    /// in parallel, then write the output of each read in order
+    fn process_reads(
+        &mut self,
+        pool: &ThreadPool,
+        coverage: Option<&Coverage>,
+        reads: Vec<ScoredRead>,
+    ) -> Result<()> {
+        let reads = reads
+            .into_iter()
+            .filter_map(|read| self.filter_read(coverage, read))
+            .collect::<Vec<_>>();
+        let segmenter = &self.segmenter;
+        let with_posteriors = self.posteriors_writer.is_some();
+        let processed: Vec<_> = pool.install(|| {
+            reads
+                .into_par_iter()
+                .map(|read| {
+                    let nucs = segmenter.footprints(&read);
+                    let posteriors =
+                        with_posteriors.then(|| read_posteriors(&segmenter.models, &read));
+                    (read, nucs, posteriors)
+                })
+                .collect()
+        });
+        for (read, nucs, posteriors) in processed {
+            self.write_read(&read, nucs, posteriors)?;
+        }
+        Ok(())
+    }
+
+    /// Write the footprints and per-read output of the read, and the
+    /// footprints to the output for its strand if split by strand
+    fn write_read(
+        &mut self,
+        read: &ScoredRead,
+        nucs: Vec<(usize, usize)>,
+        posteriors: Option<ReadPosteriors>,
+    ) -> Result<()> {
+        if let Some(per_read_writer) = self.per_read_writer.as_mut() {
+            let scoring_method = self.segmenter.models.pos_bkde.scoring_method();
+            per_read(per_read_writer, read, scoring_method)?;
+        }
+        if let Some(posteriors) = posteriors {
+            self.posteriors.push(posteriors);
+            if self.posteriors.len() >= POSTERIORS_BATCH_SIZE {
+                self.flush_posteriors()?;
+            }
+        }
+        match self.output_format {
+            SmaOutputFormat::Bed => {
+                let name = self.segmenter.models.bed_name(read);
+                if self.per_chromosome.is_some() {
+                    let writer = self.chrom_writer(read.chrom())?;
+                    return sma(writer, read, &name, nucs);
+                }
+                if let Some(strand_writers) = self.strand_writers.as_mut() {
+                    let writer = strand_writers.get_mut(read.strand());
+                    sma(writer, read, &name, nucs.clone())?;
+                }
+                sma(&mut self.writer, read, &name, nucs)
+            }
+            SmaOutputFormat::Wig | SmaOutputFormat::BedGraph => {
+                self.accessibility.add_read(read, &nucs);
+                Ok(())
+            }
+        }
+    }
+
+    /// Writer of the BED output of the chromosome with
+    /// [SmaOptions::per_chromosome], see [ChromWriters::get]. New files start
+    /// with a track line.
+    fn chrom_writer(&mut self, chrom: &str) -> Result<&mut BufWriter<File>> {
+        let output_prefix = self
+            .per_chromosome
+            .as_ref()
+            .ok_or_else(|| eyre::eyre!("No output prefix for output per chromosome"))?;
+        let track_name = self.track_name_or_default();
+        let (writer, created) = self
+            .chrom_writers
+            .get(output_prefix, chrom, self.overwrite)?;
+        if created {
+            writeln!(
+                writer,
+                "track name=\"{track_name}.{chrom}\" itemRgb=\"on\" visibility=2"
+            )?;
+        }
+        Ok(writer)
+    }
+
+    fn thread_pool(&self) -> Result<ThreadPool> {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(self.threads.unwrap_or(0))
+            .build()?;
+        Ok(pool)
+    }
+
+    pub fn run_modfile(mut self, mut mod_file: ModFile) -> Result<()> {
+        self.check_scoring_method(mod_file.scoring_method()?)?;
+        self.start()?;
+
+        let coverage = if self.min_coverage > 1 {
+            let mut coverage = Coverage::default();
+            apply_read_batches(mod_file.try_clone()?, self.batch_size, |reads| {
+                reads
+                    .iter()
+                    .for_each(|read| add_coverage(&mut coverage, read));
+                Ok(())
+            })?;
+            mod_file.rewind()?;
+            Some(coverage)
+        } else {
+            None
+        };
+
+        let pool = self.thread_pool()?;
+        apply_read_batches(mod_file, self.batch_size, |reads| {
+            for read in reads.iter() {
                 log::info!("{:?}", read.metadata());
-                sma(&mut self.writer, &self.pos_bkde, &self.neg_bkde, &read)?;
-            } else {
-                log::debug!("Read {} is unaligned, skipping...", read.name())
             }
-            Ok(())
-        })
+            self.process_reads(&pool, coverage.as_ref(), reads)
+        })?;
+        self.finish()
     }
 
     pub fn run<P>(mut self, scores_filepath: P) -> Result<()>
     where
         P: AsRef<Path>,
     {
-        let track_name = self
-            .track_name
-            .clone()
-            .unwrap_or_else(|| "cawlr_sma".to_string());
-        writeln!(
-            &mut self.writer,
-            "track name=\"{track_name}\" itemRgb=\"on\" visibility=2"
-        )?;
+        let metadata = read_file_metadata(&mut File::open(&scores_filepath)?)?;
+        self.check_scoring_method(ScoringMethod::from_schema(&metadata.schema)?)?;
+        self.start()?;
 
+        let batch_size = self.batch_size.unwrap_or(usize::MAX);
+        let coverage = if self.min_coverage > 1 {
+            let mut coverage = Coverage::default();
+            let scores_file = File::open(&scores_filepath)?;
+            load_apply_batched(scores_file, batch_size, |reads: Vec<ScoredRead>| {
+                reads
+                    .iter()
+                    .for_each(|read| add_coverage(&mut coverage, read));
+                Ok(())
+            })?;
+            Some(coverage)
+        } else {
+            None
+        };
+
+        let pool = self.thread_pool()?;
         let scores_file = File::open(scores_filepath)?;
-        load_apply(scores_file, |reads: Vec<ScoredRead>| {
-            for read in reads {
+        load_apply_batched(scores_file, batch_size, |reads: Vec<ScoredRead>| {
+            for read in reads.iter() {
                 log::info!("{:?}", read.metadata());
-                sma(&mut self.writer, &self.pos_bkde, &self.neg_bkde, &read)?;
             }
-            Ok(())
-        })
+            self.process_reads(&pool, coverage.as_ref(), reads)
+        })?;
+        self.finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use assert_fs::TempDir;
+    use float_eq::assert_float_eq;
+
+    use super::*;
+    use crate::arrow::{
+        arrow_utils::load_apply,
+        metadata::{Metadata, Strand},
+        scored_read::{ReadQuality, Score},
+    };
+
+    fn scored_read(name: &str, start: u64, score_pos: &[u64]) -> ScoredRead {
+        let metadata = Metadata::new(
+            name.to_string(),
+            "chrI".to_string(),
+            start,
+            100,
+            Strand::plus(),
+            String::new(),
+        );
+        let scores = score_pos
+            .iter()
+            .map(|&pos| Score::new(pos, "AAAAAA".to_string(), false, Some(0.9), 0.0, 0.9))
+            .collect();
+        ScoredRead::new(metadata, scores)
+    }
+
+    /// Read on chrI starting at its first score
+    fn read_from_scores(strand: Strand, scores: Vec<Score>) -> ScoredRead {
+        let start = scores.iter().map(|s| s.pos).min().unwrap_or(0);
+        let metadata = Metadata::new(
+            "read".to_string(),
+            "chrI".to_string(),
+            start,
+            100,
+            strand,
+            String::new(),
+        );
+        ScoredRead::new(metadata, scores)
+    }
+
+    /// Score model that is the same for every score
+    fn flat_bkde() -> BinnedKde {
+        BinnedKde::new(vec![0.001; 1000])
+    }
+
+    /// Positive and negative control score models, accessible positions are
+    /// more likely to have high scores
+    fn ramp_bkdes() -> (BinnedKde, BinnedKde) {
+        let pos_bkde = BinnedKde::new((1..=1000).map(|i| i as f64 / 500_500.).collect());
+        let neg_bkde = BinnedKde::new((1..=1000).rev().map(|i| i as f64 / 500_500.).collect());
+        (pos_bkde, neg_bkde)
+    }
+
+    /// Write the reads to an Arrow file, like the output of cawlr score
+    fn write_scores(scores_path: &Path, reads: &[ScoredRead]) -> Result<()> {
+        let mut writer = wrap_writer(File::create(scores_path)?, &ScoredRead::schema())?;
+        save(&mut writer, reads)?;
+        writer.finish()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_motifs() {
+        let sink = || Box::new(std::io::sink());
+        let mut read = scored_read("read1", 1000, &[1010, 1020]);
+        read.scores[1].kmer = "CAGTTT".to_string();
+
+        let sma = SmaOptions::new(flat_bkde(), flat_bkde(), Motif::all_chg(), sink());
+        let filtered = sma.filter_motifs(read.clone());
+        assert_eq!(filtered.scores().len(), 1);
+        assert_eq!(filtered.scores()[0].pos, 1020);
+
+        let sma = SmaOptions::new(flat_bkde(), flat_bkde(), Vec::new(), sink());
+        assert_eq!(sma.filter_motifs(read.clone()).scores().len(), 2);
+
+        read.metadata.strand = Strand::minus();
+        let sma = SmaOptions::new(flat_bkde(), flat_bkde(), Motif::all_cg(), sink());
+        assert_eq!(sma.filter_motifs(read).scores().len(), 2);
+    }
+
+    fn run_sma(temp_dir: &TempDir, scores_path: &Path, min_coverage: usize) -> Result<Vec<String>> {
+        let output = temp_dir.path().join(format!("sma_{min_coverage}.bed"));
+        let writer = Box::new(File::create(&output)?);
+        let mut sma = SmaOptions::new(flat_bkde(), flat_bkde(), Vec::new(), writer);
+        sma.min_coverage(min_coverage);
+        sma.run(scores_path)?;
+        let lines = std::fs::read_to_string(output)?
+            .lines()
+            .skip(1)
+            .map(String::from)
+            .collect();
+        Ok(lines)
+    }
+
+    #[test]
+    fn test_min_coverage() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let scores_path = temp_dir.path().join("scores.arrow");
+        let reads = vec![
+            scored_read("read1", 1000, &[1010]),
+            scored_read("read2", 1005, &[1010]),
+        ];
+        write_scores(&scores_path, &reads)?;
+
+        let lines = run_sma(&temp_dir, &scores_path, 1)?;
+        assert_eq!(lines.len(), 2);
+        let lines = run_sma(&temp_dir, &scores_path, 2)?;
+        assert_eq!(lines.len(), 2);
+
+        let lines = run_sma(&temp_dir, &scores_path, 3)?;
+        assert!(lines.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_min_scored_positions() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let scores_path = temp_dir.path().join("scores.arrow");
+        let few = scored_read("few", 1000, &[1010]);
+        let stored = scored_read("stored", 1000, &[1010]).with_quality(ReadQuality {
+            n_motif_positions: 5,
+            ..ReadQuality::default()
+        });
+        let many = scored_read("many", 1000, &[1010, 1020, 1030]);
+        write_scores(&scores_path, &[few, stored, many])?;
+
+        let output = temp_dir.path().join("sma.bed");
+        let mut sma = SmaOptions::new(
+            flat_bkde(),
+            flat_bkde(),
+            Vec::new(),
+            Box::new(File::create(&output)?),
+        );
+        sma.min_scored_positions(3);
+        sma.run(&scores_path)?;
+        let names = std::fs::read_to_string(output)?
+            .lines()
+            .skip(1)
+            .map(|line| line.split('\t').nth(3).unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["stored", "many"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_scoring_method_mismatch() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let scores_path = temp_dir.path().join("scores.arrow");
+        let mut read = scored_read("read1", 1000, &[]);
+        read.scores = (0..40)
+            .map(|i| {
+                let x = i as f64 / 10. - 2.;
+                Score::new(1000 + i, "AAAAAA".to_string(), false, Some(x), 0.0, x)
+            })
+            .collect();
+        let schema = ScoringMethod::LogOdds.schema();
+        let mut writer = wrap_writer(File::create(&scores_path)?, &schema)?;
+        save(&mut writer, &[read])?;
+        writer.finish()?;
+
+        let log_odds_bkde = || {
+            crate::score_model::Options::default()
+                .bins(100)
+                .run(File::open(&scores_path).unwrap())
+                .unwrap()
+        };
+        assert_eq!(log_odds_bkde().scoring_method(), ScoringMethod::LogOdds);
+        let sma = |pos_bkde, neg_bkde| -> Result<()> {
+            let writer = Box::new(std::io::sink());
+            SmaOptions::new(pos_bkde, neg_bkde, Vec::new(), writer).run(&scores_path)
+        };
+
+        assert!(sma(log_odds_bkde(), log_odds_bkde()).is_ok());
+        assert!(sma(flat_bkde(), flat_bkde()).is_err());
+        assert!(sma(log_odds_bkde(), flat_bkde()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_per_read_output() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let scores_path = temp_dir.path().join("scores.arrow");
+        let mut mixed_read = scored_read("read2", 1000, &[1010, 1020, 1030, 1040]);
+        mixed_read.scores[1].score = 0.1;
+        let reads = vec![scored_read("read1", 1000, &[1020, 1010, 1050]), mixed_read];
+        write_scores(&scores_path, &reads)?;
+
+        let mut per_read_output = Vec::new();
+        let mut sma = SmaOptions::new(
+            flat_bkde(),
+            flat_bkde(),
+            Vec::new(),
+            Box::new(std::io::sink()),
+        );
+        sma.per_read_output(Some(Box::new(&mut per_read_output)));
+        sma.run(&scores_path)?;
+
+        let per_read_output = String::from_utf8(per_read_output)?;
+        let lines = per_read_output
+            .lines()
+            .map(|line| line.split('\t').collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[0],
+            [
+                "chrI", "1010", "1051", "read1", "1000", "+", "1010", "1051", "255,0,0", "3",
+                "1,1,1", "0,10,40"
+            ]
+        );
+        assert_eq!(lines[1][4], "750");
+        assert_eq!(lines[1][9], "4");
+        assert_eq!(lines[1][11], "0,10,20,30");
+        Ok(())
+    }
+
+    #[test]
+    fn test_per_read_log_odds() -> Result<()> {
+        let score =
+            |pos, score| Score::new(pos, "AAAAAA".to_string(), false, Some(score), 0.0, score);
+        let read = read_from_scores(
+            Strand::plus(),
+            vec![score(1010, 0.3), score(1020, -0.3), score(1010, -2.0)],
+        );
+        let mut output = Vec::new();
+        per_read(&mut output, &read, ScoringMethod::LogOdds)?;
+        let output = String::from_utf8(output)?;
+        let fields = output.trim_end().split('\t').collect::<Vec<_>>();
+        assert_eq!(fields[4], "500");
+        assert_eq!(fields[9], "2");
+        assert_eq!(fields[11], "0,10");
+
+        let mut output = Vec::new();
+        per_read(&mut output, &read, ScoringMethod::ProbabilityRatio)?;
+        let output = String::from_utf8(output)?;
+        assert_eq!(output.split('\t').nth(4), Some("0"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_by_strand() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let scores_path = temp_dir.path().join("scores.arrow");
+        let plus = scored_read("plus", 1000, &[1010, 1020]);
+        let mut minus = scored_read("minus", 1000, &[1010, 1020]);
+        minus.metadata.strand = Strand::minus();
+        let mut unknown = scored_read("unknown", 1000, &[1010, 1020]);
+        unknown.metadata.strand = Strand::unknown();
+        write_scores(&scores_path, &[plus, minus, unknown])?;
+
+        let (mut output, mut plus_output, mut minus_output, mut unknown_output) =
+            (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+        let mut sma = SmaOptions::new(flat_bkde(), flat_bkde(), Vec::new(), Box::new(&mut output));
+        sma.track_name("sma")
+            .split_by_strand(Some(StrandWriters::new(
+                Box::new(&mut plus_output),
+                Box::new(&mut minus_output),
+                Box::new(&mut unknown_output),
+            )));
+        sma.run(&scores_path)?;
+
+        let output = String::from_utf8(output)?;
+        assert_eq!(output.lines().count(), 4);
+        for (strand_output, name, strand, rgb) in [
+            (plus_output, "plus", "+", "255,0,0"),
+            (minus_output, "minus", "-", "0,0,255"),
+            (unknown_output, "unknown", ".", "0,0,0"),
+        ] {
+            let strand_output = String::from_utf8(strand_output)?;
+            let lines = strand_output.lines().collect::<Vec<_>>();
+            assert_eq!(lines.len(), 2);
+            assert_eq!(
+                lines[0],
+                format!("track name=\"sma.{name}\" itemRgb=\"on\" visibility=2")
+            );
+            let fields = lines[1].split('\t').collect::<Vec<_>>();
+            assert_eq!(fields[3], name);
+            assert_eq!(fields[5], strand);
+            assert_eq!(fields[8], rgb);
+            assert!(output.lines().any(|line| line == lines[1]));
+        }
+
+        let mut sma = SmaOptions::new(
+            flat_bkde(),
+            flat_bkde(),
+            Vec::new(),
+            Box::new(std::io::sink()),
+        );
+        sma.output_format(SmaOutputFormat::Wig)
+            .split_by_strand(Some(StrandWriters::new(
+                Box::new(std::io::sink()),
+                Box::new(std::io::sink()),
+                Box::new(std::io::sink()),
+            )));
+        assert!(sma.run(&scores_path).is_err());
+
+        assert_eq!(
+            strand_bed_path("out/sma.bed", "minus"),
+            PathBuf::from("out/sma.minus.bed")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_per_chromosome() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let scores_path = temp_dir.path().join("scores.arrow");
+        let on_chrom = |name: &str, chrom: &str| {
+            let mut read = scored_read(name, 1000, &[1010, 1020]);
+            read.metadata.chrom = chrom.to_string();
+            read
+        };
+        // chrI again after chrII is appended to its file
+        let reads = [
+            on_chrom("read1", "chrI"),
+            on_chrom("read2", "chrI"),
+            on_chrom("read3", "chrII"),
+            on_chrom("read4", "chrI"),
+        ];
+        write_scores(&scores_path, &reads)?;
+
+        let prefix = temp_dir.path().join("sma");
+        let mut output = Vec::new();
+        let mut sma = SmaOptions::new(flat_bkde(), flat_bkde(), Vec::new(), Box::new(&mut output));
+        sma.track_name("sma").per_chromosome(Some(prefix.clone()));
+        sma.run(&scores_path)?;
+        assert!(output.is_empty());
+
+        for (chrom, names) in [
+            ("chrI", vec!["read1", "read2", "read4"]),
+            ("chrII", vec!["read3"]),
+        ] {
+            let path = chrom_bed_path(&prefix, chrom);
+            assert_eq!(path, temp_dir.path().join(format!("sma.{chrom}.bed")));
+            let bed = std::fs::read_to_string(path)?;
+            let lines = bed.lines().collect::<Vec<_>>();
+            assert_eq!(
+                lines[0],
+                format!("track name=\"sma.{chrom}\" itemRgb=\"on\" visibility=2")
+            );
+            let fields = lines[1..]
+                .iter()
+                .map(|line| line.split('\t').collect::<Vec<_>>())
+                .collect::<Vec<_>>();
+            assert!(fields.iter().all(|fields| fields[0] == chrom));
+            let read_names = fields.iter().map(|fields| fields[3]).collect::<Vec<_>>();
+            assert_eq!(read_names, names);
+        }
+
+        let no_tmp_files = || -> Result<bool> {
+            for entry in std::fs::read_dir(temp_dir.path())? {
+                if entry?.path().extension().is_some_and(|ext| ext == "tmp") {
+                    return Ok(false);
+                }
+            }
+            Ok(true)
+        };
+        assert!(no_tmp_files()?);
+
+        // Existing files are only replaced with overwrite
+        let chr_ii = chrom_bed_path(&prefix, "chrII");
+        std::fs::write(&chr_ii, "old")?;
+        let mut sma = SmaOptions::new(
+            flat_bkde(),
+            flat_bkde(),
+            Vec::new(),
+            Box::new(std::io::sink()),
+        );
+        sma.per_chromosome(Some(prefix.clone()));
+        assert!(sma.run(&scores_path).is_err());
+        assert_eq!(std::fs::read_to_string(&chr_ii)?, "old");
+        assert!(no_tmp_files()?);
+        let mut sma = SmaOptions::new(
+            flat_bkde(),
+            flat_bkde(),
+            Vec::new(),
+            Box::new(std::io::sink()),
+        );
+        sma.per_chromosome(Some(prefix.clone())).overwrite(true);
+        sma.run(&scores_path)?;
+        assert_ne!(std::fs::read_to_string(&chr_ii)?, "old");
+        assert!(no_tmp_files()?);
+
+        let mut sma = SmaOptions::new(
+            flat_bkde(),
+            flat_bkde(),
+            Vec::new(),
+            Box::new(std::io::sink()),
+        );
+        sma.output_format(SmaOutputFormat::BedGraph)
+            .per_chromosome(Some(prefix));
+        assert!(sma.run(&scores_path).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_posteriors_output() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let scores_path = temp_dir.path().join("scores.arrow");
+        let mut read1 = scored_read("read1", 1000, &[1010, 1020, 1030, 1040, 1050]);
+        for (score, x) in read1.scores.iter_mut().zip([0.9, 0.05, 0.5, 0.3, f64::NAN]) {
+            score.score = x;
+        }
+        let mut read2 = scored_read("read2", 1000, &[1015, 1025, 1035]);
+        for (score, x) in read2.scores.iter_mut().zip([0.7, 0.1, 0.99]) {
+            score.score = x;
+        }
+        write_scores(&scores_path, &[read1, read2])?;
+
+        let run = |posteriors_output: Option<&mut Vec<u8>>| -> Result<Vec<u8>> {
+            let (pos_bkde, neg_bkde) = ramp_bkdes();
+            let mut output = Vec::new();
+            let mut sma = SmaOptions::new(pos_bkde, neg_bkde, Vec::new(), Box::new(&mut output));
+            sma.posteriors_output(
+                posteriors_output.map(|output| Box::new(output) as Box<dyn Write + '_>),
+            );
+            sma.run(&scores_path)?;
+            Ok(output)
+        };
+        let mut posteriors_output = Vec::new();
+        let bed = run(Some(&mut posteriors_output))?;
+        assert_eq!(bed, run(None)?);
+
+        let mut reads = Vec::new();
+        load_apply(
+            std::io::Cursor::new(posteriors_output),
+            |mut rs: Vec<ReadPosteriors>| {
+                reads.append(&mut rs);
+                Ok(())
+            },
+        )?;
+        assert_eq!(reads.len(), 2);
+        assert_eq!(reads[0].name(), "read1");
+        // NaN score is skipped
+        assert_eq!(reads[0].posteriors().len(), 4);
+        assert_eq!(reads[0].posteriors()[1].pos, 1020);
+
+        let mut posteriors = reads
+            .iter()
+            .flat_map(|read| read.posteriors().iter().cloned())
+            .collect::<Vec<_>>();
+        posteriors.sort_by(|a, b| a.score.total_cmp(&b.score));
+        assert!(posteriors
+            .windows(2)
+            .all(|w| w[0].posterior <= w[1].posterior));
+        assert!(posteriors
+            .iter()
+            .all(|p| (0.0..=1.0).contains(&p.posterior)));
+        let mid = posteriors.iter().find(|p| p.score == 0.5).unwrap();
+        assert_float_eq!(mid.pos_ln_likelihood, mid.neg_ln_likelihood, abs <= 1e-2);
+        assert_float_eq!(mid.posterior, 0.5, abs <= 1e-2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_wig_output() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let scores_path = temp_dir.path().join("scores.arrow");
+        let mut chr2_read = scored_read("read3", 500, &[520, 510]);
+        chr2_read.metadata.chrom = "chrII".to_string();
+        let reads = vec![
+            scored_read("read1", 1000, &[1010, 1020]),
+            chr2_read,
+            scored_read("read2", 1005, &[1010]),
+        ];
+        write_scores(&scores_path, &reads)?;
+
+        let output = temp_dir.path().join("sma.wig");
+        let writer = Box::new(File::create(&output)?);
+        let mut sma = SmaOptions::new(flat_bkde(), flat_bkde(), Vec::new(), writer);
+        sma.output_format(SmaOutputFormat::Wig).track_name("test");
+        sma.run(&scores_path)?;
+
+        let wig = std::fs::read_to_string(output)?;
+        let lines = wig.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "track type=wiggle_0 name=\"test\" visibility=2");
+        assert_eq!(lines[1], "variableStep chrom=chrI span=1");
+        assert!(lines[2].starts_with("1011\t"));
+        assert!(lines[3].starts_with("1021\t"));
+        assert_eq!(lines[4], "variableStep chrom=chrII span=1");
+        assert!(lines[5].starts_with("511\t"));
+        assert!(lines[6].starts_with("521\t"));
+        assert_eq!(lines.len(), 7);
+        for line in [2, 3, 5, 6] {
+            let value: f64 = lines[line].split('\t').nth(1).unwrap().parse()?;
+            assert!((0.0..=1.0).contains(&value));
+        }
+        Ok(())
+    }
+
+    fn bedgraph_intervals(
+        acc: &Accessibility,
+        run_length_encode: bool,
+        smoothing_window: Option<usize>,
+    ) -> Vec<(u64, u64, f64)> {
+        let mut output = Vec::new();
+        acc.write_bedgraph(&mut output, run_length_encode, smoothing_window)
+            .unwrap();
+        String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| {
+                let cols = line.split('\t').collect::<Vec<_>>();
+                assert_eq!(cols[0], "chrI");
+                (
+                    cols[1].parse().unwrap(),
+                    cols[2].parse().unwrap(),
+                    cols[3].parse().unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_bedgraph_run_length_encode() {
+        let mut acc = Accessibility::default();
+        let chrom_acc = acc.0.entry("chrI".to_string()).or_default();
+        for (pos, counts) in [
+            (10, (1, 2)),
+            (11, (2, 4)),
+            (12, (1, 2)),
+            (13, (0, 2)),
+            (20, (0, 1)),
+            (21, (0, 3)),
+        ] {
+            chrom_acc.insert(pos, counts);
+        }
+
+        let merged = bedgraph_intervals(&acc, true, None);
+        assert_eq!(merged, vec![(10, 13, 0.5), (13, 14, 0.0), (20, 22, 0.0)]);
+
+        let unmerged = bedgraph_intervals(&acc, false, None);
+        assert_eq!(unmerged.len(), 6);
+        assert!(unmerged.iter().all(|(s, e, _)| e - s == 1));
+
+        let covered = |xs: &[(u64, u64, f64)]| xs.iter().map(|(s, e, _)| e - s).sum::<u64>();
+        assert_eq!(covered(&merged), covered(&unmerged));
+    }
+
+    #[test]
+    fn test_smoothing_window() {
+        let mut acc = Accessibility::default();
+        let chrom_acc = acc.0.entry("chrI".to_string()).or_default();
+        for pos in 0..20 {
+            let n_accessible = if pos % 2 == 0 { 4 } else { 0 };
+            chrom_acc.insert(100 + pos, (n_accessible, 4));
+        }
+
+        let adjacent_var = |xs: &[(u64, u64, f64)]| {
+            xs.windows(2)
+                .map(|w| (w[1].2 - w[0].2).powi(2))
+                .sum::<f64>()
+                / (xs.len() - 1) as f64
+        };
+        let raw = bedgraph_intervals(&acc, false, None);
+        let smoothed = bedgraph_intervals(&acc, false, Some(3));
+        assert_eq!(raw.len(), smoothed.len());
+        assert!(adjacent_var(&smoothed) < adjacent_var(&raw));
+
+        // Partial window at the ends
+        assert_float_eq!(smoothed[0].2, 0.5, abs <= 1e-9);
+        assert_float_eq!(smoothed[1].2, 2.0 / 3.0, abs <= 1e-9);
+        assert_float_eq!(smoothed[19].2, 0.5, abs <= 1e-9);
+        assert_eq!(bedgraph_intervals(&acc, false, Some(1)), raw);
+    }
+
+    #[test]
+    fn test_parse_segmentation() {
+        assert_eq!("hmm".parse::<Segmentation>(), Ok(Segmentation::Hmm));
+        assert_eq!(
+            Segmentation::default().to_string().parse::<Segmentation>(),
+            Ok(Segmentation::Independent)
+        );
+        assert_eq!(
+            "independent".parse::<Segmentation>(),
+            Ok(Segmentation::default())
+        );
+        assert_eq!(
+            "nucleosome".parse::<Segmentation>(),
+            Ok(Segmentation::Independent)
+        );
+        assert!("viterbi".parse::<Segmentation>().is_err());
+    }
+
+    #[test]
+    fn test_call_footprints() {
+        let (pos_bkde, neg_bkde) = ramp_bkdes();
+        // Low scores from 1030 to 1060, and a single noisy one at 1080
+        let positions = (1000..1100).step_by(5).collect::<Vec<_>>();
+        let mut read = scored_read("read", 1000, &positions);
+        for score in read.scores.iter_mut() {
+            if (1030..=1060).contains(&score.pos) || score.pos == 1080 {
+                score.score = 0.1;
+            }
+        }
+        let models = ScoreModels::new(pos_bkde, neg_bkde);
+        let footprints = |p_stay| call_footprints(&models, &read, p_stay);
+
+        // Each scored position is called on its own, unscored bases take the
+        // state of the next scored base
+        assert_eq!(footprints(0.5), vec![(1026, 1061), (1076, 1081)]);
+
+        let smoothed = footprints(DEFAULT_P_STAY);
+        assert_eq!(smoothed.len(), 1);
+        let (start, end) = smoothed[0];
+        assert!(start > 1025 && start <= 1030, "{smoothed:?}");
+        assert!(end > 1060 && end <= 1065, "{smoothed:?}");
+
+        // Switching costs more than the footprint explains
+        assert!(footprints(0.9999).is_empty());
+
+        let mut sma = SmaOptions::new(
+            flat_bkde(),
+            flat_bkde(),
+            Vec::new(),
+            Box::new(std::io::sink()),
+        );
+        sma.segmentation(Segmentation::Hmm).p_stay(1.0);
+        assert!(sma.start().is_err());
+        sma.footprint_length(147);
+        assert_float_eq!(sma.segmenter.p_stay, DEFAULT_P_STAY, abs <= 1e-12);
+        assert!(sma.start().is_ok());
+    }
+
+    #[test]
+    fn test_parse_motif_ctrl_scores() {
+        let scores = "1:GC,gc_pos.pickle,gc_neg.pickle"
+            .parse::<MotifCtrlScores>()
+            .unwrap();
+        assert_eq!(scores.motif.to_string(), "1:GC");
+        assert_eq!(scores.pos_ctrl_scores, PathBuf::from("gc_pos.pickle"));
+        assert_eq!(scores.neg_ctrl_scores, PathBuf::from("gc_neg.pickle"));
+        assert!("1:GC,gc_pos.pickle".parse::<MotifCtrlScores>().is_err());
+        assert!("1:GC,,gc_neg.pickle".parse::<MotifCtrlScores>().is_err());
+        assert!("GC,gc_pos.pickle,gc_neg.pickle"
+            .parse::<MotifCtrlScores>()
+            .is_err());
+    }
+
+    #[test]
+    fn test_motif_models() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let scores_path = temp_dir.path().join("scores.arrow");
+        // Low scores from 1030 to 1060, all from GC
+        let positions = (1000..1100).step_by(5).collect::<Vec<_>>();
+        let mut read = scored_read("read", 1000, &positions);
+        for score in read.scores.iter_mut() {
+            if (1030..=1060).contains(&score.pos) {
+                score.score = 0.1;
+                score.kmer = "GCAAAA".to_string();
+            }
+        }
+        write_scores(&scores_path, &[read.clone()])?;
+
+        let pos_bkde = || ramp_bkdes().0;
+        let neg_bkde = || ramp_bkdes().1;
+        let sma_line = |gc_models: Option<(BinnedKde, BinnedKde)>| -> Result<Vec<String>> {
+            let mut output = Vec::new();
+            let mut sma =
+                SmaOptions::new(pos_bkde(), neg_bkde(), Vec::new(), Box::new(&mut output));
+            sma.segmentation(Segmentation::Hmm).p_stay(0.5);
+            if let Some((gc_pos, gc_neg)) = gc_models {
+                sma.motif_models(Motif::parse_from_str("1:CG")?, neg_bkde(), pos_bkde())
+                    .motif_models(Motif::parse_from_str("1:GC")?, gc_pos, gc_neg);
+            }
+            sma.run(&scores_path)?;
+            let output = String::from_utf8(output)?;
+            let line = output.lines().nth(1).unwrap();
+            Ok(line.split('\t').map(String::from).collect())
+        };
+
+        let default_line = sma_line(None)?;
+        assert_eq!(default_line[3], "read");
+        assert!(default_line[9] != "2");
+
+        // Low GC scores are expected in accessible bases with GC models that
+        // are swapped
+        let gc_line = sma_line(Some((neg_bkde(), pos_bkde())))?;
+        assert_eq!(gc_line[3], "read|1:GC");
+        // Only the pseudo blocks at the ends of the read
+        assert_eq!(gc_line[9], "2");
+
+        // Same models as the default ones only change the name
+        let same_line = sma_line(Some((pos_bkde(), neg_bkde())))?;
+        assert_eq!(same_line[3], "read|1:GC");
+        assert_eq!(same_line[9..], default_line[9..]);
+
+        // Minus strand reads without the motif of each score use the default
+        // models, and keep their name as is
+        read.metadata.strand = Strand::minus();
+        let models = ScoreModels {
+            pos_bkde: pos_bkde(),
+            neg_bkde: neg_bkde(),
+            motif_models: vec![(Motif::parse_from_str("1:GC")?, neg_bkde(), pos_bkde())],
+        };
+        assert_eq!(models.bed_name(&read), "read");
+        assert_eq!(
+            call_footprints(&models, &read, 0.5),
+            call_footprints(&ScoreModels::new(pos_bkde(), neg_bkde()), &read, 0.5)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_motif_models_overlapping() -> Result<()> {
+        let pos_bkde = || ramp_bkdes().0;
+        let neg_bkde = || ramp_bkdes().1;
+        let models = ScoreModels {
+            pos_bkde: pos_bkde(),
+            neg_bkde: neg_bkde(),
+            motif_models: vec![
+                (Motif::parse_from_str("2:GC")?, neg_bkde(), pos_bkde()),
+                (Motif::parse_from_str("1:CG")?, pos_bkde(), neg_bkde()),
+            ],
+        };
+        let gc = Motif::parse_from_str("2:GC")?;
+        let cg = Motif::parse_from_str("1:CG")?;
+        let score = |pos, kmer: &str, motif| {
+            Score::new(pos, kmer.to_string(), false, Some(0.9), 0.0, 0.9).with_motif(motif)
+        };
+        // The part of 2:GC from the modified base on, C, also starts CpG
+        // kmers, the motif of the score picks the models
+        let read = read_from_scores(
+            Strand::plus(),
+            vec![
+                score(1010, "CGAAAA", Some(&cg)),
+                score(1020, "CAAAAA", Some(&gc)),
+            ],
+        );
+        assert_eq!(models.motif_idx(&read, &read.scores()[0]), Some(1));
+        assert_eq!(models.motif_idx(&read, &read.scores()[1]), Some(0));
+        assert_eq!(models.bed_name(&read), "read|2:GC,1:CG");
+
+        let cg_only = read_from_scores(Strand::plus(), vec![score(1010, "CGAAAA", Some(&cg))]);
+        assert_eq!(models.bed_name(&cg_only), "read|1:CG");
+
+        // Minus strand reads use the models of the motif of the score
+        let minus = read_from_scores(Strand::minus(), vec![score(1010, "GCAAAA", Some(&gc))]);
+        assert_eq!(models.motif_idx(&minus, &minus.scores()[0]), Some(0));
+
+        // Scores of motifs without their own models use the default models
+        let other = Motif::parse_from_str("1:CA")?;
+        let read = read_from_scores(Strand::plus(), vec![score(1010, "CAAAAA", Some(&other))]);
+        assert_eq!(models.motif_idx(&read, &read.scores()[0]), None);
+        assert_eq!(models.bed_name(&read), "read");
+        Ok(())
+    }
+
+    #[test]
+    fn test_clean_blocks() {
+        let blocks = vec![(10, 12), (13, 30), (35, 36), (50, 60)];
+        assert_eq!(clean_blocks(blocks.clone(), 0, 0), blocks);
+        assert_eq!(
+            clean_blocks(blocks.clone(), 0, 2),
+            vec![(10, 30), (35, 36), (50, 60)]
+        );
+        assert_eq!(clean_blocks(blocks.clone(), 3, 0), vec![(13, 30), (50, 60)]);
+        // Short blocks are kept if merging makes them long enough
+        assert_eq!(clean_blocks(blocks, 3, 6), vec![(10, 36), (50, 60)]);
+        assert!(clean_blocks(Vec::new(), 3, 6).is_empty());
+    }
+
+    #[test]
+    fn test_min_block_len_merge_gap() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let scores_path = temp_dir.path().join("scores.arrow");
+        let positions = (1000..1100).step_by(5).collect::<Vec<_>>();
+        let mut read = scored_read("read", 1000, &positions);
+        for score in read.scores.iter_mut() {
+            if [1030, 1035, 1045, 1080].contains(&score.pos) {
+                score.score = 0.1;
+            }
+        }
+        write_scores(&scores_path, &[read])?;
+
+        let blocks = |min_block_len, merge_gap| -> Result<Vec<(u64, u64)>> {
+            let (pos_bkde, neg_bkde) = ramp_bkdes();
+            let mut output = Vec::new();
+            let mut sma = SmaOptions::new(pos_bkde, neg_bkde, Vec::new(), Box::new(&mut output));
+            sma.segmentation(Segmentation::Hmm)
+                .p_stay(0.5)
+                .min_block_len(min_block_len)
+                .merge_gap(merge_gap);
+            sma.run(&scores_path)?;
+            let output = String::from_utf8(output)?;
+            let cols = output
+                .lines()
+                .nth(1)
+                .unwrap()
+                .split('\t')
+                .collect::<Vec<_>>();
+            let start: u64 = cols[1].parse()?;
+            let sizes = cols[10]
+                .split(',')
+                .map(|x| x.parse::<u64>())
+                .collect::<Result<Vec<_>, _>>()?;
+            let starts = cols[11]
+                .split(',')
+                .map(|x| x.parse::<u64>())
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(starts
+                .into_iter()
+                .zip(sizes)
+                .map(|(s, l)| (start + s, start + s + l))
+                .collect())
+        };
+
+        // First and last blocks mark the ends of the read
+        assert_eq!(
+            blocks(0, 0)?,
+            vec![
+                (1000, 1001),
+                (1026, 1036),
+                (1041, 1046),
+                (1076, 1081),
+                (1099, 1100)
+            ]
+        );
+        assert_eq!(
+            blocks(0, 6)?,
+            vec![(1000, 1001), (1026, 1046), (1076, 1081), (1099, 1100)]
+        );
+        assert_eq!(
+            blocks(6, 0)?,
+            vec![(1000, 1001), (1026, 1036), (1099, 1100)]
+        );
+        assert_eq!(
+            blocks(6, 6)?,
+            vec![(1000, 1001), (1026, 1046), (1099, 1100)]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_threads() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let scores_path = temp_dir.path().join("scores.arrow");
+        let reads = (0..20)
+            .map(|i| {
+                let positions = (0..40).map(|j| 1000 + i * 7 + j * 2).collect::<Vec<_>>();
+                let mut read = scored_read(&format!("read{i}"), 1000 + i * 7, &positions);
+                for (j, score) in read.scores.iter_mut().enumerate() {
+                    score.score = ((i as usize * 13 + j * 7) % 10) as f64 / 10.;
+                }
+                read
+            })
+            .collect::<Vec<_>>();
+        write_scores(&scores_path, &reads)?;
+
+        let run = |threads, segmentation| -> Result<Vec<u8>> {
+            let (pos_bkde, neg_bkde) = ramp_bkdes();
+            let mut output = Vec::new();
+            let mut sma = SmaOptions::new(pos_bkde, neg_bkde, Vec::new(), Box::new(&mut output));
+            sma.threads(Some(threads))
+                .batch_size(Some(6))
+                .segmentation(segmentation);
+            sma.run(&scores_path)?;
+            Ok(output)
+        };
+        for segmentation in [Segmentation::Independent, Segmentation::Hmm] {
+            let single = run(1, segmentation)?;
+            let names = String::from_utf8(single.clone())?
+                .lines()
+                .skip(1)
+                .map(|line| line.split('\t').nth(3).unwrap().to_string())
+                .collect::<Vec<_>>();
+            let expected = (0..20).map(|i| format!("read{i}")).collect::<Vec<_>>();
+            assert_eq!(names, expected);
+            assert_eq!(single, run(4, segmentation)?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_batches() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let scores_path = temp_dir.path().join("scores.arrow");
+        let mut reads = (0..20)
+            .map(|i| scored_read(&format!("read{i}"), 1000 + i * 7, &[1000 + i * 7]))
+            .collect::<Vec<_>>();
+        let unaligned = Metadata::new(
+            "unaligned".to_string(),
+            String::new(),
+            0,
+            1,
+            Strand::plus(),
+            String::new(),
+        );
+        reads.push(ScoredRead::new(unaligned, Vec::new()));
+        write_scores(&scores_path, &reads)?;
+
+        let batch_lens = |batch_size| -> Result<Vec<usize>> {
+            let mut lens = Vec::new();
+            apply_read_batches(ModFile::open_arrow(&scores_path)?, batch_size, |reads| {
+                lens.push(reads.len());
+                Ok(())
+            })?;
+            Ok(lens)
+        };
+        assert_eq!(batch_lens(None)?, vec![20]);
+        assert_eq!(batch_lens(Some(6))?, vec![6, 6, 6, 2]);
+
+        let run = |batch_size| -> Result<Vec<u8>> {
+            let (pos_bkde, neg_bkde) = ramp_bkdes();
+            let mut output = Vec::new();
+            let mut sma = SmaOptions::new(pos_bkde, neg_bkde, Vec::new(), Box::new(&mut output));
+            sma.batch_size(batch_size).min_coverage(2);
+            sma.run_modfile(ModFile::open_arrow(&scores_path)?)?;
+            Ok(output)
+        };
+        assert_eq!(run(None)?, run(Some(6))?);
+        Ok(())
     }
 }
@@ -0,0 +1,189 @@
+use std::{
+    collections::BTreeMap,
+    io::{Read, Seek, Write},
+};
+
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::arrow::{arrow_utils::load_apply, metadata::MetadataExt, scored_read::ScoredRead};
+
+/// Number of equal width bins between 0 and 1 in the score histogram
+pub const N_HISTOGRAM_BINS: usize = 20;
+
+/// Summary of the reads and scores in the output of cawlr score
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScoreStats {
+    pub n_reads: usize,
+    pub n_positions: usize,
+    pub mean_positions_per_read: f64,
+    /// Number of reads aligned to each chromosome
+    pub chrom_reads: BTreeMap<String, usize>,
+    /// Counts of scores in equal width bins from 0 to 1, scores of exactly 1
+    /// are counted in the last bin
+    pub score_histogram: Vec<usize>,
+    pub n_missing_signal: usize,
+    pub missing_signal_fraction: f64,
+    pub mean_skip_score: f64,
+    #[serde(skip)]
+    skip_score_sum: f64,
+}
+
+impl Default for ScoreStats {
+    fn default() -> Self {
+        Self {
+            n_reads: 0,
+            n_positions: 0,
+            mean_positions_per_read: f64::NAN,
+            chrom_reads: BTreeMap::new(),
+            score_histogram: vec![0; N_HISTOGRAM_BINS],
+            n_missing_signal: 0,
+            missing_signal_fraction: f64::NAN,
+            mean_skip_score: f64::NAN,
+            skip_score_sum: 0.0,
+        }
+    }
+}
+
+impl ScoreStats {
+    /// Compute the summary in a single pass over a scored Arrow file
+    pub fn from_reader<R: Read + Seek>(reader: R) -> Result<Self> {
+        let mut stats = Self::default();
+        load_apply(reader, |reads: Vec<ScoredRead>| {
+            reads.iter().for_each(|read| stats.add_read(read));
+            Ok(())
+        })?;
+        Ok(stats)
+    }
+
+    /// Add a read to the counts and update the averages
+    pub fn add_read(&mut self, read: &ScoredRead) {
+        self.n_reads += 1;
+        *self
+            .chrom_reads
+            .entry(read.chrom().to_string())
+            .or_default() += 1;
+        // Positions without a model have NaN scores
+        for score in read.scores().iter().filter(|s| !s.missing_model) {
+            self.n_positions += 1;
+            let bin = (score.score * N_HISTOGRAM_BINS as f64) as usize;
+            if (0.0..=1.0).contains(&score.score) {
+                self.score_histogram[bin.min(N_HISTOGRAM_BINS - 1)] += 1;
+            }
+            if score.signal_score.is_none() {
+                self.n_missing_signal += 1;
+            }
+            self.skip_score_sum += score.skip_score;
+        }
+
+        let n_positions = self.n_positions as f64;
+        self.mean_positions_per_read = n_positions / self.n_reads as f64;
+        self.missing_signal_fraction = self.n_missing_signal as f64 / n_positions;
+        self.mean_skip_score = self.skip_score_sum / n_positions;
+    }
+
+    pub fn write_json<W: Write>(&self, writer: &mut W) -> Result<()> {
+        serde_json::to_writer_pretty(&mut *writer, self)?;
+        writeln!(writer)?;
+        Ok(())
+    }
+
+    /// Write the summary as aligned tables meant for reading in a terminal
+    pub fn write_human<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writeln!(writer, "{:<28}{}", "Reads", self.n_reads)?;
+        writeln!(writer, "{:<28}{}", "Scored positions", self.n_positions)?;
+        writeln!(
+            writer,
+            "{:<28}{:.2}",
+            "Mean positions per read", self.mean_positions_per_read
+        )?;
+        writeln!(
+            writer,
+            "{:<28}{:.4}",
+            "Missing signal score", self.missing_signal_fraction
+        )?;
+        writeln!(
+            writer,
+            "{:<28}{:.4}",
+            "Mean skip score", self.mean_skip_score
+        )?;
+
+        writeln!(writer, "\n{:<28}Reads", "Chromosome")?;
+        for (chrom, n_reads) in self.chrom_reads.iter() {
+            writeln!(writer, "{chrom:<28}{n_reads}")?;
+        }
+
+        writeln!(writer, "\n{:<28}Positions", "Score")?;
+        let width = 1.0 / N_HISTOGRAM_BINS as f64;
+        for (i, count) in self.score_histogram.iter().enumerate() {
+            let bin = format!("{:.2}-{:.2}", i as f64 * width, (i + 1) as f64 * width);
+            writeln!(writer, "{bin:<28}{count}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::arrow::{
+        metadata::{Metadata, Strand},
+        scored_read::Score,
+    };
+
+    fn read(chrom: &str, scores: &[(f64, Option<f64>)]) -> ScoredRead {
+        let metadata = Metadata::new(
+            "read".to_string(),
+            chrom.to_string(),
+            0,
+            scores.len() as u64,
+            Strand::plus(),
+            String::new(),
+        );
+        let scores = scores
+            .iter()
+            .enumerate()
+            .map(|(pos, &(score, signal_score))| {
+                Score::new(
+                    pos as u64,
+                    "AAAAAA".to_string(),
+                    false,
+                    signal_score,
+                    0.5,
+                    score,
+                )
+            })
+            .collect();
+        ScoredRead::new(metadata, scores)
+    }
+
+    #[test]
+    fn test_score_stats() {
+        let mut stats = ScoreStats::default();
+        stats.add_read(&read("chrI", &[(0.0, Some(0.0)), (0.52, None)]));
+        stats.add_read(&read("chrI", &[(1.0, Some(1.0))]));
+        // Positions without a model aren't counted
+        let mut missing_model = read("chrII", &[(0.99, Some(0.99))]);
+        missing_model
+            .scores
+            .push(Score::missing_model(1, "CCCCCC".to_string()));
+        stats.add_read(&missing_model);
+
+        assert_eq!(stats.n_reads, 3);
+        assert_eq!(stats.n_positions, 4);
+        assert_eq!(stats.mean_positions_per_read, 4.0 / 3.0);
+        assert_eq!(stats.chrom_reads["chrI"], 2);
+        assert_eq!(stats.chrom_reads["chrII"], 1);
+        assert_eq!(stats.score_histogram[0], 1);
+        assert_eq!(stats.score_histogram[10], 1);
+        assert_eq!(stats.score_histogram[N_HISTOGRAM_BINS - 1], 2);
+        assert_eq!(stats.missing_signal_fraction, 0.25);
+        assert_eq!(stats.mean_skip_score, 0.5);
+
+        let mut json = Vec::new();
+        stats.write_json(&mut json).unwrap();
+        let parsed: ScoreStats = serde_json::from_slice(&json).unwrap();
+        assert_eq!(parsed.n_reads, 3);
+        assert_eq!(parsed.score_histogram, stats.score_histogram);
+    }
+}
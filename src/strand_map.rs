@@ -1,7 +1,7 @@
 //! Provides StrandMap struct to get strand information from bam files.
 //!
-//! Intended to eventually replace PlusStrandMap and eventually add more
-//! metadata like alignment info from bam
+//! Replaces PlusStrandMap, and may eventually add more metadata like
+//! alignment info from bam
 use std::{path::Path, str::from_utf8};
 
 use bam::BamReader;
@@ -10,34 +10,116 @@ use fnv::FnvHashMap;
 
 use crate::arrow::metadata::Strand;
 
-#[derive(Default)]
+/// Strand of each read aligned in bam files
+#[derive(Debug, Default, Clone)]
 pub struct StrandMap(FnvHashMap<Vec<u8>, Strand>);
 
-#[allow(dead_code)]
 impl StrandMap {
     fn new(db: FnvHashMap<Vec<u8>, Strand>) -> Self {
         Self(db)
     }
 
+    /// Strands of the primary alignments in the bam file, see
+    /// [StrandMapOptions] to include other alignments
     pub fn from_bam_file<P: AsRef<Path>>(bam_file: P) -> Result<Self> {
+        StrandMapOptions::default().run(std::iter::once(bam_file))
+    }
+
+    /// Merge the strands of reads across bam files, such as per-flowcell or
+    /// per-chromosome shards. Reads mapped to both strands, in one file or
+    /// across files, are set to Strand::unknown().
+    pub fn from_multiple_bam_files<P: AsRef<Path>>(
+        bam_files: impl Iterator<Item = P>,
+    ) -> Result<Self> {
+        StrandMapOptions::default().run(bam_files)
+    }
+
+    /// Strand of the read, Strand::unknown() if its alignments are on both
+    /// strands
+    pub fn get<B>(&self, read_id: B) -> Option<Strand>
+    where
+        B: AsRef<[u8]>,
+    {
+        let read_id = read_id.as_ref();
+        self.0.get(read_id).copied()
+    }
+
+    /// Whether the read is on the plus strand like [PlusStrandMap::get], None
+    /// if the read is missing or its strand is unknown
+    ///
+    /// [PlusStrandMap::get]: crate::plus_strand_map::PlusStrandMap::get
+    pub fn get_plus_stranded<B>(&self, read_id: B) -> Option<bool>
+    where
+        B: AsRef<[u8]>,
+    {
+        self.get(read_id)
+            .filter(|strand| !strand.is_unknown_strand())
+            .map(|strand| !strand.is_minus_strand())
+    }
+
+    pub fn insert<B>(&mut self, read_id: B, strand: Strand)
+    where
+        B: Into<Vec<u8>>,
+    {
+        self.0.insert(read_id.into(), strand);
+    }
+}
+
+/// Which alignments in the bam files are used by [StrandMap]. Like
+/// `samtools view -F 2304`, supplementary and secondary alignments are
+/// skipped by default since they can be on the other strand than the primary
+/// alignment of the read.
+#[derive(Debug, Default, Clone)]
+pub struct StrandMapOptions {
+    include_supplementary: bool,
+    include_secondary: bool,
+}
+
+impl StrandMapOptions {
+    /// Also use supplementary alignments
+    pub fn include_supplementary(&mut self, include_supplementary: bool) -> &mut Self {
+        self.include_supplementary = include_supplementary;
+        self
+    }
+
+    /// Also use secondary alignments
+    pub fn include_secondary(&mut self, include_secondary: bool) -> &mut Self {
+        self.include_secondary = include_secondary;
+        self
+    }
+
+    /// Strands of the reads across all of the bam files, reads whose
+    /// alignments are on both strands are set to Strand::unknown()
+    pub fn run<P: AsRef<Path>>(&self, bam_files: impl Iterator<Item = P>) -> Result<StrandMap> {
         let mut acc = FnvHashMap::default();
-        let reader = BamReader::from_path(bam_file, 2u16)?;
-        for record in reader {
-            let record = record?;
-            let read_name = record.name();
-
-            log::debug!("ReadName from bam: {:?}", from_utf8(read_name));
-
-            let plus_stranded = !record.flag().is_reverse_strand();
-            let strand = if plus_stranded {
-                Strand::plus()
-            } else {
-                Strand::minus()
-            };
-            let entry = acc.entry(read_name.to_owned()).or_insert(strand);
-            if *entry != strand {
-                *entry = Strand::unknown();
-                log::warn!("Multimapped read has strand swap");
+        for bam_file in bam_files {
+            let reader = BamReader::from_path(bam_file, 2u16)?;
+            for record in reader {
+                let record = record?;
+                let flag = record.flag();
+                if (flag.is_supplementary() && !self.include_supplementary)
+                    || (flag.is_secondary() && !self.include_secondary)
+                {
+                    continue;
+                }
+                let read_name = record.name();
+
+                log::debug!("ReadName from bam: {:?}", from_utf8(read_name));
+
+                let plus_stranded = !flag.is_reverse_strand();
+                let strand = if plus_stranded {
+                    Strand::plus()
+                } else {
+                    Strand::minus()
+                };
+                let entry = acc.entry(read_name.to_owned()).or_insert(strand);
+                if *entry != strand {
+                    *entry = Strand::unknown();
+                    log::warn!(
+                        "Multimapped read {} has strand swap",
+                        String::from_utf8_lossy(read_name)
+                    );
+                }
             }
         }
         Ok(StrandMap::new(acc))
@@ -46,8 +128,39 @@ impl StrandMap {
 
 #[cfg(test)]
 mod test {
+    use assert_fs::TempDir;
+    use bam::{header::HeaderEntry, BamWriter, Header, Record, RecordWriter};
+
     use super::*;
 
+    fn record(read_name: &[u8], plus_stranded: bool) -> Result<Record> {
+        let mut record = Record::new();
+        record.set_name(read_name.iter().copied());
+        record.set_ref_id(0);
+        record.set_start(10);
+        record
+            .set_seq_qual(b"ACGT".iter().copied(), std::iter::empty())
+            .map_err(|e| eyre::eyre!(e))?;
+        record
+            .set_cigar(b"4M".iter().copied())
+            .map_err(|e| eyre::eyre!(e))?;
+        record.flag_mut().set_strand(plus_stranded);
+        Ok(record)
+    }
+
+    fn write_bam(path: &Path, records: &[Record]) -> Result<()> {
+        let mut header = Header::new();
+        header
+            .push_entry(HeaderEntry::ref_sequence("chrI".to_string(), 1000))
+            .map_err(|e| eyre::eyre!(e))?;
+        let mut writer = BamWriter::from_path(path, header)?;
+        for record in records {
+            writer.write(record)?;
+        }
+        writer.finish()?;
+        Ok(())
+    }
+
     #[test]
     fn test_from_bam_file() {
         let filepath = "extra/single_read.bam";
@@ -65,4 +178,84 @@ mod test {
         assert!(psmap.0.contains_key(read_id));
         assert_eq!(psmap.0.get(read_id), Some(&Strand::minus()));
     }
+
+    #[test]
+    fn test_from_multiple_bam_files() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let first = temp_dir.path().join("first.bam");
+        let second = temp_dir.path().join("second.bam");
+        write_bam(
+            &first,
+            &[
+                record(b"swapped", true)?,
+                record(b"plus", true)?,
+                record(b"same", false)?,
+            ],
+        )?;
+        write_bam(
+            &second,
+            &[
+                record(b"swapped", false)?,
+                record(b"minus", false)?,
+                record(b"same", false)?,
+            ],
+        )?;
+
+        let smap = StrandMap::from_multiple_bam_files([&first, &second].into_iter())?;
+        assert_eq!(smap.0.len(), 4);
+        assert_eq!(smap.get(b"swapped"), Some(Strand::unknown()));
+        assert_eq!(smap.get(b"plus"), Some(Strand::plus()));
+        assert_eq!(smap.get(b"minus"), Some(Strand::minus()));
+        assert_eq!(smap.get(b"same"), Some(Strand::minus()));
+        assert_eq!(smap.get(b"missing"), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_skip_supplementary() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let path = temp_dir.path().join("supplementary.bam");
+        let mut supplementary = record(b"read", false)?;
+        supplementary.flag_mut().set_supplementary(true);
+        let mut secondary = record(b"other", false)?;
+        secondary.flag_mut().set_secondary(true);
+        write_bam(
+            &path,
+            &[
+                record(b"read", true)?,
+                supplementary,
+                record(b"other", true)?,
+                secondary,
+            ],
+        )?;
+
+        let smap = StrandMap::from_bam_file(&path)?;
+        assert_eq!(smap.get(b"read"), Some(Strand::plus()));
+        assert_eq!(smap.get(b"other"), Some(Strand::plus()));
+
+        let smap = StrandMapOptions::default()
+            .include_supplementary(true)
+            .run(std::iter::once(&path))?;
+        assert_eq!(smap.get(b"read"), Some(Strand::unknown()));
+        assert_eq!(smap.get(b"other"), Some(Strand::plus()));
+
+        let smap = StrandMapOptions::default()
+            .include_secondary(true)
+            .run(std::iter::once(&path))?;
+        assert_eq!(smap.get(b"read"), Some(Strand::plus()));
+        assert_eq!(smap.get(b"other"), Some(Strand::unknown()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_plus_stranded() {
+        let mut smap = StrandMap::default();
+        smap.insert("plus", Strand::plus());
+        smap.insert("minus", Strand::minus());
+        smap.insert("unknown", Strand::unknown());
+        assert_eq!(smap.get_plus_stranded("plus"), Some(true));
+        assert_eq!(smap.get_plus_stranded("minus"), Some(false));
+        assert_eq!(smap.get_plus_stranded("unknown"), None);
+        assert_eq!(smap.get_plus_stranded("missing"), None);
+    }
 }
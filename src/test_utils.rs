@@ -0,0 +1,45 @@
+use std::{
+    env,
+    fs::{self, File},
+    path::PathBuf,
+};
+
+use assert_fs::TempDir;
+use eyre::Result;
+
+use crate::{collapse::CollapseOptions, utils::temp_fai_path};
+
+/// Collapse the single read test data into the temporary directory, returning
+/// the path to the collapsed reads
+pub(crate) fn collapse_single_read(temp_dir: &TempDir) -> Result<PathBuf> {
+    let collapsed = temp_dir.path().join("collapse");
+    CollapseOptions::try_new("extra/single_read.bam", Some(&collapsed))?
+        .progress(false)
+        .run(File::open("extra/single_read.eventalign.txt")?)?;
+    Ok(collapsed)
+}
+
+/// Copy of the sacCer3 genome without an index next to it, indexed in the
+/// temp directory instead like a fasta in a directory that can't be written
+/// to. The index is named after the copy, so it is unique to the test, and
+/// removed when dropped.
+pub(crate) struct TempIndexedGenome {
+    pub(crate) path: PathBuf,
+    fai: PathBuf,
+}
+
+impl TempIndexedGenome {
+    pub(crate) fn new(temp_dir: &TempDir) -> Result<Self> {
+        let path = temp_dir.path().join("genome.fa");
+        fs::copy("extra/sacCer3.fa", &path)?;
+        let fai = temp_fai_path(&path, &env::temp_dir())?;
+        fs::copy("extra/sacCer3.fa.fai", &fai)?;
+        Ok(Self { path, fai })
+    }
+}
+
+impl Drop for TempIndexedGenome {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.fai);
+    }
+}
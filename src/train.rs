@@ -1,14 +1,18 @@
 use std::{
     borrow::Borrow,
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     fmt::{Debug, Display},
     fs::File,
+    io::{Read, Write},
     path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
 };
 
 use bio::io::fasta::IndexedReader;
-use eyre::Result;
+use eyre::{Context as _, Result};
 use fnv::{FnvHashMap, FnvHashSet};
+use indicatif::{ProgressBar, ProgressStyle};
 use linfa::{
     traits::{Fit, Transformer},
     DatasetBase, ParamGuard,
@@ -18,16 +22,70 @@ use ndarray::Array;
 use rayon::prelude::{IntoParallelIterator, ParallelIterator};
 use rv::prelude::{Gaussian, Mixture};
 use serde::{Deserialize, Serialize};
+use serde_pickle::{HashableValue, Value};
 
-use crate::arrow::{
-    arrow_utils::load_apply,
-    eventalign::Eventalign,
-    metadata::{MetadataExt, Strand},
+use crate::{
+    arrow::{
+        arrow_utils::load_apply,
+        eventalign::Eventalign,
+        metadata::{MetadataExt, Strand},
+    },
+    context::Context,
+    utils::open_genome_fasta,
 };
 
 pub(crate) type ModelDB = FnvHashMap<String, ModelParams>;
 type KmerMeans = FnvHashMap<String, Vec<f64>>;
 
+/// Kmer size of the nanopolish R9.4 pore model
+pub const DEFAULT_KMER_SIZE: usize = 6;
+
+fn default_kmer_size() -> usize {
+    DEFAULT_KMER_SIZE
+}
+
+/// Errors if the kmer size is 0, since every kmer covers at least one base
+pub(crate) fn check_kmer_size(kmer_size: usize) -> Result<()> {
+    if kmer_size == 0 {
+        return Err(eyre::eyre!("Kmer size must be at least 1"));
+    }
+    Ok(())
+}
+
+/// Separate values for reads aligned to the plus and minus strand
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub(crate) struct Stranded<T> {
+    plus: T,
+    minus: T,
+}
+
+impl<T> Stranded<T> {
+    /// Returns None for reads with an unknown strand
+    pub(crate) fn get(&self, strand: Strand) -> Option<&T> {
+        if strand.is_unknown_strand() {
+            None
+        } else if strand.is_minus_strand() {
+            Some(&self.minus)
+        } else {
+            Some(&self.plus)
+        }
+    }
+
+    pub(crate) fn get_mut(&mut self, strand: Strand) -> Option<&mut T> {
+        if strand.is_unknown_strand() {
+            None
+        } else if strand.is_minus_strand() {
+            Some(&mut self.minus)
+        } else {
+            Some(&mut self.plus)
+        }
+    }
+
+    fn into_strands(self) -> impl Iterator<Item = (Strand, T)> {
+        [(Strand::plus(), self.plus), (Strand::minus(), self.minus)].into_iter()
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct ModelParams {
     is_single: bool,
@@ -64,6 +122,23 @@ impl ModelParams {
         self.weight
     }
 
+    pub fn n_components(&self) -> usize {
+        if self.is_single {
+            1
+        } else {
+            2
+        }
+    }
+
+    /// Weight, mean, and standard deviation of each component
+    pub fn components(&self) -> Vec<(f64, f64, f64)> {
+        let mut acc = vec![(self.weight_a(), self.mu_a, self.sigma_a)];
+        if !self.is_single {
+            acc.push((self.weight_b(), self.mu_b, self.sigma_b));
+        }
+        acc
+    }
+
     fn weight_b(&self) -> f64 {
         1. - self.weight
     }
@@ -76,8 +151,13 @@ impl ModelParams {
         }
     }
 
+    /// Mixture with one component if the kmer was trained with a single
+    /// Gaussian, otherwise two components
     pub fn mixture(&self) -> Mixture<Gaussian> {
         let g1 = Gaussian::new_unchecked(self.mu_a, self.sigma_a);
+        if self.is_single {
+            return Mixture::new_unchecked(vec![1.0], vec![g1]);
+        }
         let g2 = Gaussian::new_unchecked(self.mu_b, self.sigma_b);
         let components = vec![g1, g2];
         let weights = vec![self.weight_a(), self.weight_b()];
@@ -105,16 +185,268 @@ impl<T: Borrow<Mixture<Gaussian>>> From<T> for ModelParams {
     }
 }
 
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+/// Version of the saved model format, stored under the "version" key of the
+/// pickled model. Bump when fields are added to [Model] and add a migration
+/// from the previous version.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ModelVersion {
+    /// Models saved before the version was recorded
+    V1,
+    /// Adds the version
+    V2,
+    /// Adds per-kmer sample counts and provenance
+    V3,
+    /// Adds the number of times each kmer was counted for skips
+    V4,
+}
+
+impl ModelVersion {
+    /// Version written by [CawlrIO::save]
+    ///
+    /// [CawlrIO::save]: crate::utils::CawlrIO::save
+    pub const CURRENT: ModelVersion = ModelVersion::V4;
+
+    const KEY: &'static str = "version";
+
+    fn number(self) -> i64 {
+        match self {
+            Self::V1 => 1,
+            Self::V2 => 2,
+            Self::V3 => 3,
+            Self::V4 => 4,
+        }
+    }
+
+    fn from_number(n: i64) -> Result<Self> {
+        match n {
+            1 => Ok(Self::V1),
+            2 => Ok(Self::V2),
+            3 => Ok(Self::V3),
+            4 => Ok(Self::V4),
+            n if n > Self::CURRENT.number() => Err(eyre::eyre!(
+                "Model format version {n} is newer than the latest version supported by this \
+                 version of cawlr ({}), upgrade cawlr to load it",
+                Self::CURRENT.number()
+            )),
+            n => Err(eyre::eyre!("Invalid model format version {n}")),
+        }
+    }
+}
+
+impl Display for ModelVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "v{}", self.number())
+    }
+}
+
+/// How a model was trained, for reporting where its data came from
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Provenance {
+    /// Files the training data was read from
+    pub inputs: Vec<String>,
+    /// Version of cawlr used for training
+    pub cawlr_version: String,
+    /// Options used for training, ie number of samples
+    pub options: BTreeMap<String, String>,
+    /// When training finished, in seconds since the Unix epoch
+    pub timestamp: u64,
+}
+
+impl Provenance {
+    /// Provenance for a model trained now with this version of cawlr
+    pub(crate) fn new(inputs: Vec<String>, options: BTreeMap<String, String>) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        Self {
+            inputs,
+            cawlr_version: env!("CARGO_PKG_VERSION").to_string(),
+            options,
+            timestamp,
+        }
+    }
+}
+
+/// Weight, mean and standard deviation of one component of a kmer's GMM
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+pub struct GaussianComponent {
+    pub weight: f64,
+    pub mean: f64,
+    pub std: f64,
+}
+
+/// Trained parameters of a single kmer, from [Model::inspect_kmer]
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct KmerModel {
+    pub kmer: String,
+    pub components: Vec<GaussianComponent>,
+    /// Fraction of times the kmer had signal data, None if skips weren't
+    /// trained
+    pub skip_prob: Option<f64>,
+}
+
+impl Display for KmerModel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.kmer)?;
+        for (i, c) in self.components.iter().enumerate() {
+            write!(
+                f,
+                "\tcomponent{i}: weight={} mean={} std={}",
+                c.weight, c.mean, c.std
+            )?;
+        }
+        match self.skip_prob {
+            Some(skip_prob) => write!(f, "\tskip_prob={skip_prob}"),
+            None => write!(f, "\tskip_prob=NA"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Model {
     gmms: ModelDB,
     skips: FnvHashMap<String, f64>,
+    kmer_size: usize,
+    // Only filled in when trained in stranded mode
+    stranded_gmms: Stranded<ModelDB>,
+    // Number of samples used to train each kmer in gmms
+    n_samples: FnvHashMap<String, usize>,
+    provenance: Option<Provenance>,
+    // Number of times each kmer in skips was counted
+    n_skip_samples: FnvHashMap<String, usize>,
+}
+
+/// Model saved before skip sample counts were added
+#[derive(Serialize, Deserialize)]
+struct ModelV3 {
+    gmms: ModelDB,
+    skips: FnvHashMap<String, f64>,
+    kmer_size: usize,
+    stranded_gmms: Stranded<ModelDB>,
+    n_samples: FnvHashMap<String, usize>,
+    provenance: Option<Provenance>,
+}
+
+impl From<ModelV3> for Model {
+    fn from(v3: ModelV3) -> Self {
+        Self {
+            gmms: v3.gmms,
+            skips: v3.skips,
+            kmer_size: v3.kmer_size,
+            stranded_gmms: v3.stranded_gmms,
+            n_samples: v3.n_samples,
+            provenance: v3.provenance,
+            n_skip_samples: FnvHashMap::default(),
+        }
+    }
+}
+
+/// Model saved before sample counts and provenance were added
+#[derive(Serialize, Deserialize)]
+struct ModelV2 {
+    gmms: ModelDB,
+    skips: FnvHashMap<String, f64>,
+    kmer_size: usize,
+    stranded_gmms: Stranded<ModelDB>,
+}
+
+impl From<ModelV2> for Model {
+    fn from(v2: ModelV2) -> Self {
+        ModelV3 {
+            gmms: v2.gmms,
+            skips: v2.skips,
+            kmer_size: v2.kmer_size,
+            stranded_gmms: v2.stranded_gmms,
+            n_samples: FnvHashMap::default(),
+            provenance: None,
+        }
+        .into()
+    }
+}
+
+/// Model saved before the format was versioned
+#[derive(Serialize, Deserialize)]
+struct ModelV1 {
+    gmms: ModelDB,
+    skips: FnvHashMap<String, f64>,
+    // Models saved before kmer_size was added are all 6-mer models
+    #[serde(default = "default_kmer_size")]
+    kmer_size: usize,
+    #[serde(default)]
+    stranded_gmms: Stranded<ModelDB>,
+}
+
+impl From<ModelV1> for Model {
+    fn from(v1: ModelV1) -> Self {
+        ModelV2 {
+            gmms: v1.gmms,
+            skips: v1.skips,
+            kmer_size: v1.kmer_size,
+            stranded_gmms: v1.stranded_gmms,
+        }
+        .into()
+    }
+}
+
+impl Default for Model {
+    fn default() -> Self {
+        Model::new(ModelDB::default(), FnvHashMap::default())
+    }
 }
 
 impl Model {
     pub(crate) fn new(gmms: ModelDB, skips: FnvHashMap<String, f64>) -> Self {
-        Self { gmms, skips }
+        Self {
+            gmms,
+            skips,
+            kmer_size: DEFAULT_KMER_SIZE,
+            stranded_gmms: Stranded::default(),
+            n_samples: FnvHashMap::default(),
+            provenance: None,
+            n_skip_samples: FnvHashMap::default(),
+        }
+    }
+
+    /// Empty model for kmers of length kmer_size
+    pub(crate) fn with_kmer_size(kmer_size: usize) -> Self {
+        Self {
+            kmer_size,
+            ..Model::default()
+        }
+    }
+
+    /// Length of the kmers the model was trained on
+    pub fn kmer_size(&self) -> usize {
+        self.kmer_size
+    }
+
+    /// Number of samples used to train the kmer, None for kmers that weren't
+    /// trained or models saved before sample counts were recorded
+    pub fn n_samples(&self, kmer: &str) -> Option<usize> {
+        self.n_samples.get(kmer).copied()
+    }
+
+    /// Number of times the kmer was counted when training skips, None for
+    /// kmers without skips or models saved before skip counts were recorded
+    pub fn n_skip_samples(&self, kmer: &str) -> Option<usize> {
+        self.n_skip_samples.get(kmer).copied()
     }
+
+    /// How the model was trained, None for models saved before provenance
+    /// was recorded
+    pub fn provenance(&self) -> Option<&Provenance> {
+        self.provenance.as_ref()
+    }
+
+    pub(crate) fn set_n_samples(&mut self, kmer: String, n_samples: usize) {
+        self.n_samples.insert(kmer, n_samples);
+    }
+
+    pub(crate) fn set_provenance(&mut self, provenance: Provenance) {
+        self.provenance = Some(provenance);
+    }
+
     /// Get a reference to the model's gmms.
     pub(crate) fn gmms(&self) -> &ModelDB {
         &self.gmms
@@ -125,10 +457,161 @@ impl Model {
         &self.skips
     }
 
+    /// Replace the fraction of times each kmer had signal data
+    pub(crate) fn set_skips(&mut self, skips: FnvHashMap<String, f64>) {
+        self.skips = skips;
+    }
+
+    /// Replace the number of times each kmer was counted for skips
+    pub(crate) fn set_n_skip_samples(&mut self, n_skip_samples: FnvHashMap<String, usize>) {
+        self.n_skip_samples = n_skip_samples;
+    }
+
     pub(crate) fn insert_gmm(&mut self, kmer: String, gmm: Mixture<Gaussian>) {
         let gmm = ModelParams::from(gmm);
         self.gmms.insert(kmer, gmm);
     }
+
+    pub(crate) fn insert_stranded_gmm(
+        &mut self,
+        kmer: String,
+        strand: Strand,
+        gmm: Mixture<Gaussian>,
+    ) {
+        if let Some(gmms) = self.stranded_gmms.get_mut(strand) {
+            gmms.insert(kmer, ModelParams::from(gmm));
+        }
+    }
+
+    /// True if the model was trained separately on each strand
+    pub fn is_stranded(&self) -> bool {
+        self.stranded_gmms.plus.len() + self.stranded_gmms.minus.len() > 0
+    }
+
+    /// Get the parameters for the kmer on the given strand, falling back to
+    /// the parameters trained on both strands if there is no strand specific
+    /// model.
+    pub(crate) fn gmm(&self, kmer: &str, strand: Strand) -> Option<&ModelParams> {
+        self.stranded_gmms
+            .get(strand)
+            .and_then(|gmms| gmms.get(kmer))
+            .or_else(|| self.gmms.get(kmer))
+    }
+
+    /// Pickle the model with the current format version
+    pub(crate) fn write_versioned<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let mut value = serde_pickle::to_value(self)?;
+        if let Value::Dict(dict) = &mut value {
+            dict.insert(
+                HashableValue::String(ModelVersion::KEY.to_string()),
+                Value::I64(ModelVersion::CURRENT.number()),
+            );
+        }
+        serde_pickle::value_to_writer(writer, &value, Default::default())?;
+        Ok(())
+    }
+
+    /// Load a pickled model, upgrading models saved in older formats
+    pub(crate) fn read_versioned<R: Read>(reader: R) -> Result<Self> {
+        let mut value = serde_pickle::value_from_reader(reader, Default::default())?;
+        let version = match &mut value {
+            Value::Dict(dict) => {
+                match dict.remove(&HashableValue::String(ModelVersion::KEY.to_string())) {
+                    Some(Value::I64(n)) => ModelVersion::from_number(n)?,
+                    Some(v) => return Err(eyre::eyre!("Invalid model format version {v}")),
+                    None => ModelVersion::V1,
+                }
+            }
+            _ => return Err(eyre::eyre!("Invalid model file, expected a dictionary")),
+        };
+        log::debug!("Loading model format {version}");
+        let model = match version {
+            ModelVersion::V1 => serde_pickle::from_value::<ModelV1>(value)?.into(),
+            ModelVersion::V2 => serde_pickle::from_value::<ModelV2>(value)?.into(),
+            ModelVersion::V3 => serde_pickle::from_value::<ModelV3>(value)?.into(),
+            ModelVersion::V4 => serde_pickle::from_value(value)?,
+        };
+        Ok(model)
+    }
+
+    /// Parameters of the GMM trained on both strands for the kmer, None if
+    /// the kmer isn't in the model
+    pub fn inspect_kmer(&self, kmer: &str) -> Option<KmerModel> {
+        let params = self.gmms.get(kmer)?;
+        let components = params
+            .components()
+            .into_iter()
+            .map(|(weight, mean, std)| GaussianComponent { weight, mean, std })
+            .collect();
+        Some(KmerModel {
+            kmer: kmer.to_string(),
+            components,
+            skip_prob: self.skips.get(kmer).copied(),
+        })
+    }
+
+    /// Write the parameters of the kmer from [Model::inspect_kmer] as JSON
+    pub fn write_kmer_json<W: Write>(&self, writer: &mut W, kmer: &str) -> Result<()> {
+        let kmer_model = self
+            .inspect_kmer(kmer)
+            .ok_or_else(|| eyre::eyre!("Kmer {kmer} not found in model"))?;
+        serde_json::to_writer_pretty(&mut *writer, &kmer_model)?;
+        writeln!(writer)?;
+        Ok(())
+    }
+
+    /// Write a tab-separated summary of the model parameters for each kmer,
+    /// with the component weights, means and standard deviations as JSON
+    /// arrays. If kmer is given, only that kmer is written. The provenance,
+    /// if any, is written first as lines starting with #.
+    pub fn write_summary<W: Write>(&self, writer: &mut W, kmer: Option<&str>) -> Result<()> {
+        let mut kmers = match kmer {
+            Some(kmer) if self.gmms.contains_key(kmer) => vec![kmer],
+            Some(kmer) => return Err(eyre::eyre!("Kmer {kmer} not found in model")),
+            None => self.gmms.keys().map(String::as_str).collect(),
+        };
+        kmers.sort_unstable();
+
+        if let Some(provenance) = &self.provenance {
+            writeln!(writer, "#cawlr_version={}", provenance.cawlr_version)?;
+            writeln!(writer, "#timestamp={}", provenance.timestamp)?;
+            for input in provenance.inputs.iter() {
+                writeln!(writer, "#input={input}")?;
+            }
+            for (key, value) in provenance.options.iter() {
+                writeln!(writer, "#{key}={value}")?;
+            }
+        }
+        writeln!(
+            writer,
+            "kmer\tn_components\tweights\tmeans\tstds\tskip_freq\tn_samples\tn_skip_samples"
+        )?;
+        for kmer in kmers {
+            let params = &self.gmms[kmer];
+            let components = params.components();
+            let weights: Vec<f64> = components.iter().map(|c| c.0).collect();
+            let means: Vec<f64> = components.iter().map(|c| c.1).collect();
+            let stds: Vec<f64> = components.iter().map(|c| c.2).collect();
+            let skip_freq = self.skips.get(kmer).copied().unwrap_or(f64::NAN);
+            let n_samples = self
+                .n_samples(kmer)
+                .map(|n| n.to_string())
+                .unwrap_or_else(|| "NA".to_string());
+            let n_skip_samples = self
+                .n_skip_samples(kmer)
+                .map(|n| n.to_string())
+                .unwrap_or_else(|| "NA".to_string());
+            writeln!(
+                writer,
+                "{kmer}\t{}\t{}\t{}\t{}\t{skip_freq}\t{n_samples}\t{n_skip_samples}",
+                params.n_components(),
+                serde_json::to_string(&weights)?,
+                serde_json::to_string(&means)?,
+                serde_json::to_string(&stds)?,
+            )?;
+        }
+        Ok(())
+    }
 }
 
 struct Skips {
@@ -165,12 +648,80 @@ impl Default for Skips {
     }
 }
 
-struct KmerSkips(FnvHashMap<Vec<u8>, Skips>);
+pub(crate) struct KmerSkips(FnvHashMap<Vec<u8>, Skips>);
 
 impl KmerSkips {
-    fn new() -> Self {
+    pub(crate) fn new() -> Self {
         Self(FnvHashMap::default())
     }
+
+    /// Count whether each kmer in the read's reference context had signal
+    /// data, using the same kmers as the skipping score in cawlr score.
+    pub(crate) fn add_context(&mut self, read: &Eventalign, context: &Context) {
+        let pos_scores = read
+            .signal_iter()
+            .map(|signal| signal.pos)
+            .collect::<FnvHashSet<_>>();
+        for pos in read.start_0b()..read.start_0b() + read.np_length() {
+            if let Some(kmer) = context.kmer_at(pos) {
+                let kskip = self.0.entry(kmer.to_owned()).or_default();
+                kskip.had_score(pos_scores.contains(&pos));
+            }
+        }
+    }
+
+    /// Number of times each kmer was counted
+    pub(crate) fn totals(&self) -> Result<FnvHashMap<String, usize>> {
+        let mut totals = FnvHashMap::default();
+        for (kmer, skips) in self.0.iter() {
+            totals.insert(String::from_utf8(kmer.clone())?, skips.total);
+        }
+        Ok(totals)
+    }
+
+    /// Fraction of times each kmer had signal data
+    pub(crate) fn into_ratios(self) -> Result<FnvHashMap<String, f64>> {
+        let mut ratios = FnvHashMap::default();
+        for (kmer, skips) in self.0.into_iter() {
+            let kmer = String::from_utf8(kmer)?;
+            let ratio = (skips.count as f64) / (skips.total as f64);
+            ratios.insert(kmer, ratio);
+        }
+        Ok(ratios)
+    }
+}
+
+/// How far training has gotten through the kmers
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct TrainProgress {
+    /// Kmers finished so far, including ones that failed to train
+    pub n_done: usize,
+    /// Kmers that failed to train so far
+    pub n_failed: usize,
+    /// Total number of kmers to train
+    pub n_kmers: usize,
+}
+
+impl TrainProgress {
+    pub(crate) fn update_bar(&self, pb: &ProgressBar) {
+        pb.set_position(self.n_done as u64);
+        pb.set_message(format!("{} failed", self.n_failed));
+    }
+}
+
+/// Progress bar over the kmers being trained, with the number of failures as
+/// the message. Hidden if show is false, and indicatif doesn't draw it when
+/// stderr isn't a terminal.
+pub(crate) fn kmer_progress_bar(n_kmers: usize, show: bool) -> ProgressBar {
+    if !show {
+        return ProgressBar::hidden();
+    }
+    let style = ProgressStyle::default_bar()
+        .template("[{elapsed_precise}] {bar:40} {pos}/{len} kmers, {msg} (ETA {eta})")
+        .unwrap();
+    ProgressBar::new(n_kmers as u64)
+        .with_style(style)
+        .with_message("0 failed")
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
@@ -193,14 +744,20 @@ pub struct Train {
     acc: KmerMeans,
     skips: KmerSkips,
     genome: IndexedReader<File>,
-    feather: PathBuf,
+    inputs: Vec<PathBuf>,
     samples: usize,
     strat: TrainStrategy,
+    kmer_size: usize,
+    stranded: bool,
+    stranded_acc: Stranded<KmerMeans>,
+    progress: bool,
 }
 
 impl Train {
+    /// Train on the reads in all of the inputs together, ie from technical
+    /// replicates. Errors if there are no inputs.
     pub fn try_new<P, Q>(
-        filename: P,
+        inputs: &[P],
         genome: Q,
         samples: usize,
         strat: TrainStrategy,
@@ -209,19 +766,44 @@ impl Train {
         P: AsRef<Path>,
         Q: AsRef<Path> + Debug,
     {
-        let genome =
-            IndexedReader::from_file(&genome).map_err(|_| eyre::eyre!("Failed to read genome."))?;
-        let feather = filename.as_ref().to_owned();
+        if inputs.is_empty() {
+            return Err(eyre::eyre!("No inputs to train on"));
+        }
+        let genome = open_genome_fasta(genome.as_ref())?;
+        let inputs = inputs.iter().map(|p| p.as_ref().to_owned()).collect();
         Ok(Self {
             acc: FnvHashMap::default(),
             skips: KmerSkips::new(),
             genome,
-            feather,
+            inputs,
             samples,
             strat,
+            kmer_size: DEFAULT_KMER_SIZE,
+            stranded: false,
+            stranded_acc: Stranded::default(),
+            progress: false,
         })
     }
 
+    /// Length of kmers in the input, defaults to 6. Errors if it is 0.
+    pub fn kmer_size(&mut self, kmer_size: usize) -> Result<&mut Self> {
+        check_kmer_size(kmer_size)?;
+        self.kmer_size = kmer_size;
+        Ok(self)
+    }
+
+    /// Also train a separate model for each kmer on each strand
+    pub fn stranded(&mut self, stranded: bool) -> &mut Self {
+        self.stranded = stranded;
+        self
+    }
+
+    /// Show a progress bar while training the kmers
+    pub fn progress(&mut self, progress: bool) -> &mut Self {
+        self.progress = progress;
+        self
+    }
+
     fn kmer_means_insufficient(&self) -> bool {
         self.acc.is_empty() || insufficient(&self.acc, self.samples)
     }
@@ -231,32 +813,23 @@ impl Train {
     }
 
     pub fn run(mut self) -> Result<Model> {
-        let file = File::open(&self.feather)?;
-        load_apply(file, |eventaligns| {
-            for eventalign in eventaligns.into_iter() {
-                if self.kmer_means_insufficient() || self.kmer_skips_insufficient() {
-                    match self.strat {
-                        TrainStrategy::AvgSample => self.read_to_kmer_means(&eventalign),
-                        TrainStrategy::AllSamples => self.read_to_kmer_samples(&eventalign),
-                    }
-                    self.read_to_skip_counts(&eventalign)?;
-                }
-            }
-            Ok(())
-        })?;
+        for input in self.inputs.clone() {
+            self.load_input(&input).wrap_err_with(|| {
+                format!("Failed to load training data from {}", input.display())
+            })?;
+        }
 
-        // let mut gmms = self.acc;
-        let gmms = self
+        let n_kmers = self.acc.len() + self.stranded_acc.plus.len() + self.stranded_acc.minus.len();
+        let pb = kmer_progress_bar(n_kmers, self.progress);
+        let n_failed = AtomicUsize::new(0);
+
+        let n_samples = self
             .acc
-            .into_par_iter()
-            .filter_map(|item| {
-                if let Ok(Some(gmm)) = train_gmm(item.1) {
-                    Some((item.0, ModelParams::from(gmm)))
-                } else {
-                    None
-                }
-            })
-            .collect();
+            .iter()
+            .map(|(kmer, samples)| (kmer.clone(), samples.len()))
+            .collect::<Vec<_>>();
+        // let mut gmms = self.acc;
+        let gmms = train_gmms(self.acc, &pb, &n_failed);
 
         // for (kmer, kmer_mean) in x {
         //     if kmer_mean.len() > 1 {
@@ -269,18 +842,65 @@ impl Train {
         //     }
         // }
 
-        let mut ratios = FnvHashMap::default();
-        for (kmer, skips) in self.skips.0.into_iter() {
-            let kmer = String::from_utf8(kmer)?;
-            let ratio = (skips.count as f64) / (skips.total as f64);
-            ratios.insert(kmer, ratio);
+        let n_skip_samples = self.skips.totals()?;
+        let ratios = self.skips.into_ratios()?;
+        let mut model = Model::new(gmms, ratios);
+        model.kmer_size = self.kmer_size;
+        model.set_n_skip_samples(n_skip_samples);
+        for (kmer, n) in n_samples {
+            if model.gmms.contains_key(&kmer) {
+                model.set_n_samples(kmer, n);
+            }
         }
-
-        let model = Model::new(gmms, ratios);
+        let inputs = self
+            .inputs
+            .iter()
+            .map(|input| input.display().to_string())
+            .collect();
+        let options = BTreeMap::from([
+            ("samples".to_string(), self.samples.to_string()),
+            ("strategy".to_string(), self.strat.to_string()),
+            ("kmer_size".to_string(), self.kmer_size.to_string()),
+            ("stranded".to_string(), self.stranded.to_string()),
+        ]);
+        model.set_provenance(Provenance::new(inputs, options));
+        for (strand, acc) in self.stranded_acc.into_strands() {
+            let gmms = train_gmms(acc, &pb, &n_failed);
+            if let Some(stranded_gmms) = model.stranded_gmms.get_mut(strand) {
+                *stranded_gmms = gmms;
+            }
+        }
+        pb.finish();
 
         Ok(model)
     }
 
+    /// Add the reads in the file to the samples, until there are enough
+    /// samples for every kmer
+    fn load_input(&mut self, input: &Path) -> Result<()> {
+        let file = File::open(input)?;
+        let mut n_reads = 0;
+        load_apply(file, |eventaligns: Vec<Eventalign>| {
+            for eventalign in eventaligns.into_iter() {
+                eventalign.check_kmer_size(self.kmer_size)?;
+                if self.kmer_means_insufficient() || self.kmer_skips_insufficient() {
+                    n_reads += 1;
+                    match self.strat {
+                        TrainStrategy::AvgSample => self.read_to_kmer_means(&eventalign),
+                        TrainStrategy::AllSamples => self.read_to_kmer_samples(&eventalign),
+                    }
+                    if self.stranded {
+                        self.read_to_stranded(&eventalign);
+                    }
+                    self.read_to_skip_counts(&eventalign)?;
+                }
+            }
+            Ok(())
+        })?;
+        log::info!("Used {n_reads} reads from {}", input.display());
+        Ok(())
+    }
+
     fn read_to_kmer_means(&mut self, read: &Eventalign) {
         for signal in read.signal_iter() {
             let kmer = signal.kmer.clone();
@@ -303,13 +923,31 @@ impl Train {
         }
     }
 
+    /// Add the read's data to the per-strand samples, using the same
+    /// strategy as for the unstranded model.
+    fn read_to_stranded(&mut self, read: &Eventalign) {
+        let Some(acc) = self.stranded_acc.get_mut(read.strand()) else {
+            return;
+        };
+        for signal in read.signal_iter() {
+            let entry = acc.entry(signal.kmer.clone()).or_default();
+            if entry.len() > self.samples {
+                continue;
+            }
+            match self.strat {
+                TrainStrategy::AvgSample => entry.push(signal.signal_mean),
+                TrainStrategy::AllSamples => entry.extend_from_slice(&signal.samples),
+            }
+        }
+    }
+
     fn read_to_skip_counts(&mut self, read: &Eventalign) -> Result<()> {
         let mut pos_scores = FnvHashSet::default();
         for signal in read.signal_iter() {
             pos_scores.insert(signal.pos);
         }
         let read_seq = self.get_read_seq(read)?;
-        for (kmer, pos) in read_seq.windows(6).zip(read.start_0b()..) {
+        for (kmer, pos) in read_seq.windows(self.kmer_size).zip(read.start_0b()..) {
             let has_score = pos_scores.contains(&pos);
             let kskip = self.skips.0.entry(kmer.to_owned()).or_default();
             kskip.had_score(has_score);
@@ -330,8 +968,8 @@ impl Train {
         let strand = read.strand();
         let chrom = read.chrom();
         let start = read.start_0b();
-        self.genome_mut()
-            .fetch(chrom, start, read.seq_stop_1b_excl())?;
+        let stop = start + read.np_length() + self.kmer_size as u64 - 1;
+        self.genome_mut().fetch(chrom, start, stop)?;
         let mut seq = Vec::new();
         self.genome_mut().read(&mut seq)?;
         let seq = if strand == Strand::plus() {
@@ -343,6 +981,23 @@ impl Train {
     }
 }
 
+/// Train each kmer in parallel, kmers that fail to train are left out
+fn train_gmms(acc: KmerMeans, pb: &ProgressBar, n_failed: &AtomicUsize) -> ModelDB {
+    acc.into_par_iter()
+        .filter_map(|(kmer, samples)| {
+            let gmm = if let Ok(Some(gmm)) = train_gmm(samples) {
+                Some((kmer, ModelParams::from(gmm)))
+            } else {
+                let n = n_failed.fetch_add(1, Ordering::Relaxed) + 1;
+                pb.set_message(format!("{n} failed"));
+                None
+            };
+            pb.inc(1);
+            gmm
+        })
+        .collect()
+}
+
 fn train_gmm(means: Vec<f64>) -> Result<Option<Mixture<Gaussian>>> {
     let len = means.len();
     let shape = (len, 1);
@@ -420,7 +1075,13 @@ fn insufficient<K, V, S>(dict: &HashMap<K, Vec<V>, S>, n: usize) -> bool {
 
 #[cfg(test)]
 mod test {
+    use assert_fs::TempDir;
+
     use super::*;
+    use crate::{
+        test_utils::{collapse_single_read, TempIndexedGenome},
+        utils::CawlrIO,
+    };
 
     #[test]
     fn test_insufficient() {
@@ -459,4 +1120,309 @@ mod test {
         pretty_assertions::assert_eq!(params, answer);
         pretty_assertions::assert_eq!(params.single(), Gaussian::new_unchecked(1., 2.));
     }
+
+    #[test]
+    fn test_write_summary() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let collapsed = collapse_single_read(&temp_dir)?;
+
+        let train = Train::try_new(
+            &[&collapsed],
+            "extra/sacCer3.fa",
+            50_000,
+            TrainStrategy::AllSamples,
+        )?;
+        let model = train.run()?;
+
+        let mut summary = Vec::new();
+        model.write_summary(&mut summary, None)?;
+        let summary = String::from_utf8(summary)?;
+        let provenance = summary
+            .lines()
+            .take_while(|line| line.starts_with('#'))
+            .collect::<Vec<_>>();
+        assert!(provenance.contains(&"#strategy=all"), "{provenance:?}");
+        assert!(provenance
+            .iter()
+            .any(|line| line.starts_with("#input=") && line.ends_with("collapse")));
+        let mut lines = summary.lines().skip(provenance.len());
+        assert_eq!(
+            lines.next(),
+            Some("kmer\tn_components\tweights\tmeans\tstds\tskip_freq\tn_samples\tn_skip_samples")
+        );
+        let rows = lines.collect::<Vec<_>>();
+        assert!(!rows.is_empty());
+        assert_eq!(rows.len(), model.gmms().len());
+        for row in rows.iter() {
+            let cols = row.split('\t').collect::<Vec<_>>();
+            assert_eq!(cols.len(), 8);
+            let n_components: usize = cols[1].parse()?;
+            let means: Vec<f64> = serde_json::from_str(cols[3])?;
+            assert_eq!(means.len(), n_components);
+            let n_samples: usize = cols[6].parse()?;
+            assert_eq!(Some(n_samples), model.n_samples(cols[0]));
+            let n_skip_samples: usize = cols[7].parse()?;
+            assert!(n_skip_samples > 0);
+            assert_eq!(Some(n_skip_samples), model.n_skip_samples(cols[0]));
+        }
+
+        let kmer = model.gmms().keys().next().unwrap().clone();
+        let mut summary = Vec::new();
+        model.write_summary(&mut summary, Some(&kmer))?;
+        let summary = String::from_utf8(summary)?;
+        let rows = summary
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .collect::<Vec<_>>();
+        assert_eq!(rows.len(), 2);
+        assert!(rows[1].starts_with(&kmer));
+
+        let mut summary = Vec::new();
+        assert!(model.write_summary(&mut summary, Some("NOTAKMER")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_kmer_size_mismatch() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let collapsed = collapse_single_read(&temp_dir)?;
+
+        let mut train = Train::try_new(
+            &[&collapsed],
+            "extra/sacCer3.fa",
+            50_000,
+            TrainStrategy::AllSamples,
+        )?;
+        assert!(train.kmer_size(0).is_err());
+        train.kmer_size(5)?;
+        assert!(train.run().is_err());
+
+        let train = Train::try_new(
+            &[&collapsed],
+            "extra/sacCer3.fa",
+            50_000,
+            TrainStrategy::AllSamples,
+        )?;
+        let model = train.run()?;
+        assert_eq!(model.kmer_size(), DEFAULT_KMER_SIZE);
+
+        let model_path = temp_dir.path().join("model.pickle");
+        model.save_as(&model_path)?;
+        assert_eq!(Model::load(&model_path)?.kmer_size(), DEFAULT_KMER_SIZE);
+        Ok(())
+    }
+
+    #[test]
+    fn test_stranded() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let collapsed = collapse_single_read(&temp_dir)?;
+
+        let mut train = Train::try_new(
+            &[&collapsed],
+            "extra/sacCer3.fa",
+            50_000,
+            TrainStrategy::AllSamples,
+        )?;
+        train.stranded(true);
+        let model = train.run()?;
+        assert!(model.is_stranded());
+
+        // Single read is on the plus strand, so minus strand lookups fall back
+        // to the unstranded model
+        assert!(!model.stranded_gmms.plus.is_empty());
+        assert!(model.stranded_gmms.minus.is_empty());
+        for (kmer, params) in model.gmms().iter() {
+            assert_eq!(model.gmm(kmer, Strand::minus()), Some(params));
+            assert_eq!(model.gmm(kmer, Strand::unknown()), Some(params));
+            if let Some(plus) = model.stranded_gmms.plus.get(kmer) {
+                assert_eq!(model.gmm(kmer, Strand::plus()), Some(plus));
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_genome_index_in_temp_dir() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let collapsed = collapse_single_read(&temp_dir)?;
+        let genome = TempIndexedGenome::new(&temp_dir)?;
+
+        let train = Train::try_new(
+            &[&collapsed],
+            &genome.path,
+            50_000,
+            TrainStrategy::AllSamples,
+        )?;
+        let model = train.run()?;
+        assert!(!model.gmms().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_multiple_inputs() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let collapsed = collapse_single_read(&temp_dir)?;
+        let train = |inputs: &[&PathBuf]| {
+            Train::try_new(
+                inputs,
+                "extra/sacCer3.fa",
+                50_000,
+                TrainStrategy::AllSamples,
+            )
+        };
+
+        let model = train(&[&collapsed, &collapsed])?.run()?;
+        assert!(!model.gmms().is_empty());
+
+        let missing = temp_dir.path().join("missing.arrow");
+        let err = train(&[&collapsed, &missing])?.run().unwrap_err();
+        assert!(err.to_string().contains("missing.arrow"), "{err}");
+
+        assert!(train(&[]).is_err());
+        Ok(())
+    }
+
+    fn versioned_test_model() -> Model {
+        let mut model = Model::with_kmer_size(5);
+        let mix = Mixture::new_unchecked(
+            vec![0.7, 0.3],
+            vec![
+                Gaussian::new_unchecked(90.0, 2.0),
+                Gaussian::new_unchecked(100.0, 3.0),
+            ],
+        );
+        model.insert_gmm("AAAAA".to_string(), mix.clone());
+        model.insert_stranded_gmm("AAAAA".to_string(), Strand::plus(), mix);
+        model.skips.insert("AAAAA".to_string(), 0.9);
+        model.set_n_samples("AAAAA".to_string(), 100);
+        model.set_n_skip_samples(FnvHashMap::from_iter([("AAAAA".to_string(), 200)]));
+        model.set_provenance(Provenance::new(
+            vec!["pos.arrow".to_string()],
+            BTreeMap::from([("samples".to_string(), "100".to_string())]),
+        ));
+        model
+    }
+
+    #[test]
+    fn test_model_version_round_trip() -> Result<()> {
+        let model = versioned_test_model();
+        let mut buf = Vec::new();
+        model.save(&mut buf)?;
+
+        let value = serde_pickle::value_from_slice(&buf, Default::default())?;
+        let version = match value {
+            Value::Dict(dict) => dict
+                .get(&HashableValue::String("version".to_string()))
+                .cloned(),
+            _ => None,
+        };
+        assert_eq!(version, Some(Value::I64(4)));
+
+        let loaded = Model::read_versioned(buf.as_slice())?;
+        assert_eq!(loaded.gmms, model.gmms);
+        assert_eq!(loaded.skips, model.skips);
+        assert_eq!(loaded.kmer_size, 5);
+        assert_eq!(loaded.stranded_gmms, model.stranded_gmms);
+        assert_eq!(loaded.n_samples("AAAAA"), Some(100));
+        assert_eq!(loaded.n_skip_samples("AAAAA"), Some(200));
+        assert_eq!(loaded.provenance(), model.provenance());
+        Ok(())
+    }
+
+    #[test]
+    fn test_model_v3_migration() -> Result<()> {
+        let model = versioned_test_model();
+        let v3 = ModelV3 {
+            gmms: model.gmms.clone(),
+            skips: model.skips.clone(),
+            kmer_size: model.kmer_size,
+            stranded_gmms: model.stranded_gmms.clone(),
+            n_samples: model.n_samples.clone(),
+            provenance: model.provenance.clone(),
+        };
+        let mut value = serde_pickle::to_value(&v3)?;
+        if let Value::Dict(dict) = &mut value {
+            dict.insert(HashableValue::String("version".to_string()), Value::I64(3));
+        }
+        let mut buf = Vec::new();
+        serde_pickle::value_to_writer(&mut buf, &value, Default::default())?;
+        let loaded = Model::read_versioned(buf.as_slice())?;
+        assert_eq!(loaded.gmms, model.gmms);
+        assert_eq!(loaded.n_samples("AAAAA"), Some(100));
+        assert_eq!(loaded.n_skip_samples("AAAAA"), None);
+        assert_eq!(loaded.provenance(), model.provenance());
+        Ok(())
+    }
+
+    #[test]
+    fn test_model_v2_migration() -> Result<()> {
+        let model = versioned_test_model();
+        let v2 = ModelV2 {
+            gmms: model.gmms.clone(),
+            skips: model.skips.clone(),
+            kmer_size: model.kmer_size,
+            stranded_gmms: model.stranded_gmms.clone(),
+        };
+        let mut value = serde_pickle::to_value(&v2)?;
+        if let Value::Dict(dict) = &mut value {
+            dict.insert(HashableValue::String("version".to_string()), Value::I64(2));
+        }
+        let mut buf = Vec::new();
+        serde_pickle::value_to_writer(&mut buf, &value, Default::default())?;
+        let loaded = Model::read_versioned(buf.as_slice())?;
+        assert_eq!(loaded.gmms, model.gmms);
+        assert_eq!(loaded.kmer_size, 5);
+        assert_eq!(loaded.n_samples("AAAAA"), None);
+        assert!(loaded.provenance().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_model_v1_migration() -> Result<()> {
+        let model = versioned_test_model();
+
+        // Saved after kmer_size and stranded models were added
+        let v1 = ModelV1 {
+            gmms: model.gmms.clone(),
+            skips: model.skips.clone(),
+            kmer_size: model.kmer_size,
+            stranded_gmms: model.stranded_gmms.clone(),
+        };
+        let buf = serde_pickle::to_vec(&v1, Default::default())?;
+        let loaded = Model::read_versioned(buf.as_slice())?;
+        assert_eq!(loaded.gmms, model.gmms);
+        assert_eq!(loaded.kmer_size, 5);
+        assert_eq!(loaded.stranded_gmms, model.stranded_gmms);
+
+        // Saved before either was added
+        #[derive(Serialize)]
+        struct Oldest {
+            gmms: ModelDB,
+            skips: FnvHashMap<String, f64>,
+        }
+        let oldest = Oldest {
+            gmms: model.gmms.clone(),
+            skips: model.skips.clone(),
+        };
+        let buf = serde_pickle::to_vec(&oldest, Default::default())?;
+        let loaded = Model::read_versioned(buf.as_slice())?;
+        assert_eq!(loaded.gmms, model.gmms);
+        assert_eq!(loaded.skips, model.skips);
+        assert_eq!(loaded.kmer_size, DEFAULT_KMER_SIZE);
+        assert!(!loaded.is_stranded());
+        Ok(())
+    }
+
+    #[test]
+    fn test_model_newer_version() -> Result<()> {
+        let mut value = serde_pickle::to_value(&versioned_test_model())?;
+        if let Value::Dict(dict) = &mut value {
+            dict.insert(HashableValue::String("version".to_string()), Value::I64(99));
+        }
+        let mut buf = Vec::new();
+        serde_pickle::value_to_writer(&mut buf, &value, Default::default())?;
+        let err = Model::read_versioned(buf.as_slice()).unwrap_err();
+        assert!(err.to_string().contains("newer"), "{err}");
+        Ok(())
+    }
 }
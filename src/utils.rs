@@ -1,18 +1,19 @@
 use std::{
-    collections::HashMap,
-    fs::File,
-    hash::{BuildHasher, Hash},
-    io::{stdout, Read, Seek, Write},
+    collections::{BTreeMap, HashMap},
+    env,
+    fs::{self, File},
+    hash::{BuildHasher, Hash, Hasher},
+    io::{self, stdout, BufRead, BufReader, BufWriter, Read, Seek, Write},
     path::{Path, PathBuf},
     process::Output,
     time::Duration,
 };
 
-use bio::io::fasta::IndexedReader;
+use bio::io::fasta::{Index, IndexedReader};
 use eyre::{Context, Result};
-use fnv::FnvHashMap;
+use fnv::{FnvHashMap, FnvHasher};
 use indicatif::{ProgressBar, ProgressStyle};
-use serde::{de::DeserializeOwned, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_pickle::from_reader;
 use which::which;
 
@@ -35,6 +36,196 @@ where
     }
 }
 
+/// Error if the output already exists, unless it is allowed to be overwritten.
+pub fn check_output<P: AsRef<Path>>(output: P, overwrite: bool) -> Result<()> {
+    let output = output.as_ref();
+    if !overwrite && output.exists() {
+        return Err(eyre::eyre!(
+            "Output {} already exists, use --overwrite to replace it",
+            output.display()
+        ));
+    }
+    Ok(())
+}
+
+/// Create the output directory for a pipeline. An existing directory is
+/// removed if overwrite is true, otherwise it is an error.
+pub fn prepare_output_dir<P: AsRef<Path>>(output_dir: P, overwrite: bool) -> Result<()> {
+    let output_dir = output_dir.as_ref();
+    check_output(output_dir, overwrite)?;
+    if output_dir.exists() {
+        fs::remove_dir_all(output_dir)?;
+    }
+    fs::create_dir_all(output_dir)?;
+    Ok(())
+}
+
+/// Writes to {path}.tmp and only moves it to path once [commit] is called, so
+/// a crash or error while writing never leaves a partial file that looks
+/// complete. The temporary file is removed if the writer is dropped without
+/// being committed.
+///
+/// [commit]: AtomicFileWriter::commit
+pub struct AtomicFileWriter {
+    path: PathBuf,
+    tmp_path: PathBuf,
+    writer: Option<BufWriter<File>>,
+}
+
+impl AtomicFileWriter {
+    /// Fails if the path already exists and overwrite is false, see
+    /// [check_output].
+    pub fn create<P: AsRef<Path>>(path: P, overwrite: bool) -> Result<Self> {
+        check_output(&path, overwrite)?;
+        let path = path.as_ref().to_path_buf();
+        let mut tmp_path = path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+        let writer = BufWriter::new(File::create(&tmp_path)?);
+        Ok(Self {
+            path,
+            tmp_path,
+            writer: Some(writer),
+        })
+    }
+
+    /// Flush everything to disk and move the temporary file to the final path
+    pub fn commit(mut self) -> Result<()> {
+        if let Some(writer) = self.writer.take() {
+            let file = writer.into_inner().map_err(|e| e.into_error())?;
+            file.sync_all()?;
+            fs::rename(&self.tmp_path, &self.path)?;
+        }
+        Ok(())
+    }
+
+    fn writer(&mut self) -> &mut BufWriter<File> {
+        self.writer
+            .as_mut()
+            .expect("Writer only taken when committed")
+    }
+}
+
+impl Write for AtomicFileWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer().flush()
+    }
+}
+
+impl Drop for AtomicFileWriter {
+    fn drop(&mut self) {
+        if self.writer.take().is_some() {
+            let _ = fs::remove_file(&self.tmp_path);
+        }
+    }
+}
+
+/// Call f with a writer to stdout if no filename is given, otherwise to an
+/// [AtomicFileWriter] that is only committed if f succeeds.
+pub fn with_stdout_or_file<P, F>(filename: Option<&P>, overwrite: bool, f: F) -> Result<()>
+where
+    P: AsRef<Path>,
+    F: FnOnce(&mut dyn Write) -> Result<()>,
+{
+    match filename {
+        Some(fp) => {
+            let mut writer = AtomicFileWriter::create(fp, overwrite)?;
+            f(&mut writer)?;
+            writer.commit()
+        }
+        None => {
+            let mut handle = stdout().lock();
+            f(&mut handle)?;
+            handle.flush()?;
+            Ok(())
+        }
+    }
+}
+
+/// Default values for command line options, read from a TOML file passed
+/// with --config. Each subcommand has its own table, ie [score] or
+/// [npsmlr.train], with keys matching the long option names.
+#[derive(Debug, Default, Deserialize)]
+#[serde(transparent)]
+pub struct CawlrConfig(BTreeMap<String, ConfigEntry>);
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum ConfigEntry {
+    /// Options for a nested subcommand
+    Subcommand(CawlrConfig),
+    /// Options that take multiple values, ie motif = ["2:GC", "1:AT"]
+    Values(Vec<ConfigValue>),
+    Value(ConfigValue),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum ConfigValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+}
+
+impl CawlrConfig {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .wrap_err_with(|| format!("Failed to read config {}", path.display()))?;
+        let config = toml::from_str(&contents)
+            .wrap_err_with(|| format!("Failed to parse config {}", path.display()))?;
+        Ok(config)
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &ConfigEntry)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v))
+    }
+}
+
+impl ConfigEntry {
+    /// Values as they would be written on the command line, with ~ and
+    /// environment variables in strings expanded
+    pub fn values(&self) -> Result<Vec<String>> {
+        self.values_with_env(|var| env::var(var))
+    }
+
+    /// Like [ConfigEntry::values], with environment variables looked up by
+    /// env instead of from the process environment
+    fn values_with_env<F>(&self, env: F) -> Result<Vec<String>>
+    where
+        F: Fn(&str) -> Result<String, env::VarError>,
+    {
+        match self {
+            Self::Subcommand(_) => Err(eyre::eyre!("Expected a value, found a table")),
+            Self::Values(values) => values.iter().map(|v| v.expand(&env)).collect(),
+            Self::Value(value) => Ok(vec![value.expand(&env)?]),
+        }
+    }
+}
+
+impl ConfigValue {
+    fn expand<F>(&self, env: &F) -> Result<String>
+    where
+        F: Fn(&str) -> Result<String, env::VarError>,
+    {
+        let value = match self {
+            Self::Bool(b) => b.to_string(),
+            Self::Int(n) => n.to_string(),
+            Self::Float(x) => x.to_string(),
+            Self::String(s) => {
+                let expanded = shellexpand::env_with_context(s, |var| env(var).map(Some))?;
+                shellexpand::tilde(&expanded).into_owned()
+            }
+        };
+        Ok(value)
+    }
+}
+
 pub trait CawlrIO {
     fn save<W: Write>(&self, writer: &mut W) -> Result<()>;
     fn save_as<P>(&self, filename: P) -> Result<()>
@@ -77,8 +268,7 @@ where
 
 impl CawlrIO for Model {
     fn save<W: Write>(&self, writer: &mut W) -> Result<()> {
-        serde_pickle::to_writer(writer, self, Default::default())?;
-        Ok(())
+        self.write_versioned(writer)
     }
 
     fn save_as<P>(&self, filename: P) -> Result<()>
@@ -86,17 +276,17 @@ impl CawlrIO for Model {
         P: AsRef<Path>,
     {
         let mut file = File::create(filename)?;
-        serde_pickle::to_writer(&mut file, &self, Default::default())?;
-        Ok(())
+        self.write_versioned(&mut file)
     }
 
+    /// Models saved by older versions of cawlr are upgraded to the current
+    /// format, see [ModelVersion](crate::train::ModelVersion)
     fn load<P>(filename: P) -> Result<Self>
     where
         P: AsRef<Path>,
     {
         let file = File::open(filename)?;
-        let model_db = from_reader(file, Default::default())?;
-        Ok(model_db)
+        Model::read_versioned(BufReader::new(file))
     }
 }
 
@@ -114,6 +304,205 @@ where
     chrom_lens
 }
 
+/// Line lengths and position in the file of one sequence in a fasta index
+struct FaiRecord {
+    name: String,
+    length: u64,
+    offset: u64,
+    line_bases: u64,
+    line_width: u64,
+    /// A line shorter than the others was seen, it must be the last one
+    ended: bool,
+}
+
+/// Write a samtools faidx compatible index of the fasta. Errors if the fasta
+/// is malformed, with sequence before the first header or lines within a
+/// sequence of different lengths.
+pub fn build_fasta_index<R: BufRead, W: Write>(mut reader: R, mut writer: W) -> Result<()> {
+    let write_record = |writer: &mut W, rec: &FaiRecord| {
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}",
+            rec.name, rec.length, rec.offset, rec.line_bases, rec.line_width
+        )
+    };
+    let mut record: Option<FaiRecord> = None;
+    let mut offset = 0;
+    let mut line = Vec::new();
+    for line_no in 1.. {
+        line.clear();
+        let width = reader.read_until(b'\n', &mut line)? as u64;
+        if width == 0 {
+            break;
+        }
+        offset += width;
+        let bases = line
+            .iter()
+            .rev()
+            .skip_while(|&&b| b == b'\n' || b == b'\r')
+            .count() as u64;
+
+        if line[0] == b'>' {
+            if let Some(rec) = record.take() {
+                write_record(&mut writer, &rec)?;
+            }
+            let name = String::from_utf8_lossy(&line[1..])
+                .split_whitespace()
+                .next()
+                .map(String::from)
+                .ok_or_else(|| eyre::eyre!("Missing sequence name on line {line_no}"))?;
+            record = Some(FaiRecord {
+                name,
+                length: 0,
+                offset,
+                line_bases: 0,
+                line_width: 0,
+                ended: false,
+            });
+            continue;
+        }
+
+        let rec = record
+            .as_mut()
+            .ok_or_else(|| eyre::eyre!("Sequence before the first header on line {line_no}"))?;
+        if bases == 0 {
+            rec.ended = true;
+            continue;
+        }
+        if rec.ended {
+            return Err(eyre::eyre!(
+                "Line {line_no} of {} follows a shorter line, all lines of a sequence except the last must be the same length",
+                rec.name
+            ));
+        }
+        if rec.line_bases == 0 {
+            rec.line_bases = bases;
+            rec.line_width = width;
+        } else if bases > rec.line_bases {
+            return Err(eyre::eyre!(
+                "Line {line_no} of {} is longer than the lines before it",
+                rec.name
+            ));
+        }
+        if bases < rec.line_bases || width != rec.line_width {
+            rec.ended = true;
+        }
+        rec.length += bases;
+    }
+    if let Some(rec) = record {
+        write_record(&mut writer, &rec)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Index next to the fasta, where samtools faidx writes it
+fn fai_path(path: &Path) -> PathBuf {
+    let mut fai = path.as_os_str().to_owned();
+    fai.push(".fai");
+    PathBuf::from(fai)
+}
+
+/// Index in the temp directory for fastas in directories that can't be
+/// written to, named after the full path of the fasta
+pub(crate) fn temp_fai_path(path: &Path, temp_dir: &Path) -> Result<PathBuf> {
+    let mut hasher = FnvHasher::default();
+    path.canonicalize()?.hash(&mut hasher);
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    Ok(temp_dir.join(format!("{name}.{:016x}.fai", hasher.finish())))
+}
+
+/// Index for the fasta, either from samtools faidx or written to temp_dir by
+/// [index_genome_fasta] since the fasta was last modified
+fn find_fasta_index(path: &Path, temp_dir: &Path) -> Option<PathBuf> {
+    let fai = fai_path(path);
+    if fai.is_file() {
+        return Some(fai);
+    }
+    let temp_fai = temp_fai_path(path, temp_dir).ok()?;
+    let modified = |p: &Path| p.metadata().and_then(|m| m.modified()).ok();
+    match (modified(&temp_fai), modified(path)) {
+        (Some(fai_time), Some(fasta_time)) if fai_time >= fasta_time => Some(temp_fai),
+        _ => None,
+    }
+}
+
+/// Index the genome fasta if it doesn't have an index yet, returning the
+/// path to the index. Like samtools faidx the index is written next to the
+/// fasta, or to the temp directory if that fails. Only errors if the fasta
+/// is missing or malformed.
+pub fn index_genome_fasta(path: &Path) -> Result<PathBuf> {
+    if !path.is_file() {
+        return Err(eyre::eyre!("Genome fasta {} not found", path.display()));
+    }
+    let temp_dir = env::temp_dir();
+    if let Some(fai) = find_fasta_index(path, &temp_dir) {
+        return Ok(fai);
+    }
+
+    log::info!("Indexing genome fasta {}", path.display());
+    let mut index = Vec::new();
+    build_fasta_index(BufReader::new(File::open(path)?), &mut index)
+        .wrap_err_with(|| format!("Failed to index genome fasta {}", path.display()))?;
+    let write_index = |fai: &Path| -> Result<()> {
+        let mut writer = AtomicFileWriter::create(fai, true)?;
+        writer.write_all(&index)?;
+        writer.commit()
+    };
+
+    let fai = fai_path(path);
+    if let Err(e) = write_index(&fai) {
+        let temp_fai = temp_fai_path(path, &temp_dir)?;
+        log::warn!(
+            "Failed to write index {}: {e}, writing it to {} instead",
+            fai.display(),
+            temp_fai.display()
+        );
+        write_index(&temp_fai)?;
+        return Ok(temp_fai);
+    }
+    Ok(fai)
+}
+
+/// Open the genome fasta with its index, including indexes written to the
+/// temp directory by [index_genome_fasta]
+pub fn open_genome_fasta(path: &Path) -> Result<IndexedReader<File>> {
+    open_genome_fasta_in(path, &env::temp_dir())
+}
+
+/// Like [open_genome_fasta], looking for indexes written to temp_dir
+fn open_genome_fasta_in(path: &Path, temp_dir: &Path) -> Result<IndexedReader<File>> {
+    let fai = find_fasta_index(path, temp_dir).ok_or_else(|| {
+        eyre::eyre!(
+            "Missing index {} for genome fasta, run samtools faidx {}",
+            fai_path(path).display(),
+            path.display()
+        )
+    })?;
+    let index = Index::from_file(&fai)
+        .map_err(|e| eyre::eyre!("Failed to read genome index {}: {e}", fai.display()))?;
+    Ok(IndexedReader::with_index(File::open(path)?, index))
+}
+
+/// Check that the genome fasta exists, has a .fai index from samtools faidx
+/// or [index_genome_fasta], and can be opened, so commands using it fail
+/// before any expensive work.
+pub fn validate_genome_fasta(path: &Path) -> Result<()> {
+    validate_genome_fasta_in(path, &env::temp_dir())
+}
+
+/// Like [validate_genome_fasta], looking for indexes written to temp_dir
+fn validate_genome_fasta_in(path: &Path, temp_dir: &Path) -> Result<()> {
+    if !path.is_file() {
+        return Err(eyre::eyre!(
+            "Genome fasta {} not found, it must exist and be indexed with samtools faidx",
+            path.display()
+        ));
+    }
+    open_genome_fasta_in(path, temp_dir)?;
+    Ok(())
+}
+
 pub fn find_binary(name: &'static str, binary_filepath: &Option<PathBuf>) -> eyre::Result<PathBuf> {
     if let Some(p) = binary_filepath {
         Ok(p.to_path_buf())
@@ -189,3 +578,195 @@ pub fn parse_name_from_output_dir<P: AsRef<Path>>(path: P) -> eyre::Result<Strin
         .ok_or(eyre::eyre!("Invalid path name"))?;
     Ok(name.to_string())
 }
+
+#[cfg(test)]
+mod test {
+    use assert_fs::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_atomic_file_writer() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let output = temp_dir.path().join("output.txt");
+        let tmp_output = temp_dir.path().join("output.txt.tmp");
+
+        let mut writer = AtomicFileWriter::create(&output, false)?;
+        write!(writer, "first")?;
+        writer.flush()?;
+        assert!(tmp_output.exists());
+        assert!(!output.exists());
+        writer.commit()?;
+        assert!(!tmp_output.exists());
+        assert_eq!(fs::read_to_string(&output)?, "first");
+
+        assert!(AtomicFileWriter::create(&output, false).is_err());
+        let mut writer = AtomicFileWriter::create(&output, true)?;
+        write!(writer, "second")?;
+        writer.commit()?;
+        assert_eq!(fs::read_to_string(&output)?, "second");
+        Ok(())
+    }
+
+    #[test]
+    fn test_failed_write_no_partial_output() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let output = temp_dir.path().join("output.txt");
+        let tmp_output = temp_dir.path().join("output.txt.tmp");
+
+        // Writing succeeds partway before the disk fills up
+        let res = with_stdout_or_file(Some(&output), false, |writer| {
+            writer.write_all(&[b'x'; 100_000])?;
+            Err(io::Error::new(io::ErrorKind::Other, "No space left on device").into())
+        });
+        assert!(res.is_err());
+        assert!(!output.exists());
+        assert!(!tmp_output.exists());
+
+        // A previous output is left untouched by a failed overwrite
+        fs::write(&output, "previous")?;
+        let res = with_stdout_or_file(Some(&output), true, |writer| {
+            writer.write_all(b"partial")?;
+            Err(eyre::eyre!("Failed"))
+        });
+        assert!(res.is_err());
+        assert_eq!(fs::read_to_string(&output)?, "previous");
+        assert!(!tmp_output.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_config() -> Result<()> {
+        let vars = |var: &str| match var {
+            "CAWLR_TEST_GENOME_DIR" => Ok("/data".to_string()),
+            _ => Err(env::VarError::NotPresent),
+        };
+        let config: CawlrConfig = toml::from_str(
+            r#"
+            [score]
+            genome = "$CAWLR_TEST_GENOME_DIR/sacCer3.fa"
+            cutoff = 5.0
+            motif = ["2:GC", "1:AT"]
+
+            [npsmlr.train]
+            samples = 100
+            single = true
+            "#,
+        )?;
+        let entries = config.entries().collect::<Vec<_>>();
+        assert_eq!(entries.len(), 2);
+
+        let (name, score) = entries[1];
+        assert_eq!(name, "score");
+        let score = match score {
+            ConfigEntry::Subcommand(score) => score.entries().collect::<BTreeMap<_, _>>(),
+            _ => panic!("Expected a table for score"),
+        };
+        assert_eq!(
+            score["genome"].values_with_env(vars)?,
+            vec!["/data/sacCer3.fa"]
+        );
+        assert!(score["genome"]
+            .values_with_env(|_| Err(env::VarError::NotPresent))
+            .is_err());
+        assert_eq!(score["cutoff"].values()?, vec!["5"]);
+        assert_eq!(score["motif"].values()?, vec!["2:GC", "1:AT"]);
+
+        let (name, npsmlr) = entries[0];
+        assert_eq!(name, "npsmlr");
+        assert!(npsmlr.values().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_prepare_output_dir() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let output_dir = temp_dir.path().join("pipeline");
+        prepare_output_dir(&output_dir, false)?;
+        fs::write(output_dir.join("log.txt"), "log")?;
+
+        assert!(prepare_output_dir(&output_dir, false).is_err());
+        assert!(output_dir.join("log.txt").exists());
+        prepare_output_dir(&output_dir, true)?;
+        assert!(output_dir.exists());
+        assert!(!output_dir.join("log.txt").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_genome_fasta() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let genome = temp_dir.path().join("genome.fa");
+        let err = validate_genome_fasta(&genome).unwrap_err().to_string();
+        assert!(err.contains("samtools faidx"), "{err}");
+
+        fs::write(&genome, ">chrI\nACGT\n")?;
+        let err = validate_genome_fasta(&genome).unwrap_err().to_string();
+        assert!(err.contains("genome.fa.fai"), "{err}");
+        assert!(err.contains("run samtools faidx"), "{err}");
+
+        // Index written to the temp directory when the fasta's directory
+        // isn't writable
+        let fai_dir = TempDir::new()?;
+        assert!(validate_genome_fasta_in(&genome, fai_dir.path()).is_err());
+        fs::write(
+            temp_fai_path(&genome, fai_dir.path())?,
+            "chrI\t4\t6\t4\t5\n",
+        )?;
+        validate_genome_fasta_in(&genome, fai_dir.path())?;
+
+        validate_genome_fasta(Path::new("extra/sacCer3.fa"))
+    }
+
+    #[test]
+    fn test_build_fasta_index() -> Result<()> {
+        let mut index = Vec::new();
+        build_fasta_index(BufReader::new(File::open("extra/sacCer3.fa")?), &mut index)?;
+        assert_eq!(
+            String::from_utf8(index)?,
+            fs::read_to_string("extra/sacCer3.fa.fai")?
+        );
+
+        let mut index = Vec::new();
+        build_fasta_index(
+            ">chrI desc\nACGT\nACGT\nAC\n>chrII\nAAA\n".as_bytes(),
+            &mut index,
+        )?;
+        assert_eq!(
+            String::from_utf8(index)?,
+            "chrI\t10\t11\t4\t5\nchrII\t3\t31\t3\t4\n"
+        );
+
+        for malformed in [
+            "ACGT\n>chrI\nACGT\n",
+            ">chrI\nACGT\nAC\nACGT\n",
+            ">chrI\nAC\nACGT\n",
+        ] {
+            assert!(build_fasta_index(malformed.as_bytes(), Vec::new()).is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_index_genome_fasta() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let genome = temp_dir.path().join("genome.fa");
+        fs::write(&genome, ">chrI\nACGTAC\nGTACGT\nAC\n")?;
+        assert!(open_genome_fasta(&genome).is_err());
+
+        let fai = index_genome_fasta(&genome)?;
+        assert_eq!(fai, temp_dir.path().join("genome.fa.fai"));
+        validate_genome_fasta(&genome)?;
+
+        let mut reader = open_genome_fasta(&genome)?;
+        let mut seq = Vec::new();
+        reader.fetch("chrI", 4, 9)?;
+        reader.read(&mut seq)?;
+        assert_eq!(seq, b"ACGTA");
+
+        let malformed = temp_dir.path().join("malformed.fa");
+        fs::write(&malformed, "ACGT\n")?;
+        assert!(index_genome_fasta(&malformed).is_err());
+        Ok(())
+    }
+}
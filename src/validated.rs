@@ -1,22 +1,110 @@
 //! Data that has been validated for passing to training models
 
-pub struct ValidSampleData(Vec<f64>);
+use std::ops::{Bound, RangeBounds};
+
+use crate::arrow::metadata::Strand;
+
+/// Fraction of valid samples below which training warns about the kmer
+pub const MIN_FRACTION_VALID: f64 = 0.5;
+
+pub struct ValidSampleData {
+    xs: Vec<f64>,
+    n_original: usize,
+}
 
 impl ValidSampleData {
     pub fn validated(xs: Vec<f64>) -> Option<Self> {
+        Self::validated_within(xs, &(Bound::Excluded(40.), Bound::Excluded(170.)))
+    }
+
+    /// Like [ValidSampleData::validated], but keeps measurements within
+    /// signal_range instead of between 40 and 170
+    pub fn validated_within<R: RangeBounds<f64>>(xs: Vec<f64>, signal_range: &R) -> Option<Self> {
+        let n_original = xs.len();
         let xs: Vec<f64> = xs
             .into_iter()
-            .filter(|&x| (x > 40.) && (x < 170.))
+            .filter(|x| signal_range.contains(x))
             .collect();
         if xs.len() < 2 {
             None
         } else {
-            Some(ValidSampleData(xs))
+            Some(ValidSampleData { xs, n_original })
         }
     }
 
+    /// Number of samples before validation
+    pub fn n_original(&self) -> usize {
+        self.n_original
+    }
+
+    /// Number of samples kept by validation
+    pub fn n_valid(&self) -> usize {
+        self.xs.len()
+    }
+
+    /// Fraction of the samples kept by validation, a low fraction usually
+    /// means a problem normalizing the signal upstream
+    pub fn fraction_valid(&self) -> f64 {
+        self.n_valid() as f64 / self.n_original as f64
+    }
+
     pub fn inner(self) -> Vec<f64> {
-        self.0
+        self.xs
+    }
+}
+
+/// Number of samples kept by validation for a single kmer
+#[derive(Debug, Clone, PartialEq)]
+pub struct KmerValidation {
+    pub kmer: String,
+    /// Strand of the reads used, None if reads from both strands were used
+    pub strand: Option<Strand>,
+    /// Number of measurements of the kmer read from the inputs
+    pub n_original: usize,
+    /// Number of those measurements that were finite and within the signal
+    /// range
+    pub n_valid: usize,
+}
+
+impl KmerValidation {
+    pub fn fraction_valid(&self) -> f64 {
+        self.n_valid as f64 / self.n_original as f64
+    }
+}
+
+/// Validation statistics for every kmer that passed validation. Kmers with
+/// fewer than two valid samples are reported as failed in the training
+/// report instead.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ValidationReport {
+    kmers: Vec<KmerValidation>,
+}
+
+impl ValidationReport {
+    pub(crate) fn add(
+        &mut self,
+        kmer: &str,
+        strand: Option<Strand>,
+        n_original: usize,
+        n_valid: usize,
+    ) {
+        self.kmers.push(KmerValidation {
+            kmer: kmer.to_string(),
+            strand,
+            n_original,
+            n_valid,
+        });
+    }
+
+    pub fn kmers(&self) -> &[KmerValidation] {
+        &self.kmers
+    }
+
+    /// Kmers where less than [MIN_FRACTION_VALID] of the samples were valid
+    pub fn mostly_invalid(&self) -> impl Iterator<Item = &KmerValidation> {
+        self.kmers
+            .iter()
+            .filter(|k| k.fraction_valid() < MIN_FRACTION_VALID)
     }
 }
 
@@ -50,4 +138,41 @@ mod test {
         let xs = ValidSampleData::validated(case);
         assert!(xs.is_none(), "large finite");
     }
+
+    #[test]
+    fn test_validated_within() {
+        let case = vec![60.0, 200.0, 250.0, 251.0, f64::NAN];
+        let xs = ValidSampleData::validated_within(case, &(60.0..=250.0)).unwrap();
+        assert_eq!(xs.inner(), vec![60.0, 200.0, 250.0]);
+
+        let case = vec![200.0, 210.0];
+        assert!(ValidSampleData::validated(case.clone()).is_none());
+        assert!(ValidSampleData::validated_within(case, &(60.0..=250.0)).is_some());
+    }
+
+    #[test]
+    fn test_validation_counts() {
+        let case = vec![100.0, f64::NAN, 110.0, f64::INFINITY, 120.0, f64::NAN];
+        let xs = ValidSampleData::validated(case).unwrap();
+        assert_eq!(xs.n_original(), 6);
+        assert_eq!(xs.n_valid(), 3);
+        assert_eq!(xs.fraction_valid(), 0.5);
+
+        let case = vec![100.0, f64::NAN, f64::NAN, f64::NAN, 120.0];
+        let ys = ValidSampleData::validated_within(case, &(60.0..=250.0)).unwrap();
+        assert_eq!(ys.n_original(), 5);
+        assert_eq!(ys.n_valid(), 2);
+
+        let mut report = ValidationReport::default();
+        report.add("AAAAAA", None, xs.n_original(), xs.n_valid());
+        report.add(
+            "CCCCCC",
+            Some(Strand::plus()),
+            ys.n_original(),
+            ys.n_valid(),
+        );
+        assert_eq!(report.kmers().len(), 2);
+        let mostly_invalid: Vec<_> = report.mostly_invalid().map(|k| &k.kmer).collect();
+        assert_eq!(mostly_invalid, ["CCCCCC"]);
+    }
 }
@@ -0,0 +1,20 @@
+use std::{error::Error, process::Command};
+
+use escargot::CargoBuild;
+
+#[test]
+fn bash_completions() -> Result<(), Box<dyn Error>> {
+    let run = CargoBuild::new().package("cawlr").run()?;
+    let output = Command::new(run.path())
+        .arg("completions")
+        .arg("--shell")
+        .arg("bash")
+        .output()?;
+    assert!(output.status.success());
+
+    let script = String::from_utf8(output.stdout)?;
+    assert!(script.starts_with("_cawlr()"), "{script}");
+    assert!(script.contains("complete -F _cawlr"));
+    assert!(script.contains("npsmlr"));
+    Ok(())
+}
@@ -0,0 +1,41 @@
+use std::{error::Error, process::Command};
+
+use assert_fs::TempDir;
+use escargot::CargoBuild;
+
+#[test]
+fn score_genome_from_config() -> Result<(), Box<dyn Error>> {
+    let temp_dir = TempDir::new()?;
+    let run = CargoBuild::new().package("cawlr").run()?;
+
+    // Genome without a .fai index, so cawlr score stops after checking for it
+    let genome = temp_dir.path().join("genome.fa");
+    std::fs::write(&genome, ">chrI\nACGT\n")?;
+    let config = temp_dir.path().join("config.toml");
+    std::fs::write(
+        &config,
+        format!("[score]\ngenome = \"{}\"\n", genome.display()),
+    )?;
+
+    let output = Command::new(run.path())
+        .arg("score")
+        .arg("--config")
+        .arg(&config)
+        .arg("-i")
+        .arg("in.arrow")
+        .arg("-o")
+        .arg(temp_dir.path().join("out.arrow"))
+        .arg("--pos-ctrl")
+        .arg("pos.pickle")
+        .arg("--neg-ctrl")
+        .arg("neg.pickle")
+        .arg("-r")
+        .arg("ranks.pickle")
+        .arg("--no-auto-index")
+        .output()?;
+    let stderr = String::from_utf8(output.stderr)?;
+    assert!(!output.status.success());
+    assert!(!stderr.contains("--genome"), "{stderr}");
+    assert!(stderr.contains("Missing index"), "{stderr}");
+    Ok(())
+}
@@ -1,10 +1,58 @@
-use std::{error::Error, process::Command};
+use std::{error::Error, io::Cursor, path::Path, process::Command};
 
 use assert_cmd::prelude::OutputAssertExt;
 use assert_fs::{assert::PathAssert, fixture::PathChild, TempDir};
 use escargot::CargoBuild;
+use libcawlr::{
+    arrow::{arrow_utils::load_apply, eventalign::Eventalign},
+    rank::load_ranks,
+};
 use predicates::prelude::predicate;
 
+/// Checks the wig has a track line followed by variableStep blocks with
+/// increasing positions and values between 0 and 1
+fn check_wig(path: &Path) -> Result<(), Box<dyn Error>> {
+    let wig = std::fs::read_to_string(path)?;
+    let mut lines = wig.lines();
+    let track = lines.next().ok_or("Empty wig file")?;
+    assert!(track.starts_with("track type=wiggle_0"), "{track}");
+
+    let mut last_pos = None;
+    let mut n_values = 0;
+    for line in lines {
+        if let Some(header) = line.strip_prefix("variableStep ") {
+            assert!(header.starts_with("chrom="), "{line}");
+            assert!(header.ends_with("span=1"), "{line}");
+            last_pos = Some(0);
+            continue;
+        }
+        let (pos, value) = line.split_once('\t').ok_or("Missing value column")?;
+        let pos: u64 = pos.parse()?;
+        let value: f64 = value.parse()?;
+        let prev = last_pos.ok_or("Value line before variableStep header")?;
+        assert!(pos > prev, "{line}");
+        assert!((0.0..=1.0).contains(&value), "{line}");
+        last_pos = Some(pos);
+        n_values += 1;
+    }
+    assert!(n_values > 0);
+    Ok(())
+}
+
+/// Number of reads and total number of blocks in BED12 output from cawlr sma
+fn sma_block_counts(path: &Path) -> Result<(usize, usize), Box<dyn Error>> {
+    let bed = std::fs::read_to_string(path)?;
+    let mut n_reads = 0;
+    let mut n_blocks = 0;
+    for line in bed.lines().skip(1) {
+        let cols = line.split('\t').collect::<Vec<_>>();
+        assert_eq!(cols.len(), 12, "{line}");
+        n_blocks += cols[9].parse::<usize>()?;
+        n_reads += 1;
+    }
+    Ok((n_reads, n_blocks))
+}
+
 #[test]
 fn integration() -> Result<(), Box<dyn Error>> {
     let temp_dir = TempDir::new()?.into_persistent_if(std::env::var("TEST_PERSIST").is_ok());
@@ -60,6 +108,54 @@ fn integration() -> Result<(), Box<dyn Error>> {
         .assert()
         .success();
 
+    eprintln!("Preprocessing single read to stdout");
+    let stdout = Command::new(cawlr)
+        .arg("collapse")
+        .arg("-i")
+        .arg("extra/single_read.eventalign.txt")
+        .arg("-b")
+        .arg("extra/single_read.bam")
+        .arg("-o")
+        .arg("-")
+        .output()?;
+    assert!(stdout.status.success());
+    let mut n_reads = 0;
+    load_apply(Cursor::new(stdout.stdout), |reads: Vec<Eventalign>| {
+        n_reads += reads.len();
+        Ok(())
+    })?;
+    assert_eq!(n_reads, 1);
+
+    eprintln!("Inspecting single read collapse output");
+    let inspect = Command::new(cawlr)
+        .arg("inspect-arrow")
+        .arg("-i")
+        .arg(&single_read_output)
+        .arg("-n")
+        .arg("1")
+        .output()?;
+    assert!(inspect.status.success());
+    let inspect = String::from_utf8(inspect.stdout)?;
+    let columns = inspect
+        .lines()
+        .take_while(|line| !line.is_empty())
+        .skip(2)
+        .filter_map(|line| line.split_whitespace().next())
+        .collect::<Vec<_>>();
+    for column in [
+        "eventalign.metadata.name",
+        "eventalign.metadata.chrom",
+        "eventalign.metadata.start",
+        "eventalign.signal_data",
+    ] {
+        assert!(
+            columns.contains(&column),
+            "{column} missing from {columns:?}"
+        );
+    }
+    let rows = inspect.split("\n\n").nth(1).ok_or("No rows printed")?;
+    assert_eq!(rows.lines().count(), 3);
+
     // Indexing
     Command::new(cawlr)
         .arg("index")
@@ -137,6 +233,118 @@ fn integration() -> Result<(), Box<dyn Error>> {
         .assert()
         .success();
 
+    eprintln!("Ranking kmers around motifs");
+    let motif_ranks = temp_dir.path().join("motif_ranks");
+    Command::new(cawlr)
+        .arg("rank")
+        .arg("--neg-ctrl")
+        .arg(&neg_train)
+        .arg("--pos-ctrl")
+        .arg(&pos_train)
+        .arg("-m")
+        .arg("1:TA")
+        .arg("-m")
+        .arg("2:AT")
+        .arg("-o")
+        .arg(&motif_ranks)
+        .env("RUST_BACKTRACE", "full")
+        .assert()
+        .success();
+
+    eprintln!("Scoring single read with motif ranks");
+    let motif_scores = temp_dir.path().join("single_motif_scores");
+    Command::new(cawlr)
+        .arg("score")
+        .arg("-m")
+        .arg("1:TA")
+        .arg("-m")
+        .arg("2:AT")
+        .arg("--neg-ctrl")
+        .arg(&neg_train)
+        .arg("--pos-ctrl")
+        .arg(&pos_train)
+        .arg("-i")
+        .arg(&single_read_output)
+        .arg("-r")
+        .arg(&motif_ranks)
+        .arg("-g")
+        .arg(genome)
+        .arg("-o")
+        .arg(&motif_scores)
+        .env("RUST_BACKTRACE", "1")
+        .assert()
+        .success();
+    assert_eq!(std::fs::read(&scores)?, std::fs::read(&motif_scores)?);
+
+    eprintln!("Scoring single read with the top 100 ranks");
+    let top_ranks = temp_dir.path().join("top_ranks");
+    Command::new(cawlr)
+        .arg("rank")
+        .arg("--neg-ctrl")
+        .arg(&neg_train)
+        .arg("--pos-ctrl")
+        .arg(&pos_train)
+        .arg("--filter-min-rank")
+        .arg("100")
+        .arg("-o")
+        .arg(&top_ranks)
+        .env("RUST_BACKTRACE", "full")
+        .assert()
+        .success();
+    let (top_ranks_map, _) = load_ranks(&top_ranks)?;
+    assert!(!top_ranks_map.is_empty() && top_ranks_map.len() <= 100);
+    Command::new(cawlr)
+        .arg("score")
+        .arg("-m")
+        .arg("1:TA")
+        .arg("-m")
+        .arg("2:AT")
+        .arg("--neg-ctrl")
+        .arg(&neg_train)
+        .arg("--pos-ctrl")
+        .arg(&pos_train)
+        .arg("-i")
+        .arg(&single_read_output)
+        .arg("-r")
+        .arg(&top_ranks)
+        .arg("-g")
+        .arg(genome)
+        .arg("-o")
+        .arg(temp_dir.path().join("single_top_scores"))
+        .env("RUST_BACKTRACE", "1")
+        .assert()
+        .success();
+
+    eprintln!("Summarizing single read scores");
+    let stats = Command::new(cawlr)
+        .arg("stats")
+        .arg("-i")
+        .arg(&scores)
+        .env("RUST_BACKTRACE", "1")
+        .assert()
+        .success();
+    let stats: serde_json::Value = serde_json::from_slice(&stats.get_output().stdout)?;
+    assert_eq!(stats["n_reads"], 1);
+
+    eprintln!("Counting single read coverage");
+    let coverage = temp_dir.path().join("single_coverage.bed");
+    Command::new(cawlr)
+        .arg("coverage")
+        .arg("-i")
+        .arg(&scores)
+        .arg("-o")
+        .arg(&coverage)
+        .env("RUST_BACKTRACE", "1")
+        .assert()
+        .success();
+    let coverage = std::fs::read_to_string(&coverage)?;
+    assert!(coverage.lines().count() > 0);
+    for line in coverage.lines() {
+        let cols: Vec<&str> = line.split('\t').collect();
+        assert_eq!(cols.len(), 6, "{line}");
+        assert_eq!(cols[4], "1", "{line}");
+    }
+
     eprintln!("Scoring positive controls");
     let pos_scores = temp_dir.path().join("pos_scores");
     Command::new(cawlr)
@@ -157,6 +365,58 @@ fn integration() -> Result<(), Box<dyn Error>> {
         .assert()
         .success();
 
+    eprintln!("Scoring positive controls on a single thread");
+    let single_thread_scores = temp_dir.path().join("single_thread_pos_scores");
+    Command::new(cawlr)
+        .arg("score")
+        .arg("--neg-ctrl")
+        .arg(&neg_train)
+        .arg("--pos-ctrl")
+        .arg(&pos_train)
+        .arg("-i")
+        .arg(&pos_output)
+        .arg("-r")
+        .arg(&ranks)
+        .arg("-g")
+        .arg(genome)
+        .arg("-o")
+        .arg(&single_thread_scores)
+        .arg("-j")
+        .arg("1")
+        .env("RUST_BACKTRACE", "1")
+        .assert()
+        .success();
+    assert_eq!(
+        std::fs::read(&pos_scores)?,
+        std::fs::read(&single_thread_scores)?
+    );
+
+    eprintln!("Scoring positive controls one read at a time");
+    let single_read_batch_scores = temp_dir.path().join("single_read_batch_pos_scores");
+    Command::new(cawlr)
+        .arg("score")
+        .arg("--neg-ctrl")
+        .arg(&neg_train)
+        .arg("--pos-ctrl")
+        .arg(&pos_train)
+        .arg("-i")
+        .arg(&pos_output)
+        .arg("-r")
+        .arg(&ranks)
+        .arg("-g")
+        .arg(genome)
+        .arg("-o")
+        .arg(&single_read_batch_scores)
+        .arg("--batch-reads")
+        .arg("1")
+        .env("RUST_BACKTRACE", "1")
+        .assert()
+        .success();
+    assert_eq!(
+        std::fs::read(&pos_scores)?,
+        std::fs::read(&single_read_batch_scores)?
+    );
+
     eprintln!("Scoring negative controls");
     let neg_scores = temp_dir.path().join("neg_scores");
     Command::new(cawlr)
@@ -179,7 +439,7 @@ fn integration() -> Result<(), Box<dyn Error>> {
 
     eprintln!("Compute pos ctrl kernel density estimate");
     let pos_bkde_model = temp_dir.path().join("pos_bkde_model");
-    Command::new(cawlr)
+    let model_scores = Command::new(cawlr)
         .arg("model-scores")
         .arg("-i")
         .arg(&pos_scores)
@@ -187,9 +447,21 @@ fn integration() -> Result<(), Box<dyn Error>> {
         .arg("1000")
         .arg("-o")
         .arg(&pos_bkde_model)
+        .arg("--percentile")
+        .arg("0.05")
+        .arg("--percentile")
+        .arg("0.95")
         .env("RUST_BACKTRACE", "1")
         .assert()
         .success();
+    let stdout = String::from_utf8(model_scores.get_output().stdout.clone())?;
+    let percentiles = stdout
+        .lines()
+        .map(|line| line.split('\t').nth(1).ok_or("Missing percentile score"))
+        .map(|score| Ok(score?.parse::<f64>()?))
+        .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+    assert_eq!(percentiles.len(), 2);
+    assert!(percentiles[0] <= percentiles[1], "{stdout}");
 
     eprintln!("Compute neg ctrl kernel density estimate");
     let neg_bkde_model = temp_dir.path().join("neg_bkde_model");
@@ -207,6 +479,7 @@ fn integration() -> Result<(), Box<dyn Error>> {
 
     eprintln!("Single molecule analysis");
     let sma_bed = temp_dir.path().join("sma_bed");
+    let per_read_bed = temp_dir.path().join("per_read.bed");
     Command::new(cawlr)
         .arg("sma")
         .arg("--neg-ctrl-scores")
@@ -217,9 +490,179 @@ fn integration() -> Result<(), Box<dyn Error>> {
         .arg(&scores)
         .arg("-o")
         .arg(&sma_bed)
+        .arg("--per-read-output")
+        .arg(&per_read_bed)
+        .env("RUST_BACKTRACE", "1")
+        .assert()
+        .success();
+    let per_read = std::fs::read_to_string(&per_read_bed)?;
+    assert!(!per_read.is_empty());
+    for line in per_read.lines() {
+        let cols = line.split('\t').collect::<Vec<_>>();
+        assert_eq!(cols.len(), 12);
+        let score: u64 = cols[4].parse()?;
+        assert!(score <= 1000);
+        let n_blocks: usize = cols[9].parse()?;
+        assert_eq!(cols[11].split(',').count(), n_blocks);
+    }
+
+    eprintln!("Single molecule analysis on 1 and 8 threads");
+    let mut thread_beds = Vec::new();
+    for threads in [1, 8] {
+        let sma_threads_bed = temp_dir.path().join(format!("sma_{threads}_threads.bed"));
+        Command::new(cawlr)
+            .arg("sma")
+            .arg("--neg-ctrl-scores")
+            .arg(&neg_bkde_model)
+            .arg("--pos-ctrl-scores")
+            .arg(&pos_bkde_model)
+            .arg("-i")
+            .arg(&pos_scores)
+            .arg("-o")
+            .arg(&sma_threads_bed)
+            .arg("--threads")
+            .arg(threads.to_string())
+            .env("RUST_BACKTRACE", "1")
+            .assert()
+            .success();
+        // Track names differ with the file name
+        let bed = std::fs::read_to_string(&sma_threads_bed)?;
+        thread_beds.push(bed.lines().skip(1).map(String::from).collect::<Vec<_>>());
+    }
+    assert!(!thread_beds[0].is_empty());
+    assert_eq!(thread_beds[0], thread_beds[1]);
+
+    eprintln!("Single molecule analysis a few reads at a time");
+    let sma_batch_bed = temp_dir.path().join("sma_batch.bed");
+    Command::new(cawlr)
+        .arg("sma")
+        .arg("--neg-ctrl-scores")
+        .arg(&neg_bkde_model)
+        .arg("--pos-ctrl-scores")
+        .arg(&pos_bkde_model)
+        .arg("-i")
+        .arg(&pos_scores)
+        .arg("-o")
+        .arg(&sma_batch_bed)
+        .arg("--batch-size")
+        .arg("3")
+        .env("RUST_BACKTRACE", "1")
+        .assert()
+        .success();
+    let bed = std::fs::read_to_string(&sma_batch_bed)?;
+    assert_eq!(bed.lines().skip(1).collect::<Vec<_>>(), thread_beds[0]);
+
+    eprintln!("Single molecule analysis per chromosome");
+    let chrom_dir = temp_dir.path().join("sma_per_chrom");
+    std::fs::create_dir(&chrom_dir)?;
+    Command::new(cawlr)
+        .arg("sma")
+        .arg("--neg-ctrl-scores")
+        .arg(&neg_bkde_model)
+        .arg("--pos-ctrl-scores")
+        .arg(&pos_bkde_model)
+        .arg("-i")
+        .arg(&pos_scores)
+        .arg("-o")
+        .arg(chrom_dir.join("sma"))
+        .arg("--per-chromosome")
+        .env("RUST_BACKTRACE", "1")
+        .assert()
+        .success();
+    let mut chrom_lines = Vec::new();
+    for entry in std::fs::read_dir(&chrom_dir)? {
+        let path = entry?.path();
+        let file_name = path.file_name().unwrap().to_string_lossy().to_string();
+        let chrom = file_name
+            .strip_prefix("sma.")
+            .and_then(|name| name.strip_suffix(".bed"))
+            .unwrap();
+        let bed = std::fs::read_to_string(&path)?;
+        for line in bed.lines().skip(1) {
+            assert_eq!(line.split('\t').next(), Some(chrom));
+            chrom_lines.push(line.to_string());
+        }
+    }
+    chrom_lines.sort();
+    let mut all_lines = thread_beds[0].clone();
+    all_lines.sort();
+    assert_eq!(chrom_lines, all_lines);
+
+    eprintln!("Single molecule analysis with minimum coverage");
+    let sma_cov_bed = temp_dir.path().join("sma_cov_bed");
+    Command::new(cawlr)
+        .arg("sma")
+        .arg("--neg-ctrl-scores")
+        .arg(&neg_bkde_model)
+        .arg("--pos-ctrl-scores")
+        .arg(&pos_bkde_model)
+        .arg("-i")
+        .arg(&scores)
+        .arg("-o")
+        .arg(&sma_cov_bed)
+        .arg("--min-coverage")
+        .arg("3")
         .env("RUST_BACKTRACE", "1")
         .assert()
         .success();
+    // The scores are from a single read, so every position is covered once
+    // and only the track line is left
+    assert_ne!(sma_block_counts(&sma_bed)?.0, 0);
+    assert_eq!(sma_block_counts(&sma_cov_bed)?, (0, 0));
+    let cov_bed = std::fs::read_to_string(&sma_cov_bed)?;
+    assert!(cov_bed.starts_with("track "), "{cov_bed}");
+
+    eprintln!("Single molecule analysis with wig output");
+    let sma_wig = temp_dir.path().join("sma.wig");
+    Command::new(cawlr)
+        .arg("sma")
+        .arg("--neg-ctrl-scores")
+        .arg(&neg_bkde_model)
+        .arg("--pos-ctrl-scores")
+        .arg(&pos_bkde_model)
+        .arg("-i")
+        .arg(&scores)
+        .arg("-o")
+        .arg(&sma_wig)
+        .arg("--output-format")
+        .arg("wig")
+        .env("RUST_BACKTRACE", "1")
+        .assert()
+        .success();
+    check_wig(&sma_wig)?;
+
+    eprintln!("Single molecule analysis with HMM segmentation");
+    let segmentations = [
+        ("independent", vec![]),
+        ("hmm", vec![]),
+        ("hmm", vec!["--p-stay", "0.5"]),
+    ];
+    let mut block_counts = Vec::new();
+    for (i, (segmentation, args)) in segmentations.iter().enumerate() {
+        let sma_seg_bed = temp_dir.path().join(format!("sma_seg_{i}.bed"));
+        Command::new(cawlr)
+            .arg("sma")
+            .arg("--neg-ctrl-scores")
+            .arg(&neg_bkde_model)
+            .arg("--pos-ctrl-scores")
+            .arg(&pos_bkde_model)
+            .arg("-i")
+            .arg(&pos_scores)
+            .arg("-o")
+            .arg(&sma_seg_bed)
+            .arg("--segmentation")
+            .arg(segmentation)
+            .args(args)
+            .env("RUST_BACKTRACE", "1")
+            .assert()
+            .success();
+        block_counts.push(sma_block_counts(&sma_seg_bed)?);
+    }
+    let (n_reads, _) = block_counts[0];
+    assert!(n_reads > 0);
+    assert!(block_counts.iter().all(|&(n, _)| n == n_reads));
+    // Positions called on their own fragment reads into more blocks
+    assert!(block_counts[1].1 <= block_counts[2].1, "{block_counts:?}");
 
     temp_dir.close()?;
     Ok(())
@@ -1,9 +1,16 @@
-use std::{error::Error, process::Command};
+use std::{error::Error, fs::File, path::Path, process::Command};
 
+use arrow2::{datatypes::Field, io::ipc::read::read_file_metadata};
 use assert_cmd::prelude::OutputAssertExt;
 use assert_fs::TempDir;
 use escargot::CargoBuild;
+use libcawlr::{
+    arrow::{arrow_utils::load_apply, scored_read::ScoredRead},
+    npsmlr::train::{DEFAULT_SIGNAL_MAX, DEFAULT_SIGNAL_MIN},
+};
 use log::LevelFilter;
+use predicates::prelude::predicate;
+use rusqlite::Connection;
 
 #[test]
 fn integration_npsmlr() -> Result<(), Box<dyn Error>> {
@@ -74,6 +81,7 @@ fn integration_npsmlr() -> Result<(), Box<dyn Error>> {
 
     log::info!("Training on positive control");
     let pos_train = temp_dir.path().join("pos_control.train");
+    let pos_db = temp_dir.path().join("pos_control.db");
     Command::new(cawlr)
         .arg("npsmlr")
         .arg("train")
@@ -81,10 +89,57 @@ fn integration_npsmlr() -> Result<(), Box<dyn Error>> {
         .arg(&pos_output)
         .arg("-o")
         .arg(&pos_train)
+        .arg("-g")
+        .arg("extra/sacCer3.fa")
+        .arg("--output-db")
+        .arg(&pos_db)
         .env("RUST_BACKTRACE", "full")
         .assert()
         .success();
 
+    log::info!("Checking kept training database");
+    let connection = Connection::open(&pos_db)?;
+    let n_rows: usize = connection.query_row("SELECT COUNT(*) FROM data", (), |row| row.get(0))?;
+    assert!(n_rows > 0);
+    let mut stmt = connection.prepare("SELECT kmer, strand, sample FROM data")?;
+    let rows = stmt.query_map((), |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, f64>(2)?,
+        ))
+    })?;
+    for row in rows {
+        let (kmer, strand, sample) = row?;
+        assert_eq!(kmer.len(), 6, "{kmer}");
+        assert!(["+", "-", "."].contains(&strand.as_str()), "{strand}");
+        assert!((DEFAULT_SIGNAL_MIN..=DEFAULT_SIGNAL_MAX).contains(&sample));
+    }
+    drop(stmt);
+    connection.close().map_err(|(_, e)| e)?;
+    for sidecar in ["pos_control.db-wal", "pos_control.db-shm"] {
+        assert!(!temp_dir.path().join(sidecar).exists());
+    }
+
+    log::info!("Checking a kept training database isn't replaced without --overwrite");
+    Command::new(cawlr)
+        .arg("npsmlr")
+        .arg("train")
+        .arg("-i")
+        .arg(&pos_output)
+        .arg("-o")
+        .arg(temp_dir.path().join("pos_control_again.train"))
+        .arg("--output-db")
+        .arg(&pos_db)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("already exists"));
+    let connection = Connection::open(&pos_db)?;
+    let n_rows_after: usize =
+        connection.query_row("SELECT COUNT(*) FROM data", (), |row| row.get(0))?;
+    assert_eq!(n_rows, n_rows_after);
+    connection.close().map_err(|(_, e)| e)?;
+
     log::info!("Training on negative control");
     let neg_train = temp_dir.path().join("neg_control.train");
     Command::new(cawlr)
@@ -94,6 +149,8 @@ fn integration_npsmlr() -> Result<(), Box<dyn Error>> {
         .arg(&neg_output)
         .arg("-o")
         .arg(&neg_train)
+        .arg("-g")
+        .arg("extra/sacCer3.fa")
         .arg("--single")
         .env("RUST_BACKTRACE", "full")
         .assert()
@@ -164,6 +221,67 @@ fn integration_npsmlr() -> Result<(), Box<dyn Error>> {
         .assert()
         .success();
 
+    let mut n_scores = 0;
+    load_apply(File::open(&pos_scores)?, |reads: Vec<ScoredRead>| {
+        n_scores += reads.iter().map(|read| read.scores().len()).sum::<usize>();
+        Ok(())
+    })?;
+    assert!(n_scores > 0, "No positions scored in positive control");
+
+    log::info!("Scoring positive controls with skipping scores");
+    let pos_skip_scores = temp_dir.path().join("pos_skip_scores");
+    Command::new(cawlr)
+        .arg("npsmlr")
+        .arg("score")
+        .arg("-m")
+        .arg("2:GC")
+        .arg("--neg-ctrl")
+        .arg(&neg_train)
+        .arg("--pos-ctrl")
+        .arg(&pos_train)
+        .arg("-i")
+        .arg(&pos_output)
+        .arg("-r")
+        .arg(&ranks)
+        .arg("-g")
+        .arg("extra/sacCer3.fa")
+        .arg("-o")
+        .arg(&pos_skip_scores)
+        .env("RUST_BACKTRACE", "1")
+        .assert()
+        .success();
+
+    log::info!("Scoring positive controls with cawlr score");
+    let pos_classic_scores = temp_dir.path().join("pos_classic_scores");
+    Command::new(cawlr)
+        .arg("score")
+        .arg("-m")
+        .arg("2:GC")
+        .arg("--neg-ctrl")
+        .arg(&neg_train)
+        .arg("--pos-ctrl")
+        .arg(&pos_train)
+        .arg("-i")
+        .arg(&pos_output)
+        .arg("-r")
+        .arg(&ranks)
+        .arg("-g")
+        .arg("extra/sacCer3.fa")
+        .arg("-o")
+        .arg(&pos_classic_scores)
+        .env("RUST_BACKTRACE", "1")
+        .assert()
+        .success();
+
+    let (npsmlr_fields, npsmlr_mean) = score_summary(&pos_skip_scores)?;
+    let (classic_fields, classic_mean) = score_summary(&pos_classic_scores)?;
+    assert_eq!(npsmlr_fields, classic_fields);
+    log::info!("Mean positive control score: npsmlr {npsmlr_mean}, classic {classic_mean}");
+    assert!(
+        (npsmlr_mean - classic_mean).abs() < 0.25,
+        "npsmlr mean {npsmlr_mean} and classic mean {classic_mean} differ"
+    );
+
     log::info!("Scoring negative controls");
     let neg_scores = temp_dir.path().join("neg_scores");
     Command::new(cawlr)
@@ -233,3 +351,22 @@ fn integration_npsmlr() -> Result<(), Box<dyn Error>> {
     temp_dir.close()?;
     Ok(())
 }
+
+/// Schema fields and mean of the finite scores in a scored Arrow file
+fn score_summary(path: &Path) -> Result<(Vec<Field>, f64), Box<dyn Error>> {
+    let mut file = File::open(path)?;
+    let fields = read_file_metadata(&mut file)?.schema.fields;
+    let mut total = 0.0;
+    let mut n_scores = 0;
+    load_apply(File::open(path)?, |reads: Vec<ScoredRead>| {
+        for score in reads.iter().flat_map(|read| read.scores()) {
+            if score.score.is_finite() {
+                total += score.score;
+                n_scores += 1;
+            }
+        }
+        Ok(())
+    })?;
+    assert!(n_scores > 0, "No positions scored in {}", path.display());
+    Ok((fields, total / n_scores as f64))
+}